@@ -6,7 +6,9 @@ use mls_rs_core::crypto::{SignaturePublicKey, SignatureSecretKey};
 use crate::api::account::AccountManager;
 use crate::api::account::get_default_db_path;
 use crate::api::device::types::{
-    config::cipher_suite, custom_mls::credentials::AccountCredential, errors::GroupError,
+    config::cipher_suite,
+    custom_mls::credentials::{AccountCredential, TrustedServerKeys},
+    errors::GroupError,
 };
 
 #[derive(Clone, MlsSize, MlsEncode, MlsDecode)]
@@ -87,6 +89,33 @@ impl Account {
         account_manager.list_accounts().await
     }
 
+    /// Server keys trusted to certify accounts
+    ///
+    /// The key received on registration and the keys added for the account's
+    /// server, e.g. while the server rotates its key. Checks are skipped only
+    /// if the user turned them off for the server.
+    pub async fn trusted_server_keys(&self) -> Result<TrustedServerKeys> {
+        let account_manager = AccountManager::new(get_default_db_path()).await?;
+        if account_manager
+            .skips_certificate_checks(&self.server_address)
+            .await?
+        {
+            return Ok(TrustedServerKeys::skip_checks());
+        }
+        let mut keys = account_manager
+            .get_server_keys(&self.server_address)
+            .await?;
+        if let Some(key) = &self.server_public_key
+            && !key.is_empty()
+            && !keys.contains(key)
+        {
+            keys.insert(0, key.clone());
+        }
+        Ok(TrustedServerKeys::new(
+            keys.into_iter().map(SignaturePublicKey::new).collect(),
+        ))
+    }
+
     pub async fn update_avatar(&self, avatar_url: String) -> Result<()> {
         let account_manager = AccountManager::new(get_default_db_path()).await?;
         account_manager
@@ -1,10 +1,10 @@
 use anyhow::Result;
-use dirs;
 use mls_rs_codec::MlsEncode;
 use sqlx::{Row, SqlitePool, sqlite::SqliteConnectOptions};
 use std::{path::PathBuf, str::FromStr};
 
 use crate::api::account::account::Account;
+use crate::api::storage;
 
 pub struct AccountManager {
     pool: SqlitePool,
@@ -35,6 +35,27 @@ impl AccountManager {
         .execute(&pool)
         .await?;
 
+        // Ключи сервера, которым доверяют помимо полученного при регистрации (ротация)
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS trusted_server_keys (
+                server_address TEXT NOT NULL,
+                public_key BLOB NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY(server_address, public_key)
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        // Серверы, для которых пользователь явно отключил проверку сертификатов
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS unchecked_certificate_servers (
+                server_address TEXT PRIMARY KEY
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
         // Create indexes for performance
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_username ON accounts(username)")
             .execute(&pool)
@@ -138,6 +159,68 @@ impl AccountManager {
         Ok(())
     }
 
+    pub async fn add_server_key(&self, server_address: &str, public_key: &[u8]) -> Result<()> {
+        sqlx::query(
+            "INSERT OR IGNORE INTO trusted_server_keys (server_address, public_key) VALUES (?, ?)",
+        )
+        .bind(server_address)
+        .bind(public_key)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_server_key(&self, server_address: &str, public_key: &[u8]) -> Result<()> {
+        sqlx::query("DELETE FROM trusted_server_keys WHERE server_address = ? AND public_key = ?")
+            .bind(server_address)
+            .bind(public_key)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_server_keys(&self, server_address: &str) -> Result<Vec<Vec<u8>>> {
+        let keys = sqlx::query_scalar(
+            "SELECT public_key FROM trusted_server_keys WHERE server_address = ? ORDER BY created_at",
+        )
+        .bind(server_address)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(keys)
+    }
+
+    pub async fn set_skip_certificate_checks(
+        &self,
+        server_address: &str,
+        skip: bool,
+    ) -> Result<()> {
+        let query = if skip {
+            "INSERT OR IGNORE INTO unchecked_certificate_servers (server_address) VALUES (?)"
+        } else {
+            "DELETE FROM unchecked_certificate_servers WHERE server_address = ?"
+        };
+        sqlx::query(query)
+            .bind(server_address)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn skips_certificate_checks(&self, server_address: &str) -> Result<bool> {
+        let skip = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM unchecked_certificate_servers WHERE server_address = ?)",
+        )
+        .bind(server_address)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(skip)
+    }
+
     pub async fn list_accounts(&self) -> Result<Vec<Account>> {
         use crate::api::device::types::custom_mls::credentials::AccountCredential;
         use mls_rs_codec::MlsDecode;
@@ -178,20 +261,5 @@ impl AccountManager {
 
 // Utility function to get default database path
 pub fn get_default_db_path() -> std::path::PathBuf {
-    #[cfg(not(target_os = "ios"))]
-    {
-        let mut path = dirs::home_dir().expect("Could not find home directory");
-        path.push(".ship");
-        std::fs::create_dir_all(&path).expect("Could not create .ship directory");
-        path.push("accounts.db");
-        path
-    }
-    #[cfg(target_os = "ios")]
-    {
-        let mut path = dirs::document_dir().expect("Could not find home directory");
-        path.push("ship");
-        std::fs::create_dir_all(&path).expect("Could not create .ship directory");
-        path.push("accounts.db");
-        path
-    }
+    storage::layout().accounts_db()
 }
@@ -0,0 +1,386 @@
+//! Password-encrypted backup of the local state of a device
+//!
+//! A backup holds the account, the group database, the MLS state, the
+//! contacts database and the voice identity. The databases are copied with
+//! `VACUUM INTO` while inbound messages are held back, so they match.
+//!
+//! Messages acknowledged by this device are not delivered again, so restoring
+//! MLS state older than what was delivered would leave groups unable to
+//! decrypt. Restore refuses backups behind the delivery watermarks of the
+//! install. The delivery server has no request returning what a device
+//! acknowledged, it only confirms the device is still registered; restore
+//! fails if the server cannot be reached.
+
+use sqlx::{
+    ConnectOptions, Connection,
+    sqlite::{SqliteConnectOptions, SqliteConnection},
+};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::api::{
+    account::Account,
+    device::{
+        connection::Backend,
+        db::GroupManager,
+        device::Device,
+        group::unix_now,
+        types::{
+            backup::{
+                ACCOUNT_FILE, BACKUP_VERSION, BackupManifest, BackupReader, BackupWriter,
+                CONTACTS_DB_FILE, GROUP_DB_FILE, MLS_DB_FILE, VOICE_FILE,
+            },
+            errors::GroupError,
+            identity_keypair::IdentityKeypair,
+        },
+    },
+    storage,
+};
+
+/// Largest account record accepted from a backup
+const MAX_ACCOUNT_LEN: u64 = 1024 * 1024;
+
+impl Device {
+    /// Write a backup of this device encrypted with `password` to `path`
+    ///
+    /// The caller must keep other commands away from the device, inbound
+    /// messages are paused while the databases are copied. `path` is only
+    /// replaced once the backup is complete.
+    pub async fn create_backup(&self, password: &str, path: &Path) -> Result<(), GroupError> {
+        let user_id = self.user_id();
+        let work_dir = work_dir(user_id, ".backup")?;
+        let written = self.write_backup(password, path, &work_dir).await;
+        let _ = tokio::fs::remove_dir_all(&work_dir).await;
+        written
+    }
+
+    async fn write_backup(
+        &self,
+        password: &str,
+        path: &Path,
+        work_dir: &Path,
+    ) -> Result<(), GroupError> {
+        let user_id = self.user_id();
+        let layout = storage::layout();
+
+        let mut files = Vec::new();
+        {
+            let _processing = self.groups.lock_processing().await;
+            for (name, source, required) in [
+                (GROUP_DB_FILE, layout.group_db(user_id), true),
+                (MLS_DB_FILE, layout.mls_db(user_id, &self.device_id), true),
+                (CONTACTS_DB_FILE, layout.contacts_db(user_id), false),
+            ] {
+                if !source.exists() {
+                    if required {
+                        return Err(GroupError::StorageError(format!(
+                            "{} does not exist",
+                            source.display()
+                        )));
+                    }
+                    continue;
+                }
+                let snapshot = work_dir.join(name);
+                snapshot_database(&source, &snapshot).await?;
+                files.push((name, snapshot));
+            }
+            let voice = layout.voice_file(user_id);
+            if voice.exists() {
+                let copy = work_dir.join(VOICE_FILE);
+                tokio::fs::copy(&voice, &copy).await.map_err(|e| {
+                    GroupError::StorageError(format!("Failed to read {}: {}", voice.display(), e))
+                })?;
+                files.push((VOICE_FILE, copy));
+            }
+        }
+
+        let manifest = BackupManifest {
+            version: BACKUP_VERSION,
+            created_at: unix_now()? as u64,
+            user_id,
+            device_id: self.device_id.clone(),
+        };
+        let account = self.account.to_mls_bytes()?;
+        let password = password.to_string();
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            write_archive(&path, &password, &manifest, &account, &files)
+        })
+        .await
+        .map_err(|e| GroupError::StorageError(format!("Backup task failed: {}", e)))?
+    }
+}
+
+/// Backup decrypted into a temporary directory of its account, see [`unpack_backup`]
+///
+/// The directory is removed when dropped.
+pub struct UnpackedBackup {
+    pub manifest: BackupManifest,
+    account: Account,
+    work_dir: PathBuf,
+}
+
+impl Drop for UnpackedBackup {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.work_dir);
+    }
+}
+
+/// Decrypt a backup made by [`Device::create_backup`] without changing the install
+///
+/// - Errors: `GroupError::InvalidMessage` if it is not a supported backup,
+///   `GroupError::CryptoError` for a wrong password or a corrupted file
+pub async fn unpack_backup(path: &Path, password: &str) -> Result<UnpackedBackup, GroupError> {
+    let path = path.to_path_buf();
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || unpack_archive(&path, &password))
+        .await
+        .map_err(|e| GroupError::StorageError(format!("Restore task failed: {}", e)))?
+}
+
+/// Restore a backup unpacked by [`unpack_backup`], returning its account
+///
+/// The account must not be logged in. Nothing is replaced unless:
+/// - the device identity in the backup belongs to its account and device;
+/// - the install has no other device of the account;
+/// - no group of the install was delivered at a later epoch than in the backup;
+/// - the delivery server lists the device of the backup for its account.
+pub async fn restore_backup(backup: UnpackedBackup) -> Result<Account, GroupError> {
+    let manifest = &backup.manifest;
+    restore_files(manifest, &backup.account, &backup.work_dir).await?;
+
+    backup
+        .account
+        .save_to_db()
+        .await
+        .map_err(|e| GroupError::StorageError(format!("Failed to save account: {}", e)))?;
+    log::info!(
+        "Restored backup of device {} of account {} from {}",
+        manifest.device_id,
+        manifest.user_id,
+        manifest.created_at
+    );
+    Ok(backup.account.clone())
+}
+
+async fn restore_files(
+    manifest: &BackupManifest,
+    account: &Account,
+    work_dir: &Path,
+) -> Result<(), GroupError> {
+    let user_id = manifest.user_id;
+    let layout = storage::layout();
+
+    for name in [GROUP_DB_FILE, MLS_DB_FILE] {
+        if !work_dir.join(name).exists() {
+            return Err(GroupError::InvalidMessage(format!(
+                "Backup has no {}",
+                name
+            )));
+        }
+    }
+
+    // Device identity of the backup
+    let backup_db = GroupManager::new(work_dir.join(GROUP_DB_FILE)).await?;
+    let user = backup_db.load_user(user_id as i64).await;
+    let backup_watermarks = backup_db.get_delivery_watermarks().await;
+    backup_db.close().await;
+    let (device_id, identity_bytes) = user.map_err(|e| {
+        GroupError::CredentialError(format!("Backup has no device identity: {}", e))
+    })?;
+    let identity = IdentityKeypair::from_bytes(&mut &*identity_bytes)?;
+    if device_id != manifest.device_id
+        || identity.credential.device_id.device_id != device_id
+        || identity.credential.device_id.user_id != user_id
+        || identity.credential.user_public_key != account.credential.public_key
+    {
+        return Err(GroupError::CredentialError(
+            "Device identity of the backup does not match its account".to_string(),
+        ));
+    }
+    let backup_watermarks = backup_watermarks?;
+
+    // State of the install
+    let group_db = layout.group_db(user_id);
+    if group_db.exists() {
+        let current_db = GroupManager::new(group_db.clone()).await?;
+        let current_user = current_db.load_user(user_id as i64).await.ok();
+        let current_watermarks = current_db.get_delivery_watermarks().await;
+        current_db.close().await;
+
+        if let Some((current_device, _)) = current_user
+            && current_device != device_id
+        {
+            return Err(GroupError::CredentialError(format!(
+                "Backup is of device {}, this install has device {}",
+                device_id, current_device
+            )));
+        }
+        let stale = current_watermarks?
+            .iter()
+            .filter(|(group_id, epoch)| {
+                backup_watermarks
+                    .get(*group_id)
+                    .is_none_or(|backup_epoch| backup_epoch < epoch)
+            })
+            .count();
+        if stale > 0 {
+            return Err(GroupError::StaleBackup(stale));
+        }
+    }
+    check_device_registered(account, &device_id).await?;
+
+    for (name, target) in [
+        (GROUP_DB_FILE, group_db),
+        (MLS_DB_FILE, layout.mls_db(user_id, &device_id)),
+        (CONTACTS_DB_FILE, layout.contacts_db(user_id)),
+        (VOICE_FILE, layout.voice_file(user_id)),
+    ] {
+        let source = work_dir.join(name);
+        if source.exists() {
+            storage::replace_database(&source, &target).map_err(|e| {
+                GroupError::StorageError(format!("Failed to restore {}: {}", name, e))
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Check with the delivery server that `device_id` is still a device of the account
+async fn check_device_registered(account: &Account, device_id: &str) -> Result<(), GroupError> {
+    let backend = Backend::new(account.server_address.clone())
+        .await
+        .map_err(|e| GroupError::BackendError(format!("Delivery server unreachable: {}", e)))?;
+    let devices = backend
+        .get_users_devices(account.user_id)
+        .await
+        .map_err(|e| GroupError::BackendError(format!("Failed to fetch devices: {}", e)))?;
+    if !devices.iter().any(|device| device.device_id == device_id) {
+        return Err(GroupError::CredentialError(format!(
+            "Device {} is no longer registered on the delivery server",
+            device_id
+        )));
+    }
+    Ok(())
+}
+
+/// Write the archive next to `path`, then move it in place
+fn write_archive(
+    path: &Path,
+    password: &str,
+    manifest: &BackupManifest,
+    account: &[u8],
+    files: &[(&str, PathBuf)],
+) -> Result<(), GroupError> {
+    let partial = PathBuf::from(format!("{}.part", path.display()));
+    let write_error =
+        |e: std::io::Error| GroupError::StorageError(format!("Failed to write backup: {}", e));
+    let written = (|| {
+        let output = BufWriter::new(File::create(&partial).map_err(write_error)?);
+        let mut writer = BackupWriter::new(output, password, manifest)?;
+        writer.add_file(ACCOUNT_FILE, &mut &*account, account.len() as u64)?;
+        for (name, source) in files {
+            let mut file = File::open(source).map_err(write_error)?;
+            let len = file.metadata().map_err(write_error)?.len();
+            writer.add_file(name, &mut file, len)?;
+        }
+        let output = writer
+            .finish()?
+            .into_inner()
+            .map_err(|e| write_error(e.into_error()))?;
+        output.sync_all().map_err(write_error)?;
+        std::fs::rename(&partial, path).map_err(write_error)
+    })();
+    if written.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+    written
+}
+
+/// Decrypt the archive at `path` into a new work directory of its account
+fn unpack_archive(path: &Path, password: &str) -> Result<UnpackedBackup, GroupError> {
+    let input = File::open(path)
+        .map_err(|e| GroupError::StorageError(format!("Failed to read backup: {}", e)))?;
+    let (mut reader, manifest) = BackupReader::open(BufReader::new(input), password)?;
+    let work_dir = work_dir(manifest.user_id, ".restore")?;
+    match unpack_files(&mut reader, &work_dir, manifest.user_id) {
+        Ok(account) => Ok(UnpackedBackup {
+            manifest,
+            account,
+            work_dir,
+        }),
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(&work_dir);
+            Err(e)
+        }
+    }
+}
+
+fn unpack_files(
+    reader: &mut BackupReader<BufReader<File>>,
+    work_dir: &Path,
+    user_id: u64,
+) -> Result<Account, GroupError> {
+    let unpack_error =
+        |e: std::io::Error| GroupError::StorageError(format!("Failed to unpack backup: {}", e));
+    let mut account = None;
+    while let Some((name, len)) = reader.next_file()? {
+        // Только известные имена: имя файла из архива становится путём
+        match name.as_str() {
+            ACCOUNT_FILE if len <= MAX_ACCOUNT_LEN => {
+                let mut bytes = Vec::new();
+                reader.read_file(&mut bytes, len)?;
+                account = Some(bytes);
+            }
+            GROUP_DB_FILE | MLS_DB_FILE | CONTACTS_DB_FILE | VOICE_FILE => {
+                let mut file =
+                    BufWriter::new(File::create(work_dir.join(&name)).map_err(unpack_error)?);
+                reader.read_file(&mut file, len)?;
+                file.flush().map_err(unpack_error)?;
+            }
+            name => {
+                return Err(GroupError::InvalidMessage(format!(
+                    "Unexpected file in backup: {}",
+                    name
+                )));
+            }
+        }
+    }
+    let account =
+        account.ok_or_else(|| GroupError::InvalidMessage("Backup has no account".to_string()))?;
+    let account = Account::from_mls_bytes(&mut &*account)?;
+    if account.user_id != user_id {
+        return Err(GroupError::CredentialError(
+            "Backup account does not match its manifest".to_string(),
+        ));
+    }
+    Ok(account)
+}
+
+/// Empty directory for temporary files of an account
+fn work_dir(user_id: u64, name: &str) -> Result<PathBuf, GroupError> {
+    let dir = storage::layout().account_dir(user_id).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).map_err(|e| {
+        GroupError::StorageError(format!("Failed to create {}: {}", dir.display(), e))
+    })?;
+    Ok(dir)
+}
+
+/// Consistent copy of an SQLite database, including its WAL
+async fn snapshot_database(source: &Path, target: &Path) -> Result<(), GroupError> {
+    let options = SqliteConnectOptions::from_str(&format!("sqlite:{}", source.display()))?
+        .read_only(true)
+        .disable_statement_logging();
+    let mut connection = SqliteConnection::connect_with(&options).await?;
+    sqlx::query("VACUUM INTO ?1")
+        .bind(target.display().to_string())
+        .execute(&mut connection)
+        .await?;
+    connection.close().await?;
+    Ok(())
+}
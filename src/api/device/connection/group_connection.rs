@@ -135,13 +135,7 @@ impl Backend {
 
     pub async fn get_users_devices(&self, user_id: u64) -> Result<Vec<Device>, Status> {
         let request = GetUsersDevicesRequest { user_id };
-        let response = self
-            .client
-            .lock()
-            .await
-            .get_users_devices(request)
-            .await
-            .unwrap();
+        let response = self.client.lock().await.get_users_devices(request).await?;
         Ok(response.into_inner().devices)
     }
 
@@ -1,11 +1,26 @@
+use crate::api::device::types::audit::{AuditCategory, GroupAuditEntry};
+use crate::api::device::types::custom_mls::credentials::AccountCredential;
 use crate::api::device::types::errors::GroupError;
+use crate::api::device::types::extensions::group_config::group_config::{
+    ConfigChange, GroupConfig,
+};
 use crate::api::device::types::group::GroupId;
-use crate::api::device::types::message::{GroupTextMessage, UserGroupMessage};
-use dirs;
-
+use crate::api::device::types::message::{
+    GroupDraftMessage, GroupPollClose, GroupPollMessage, GroupPollVote, GroupTextMessage, Mention,
+    UserGroupMessage,
+};
+use crate::api::device::types::poll::{PollResults, poll_cutoff, tally};
+use crate::api::device::types::text_entity::{TextEntity, decode_entities, encode_entities};
+use crate::api::storage;
+
+use mls_rs_codec::{MlsDecode, MlsEncode};
 use moka::future::{Cache, CacheBuilder};
 use sha2::Digest;
-use sqlx::{ConnectOptions, Row, SqlitePool, sqlite::SqliteConnectOptions};
+use sqlx::{
+    ConnectOptions, Row, SqliteConnection, SqlitePool,
+    sqlite::{SqliteConnectOptions, SqliteRow},
+};
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::{Duration, Instant};
@@ -49,11 +64,157 @@ lazy_static::lazy_static! {
     pub static ref GROUP_METRICS: GlobalMetrics = GlobalMetrics::default();
 }
 
+/// Reply statistics of a message, counting replies to replies as well
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadStats {
+    pub reply_count: u32,
+    pub last_reply_at: Option<i64>,
+    pub unread_count: u32,
+}
+
+/// Earlier text of an edited message
+#[derive(Debug, Clone)]
+pub struct MessageRevision {
+    pub text: String,
+    pub entities: Vec<TextEntity>,
+    // Date the text was written: the send date for the original, the edit date otherwise
+    pub edit_date: i64,
+}
+
+/// State of a message in the local send queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduledStatus {
+    Pending,
+    // Claimed by the scheduler, cannot be edited or cancelled
+    Sending,
+    Failed,
+}
+
+impl ScheduledStatus {
+    fn from_i64(status: i64) -> Self {
+        match status {
+            1 => ScheduledStatus::Sending,
+            2 => ScheduledStatus::Failed,
+            _ => ScheduledStatus::Pending,
+        }
+    }
+}
+
+/// Message waiting in the local queue until `send_at`
+#[derive(Debug, Clone)]
+pub struct ScheduledMessage {
+    pub group_id: GroupId,
+    pub message_id: i64,
+    pub send_at: i64,
+    pub message: UserGroupMessage,
+    pub status: ScheduledStatus,
+    pub last_error: Option<String>,
+}
+
+/// Draft of a group as stored on this device
+#[derive(Debug, Clone)]
+pub struct Draft {
+    pub message: GroupDraftMessage,
+    // Attachment picked on this device, not synced to the other devices
+    pub attachment_path: Option<String>,
+}
+
+impl Draft {
+    pub fn is_empty(&self) -> bool {
+        self.message.is_empty() && self.attachment_path.is_none()
+    }
+}
+
+/// Public key of a contact that differs from the pinned one
+#[derive(Debug, Clone)]
+pub struct KeyChange {
+    pub user_id: u64,
+    pub old_public_key: Vec<u8>,
+    pub new_public_key: Vec<u8>,
+    // The contact was verified with the old key
+    pub was_verified: bool,
+    pub changed_at: i64,
+}
+
+// Images up to this size are returned inline with the message
+const MAX_INLINE_MEDIA_SIZE: i64 = 1024 * 1024 * 100;
+
 // Helper function to log timing
 fn log_operation_time(operation: &str, duration: Duration) {
     log::info!("Operation '{}' took {:?}", operation, duration);
 }
 
+// Store the mentions of a message; mentions of `read_users` start out read
+async fn insert_mentions(
+    conn: &mut SqliteConnection,
+    group_id: &[u8],
+    message_id: i64,
+    mentions: &[Mention],
+    read_users: &[i64],
+) -> Result<()> {
+    for mention in mentions {
+        sqlx::query(
+            "INSERT OR IGNORE INTO message_mentions (
+                group_id, message_id, user_id, range_start, range_length, read
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )
+        .bind(group_id)
+        .bind(message_id)
+        .bind(mention.user_id)
+        .bind(mention.offset as i64)
+        .bind(mention.length as i64)
+        .bind(read_users.contains(&mention.user_id))
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+// Store the formatting entities of a message, replacing earlier ones
+async fn insert_entities(
+    conn: &mut SqliteConnection,
+    group_id: &[u8],
+    message_id: i64,
+    entities: &[TextEntity],
+) -> Result<()> {
+    if entities.is_empty() {
+        sqlx::query("DELETE FROM message_entities WHERE group_id = ?1 AND message_id = ?2")
+            .bind(group_id)
+            .bind(message_id)
+            .execute(&mut *conn)
+            .await?;
+        return Ok(());
+    }
+    sqlx::query(
+        "INSERT OR REPLACE INTO message_entities (group_id, message_id, entities)
+         VALUES (?1, ?2, ?3)",
+    )
+    .bind(group_id)
+    .bind(message_id)
+    .bind(encode_entities(entities))
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+// Bind a list of ids as a JSON array, read back with `json_each`
+fn json_ids(ids: &[i64]) -> Result<String> {
+    serde_json::to_string(ids)
+        .map_err(|e| GroupError::EncodingError(format!("Failed to encode ids: {}", e)))
+}
+
+fn scheduled_message_from_row(row: &SqliteRow) -> Result<ScheduledMessage> {
+    let message: Vec<u8> = row.get("message");
+    Ok(ScheduledMessage {
+        group_id: GroupId::new(row.get("group_id")),
+        message_id: row.get("message_id"),
+        send_at: row.get("send_at"),
+        message: UserGroupMessage::from_bytes(&message).map_err(GroupError::InvalidMessage)?,
+        status: ScheduledStatus::from_i64(row.get("status")),
+        last_error: row.get("last_error"),
+    })
+}
+
 impl GroupManager {
     pub async fn new(db_path: PathBuf) -> Result<Self> {
         // Create database connection options
@@ -133,6 +294,228 @@ impl GroupManager {
         .execute(&pool)
         .await?;
 
+        // Ключ, с которым контакт был проверен по номеру безопасности
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS verified_contacts (
+                user_id INTEGER PRIMARY KEY,
+                public_key BLOB NOT NULL,
+                verified_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        // Последняя смена ключа контакта, пока пользователь её не подтвердил
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS contact_key_changes (
+                user_id INTEGER PRIMARY KEY,
+                old_public_key BLOB NOT NULL,
+                new_public_key BLOB NOT NULL,
+                was_verified INTEGER NOT NULL,
+                changed_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+                acknowledged INTEGER NOT NULL DEFAULT 0
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS device_settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        // Epoch of each group once the server delivered a message, acked messages are gone from it
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS delivery_watermarks (
+                group_id BLOB PRIMARY KEY,
+                epoch INTEGER NOT NULL,
+                delivered_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS group_audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                group_id BLOB NOT NULL,
+                epoch INTEGER NOT NULL,
+                committer_id INTEGER NOT NULL,
+                category TEXT NOT NULL,
+                field TEXT NOT NULL,
+                old_value TEXT NOT NULL,
+                new_value TEXT NOT NULL,
+                timestamp INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS archived_groups (
+                group_id BLOB PRIMARY KEY,
+                config BLOB NOT NULL,
+                archived_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS thread_read_state (
+                group_id BLOB NOT NULL,
+                root_message_id INTEGER NOT NULL,
+                last_read_at INTEGER NOT NULL,
+                PRIMARY KEY(group_id, root_message_id)
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS message_revisions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                message_id INTEGER NOT NULL,
+                group_id BLOB NOT NULL,
+                content BLOB NOT NULL,
+                edit_date INTEGER NOT NULL
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        // Позиция чтения: последнее прочитанное сообщение в порядке (timestamp, message_id).
+        // Строка заводится при вступлении в группу, (0, 0) — ничего не прочитано
+        let read_state_exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'group_read_state')",
+        )
+        .fetch_one(&pool)
+        .await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS group_read_state (
+                group_id BLOB PRIMARY KEY,
+                last_read_at INTEGER NOT NULL,
+                last_read_message_id INTEGER NOT NULL
+            )",
+        )
+        .execute(&pool)
+        .await?;
+        // Уже полученная история считается прочитанной
+        if !read_state_exists {
+            sqlx::query(
+                "INSERT INTO group_read_state (group_id, last_read_at, last_read_message_id)
+                 SELECT group_id, timestamp, message_id FROM (
+                     SELECT group_id, timestamp, message_id, ROW_NUMBER() OVER (
+                         PARTITION BY group_id ORDER BY timestamp DESC, message_id DESC
+                     ) AS position
+                     FROM group_messages
+                 )
+                 WHERE position = 1",
+            )
+            .execute(&pool)
+            .await?;
+        }
+
+        // Сущности форматирования ревизий, по id ревизии
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS message_revision_entities (
+                revision_id INTEGER PRIMARY KEY,
+                entities BLOB NOT NULL
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        // Сущности форматирования в том же виде, что и в зашифрованном сообщении
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS message_entities (
+                group_id BLOB NOT NULL,
+                message_id INTEGER NOT NULL,
+                entities BLOB NOT NULL,
+                PRIMARY KEY(group_id, message_id)
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS message_mentions (
+                group_id BLOB NOT NULL,
+                message_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                range_start INTEGER NOT NULL,
+                range_length INTEGER NOT NULL,
+                read INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY(group_id, message_id, user_id, range_start)
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS polls (
+                group_id BLOB NOT NULL,
+                poll_id INTEGER NOT NULL,
+                sender_id INTEGER NOT NULL,
+                poll BLOB NOT NULL,
+                created_at INTEGER NOT NULL,
+                closed_at INTEGER,
+                PRIMARY KEY(group_id, poll_id)
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        // Every vote is kept, the tally picks the latest one of each member
+        // before the poll closed
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS poll_votes (
+                group_id BLOB NOT NULL,
+                poll_id INTEGER NOT NULL,
+                voter_id INTEGER NOT NULL,
+                voted_at INTEGER NOT NULL,
+                options TEXT NOT NULL,
+                PRIMARY KEY(group_id, poll_id, voter_id, voted_at)
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        // Черновик на группу; пустой черновик хранится, чтобы старые копии
+        // с других устройств его не перезаписали. synced = 0 — ещё не отправлен
+        // на другие устройства
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS group_drafts (
+                group_id BLOB PRIMARY KEY,
+                text TEXT NOT NULL,
+                reply_message_id INTEGER,
+                attachment_path TEXT,
+                updated_at INTEGER NOT NULL,
+                synced INTEGER NOT NULL DEFAULT 0
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        // Очередь отложенных сообщений, шифруются только в момент отправки
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS scheduled_messages (
+                group_id BLOB NOT NULL,
+                message_id INTEGER NOT NULL,
+                send_at INTEGER NOT NULL,
+                message BLOB NOT NULL,
+                status INTEGER NOT NULL DEFAULT 0,
+                last_error TEXT,
+                PRIMARY KEY(group_id, message_id)
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
         // Create indexes
         sqlx::query(
             "CREATE INDEX IF NOT EXISTS idx_group_messages_group_id 
@@ -155,6 +538,34 @@ impl GroupManager {
         .execute(&pool)
         .await?;
 
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_group_messages_group_timestamp 
+             ON group_messages(group_id, timestamp, message_id)",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_group_messages_reply 
+             ON group_messages(group_id, reply_message_id)",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_message_revisions_message 
+             ON message_revisions(group_id, message_id)",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_message_mentions_user 
+             ON message_mentions(user_id, read)",
+        )
+        .execute(&pool)
+        .await?;
+
         sqlx::query(
             "CREATE INDEX IF NOT EXISTS idx_group_media_group_id 
              ON group_media(group_id)",
@@ -162,6 +573,20 @@ impl GroupManager {
         .execute(&pool)
         .await?;
 
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_group_audit_log_group 
+             ON group_audit_log(group_id, epoch)",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_scheduled_messages_due 
+             ON scheduled_messages(status, send_at)",
+        )
+        .execute(&pool)
+        .await?;
+
         // Build caches
         let contacts_cache = CacheBuilder::new(10_000)
             .time_to_live(Duration::from_secs(60 * 30))
@@ -230,20 +655,6 @@ impl GroupManager {
         }
     }
 
-    pub async fn save_contact(&self, user_id: i64, user_credential: &[u8]) -> Result<()> {
-        sqlx::query(
-            "INSERT INTO contacts (
-                user_id, user_credential
-            ) VALUES (?1, ?2)",
-        )
-        .bind(user_id)
-        .bind(user_credential)
-        .execute(&self.pool)
-        .await?;
-
-        Ok(())
-    }
-
     pub async fn get_contact(&self, user_id: i64) -> Result<Option<Vec<u8>>> {
         let result = self
             .contacts_cache
@@ -259,55 +670,338 @@ impl GroupManager {
         Ok(result.as_ref().cloned())
     }
 
-    // Find existing media with timing metrics
-    pub async fn find_existing_media(&self, media_data: &[u8]) -> Result<Option<String>> {
-        let start = Instant::now();
-
-        // First create hash of media data to compare
-        let mut hasher = sha2::Sha256::new();
-        hasher.update(media_data);
-        let hash = hasher.finalize();
-        let media_id = hex::encode(hash);
-
-        // Check existence cache first
-        if let Some(exists) = self.media_exists_cache.get(&media_id).await {
-            let duration = start.elapsed();
-            log_operation_time("Find existing media (cache)", duration);
-            GROUP_METRICS
-                .find_media_ns
-                .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
-            return Ok(if exists { Some(media_id) } else { None });
+    // Pin the credential of a contact, returning the key change if it replaces another key
+    //
+    // The new credential replaces the pinned one and the verification of the
+    // contact is removed.
+    pub async fn pin_contact(&self, credential: &AccountCredential) -> Result<Option<KeyChange>> {
+        let user_id = credential.account_id.user_id as i64;
+        let credential_bytes = credential.mls_encode_to_vec()?;
+        let Some(pinned) = self.get_contact(user_id).await? else {
+            sqlx::query(
+                "INSERT OR IGNORE INTO contacts (user_id, user_credential) VALUES (?1, ?2)",
+            )
+            .bind(user_id)
+            .bind(&credential_bytes)
+            .execute(&self.pool)
+            .await?;
+            self.contacts_cache.invalidate(&user_id).await;
+            return Ok(None);
+        };
+        let pinned = AccountCredential::mls_decode(&mut &*pinned)?;
+        if pinned.public_key.as_bytes() == credential.public_key.as_bytes() {
+            return Ok(None);
         }
 
-        let row = sqlx::query("SELECT media_id FROM group_media WHERE media_id = ?")
-            .bind(&media_id)
-            .fetch_optional(&self.pool)
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE contacts SET user_credential = ?1 WHERE user_id = ?2")
+            .bind(&credential_bytes)
+            .bind(user_id)
+            .execute(&mut *tx)
             .await?;
+        let was_verified = sqlx::query("DELETE FROM verified_contacts WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+            > 0;
+        let changed_at: i64 = sqlx::query_scalar(
+            "INSERT OR REPLACE INTO contact_key_changes (
+                user_id, old_public_key, new_public_key, was_verified
+            ) VALUES (?1, ?2, ?3, ?4)
+            RETURNING changed_at",
+        )
+        .bind(user_id)
+        .bind(pinned.public_key.as_bytes())
+        .bind(credential.public_key.as_bytes())
+        .bind(was_verified)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        self.contacts_cache.invalidate(&user_id).await;
+
+        Ok(Some(KeyChange {
+            user_id: user_id as u64,
+            old_public_key: pinned.public_key.as_bytes().to_vec(),
+            new_public_key: credential.public_key.as_bytes().to_vec(),
+            was_verified,
+            changed_at,
+        }))
+    }
 
-        let result = row.map(|r| r.get::<String, _>("media_id"));
-        // Populate existence cache
-        self.media_exists_cache
-            .insert(media_id.clone(), result.is_some())
-            .await;
+    // Compare a roster credential with the pinned one, pinning it if there is none
+    //
+    // A different key does not replace the pinned one: it is reported as a key change
+    // once per key, the contact stays verified.
+    pub async fn check_roster_key(
+        &self,
+        credential: &AccountCredential,
+    ) -> Result<Option<KeyChange>> {
+        let user_id = credential.account_id.user_id as i64;
+        let Some(pinned) = self.get_contact(user_id).await? else {
+            return self.pin_contact(credential).await;
+        };
+        let pinned = AccountCredential::mls_decode(&mut &*pinned)?;
+        if pinned.public_key.as_bytes() == credential.public_key.as_bytes() {
+            return Ok(None);
+        }
 
-        let duration = start.elapsed();
-        log_operation_time("Find existing media", duration);
-        GROUP_METRICS
-            .find_media_ns
-            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+        let was_verified: bool =
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM verified_contacts WHERE user_id = ?)")
+                .bind(user_id)
+                .fetch_one(&self.pool)
+                .await?;
+        // Та же пара ключей уже сообщена, в том числе подтверждённая
+        let changed_at: Option<i64> = sqlx::query_scalar(
+            "INSERT INTO contact_key_changes (
+                user_id, old_public_key, new_public_key, was_verified
+            ) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT(user_id) DO UPDATE SET
+                old_public_key = excluded.old_public_key,
+                new_public_key = excluded.new_public_key,
+                was_verified = excluded.was_verified,
+                changed_at = excluded.changed_at,
+                acknowledged = 0
+            WHERE (old_public_key, new_public_key)
+                != (excluded.old_public_key, excluded.new_public_key)
+            RETURNING changed_at",
+        )
+        .bind(user_id)
+        .bind(pinned.public_key.as_bytes())
+        .bind(credential.public_key.as_bytes())
+        .bind(was_verified)
+        .fetch_optional(&self.pool)
+        .await?;
 
-        Ok(result)
+        Ok(changed_at.map(|changed_at| KeyChange {
+            user_id: user_id as u64,
+            old_public_key: pinned.public_key.as_bytes().to_vec(),
+            new_public_key: credential.public_key.as_bytes().to_vec(),
+            was_verified,
+            changed_at,
+        }))
     }
 
-    // Save a message with timing metrics
-    pub async fn save_message(&self, message: &UserGroupMessage, group_id: &[u8]) -> Result<()> {
-        let start = Instant::now();
-        match message {
-            UserGroupMessage::TextMessage(message) => {
-                let mut media_id = None;
-                let mut is_media_found = false;
+    // Get the key changes the user has not acknowledged yet
+    pub async fn get_key_changes(&self) -> Result<Vec<KeyChange>> {
+        let rows = sqlx::query(
+            "SELECT user_id, old_public_key, new_public_key, was_verified, changed_at
+             FROM contact_key_changes
+             WHERE acknowledged = 0
+             ORDER BY changed_at DESC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
 
-                // Check for existing media before starting transaction
+        Ok(rows
+            .into_iter()
+            .map(|row| KeyChange {
+                user_id: row.get::<i64, _>("user_id") as u64,
+                old_public_key: row.get("old_public_key"),
+                new_public_key: row.get("new_public_key"),
+                was_verified: row.get("was_verified"),
+                changed_at: row.get("changed_at"),
+            })
+            .collect())
+    }
+
+    // Get the users among `user_ids` with a key change not acknowledged yet
+    pub async fn get_unacknowledged_key_changes(&self, user_ids: &[u64]) -> Result<Vec<u64>> {
+        let changed: Vec<i64> =
+            sqlx::query_scalar("SELECT user_id FROM contact_key_changes WHERE acknowledged = 0")
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(user_ids
+            .iter()
+            .copied()
+            .filter(|user_id| changed.contains(&(*user_id as i64)))
+            .collect())
+    }
+
+    // Acknowledge the key change of a contact, returns false if there was none
+    pub async fn acknowledge_key_change(&self, user_id: i64) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE contact_key_changes SET acknowledged = 1
+             WHERE user_id = ? AND acknowledged = 0",
+        )
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        let value = sqlx::query_scalar("SELECT value FROM device_settings WHERE key = ?")
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(value)
+    }
+
+    pub async fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO device_settings (key, value) VALUES (?, ?)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        )
+        .bind(key)
+        .bind(value)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Raise the delivery watermark of a group to `epoch`
+    pub async fn record_delivery(&self, group_id: &[u8], epoch: u64) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO delivery_watermarks (group_id, epoch) VALUES (?1, ?2)
+             ON CONFLICT(group_id) DO UPDATE SET
+                epoch = MAX(epoch, excluded.epoch),
+                delivered_at = strftime('%s', 'now')",
+        )
+        .bind(group_id)
+        .bind(epoch as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Delivery watermarks of all groups
+    pub async fn get_delivery_watermarks(&self) -> Result<HashMap<Vec<u8>, u64>> {
+        let rows = sqlx::query("SELECT group_id, epoch FROM delivery_watermarks")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| {
+                (
+                    row.get::<Vec<u8>, _>("group_id"),
+                    row.get::<i64, _>("epoch") as u64,
+                )
+            })
+            .collect())
+    }
+
+    // Close the connections, before the database file is replaced
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    // Mark a contact verified with the given public key
+    pub async fn set_contact_verified(&self, user_id: i64, public_key: &[u8]) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO verified_contacts (user_id, public_key) VALUES (?1, ?2)
+             ON CONFLICT(user_id) DO UPDATE SET
+                public_key = excluded.public_key,
+                verified_at = strftime('%s', 'now')",
+        )
+        .bind(user_id)
+        .bind(public_key)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // Forget the verification of a contact
+    pub async fn remove_contact_verification(&self, user_id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM verified_contacts WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // Get the users among `user_ids` verified with the key of their stored credential
+    pub async fn get_verified_users(&self, user_ids: &[u64]) -> Result<Vec<u64>> {
+        let rows = sqlx::query("SELECT user_id, public_key FROM verified_contacts")
+            .fetch_all(&self.pool)
+            .await?;
+        let verified_keys: HashMap<i64, Vec<u8>> = rows
+            .into_iter()
+            .map(|row| (row.get("user_id"), row.get("public_key")))
+            .collect();
+
+        let mut verified = Vec::new();
+        for &user_id in user_ids {
+            let Some(verified_key) = verified_keys.get(&(user_id as i64)) else {
+                continue;
+            };
+            let Some(credential) = self.get_contact(user_id as i64).await? else {
+                continue;
+            };
+            let credential = AccountCredential::mls_decode(&mut &*credential)?;
+            if credential.public_key.as_bytes() == verified_key.as_slice() {
+                verified.push(user_id);
+            }
+        }
+        Ok(verified)
+    }
+
+    // Find existing media with timing metrics
+    pub async fn find_existing_media(&self, media_data: &[u8]) -> Result<Option<String>> {
+        let start = Instant::now();
+
+        // First create hash of media data to compare
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(media_data);
+        let hash = hasher.finalize();
+        let media_id = hex::encode(hash);
+
+        // Check existence cache first
+        if let Some(exists) = self.media_exists_cache.get(&media_id).await {
+            let duration = start.elapsed();
+            log_operation_time("Find existing media (cache)", duration);
+            GROUP_METRICS
+                .find_media_ns
+                .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+            return Ok(if exists { Some(media_id) } else { None });
+        }
+
+        let row = sqlx::query("SELECT media_id FROM group_media WHERE media_id = ?")
+            .bind(&media_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        let result = row.map(|r| r.get::<String, _>("media_id"));
+        // Populate existence cache
+        self.media_exists_cache
+            .insert(media_id.clone(), result.is_some())
+            .await;
+
+        let duration = start.elapsed();
+        log_operation_time("Find existing media", duration);
+        GROUP_METRICS
+            .find_media_ns
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+
+        Ok(result)
+    }
+
+    // Save a message with timing metrics
+    pub async fn save_message(&self, message: &UserGroupMessage, group_id: &[u8]) -> Result<()> {
+        let start = Instant::now();
+        match message {
+            UserGroupMessage::DeleteMessage(_) => {
+                return Err(GroupError::InvalidMessage(
+                    "Tombstones are applied, not stored".to_string(),
+                ));
+            }
+            UserGroupMessage::Poll(poll) => self.save_poll(group_id, poll).await?,
+            UserGroupMessage::PollVote(vote) => self.save_poll_vote(group_id, vote).await?,
+            UserGroupMessage::PollClose(close) => self.close_poll(group_id, close).await?,
+            UserGroupMessage::Draft(_) => {
+                return Err(GroupError::InvalidMessage(
+                    "Drafts are merged, not stored".to_string(),
+                ));
+            }
+            UserGroupMessage::TextMessage(message) => {
+                let mut media_id = None;
+                let mut is_media_found = false;
+
+                // Check for existing media before starting transaction
                 if let Some(media_data) = &message.media
                     && let Ok(Some(existing_media_id)) = self.find_existing_media(media_data).await
                 {
@@ -326,16 +1020,11 @@ impl GroupManager {
                     media_id = Some(hex::encode(hash));
                 }
 
-                // Start transaction
-                let mut tx = self.pool.begin().await?;
-
                 // Handle edit case first
                 if let Some(edit_date) = message.edit_date {
                     self.redact_message(message.message_id, group_id, message, edit_date)
                         .await?;
 
-                    tx.commit().await?;
-
                     let duration = start.elapsed();
                     log_operation_time("Save message", duration);
                     GROUP_METRICS
@@ -346,6 +1035,9 @@ impl GroupManager {
                     return Ok(());
                 }
 
+                // Start transaction
+                let mut tx = self.pool.begin().await?;
+
                 // Save message first to satisfy the foreign key constraint
                 sqlx::query(
                     "INSERT INTO group_messages (
@@ -366,6 +1058,15 @@ impl GroupManager {
                 .bind(message.reply_message_id)
                 .execute(&mut *tx)
                 .await?;
+                insert_mentions(
+                    &mut tx,
+                    group_id,
+                    message.message_id,
+                    &message.mentions,
+                    &[],
+                )
+                .await?;
+                insert_entities(&mut tx, group_id, message.message_id, &message.entities).await?;
 
                 // Now save the media if it's new
                 if let Some(media_data) = &message.media
@@ -409,9 +1110,8 @@ impl GroupManager {
     // Get messages for a group with timing metrics
     pub async fn get_group_messages(&self, group_id: &[u8]) -> Result<Vec<UserGroupMessage>> {
         let cache_key = group_id.to_vec();
-        let result = self
-            .group_messages_cache
-            .get_with(cache_key.clone(), async move {
+        self.group_messages_cache
+            .try_get_with(cache_key.clone(), async move {
                 let start = Instant::now();
 
                 let rows = sqlx::query(
@@ -431,47 +1131,14 @@ impl GroupManager {
                 )
                 .bind(&cache_key)
                 .fetch_all(&self.pool)
-                .await
-                .unwrap_or_default();
-
-                let max_inline_size = 1024 * 1024 * 100;
-                let mut result_messages = Vec::with_capacity(rows.len());
-
-                for row in rows {
-                    let message_id: i64 = row.get("message_id");
-                    let sender_id: i64 = row.get("sender_id");
-                    let encrypted_content: Vec<u8> = row.get("encrypted_content");
-                    let timestamp: i64 = row.get("timestamp");
-                    let media_name: Option<String> = row.get("media_name");
-                    let media_id: Option<String> = row.get("media_id");
-                    let reply_message_id: Option<i64> = row.get("reply_message_id");
-                    let edit_date: Option<i64> = row.get("edit_date");
-                    let expires: Option<i64> = row.get("expires");
-
-                    let text = String::from_utf8_lossy(&encrypted_content).to_string();
-
-                    let media_data = if let Some(media_id_str) = &media_id {
-                        self.fetch_media_data(media_id_str, &media_name, max_inline_size)
-                            .await
-                    } else {
-                        None
-                    };
-                    let group_id = GroupId::new(cache_key.clone());
-                    let group_text_message = GroupTextMessage {
-                        message_id,
-                        group_id: group_id.to_string(),
-                        sender_id,
-                        date: timestamp,
-                        text,
-                        media: media_data,
-                        media_name,
-                        reply_message_id,
-                        expires,
-                        edit_date,
-                    };
-
-                    result_messages.push(UserGroupMessage::TextMessage(group_text_message));
-                }
+                .await?;
+
+                let result_messages = self
+                    .text_messages_from_rows(&rows, &cache_key)
+                    .await?
+                    .into_iter()
+                    .map(UserGroupMessage::TextMessage)
+                    .collect();
 
                 let duration = start.elapsed();
                 log_operation_time("Get group messages", duration);
@@ -480,10 +1147,174 @@ impl GroupManager {
                     .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
                 GROUP_METRICS.call_counts.fetch_add(1, Ordering::Relaxed);
 
-                result_messages
+                Ok::<_, GroupError>(result_messages)
             })
-            .await;
-        Ok(result)
+            .await
+            .map_err(|e| GroupError::StorageError(format!("Failed to load messages: {}", e)))
+    }
+
+    // Build text messages from group_messages rows, small images are loaded inline
+    //
+    // Mentions and entities of all rows are loaded with one query each.
+    async fn text_messages_from_rows(
+        &self,
+        rows: &[SqliteRow],
+        group_id: &[u8],
+    ) -> Result<Vec<GroupTextMessage>> {
+        let message_ids: Vec<i64> = rows.iter().map(|row| row.get("message_id")).collect();
+        let mut mentions = self.get_mentions_of(group_id, &message_ids).await?;
+        let mut entities = self.get_entities_of(group_id, &message_ids).await?;
+
+        let mut messages = Vec::with_capacity(rows.len());
+        for row in rows {
+            let encrypted_content: Vec<u8> = row.get("encrypted_content");
+            let media_name: Option<String> = row.get("media_name");
+            let media_id: Option<String> = row.get("media_id");
+
+            let media = if let Some(media_id_str) = &media_id {
+                self.fetch_media_data(media_id_str, &media_name, MAX_INLINE_MEDIA_SIZE)
+                    .await
+            } else {
+                None
+            };
+
+            let message_id: i64 = row.get("message_id");
+            let text = String::from_utf8_lossy(&encrypted_content).to_string();
+            let entities = match entities.remove(&message_id) {
+                Some(entities) => {
+                    decode_entities(&entities, &text).map_err(GroupError::InvalidMessage)?
+                }
+                None => Vec::new(),
+            };
+
+            messages.push(GroupTextMessage {
+                message_id,
+                group_id: GroupId::new(group_id.to_vec()).to_string(),
+                sender_id: row.get("sender_id"),
+                date: row.get("timestamp"),
+                text,
+                media,
+                media_name,
+                reply_message_id: row.get("reply_message_id"),
+                expires: row.get("expires"),
+                edit_date: row.get("edit_date"),
+                mentions: mentions.remove(&message_id).unwrap_or_default(),
+                entities,
+            });
+        }
+        Ok(messages)
+    }
+
+    // Get a page of the replies to `root_message_id`, replies to replies included
+    pub async fn get_thread_replies(
+        &self,
+        group_id: &[u8],
+        root_message_id: i64,
+        offset: u32,
+        limit: u32,
+    ) -> Result<Vec<GroupTextMessage>> {
+        let rows = sqlx::query(
+            "WITH RECURSIVE thread(message_id) AS (
+                SELECT message_id FROM group_messages
+                WHERE group_id = ?1 AND reply_message_id = ?2
+                UNION
+                SELECT m.message_id FROM group_messages m
+                JOIN thread t ON m.reply_message_id = t.message_id
+                WHERE m.group_id = ?1
+            )
+            SELECT m.* FROM group_messages m
+            JOIN thread t ON m.message_id = t.message_id
+            ORDER BY m.timestamp ASC, m.message_id ASC
+            LIMIT ?3 OFFSET ?4",
+        )
+        .bind(group_id)
+        .bind(root_message_id)
+        .bind(limit as i64)
+        .bind(offset as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        self.text_messages_from_rows(&rows, group_id).await
+    }
+
+    // Reply statistics of the messages `root_ids` that have replies
+    //
+    // Only the threads of `root_ids` are walked. Replies sent by `user_id` are
+    // never counted as unread.
+    pub async fn get_thread_stats(
+        &self,
+        group_id: &[u8],
+        root_ids: &[i64],
+        user_id: i64,
+    ) -> Result<HashMap<i64, ThreadStats>> {
+        if root_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let root_ids = json_ids(root_ids)?;
+        let rows = sqlx::query(
+            "WITH RECURSIVE descendants(root_id, message_id, sender_id, timestamp) AS (
+                SELECT reply_message_id, message_id, sender_id, timestamp FROM group_messages
+                WHERE group_id = ?1
+                  AND reply_message_id IN (SELECT value FROM json_each(?3))
+                UNION
+                SELECT d.root_id, m.message_id, m.sender_id, m.timestamp FROM group_messages m
+                JOIN descendants d ON m.reply_message_id = d.message_id
+                WHERE m.group_id = ?1
+            )
+            SELECT
+                d.root_id,
+                COUNT(*) AS reply_count,
+                MAX(d.timestamp) AS last_reply_at,
+                SUM(CASE
+                    WHEN d.sender_id != ?2 AND d.timestamp > COALESCE(r.last_read_at, 0) THEN 1
+                    ELSE 0
+                END) AS unread_count
+             FROM descendants d
+             LEFT JOIN thread_read_state r
+                ON r.group_id = ?1 AND r.root_message_id = d.root_id
+             GROUP BY d.root_id",
+        )
+        .bind(group_id)
+        .bind(user_id)
+        .bind(root_ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.get::<i64, _>("root_id"),
+                    ThreadStats {
+                        reply_count: row.get::<i64, _>("reply_count") as u32,
+                        last_reply_at: row.get("last_reply_at"),
+                        unread_count: row.get::<i64, _>("unread_count") as u32,
+                    },
+                )
+            })
+            .collect())
+    }
+
+    // Remember that the thread of `root_message_id` was read up to `read_at`
+    pub async fn mark_thread_read(
+        &self,
+        group_id: &[u8],
+        root_message_id: i64,
+        read_at: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO thread_read_state (group_id, root_message_id, last_read_at)
+             VALUES (?1, ?2, ?3)
+             ON CONFLICT(group_id, root_message_id)
+             DO UPDATE SET last_read_at = MAX(last_read_at, excluded.last_read_at)",
+        )
+        .bind(group_id)
+        .bind(root_message_id)
+        .bind(read_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
     }
 
     // Helper method to fetch media data
@@ -577,80 +1408,32 @@ impl GroupManager {
             .bind(group_id)
             .execute(&self.pool)
             .await?;
-
-        // Invalidate caches related to this group
-        self.group_messages_cache
-            .invalidate(&group_id.to_vec())
-            .await;
-        self.last_message_cache.invalidate(&group_id.to_vec()).await;
-
-        Ok(())
-    }
-
-    pub async fn get_last_message(&self, group_id: &GroupId) -> Result<Option<GroupTextMessage>> {
-        let key = group_id.to_vec();
-        let result = self
-            .last_message_cache
-            .get_with(key.clone(), async move {
-                let row = sqlx::query(
-                    "SELECT * FROM group_messages WHERE group_id = ? ORDER BY timestamp DESC LIMIT 1",
-                )
-                .bind(&key)
-                .fetch_optional(&self.pool)
-                .await
-                .ok()?;
-                let row = row?;
-
-                let message_id: i64 = row.get("message_id");
-                let sender_id: i64 = row.get("sender_id");
-                let encrypted_content: Vec<u8> = row.get("encrypted_content");
-                let timestamp: i64 = row.get("timestamp");
-                let media_name: Option<String> = row.get("media_name");
-                let reply_message_id: Option<i64> = row.get("reply_message_id");
-                let edit_date: Option<i64> = row.get("edit_date");
-                let expires: Option<i64> = row.get("expires");
-
-                let text = String::from_utf8_lossy(&encrypted_content).to_string();
-
-                Some(GroupTextMessage {
-                    message_id,
-                    group_id: GroupId::new(key.clone()).to_string(),
-                    sender_id,
-                    date: timestamp,
-                    text,
-                    media: None,
-                    media_name,
-                    reply_message_id,
-                    expires,
-                    edit_date,
-                })
-            })
-            .await;
-        Ok(result.as_ref().cloned())
-    }
-
-    // Redact a message (edit to replace content)
-    pub async fn redact_message(
-        &self,
-        message_id: i64,
-        group_id: &[u8],
-        new_message: &GroupTextMessage,
-        edit_date: i64,
-    ) -> Result<()> {
         sqlx::query(
-            "UPDATE group_messages 
-             SET encrypted_content = ?1, 
-                 edit_date = ?2 
-             WHERE message_id = ?3 AND group_id = ?4",
+            "DELETE FROM message_revision_entities WHERE revision_id IN (
+                 SELECT id FROM message_revisions WHERE message_id = ?1 AND group_id = ?2
+             )",
         )
-        .bind(new_message.text.as_bytes())
-        .bind(edit_date)
         .bind(message_id)
         .bind(group_id)
         .execute(&self.pool)
         .await?;
+        sqlx::query("DELETE FROM message_revisions WHERE message_id = ?1 AND group_id = ?2")
+            .bind(message_id)
+            .bind(group_id)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("DELETE FROM message_mentions WHERE message_id = ?1 AND group_id = ?2")
+            .bind(message_id)
+            .bind(group_id)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("DELETE FROM message_entities WHERE message_id = ?1 AND group_id = ?2")
+            .bind(message_id)
+            .bind(group_id)
+            .execute(&self.pool)
+            .await?;
 
-        // Invalidate caches for this group
+        // Invalidate caches related to this group
         self.group_messages_cache
             .invalidate(&group_id.to_vec())
             .await;
@@ -659,17 +1442,608 @@ impl GroupManager {
         Ok(())
     }
 
-    // Get all media for a group
-    pub async fn get_group_media(&self, group_id: &[u8]) -> Result<Vec<(String, String, i64)>> {
-        let rows = sqlx::query(
-            "SELECT media_id, media_name, LENGTH(media_data) as size
-             FROM group_media
-             WHERE group_id = ?
-             ORDER BY created_at DESC",
+    // Remove a message for good: its row, revisions, mentions, entities and
+    // media no other message uses
+    pub async fn purge_message(&self, message_id: i64, group_id: &[u8]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        let media_id: Option<String> = sqlx::query(
+            "SELECT media_id FROM group_messages WHERE message_id = ?1 AND group_id = ?2",
         )
+        .bind(message_id)
         .bind(group_id)
-        .fetch_all(&self.pool)
-        .await?;
+        .fetch_optional(&mut *tx)
+        .await?
+        .and_then(|row| row.get("media_id"));
+
+        if let Some(media_id) = &media_id {
+            // Медиа может использоваться другими сообщениями с тем же содержимым
+            sqlx::query(
+                "UPDATE group_media SET message_id = (
+                    SELECT message_id FROM group_messages
+                    WHERE media_id = ?1 AND message_id != ?2
+                    LIMIT 1
+                 )
+                 WHERE media_id = ?1",
+            )
+            .bind(media_id)
+            .bind(message_id)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query("DELETE FROM group_media WHERE media_id = ?1 AND message_id IS NULL")
+                .bind(media_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        sqlx::query("DELETE FROM group_messages WHERE message_id = ?1 AND group_id = ?2")
+            .bind(message_id)
+            .bind(group_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "DELETE FROM message_revision_entities WHERE revision_id IN (
+                 SELECT id FROM message_revisions WHERE message_id = ?1 AND group_id = ?2
+             )",
+        )
+        .bind(message_id)
+        .bind(group_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM message_revisions WHERE message_id = ?1 AND group_id = ?2")
+            .bind(message_id)
+            .bind(group_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM message_mentions WHERE message_id = ?1 AND group_id = ?2")
+            .bind(message_id)
+            .bind(group_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM message_entities WHERE message_id = ?1 AND group_id = ?2")
+            .bind(message_id)
+            .bind(group_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        self.group_messages_cache
+            .invalidate(&group_id.to_vec())
+            .await;
+        self.last_message_cache.invalidate(&group_id.to_vec()).await;
+        if let Some(media_id) = media_id {
+            self.media_data_cache.invalidate(&media_id).await;
+            self.media_exists_cache.invalidate(&media_id).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_last_message(&self, group_id: &GroupId) -> Result<Option<GroupTextMessage>> {
+        let key = group_id.to_vec();
+        self.last_message_cache
+            .try_get_with(key.clone(), async move {
+                let message_id: Option<i64> = sqlx::query_scalar(
+                    "SELECT message_id FROM group_messages WHERE group_id = ? ORDER BY timestamp DESC LIMIT 1",
+                )
+                .bind(&key)
+                .fetch_optional(&self.pool)
+                .await?;
+                match message_id {
+                    Some(message_id) => self.get_message(&key, message_id).await,
+                    None => Ok(None),
+                }
+            })
+            .await
+            .map_err(|e| GroupError::StorageError(format!("Failed to load last message: {}", e)))
+    }
+
+    // Get a single message of a group by its id, media bytes are not loaded
+    pub async fn get_message(
+        &self,
+        group_id: &[u8],
+        message_id: i64,
+    ) -> Result<Option<GroupTextMessage>> {
+        let row = sqlx::query(
+            "SELECT * FROM group_messages WHERE group_id = ?1 AND message_id = ?2 LIMIT 1",
+        )
+        .bind(group_id)
+        .bind(message_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let encrypted_content: Vec<u8> = row.get("encrypted_content");
+        let text = String::from_utf8_lossy(&encrypted_content).to_string();
+        let mentions = self.get_mentions(group_id, message_id).await?;
+        let entities = self.get_entities(group_id, message_id, &text).await?;
+        Ok(Some(GroupTextMessage {
+            message_id: row.get("message_id"),
+            group_id: GroupId::new(group_id.to_vec()).to_string(),
+            sender_id: row.get("sender_id"),
+            date: row.get("timestamp"),
+            text,
+            media: None,
+            media_name: row.get("media_name"),
+            reply_message_id: row.get("reply_message_id"),
+            expires: row.get("expires"),
+            edit_date: row.get("edit_date"),
+            mentions,
+            entities,
+        }))
+    }
+
+    // Get the mentions of a message in text order
+    pub async fn get_mentions(&self, group_id: &[u8], message_id: i64) -> Result<Vec<Mention>> {
+        let rows = sqlx::query(
+            "SELECT user_id, range_start, range_length FROM message_mentions
+             WHERE group_id = ?1 AND message_id = ?2
+             ORDER BY range_start ASC",
+        )
+        .bind(group_id)
+        .bind(message_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| Mention {
+                user_id: row.get("user_id"),
+                offset: row.get::<i64, _>("range_start") as u32,
+                length: row.get::<i64, _>("range_length") as u32,
+            })
+            .collect())
+    }
+
+    // Get the mentions of the messages `message_ids`, in text order
+    async fn get_mentions_of(
+        &self,
+        group_id: &[u8],
+        message_ids: &[i64],
+    ) -> Result<HashMap<i64, Vec<Mention>>> {
+        let rows = sqlx::query(
+            "SELECT message_id, user_id, range_start, range_length FROM message_mentions
+             WHERE group_id = ?1 AND message_id IN (SELECT value FROM json_each(?2))
+             ORDER BY message_id, range_start ASC",
+        )
+        .bind(group_id)
+        .bind(json_ids(message_ids)?)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut mentions: HashMap<i64, Vec<Mention>> = HashMap::new();
+        for row in rows {
+            mentions
+                .entry(row.get("message_id"))
+                .or_default()
+                .push(Mention {
+                    user_id: row.get("user_id"),
+                    offset: row.get::<i64, _>("range_start") as u32,
+                    length: row.get::<i64, _>("range_length") as u32,
+                });
+        }
+        Ok(mentions)
+    }
+
+    // Get the encoded formatting entities of the messages `message_ids`
+    async fn get_entities_of(
+        &self,
+        group_id: &[u8],
+        message_ids: &[i64],
+    ) -> Result<HashMap<i64, Vec<u8>>> {
+        let rows = sqlx::query(
+            "SELECT message_id, entities FROM message_entities
+             WHERE group_id = ?1 AND message_id IN (SELECT value FROM json_each(?2))",
+        )
+        .bind(group_id)
+        .bind(json_ids(message_ids)?)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get("message_id"), row.get("entities")))
+            .collect())
+    }
+
+    // Get the formatting entities of a message, validated against its current text
+    pub async fn get_entities(
+        &self,
+        group_id: &[u8],
+        message_id: i64,
+        text: &str,
+    ) -> Result<Vec<TextEntity>> {
+        let entities: Option<Vec<u8>> = sqlx::query_scalar(
+            "SELECT entities FROM message_entities WHERE group_id = ?1 AND message_id = ?2",
+        )
+        .bind(group_id)
+        .bind(message_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match entities {
+            Some(entities) => decode_entities(&entities, text).map_err(GroupError::InvalidMessage),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // Count messages mentioning `user_id` that are still unread, per group
+    //
+    // Messages sent by `user_id` are never counted.
+    pub async fn get_unread_mention_counts(&self, user_id: i64) -> Result<HashMap<Vec<u8>, u32>> {
+        let rows = sqlx::query(
+            "SELECT mm.group_id, COUNT(DISTINCT mm.message_id) AS unread
+             FROM message_mentions mm
+             JOIN group_messages m
+               ON m.group_id = mm.group_id AND m.message_id = mm.message_id
+             WHERE mm.user_id = ?1 AND mm.read = 0 AND m.sender_id != ?1
+             GROUP BY mm.group_id",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.get::<Vec<u8>, _>("group_id"),
+                    row.get::<i64, _>("unread") as u32,
+                )
+            })
+            .collect())
+    }
+
+    // Count unread messages of one group mentioning `user_id`
+    pub async fn get_unread_mention_count(&self, group_id: &[u8], user_id: i64) -> Result<u32> {
+        let unread: i64 = sqlx::query_scalar(
+            "SELECT COUNT(DISTINCT mm.message_id)
+             FROM message_mentions mm
+             JOIN group_messages m
+               ON m.group_id = mm.group_id AND m.message_id = mm.message_id
+             WHERE mm.group_id = ?1 AND mm.user_id = ?2 AND mm.read = 0
+               AND m.sender_id != ?2",
+        )
+        .bind(group_id)
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(unread as u32)
+    }
+
+    // Mark every mention of `user_id` in a group as read
+    pub async fn mark_mentions_read(&self, group_id: &[u8], user_id: i64) -> Result<()> {
+        sqlx::query(
+            "UPDATE message_mentions SET read = 1
+             WHERE group_id = ?1 AND user_id = ?2 AND read = 0",
+        )
+        .bind(group_id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // Start the read position of a group at its last message, if it has none yet
+    pub async fn seed_read_position(&self, group_id: &[u8]) -> Result<()> {
+        sqlx::query(
+            "INSERT OR IGNORE INTO group_read_state (group_id, last_read_at, last_read_message_id)
+             SELECT ?1, COALESCE(last.timestamp, 0), COALESCE(last.message_id, 0)
+             FROM (SELECT 1)
+             LEFT JOIN (
+                 SELECT timestamp, message_id FROM group_messages
+                 WHERE group_id = ?1
+                 ORDER BY timestamp DESC, message_id DESC
+                 LIMIT 1
+             ) last ON 1",
+        )
+        .bind(group_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // Count messages after the read position of every group, per group
+    //
+    // Each group is counted from its read position on the (group_id, timestamp, message_id)
+    // index. Messages sent by `user_id` are never counted.
+    pub async fn get_unread_counts(&self, user_id: i64) -> Result<HashMap<Vec<u8>, u32>> {
+        let rows = sqlx::query(
+            "SELECT r.group_id, (
+                 SELECT COUNT(*) FROM group_messages m
+                 WHERE m.group_id = r.group_id
+                   AND (m.timestamp, m.message_id) > (r.last_read_at, r.last_read_message_id)
+                   AND m.sender_id != ?1
+             ) AS unread
+             FROM group_read_state r",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.get::<Vec<u8>, _>("group_id"),
+                    row.get::<i64, _>("unread") as u32,
+                )
+            })
+            .collect())
+    }
+
+    // Move the read position forward to `message_id`, or to the last message of the group
+    //
+    // Mentions of `user_id` up to the new position are marked read as well.
+    pub async fn mark_group_read(
+        &self,
+        group_id: &[u8],
+        user_id: i64,
+        message_id: Option<i64>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        let position = sqlx::query(
+            "SELECT timestamp, message_id FROM group_messages
+             WHERE group_id = ?1 AND (?2 IS NULL OR message_id = ?2)
+             ORDER BY timestamp DESC, message_id DESC
+             LIMIT 1",
+        )
+        .bind(group_id)
+        .bind(message_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(position) = position else {
+            return Ok(());
+        };
+        let read_at: i64 = position.get("timestamp");
+        let read_message_id: i64 = position.get("message_id");
+
+        sqlx::query(
+            "INSERT INTO group_read_state (group_id, last_read_at, last_read_message_id)
+             VALUES (?1, ?2, ?3)
+             ON CONFLICT(group_id) DO UPDATE SET
+                 last_read_at = excluded.last_read_at,
+                 last_read_message_id = excluded.last_read_message_id
+             WHERE (excluded.last_read_at, excluded.last_read_message_id)
+                 > (last_read_at, last_read_message_id)",
+        )
+        .bind(group_id)
+        .bind(read_at)
+        .bind(read_message_id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            "UPDATE message_mentions SET read = 1
+             WHERE group_id = ?1 AND user_id = ?2 AND read = 0
+               AND message_id IN (
+                   SELECT message_id FROM group_messages
+                   WHERE group_id = ?1 AND (timestamp, message_id) <= (?3, ?4)
+               )",
+        )
+        .bind(group_id)
+        .bind(user_id)
+        .bind(read_at)
+        .bind(read_message_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // Move the read position back so that `message_id` and everything after it is unread;
+    // without a message only the last message of the group becomes unread
+    pub async fn mark_unread(&self, group_id: &[u8], message_id: Option<i64>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        let target = sqlx::query(
+            "SELECT timestamp, message_id FROM group_messages
+             WHERE group_id = ?1 AND (?2 IS NULL OR message_id = ?2)
+             ORDER BY timestamp DESC, message_id DESC
+             LIMIT 1",
+        )
+        .bind(group_id)
+        .bind(message_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(target) = target else {
+            return Ok(());
+        };
+
+        let previous = sqlx::query(
+            "SELECT timestamp, message_id FROM group_messages
+             WHERE group_id = ?1 AND (timestamp, message_id) < (?2, ?3)
+             ORDER BY timestamp DESC, message_id DESC
+             LIMIT 1",
+        )
+        .bind(group_id)
+        .bind(target.get::<i64, _>("timestamp"))
+        .bind(target.get::<i64, _>("message_id"))
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        match previous {
+            Some(previous) => {
+                sqlx::query(
+                    "INSERT OR REPLACE INTO group_read_state (
+                        group_id, last_read_at, last_read_message_id
+                    ) VALUES (?1, ?2, ?3)",
+                )
+                .bind(group_id)
+                .bind(previous.get::<i64, _>("timestamp"))
+                .bind(previous.get::<i64, _>("message_id"))
+                .execute(&mut *tx)
+                .await?;
+            }
+            None => {
+                sqlx::query(
+                    "INSERT OR REPLACE INTO group_read_state (
+                        group_id, last_read_at, last_read_message_id
+                    ) VALUES (?1, 0, 0)",
+                )
+                .bind(group_id)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // Get the date of the last message a sender posted to a group
+    pub async fn get_last_message_date(
+        &self,
+        group_id: &[u8],
+        sender_id: i64,
+    ) -> Result<Option<i64>> {
+        let date: Option<i64> = sqlx::query_scalar(
+            "SELECT MAX(timestamp) FROM group_messages WHERE group_id = ?1 AND sender_id = ?2",
+        )
+        .bind(group_id)
+        .bind(sender_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(date)
+    }
+
+    // Redact a message (edit to replace content)
+    pub async fn redact_message(
+        &self,
+        message_id: i64,
+        group_id: &[u8],
+        new_message: &GroupTextMessage,
+        edit_date: i64,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        // Текущий текст сохраняется как ревизия перед перезаписью, вместе с его сущностями
+        let revision = sqlx::query(
+            "INSERT INTO message_revisions (message_id, group_id, content, edit_date)
+             SELECT message_id, group_id, encrypted_content, COALESCE(edit_date, timestamp)
+             FROM group_messages
+             WHERE message_id = ?1 AND group_id = ?2",
+        )
+        .bind(message_id)
+        .bind(group_id)
+        .execute(&mut *tx)
+        .await?;
+        if revision.rows_affected() > 0 {
+            sqlx::query(
+                "INSERT INTO message_revision_entities (revision_id, entities)
+                 SELECT ?1, entities FROM message_entities
+                 WHERE group_id = ?2 AND message_id = ?3",
+            )
+            .bind(revision.last_insert_rowid())
+            .bind(group_id)
+            .bind(message_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query(
+            "UPDATE group_messages 
+             SET encrypted_content = ?1, 
+                 edit_date = ?2 
+             WHERE message_id = ?3 AND group_id = ?4",
+        )
+        .bind(new_message.text.as_bytes())
+        .bind(edit_date)
+        .bind(message_id)
+        .bind(group_id)
+        .execute(&mut *tx)
+        .await?;
+
+        // Упоминания заменяются, прочитанные остаются прочитанными
+        let read_users: Vec<i64> = sqlx::query_scalar(
+            "SELECT DISTINCT user_id FROM message_mentions
+             WHERE group_id = ?1 AND message_id = ?2 AND read = 1",
+        )
+        .bind(group_id)
+        .bind(message_id)
+        .fetch_all(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM message_mentions WHERE group_id = ?1 AND message_id = ?2")
+            .bind(group_id)
+            .bind(message_id)
+            .execute(&mut *tx)
+            .await?;
+        insert_mentions(
+            &mut tx,
+            group_id,
+            message_id,
+            &new_message.mentions,
+            &read_users,
+        )
+        .await?;
+        insert_entities(&mut tx, group_id, message_id, &new_message.entities).await?;
+
+        tx.commit().await?;
+
+        // Invalidate caches for this group
+        self.group_messages_cache
+            .invalidate(&group_id.to_vec())
+            .await;
+        self.last_message_cache.invalidate(&group_id.to_vec()).await;
+
+        Ok(())
+    }
+
+    // Get the earlier revisions of a message, oldest first
+    pub async fn get_message_revisions(
+        &self,
+        group_id: &[u8],
+        message_id: i64,
+    ) -> Result<Vec<MessageRevision>> {
+        let rows = sqlx::query(
+            "SELECT r.content, r.edit_date, e.entities FROM message_revisions r
+             LEFT JOIN message_revision_entities e ON e.revision_id = r.id
+             WHERE r.group_id = ?1 AND r.message_id = ?2
+             ORDER BY r.id ASC",
+        )
+        .bind(group_id)
+        .bind(message_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let text = String::from_utf8_lossy(&row.get::<Vec<u8>, _>("content")).to_string();
+                let entities = match row.get::<Option<Vec<u8>>, _>("entities") {
+                    Some(entities) => {
+                        decode_entities(&entities, &text).map_err(GroupError::InvalidMessage)?
+                    }
+                    None => Vec::new(),
+                };
+                Ok(MessageRevision {
+                    text,
+                    entities,
+                    edit_date: row.get("edit_date"),
+                })
+            })
+            .collect()
+    }
+
+    // Get all media for a group
+    pub async fn get_group_media(&self, group_id: &[u8]) -> Result<Vec<(String, String, i64)>> {
+        let rows = sqlx::query(
+            "SELECT media_id, media_name, LENGTH(media_data) as size
+             FROM group_media
+             WHERE group_id = ?
+             ORDER BY created_at DESC",
+        )
+        .bind(group_id)
+        .fetch_all(&self.pool)
+        .await?;
 
         let results = rows
             .into_iter()
@@ -706,6 +2080,546 @@ impl GroupManager {
         Ok(results)
     }
 
+    // Append audit entries produced by a single commit
+    pub async fn save_audit_entries(
+        &self,
+        group_id: &[u8],
+        epoch: u64,
+        committer_id: u64,
+        changes: &[(AuditCategory, ConfigChange)],
+    ) -> Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = self.pool.begin().await?;
+        for (category, change) in changes {
+            sqlx::query(
+                "INSERT INTO group_audit_log (
+                    group_id, epoch, committer_id, category, field, old_value, new_value
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )
+            .bind(group_id)
+            .bind(epoch as i64)
+            .bind(committer_id as i64)
+            .bind(category.as_str())
+            .bind(&change.field)
+            .bind(&change.old_value)
+            .bind(&change.new_value)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    // Get the audit log of a group, newest epoch first
+    pub async fn get_audit_log(&self, group_id: &[u8]) -> Result<Vec<GroupAuditEntry>> {
+        let rows = sqlx::query(
+            "SELECT epoch, committer_id, category, field, old_value, new_value, timestamp
+             FROM group_audit_log
+             WHERE group_id = ?
+             ORDER BY epoch DESC, id ASC",
+        )
+        .bind(group_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let entries = rows
+            .into_iter()
+            .filter_map(|row| {
+                let category = AuditCategory::parse(&row.get::<String, _>("category"))?;
+                Some(GroupAuditEntry {
+                    epoch: row.get::<i64, _>("epoch") as u64,
+                    committer_id: row.get::<i64, _>("committer_id") as u64,
+                    category,
+                    change: ConfigChange {
+                        field: row.get("field"),
+                        old_value: row.get("old_value"),
+                        new_value: row.get("new_value"),
+                    },
+                    timestamp: row.get("timestamp"),
+                })
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
+    // Keep the final config of a disbanded group, its messages stay in place
+    pub async fn archive_group(&self, group_id: &[u8], config: &GroupConfig) -> Result<()> {
+        let config_bytes = config.mls_encode_to_vec()?;
+        sqlx::query("INSERT OR REPLACE INTO archived_groups (group_id, config) VALUES (?1, ?2)")
+            .bind(group_id)
+            .bind(config_bytes)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // Get archived groups with their final config, most recently archived first
+    pub async fn get_archived_groups(&self) -> Result<Vec<(GroupId, GroupConfig)>> {
+        let rows =
+            sqlx::query("SELECT group_id, config FROM archived_groups ORDER BY archived_at DESC")
+                .fetch_all(&self.pool)
+                .await?;
+
+        let mut groups = Vec::with_capacity(rows.len());
+        for row in rows {
+            let config_bytes: Vec<u8> = row.get("config");
+            groups.push((
+                GroupId::new(row.get("group_id")),
+                GroupConfig::mls_decode(&mut &*config_bytes)?,
+            ));
+        }
+
+        Ok(groups)
+    }
+
+    // Get ids of archived groups, without decoding their configs
+    pub async fn get_archived_group_ids(&self) -> Result<HashSet<Vec<u8>>> {
+        let rows = sqlx::query("SELECT group_id FROM archived_groups")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.iter().map(|row| row.get("group_id")).collect())
+    }
+
+    // Save a poll, a repeated delivery keeps the first copy
+    pub async fn save_poll(&self, group_id: &[u8], poll: &GroupPollMessage) -> Result<()> {
+        sqlx::query(
+            "INSERT OR IGNORE INTO polls (group_id, poll_id, sender_id, poll, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )
+        .bind(group_id)
+        .bind(poll.poll_id)
+        .bind(poll.sender_id)
+        .bind(poll.to_bytes())
+        .bind(poll.date)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // Get a poll with the date it was closed by its creator
+    pub async fn get_poll(
+        &self,
+        group_id: &[u8],
+        poll_id: i64,
+    ) -> Result<Option<(GroupPollMessage, Option<i64>)>> {
+        let row =
+            sqlx::query("SELECT poll, closed_at FROM polls WHERE group_id = ?1 AND poll_id = ?2")
+                .bind(group_id)
+                .bind(poll_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let poll_bytes: Vec<u8> = row.get("poll");
+        let poll = GroupPollMessage::from_bytes(&poll_bytes).map_err(GroupError::InvalidMessage)?;
+        Ok(Some((poll, row.get("closed_at"))))
+    }
+
+    // Get ids of the polls of a group, newest first
+    pub async fn get_group_poll_ids(&self, group_id: &[u8]) -> Result<Vec<i64>> {
+        let rows = sqlx::query(
+            "SELECT poll_id FROM polls WHERE group_id = ?1 ORDER BY created_at DESC, poll_id",
+        )
+        .bind(group_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(|row| row.get("poll_id")).collect())
+    }
+
+    // Record a vote; two votes of a member with the same date keep the smaller option list
+    pub async fn save_poll_vote(&self, group_id: &[u8], vote: &GroupPollVote) -> Result<()> {
+        let options = vote
+            .options
+            .iter()
+            .map(|option| option.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        sqlx::query(
+            "INSERT INTO poll_votes (group_id, poll_id, voter_id, voted_at, options)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(group_id, poll_id, voter_id, voted_at)
+             DO UPDATE SET options = MIN(options, excluded.options)",
+        )
+        .bind(group_id)
+        .bind(vote.poll_id)
+        .bind(vote.voter_id)
+        .bind(vote.date)
+        .bind(options)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // Close a poll, the earliest close date wins
+    pub async fn close_poll(&self, group_id: &[u8], close: &GroupPollClose) -> Result<()> {
+        sqlx::query(
+            "UPDATE polls SET closed_at = MIN(COALESCE(closed_at, ?3), ?3)
+             WHERE group_id = ?1 AND poll_id = ?2",
+        )
+        .bind(group_id)
+        .bind(close.poll_id)
+        .bind(close.date)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // Tally the latest vote of every member cast before the poll closed
+    pub async fn get_poll_results(
+        &self,
+        group_id: &[u8],
+        poll_id: i64,
+        viewer_id: i64,
+    ) -> Result<Option<PollResults>> {
+        let Some((poll, closed_at)) = self.get_poll(group_id, poll_id).await? else {
+            return Ok(None);
+        };
+        let cutoff = poll_cutoff(&poll, closed_at);
+
+        let rows = sqlx::query(
+            "SELECT v.voter_id, v.options FROM poll_votes v
+             WHERE v.group_id = ?1 AND v.poll_id = ?2
+               AND v.voted_at = (
+                   SELECT MAX(w.voted_at) FROM poll_votes w
+                   WHERE w.group_id = v.group_id AND w.poll_id = v.poll_id
+                     AND w.voter_id = v.voter_id
+                     AND (?3 IS NULL OR w.voted_at <= ?3)
+               )
+             ORDER BY v.voter_id",
+        )
+        .bind(group_id)
+        .bind(poll_id)
+        .bind(cutoff)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let votes = rows
+            .iter()
+            .map(|row| {
+                let options: String = row.get("options");
+                let options = options
+                    .split(',')
+                    .filter_map(|option| option.parse::<u32>().ok())
+                    .collect::<Vec<_>>();
+                (row.get::<i64, _>("voter_id"), options)
+            })
+            .collect::<Vec<_>>();
+        let own_vote = votes
+            .iter()
+            .find(|(voter_id, _)| *voter_id == viewer_id)
+            .map(|(_, options)| options.clone())
+            .unwrap_or_default();
+        let (counts, voters, total_voters) = tally(&poll, &votes);
+
+        Ok(Some(PollResults {
+            poll,
+            closed_at,
+            counts,
+            voters,
+            total_voters,
+            own_vote,
+        }))
+    }
+
+    // Save a draft written on this device unless a newer one is stored; returns whether
+    // it was saved
+    //
+    // Ties on `updated_at` are broken by the text so every device keeps the same copy
+    pub async fn save_draft(
+        &self,
+        draft: &GroupDraftMessage,
+        attachment_path: Option<&str>,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "INSERT INTO group_drafts (group_id, text, reply_message_id, attachment_path, updated_at, synced)
+             VALUES (?1, ?2, ?3, ?4, ?5, 0)
+             ON CONFLICT(group_id) DO UPDATE SET
+                text = excluded.text,
+                reply_message_id = excluded.reply_message_id,
+                attachment_path = excluded.attachment_path,
+                updated_at = excluded.updated_at,
+                synced = 0
+             WHERE (excluded.updated_at, excluded.text) > (group_drafts.updated_at, group_drafts.text)",
+        )
+        .bind(&draft.group_id)
+        .bind(&draft.text)
+        .bind(draft.reply_message_id)
+        .bind(attachment_path)
+        .bind(draft.updated_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Save a draft received from another device unless a newer one is stored; returns
+    // whether it was saved
+    //
+    // The local attachment is kept, a cleared draft clears it too
+    pub async fn merge_draft(&self, draft: &GroupDraftMessage) -> Result<bool> {
+        let result = sqlx::query(
+            "INSERT INTO group_drafts (group_id, text, reply_message_id, updated_at, synced)
+             VALUES (?1, ?2, ?3, ?4, 1)
+             ON CONFLICT(group_id) DO UPDATE SET
+                text = excluded.text,
+                reply_message_id = excluded.reply_message_id,
+                attachment_path = CASE
+                    WHEN excluded.text = '' AND excluded.reply_message_id IS NULL THEN NULL
+                    ELSE group_drafts.attachment_path
+                END,
+                updated_at = excluded.updated_at,
+                synced = 1
+             WHERE (excluded.updated_at, excluded.text) > (group_drafts.updated_at, group_drafts.text)",
+        )
+        .bind(&draft.group_id)
+        .bind(&draft.text)
+        .bind(draft.reply_message_id)
+        .bind(draft.updated_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Get the draft of a group, `None` if there is none or it was cleared
+    pub async fn get_draft(&self, group_id: &[u8], user_id: i64) -> Result<Option<Draft>> {
+        let row = sqlx::query(
+            "SELECT text, reply_message_id, attachment_path, updated_at
+             FROM group_drafts WHERE group_id = ?1",
+        )
+        .bind(group_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row
+            .map(|row| Draft {
+                message: GroupDraftMessage {
+                    group_id: group_id.to_vec(),
+                    user_id,
+                    updated_at: row.get("updated_at"),
+                    text: row.get("text"),
+                    reply_message_id: row.get("reply_message_id"),
+                },
+                attachment_path: row.get("attachment_path"),
+            })
+            .filter(|draft| !draft.is_empty()))
+    }
+
+    // Get the drafts written on this device up to `updated_before` and not yet sent
+    // to the other devices
+    pub async fn get_unsynced_drafts(
+        &self,
+        user_id: i64,
+        updated_before: i64,
+    ) -> Result<Vec<GroupDraftMessage>> {
+        let rows = sqlx::query(
+            "SELECT group_id, text, reply_message_id, updated_at
+             FROM group_drafts WHERE synced = 0 AND updated_at <= ?1",
+        )
+        .bind(updated_before)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| GroupDraftMessage {
+                group_id: row.get("group_id"),
+                user_id,
+                updated_at: row.get("updated_at"),
+                text: row.get("text"),
+                reply_message_id: row.get("reply_message_id"),
+            })
+            .collect())
+    }
+
+    // Mark a draft as sent to the other devices, unless it changed since
+    pub async fn mark_draft_synced(&self, group_id: &[u8], updated_at: i64) -> Result<()> {
+        sqlx::query("UPDATE group_drafts SET synced = 1 WHERE group_id = ?1 AND updated_at = ?2")
+            .bind(group_id)
+            .bind(updated_at)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // Queue a message to be sent at `send_at`
+    pub async fn schedule_message(
+        &self,
+        group_id: &[u8],
+        message_id: i64,
+        send_at: i64,
+        message: &UserGroupMessage,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO scheduled_messages (group_id, message_id, send_at, message)
+             VALUES (?1, ?2, ?3, ?4)",
+        )
+        .bind(group_id)
+        .bind(message_id)
+        .bind(send_at)
+        .bind(message.to_bytes())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // Get queued messages of one group or of all groups, soonest first
+    pub async fn get_scheduled_messages(
+        &self,
+        group_id: Option<&[u8]>,
+    ) -> Result<Vec<ScheduledMessage>> {
+        let rows = sqlx::query(
+            "SELECT group_id, message_id, send_at, message, status, last_error
+             FROM scheduled_messages
+             WHERE ?1 IS NULL OR group_id = ?1
+             ORDER BY send_at, message_id",
+        )
+        .bind(group_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter().map(scheduled_message_from_row).collect()
+    }
+
+    // Get a queued message
+    pub async fn get_scheduled_message(
+        &self,
+        group_id: &[u8],
+        message_id: i64,
+    ) -> Result<Option<ScheduledMessage>> {
+        let row = sqlx::query(
+            "SELECT group_id, message_id, send_at, message, status, last_error
+             FROM scheduled_messages WHERE group_id = ?1 AND message_id = ?2",
+        )
+        .bind(group_id)
+        .bind(message_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.as_ref().map(scheduled_message_from_row).transpose()
+    }
+
+    // Replace a queued message and put it back in the queue; false if it is being sent
+    pub async fn update_scheduled_message(
+        &self,
+        group_id: &[u8],
+        message_id: i64,
+        send_at: i64,
+        message: &UserGroupMessage,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE scheduled_messages
+             SET send_at = ?3, message = ?4, status = 0, last_error = NULL
+             WHERE group_id = ?1 AND message_id = ?2 AND status != 1",
+        )
+        .bind(group_id)
+        .bind(message_id)
+        .bind(send_at)
+        .bind(message.to_bytes())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Remove a queued message; false if it is being sent or does not exist
+    pub async fn cancel_scheduled_message(&self, group_id: &[u8], message_id: i64) -> Result<bool> {
+        let result = sqlx::query(
+            "DELETE FROM scheduled_messages
+             WHERE group_id = ?1 AND message_id = ?2 AND status != 1",
+        )
+        .bind(group_id)
+        .bind(message_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Take pending messages due at `now`, marking them as being sent
+    pub async fn claim_due_scheduled_messages(&self, now: i64) -> Result<Vec<ScheduledMessage>> {
+        let mut tx = self.pool.begin().await?;
+
+        let rows = sqlx::query(
+            "SELECT group_id, message_id, send_at, message, status, last_error
+             FROM scheduled_messages
+             WHERE status = 0 AND send_at <= ?1
+             ORDER BY send_at, message_id",
+        )
+        .bind(now)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        sqlx::query("UPDATE scheduled_messages SET status = 1 WHERE status = 0 AND send_at <= ?1")
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        rows.iter().map(scheduled_message_from_row).collect()
+    }
+
+    // Drop a message from the queue once it was sent
+    pub async fn finish_scheduled_message(&self, group_id: &[u8], message_id: i64) -> Result<()> {
+        sqlx::query("DELETE FROM scheduled_messages WHERE group_id = ?1 AND message_id = ?2")
+            .bind(group_id)
+            .bind(message_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // Record a failed send; with `retry` the message goes back to the queue
+    pub async fn fail_scheduled_message(
+        &self,
+        group_id: &[u8],
+        message_id: i64,
+        error: &str,
+        retry: bool,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE scheduled_messages SET status = ?3, last_error = ?4
+             WHERE group_id = ?1 AND message_id = ?2",
+        )
+        .bind(group_id)
+        .bind(message_id)
+        .bind(if retry { 0 } else { 2 })
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    // Messages left in `Sending` by a crash may or may not have gone out,
+    // they are marked failed so the user decides whether to send them again
+    pub async fn fail_interrupted_scheduled_messages(&self) -> Result<()> {
+        sqlx::query(
+            "UPDATE scheduled_messages SET status = 2, last_error = 'Interrupted while sending'
+             WHERE status = 1",
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
     // Clear media cache
     pub async fn clear_media_cache(&self) -> Result<()> {
         let mut tx = self.pool.begin().await?;
@@ -741,20 +2655,5 @@ impl GroupManager {
 
 // Utility function to get default database path
 pub fn get_default_db_path(account_id: u64) -> std::path::PathBuf {
-    #[cfg(not(target_os = "ios"))]
-    {
-        let mut path = dirs::home_dir().expect("Could not find home directory");
-        path.push(".ship");
-        std::fs::create_dir_all(&path).expect("Could not create .ship directory");
-        path.push(format!("group_{}.db", account_id));
-        path
-    }
-    #[cfg(target_os = "ios")]
-    {
-        let mut path = dirs::document_dir().expect("Could not find home directory");
-        path.push("ship");
-        std::fs::create_dir_all(&path).expect("Could not create .ship directory");
-        path.push(format!("group_{}.db", account_id));
-        path
-    }
+    storage::layout().group_db(account_id)
 }
@@ -25,13 +25,16 @@ use crate::api::{
         types::{
             config::{CIPHER_SUITE, crypto},
             custom_mls::{
-                credentials::AccountCredential, identity::CustomIdentityProvider,
+                credentials::{AccountCredential, TrustedServerKeys},
+                identity::CustomIdentityProvider,
                 rules::CustomMlsRules,
             },
             errors::GroupError,
             extensions::{
                 group_config::group_extension::{
-                    GROUP_CONFIG_EXTENSION_V1, UPDATE_GROUP_CONFIG_PROPOSAL_V1,
+                    GROUP_CONFIG_EXTENSION_V1, GROUP_CONFIG_EXTENSION_V2,
+                    MIGRATE_GROUP_CONFIG_PROPOSAL_V1, UPDATE_GROUP_CONFIG_PROPOSAL_V1,
+                    UPDATE_GROUP_CONFIG_PROPOSAL_V2,
                 },
                 roster::roster_extension::{
                     ADD_USER_PROPOSAL_V1, REMOVE_USER_PROPOSAL_V1, ROSTER_EXTENSION_V1,
@@ -42,6 +45,7 @@ use crate::api::{
             signature_bytes::{InitGroupStreamTBS, RegisterGroupDeviceTBS, UploadKeyPackagesTBS},
         },
     },
+    storage,
 };
 
 pub struct Device {
@@ -53,6 +57,7 @@ pub struct Device {
     pub backend: Option<Backend>,
     pub app_handle: Option<AppHandle>,
     pub(super) contacts_parsed_cache: Cache<u64, AccountCredential>,
+    pub(super) server_keys: TrustedServerKeys,
 }
 
 impl Device {
@@ -67,7 +72,8 @@ impl Device {
         app_handle: Option<AppHandle>,
     ) -> Result<Self, GroupError> {
         let identity = IdentityKeypair::new(device_id, &account).await?;
-        let client = Self::create_client(&identity)?;
+        let server_keys = Self::load_server_keys(&account).await?;
+        let client = Self::create_client(&identity, &server_keys)?;
         let db_path = db::get_default_db_path(account.credential.account_id.user_id);
         let groups = GroupStorage::new(db_path).await?;
         let backend = Backend::new(account.server_address.clone()).await.ok();
@@ -83,6 +89,7 @@ impl Device {
             backend,
             app_handle,
             contacts_parsed_cache,
+            server_keys,
         })
     }
 
@@ -107,7 +114,8 @@ impl Device {
         app_handle: Option<AppHandle>,
     ) -> Result<Self, GroupError> {
         let identity = IdentityKeypair::from_bytes(identity)?;
-        let client = Self::create_client(&identity)?;
+        let server_keys = Self::load_server_keys(&account).await?;
+        let client = Self::create_client(&identity, &server_keys)?;
         let backend = Backend::new(account.server_address.clone()).await.ok();
         groups.load_groups(&client).await?;
         let contacts_parsed_cache = CacheBuilder::new(10_000)
@@ -122,6 +130,7 @@ impl Device {
             backend,
             app_handle,
             contacts_parsed_cache,
+            server_keys,
         })
     }
 
@@ -299,7 +308,11 @@ impl Device {
                 GroupError::ConnectionError(format!("Stream initialization failed: {}", e))
             })?;
 
-        self.init_group_handler().await
+        self.init_group_handler().await?;
+
+        // Группы, созданные до V2 конфига, переводятся после подключения к стриму
+        self.migrate_group_configs().await;
+        Ok(())
     }
 
     /// Initialize group message handler
@@ -319,6 +332,7 @@ impl Device {
             self.app_handle.clone(),
             self.device_id.clone(),
             self.account.clone(),
+            self.contacts_parsed_cache.clone(),
         );
 
         tokio::spawn(async move {
@@ -330,10 +344,30 @@ impl Device {
         Ok(())
     }
 
+    /// Server keys trusted to certify accounts, see [`Account::trusted_server_keys`]
+    async fn load_server_keys(account: &Account) -> Result<TrustedServerKeys, GroupError> {
+        let server_keys = account
+            .trusted_server_keys()
+            .await
+            .map_err(|e| GroupError::StorageError(format!("Failed to load server keys: {}", e)))?;
+        if server_keys.skips_checks() {
+            log::warn!("Account certificate checks are turned off for this server");
+        } else if server_keys.is_empty() {
+            log::warn!("No trusted server key, account certificates are rejected");
+        } else if let Err(e) = server_keys.verify(&account.credential) {
+            // Свой сертификат не проходит: ключ или формат подписи не совпадают с серверными
+            log::error!("Own account certificate does not verify: {}", e);
+        }
+        Ok(server_keys)
+    }
+
     /// Create MLS client with proper configuration
     ///
     /// Configures storage, identity provider, rules, extensions and crypto.
-    fn create_client(identity: &IdentityKeypair) -> Result<MlsClient, GroupError> {
+    fn create_client(
+        identity: &IdentityKeypair,
+        server_keys: &TrustedServerKeys,
+    ) -> Result<MlsClient, GroupError> {
         let mls_credential = identity.credential.clone().into_credential().map_err(|e| {
             GroupError::CredentialError(format!("Credential conversion failed: {}", e))
         })?;
@@ -351,13 +385,16 @@ impl Device {
             .map_err(|e| {
                 GroupError::ClientBuilderError(format!("Client builder creation failed: {}", e))
             })?
-            .identity_provider(CustomIdentityProvider)
-            .mls_rules(CustomMlsRules)
+            .identity_provider(CustomIdentityProvider::new(server_keys.clone()))
+            .mls_rules(CustomMlsRules::new(server_keys.clone()))
             .custom_proposal_type(ADD_USER_PROPOSAL_V1)
             .custom_proposal_type(REMOVE_USER_PROPOSAL_V1)
             .custom_proposal_type(UPDATE_GROUP_CONFIG_PROPOSAL_V1)
+            .custom_proposal_type(UPDATE_GROUP_CONFIG_PROPOSAL_V2)
+            .custom_proposal_type(MIGRATE_GROUP_CONFIG_PROPOSAL_V1)
             .extension_type(ROSTER_EXTENSION_V1)
             .extension_type(GROUP_CONFIG_EXTENSION_V1)
+            .extension_type(GROUP_CONFIG_EXTENSION_V2)
             .crypto_provider(crypto())
             .signing_identity(signing_identity, identity.signer.clone(), CIPHER_SUITE)
             .build())
@@ -393,22 +430,7 @@ impl Device {
         let contact = self.groups.messages.get_contact(user_id as i64).await?;
         let parsed = match contact {
             Some(user_credential) => AccountCredential::mls_decode(&mut &*user_credential)?,
-            None => {
-                let user_credential = self
-                    .backend
-                    .as_mut()
-                    .ok_or(GroupError::BackendError("Client is offline".to_string()))?
-                    .get_user_credential(user_id)
-                    .await
-                    .map_err(|e| {
-                        GroupError::BackendError(format!("Failed to fetch user credential: {}", e))
-                    })?;
-                self.groups
-                    .messages
-                    .save_contact(user_id as i64, &user_credential)
-                    .await?;
-                AccountCredential::mls_decode(&mut &*user_credential)?
-            }
+            None => self.refresh_contact(user_id).await?,
         };
         self.contacts_parsed_cache
             .insert(user_id, parsed.clone())
@@ -417,7 +439,7 @@ impl Device {
     }
 
     /// Sign TBS (To Be Signed) structure
-    async fn sign_tbs<T: MlsEncode>(&self, tbs: &T) -> Result<Vec<u8>, GroupError> {
+    pub(super) async fn sign_tbs<T: MlsEncode>(&self, tbs: &T) -> Result<Vec<u8>, GroupError> {
         let tbs_bytes = tbs
             .mls_encode_to_vec()
             .map_err(|e| GroupError::EncodingError(format!("TBS encoding failed: {}", e)))?;
@@ -433,20 +455,5 @@ impl Device {
 
 /// Build default path for the SQLite storage file for a device
 pub fn get_default_db_path(account_id: u64, device_id: &str) -> std::path::PathBuf {
-    #[cfg(not(target_os = "ios"))]
-    {
-        let mut path = dirs::home_dir().expect("Could not find home directory");
-        path.push(".ship/group");
-        std::fs::create_dir_all(&path).expect("Could not create .ship directory");
-        path.push(format!("group_{}_{}.db", account_id, device_id));
-        path
-    }
-    #[cfg(target_os = "ios")]
-    {
-        let mut path = dirs::document_dir().expect("Could not find home directory");
-        path.push(".ship/group");
-        std::fs::create_dir_all(&path).expect("Could not create .ship directory");
-        path.push(format!("group_{}_{}.db", account_id, device_id));
-        path
-    }
+    storage::layout().mls_db(account_id, device_id)
 }
@@ -0,0 +1,82 @@
+//! Direct chats between two users
+//!
+//! A direct chat is a two-party MLS group with a `GroupConfig::direct_chat`
+//! config. If both users start a chat at the same time each of them ends up
+//! in two groups; every device picks the same one for sending and shows the
+//! messages of all of them, so the two sides converge on one chat.
+
+use std::collections::BTreeMap;
+
+use rand::RngExt;
+
+use crate::api::device::{
+    device::Device,
+    types::{
+        errors::GroupError, extensions::group_config::group_config::GroupConfig, group::GroupId,
+    },
+};
+
+impl Device {
+    /// Groups backing the direct chats of the user, by peer
+    ///
+    /// The groups of a peer are ordered with the one used for sending first:
+    /// groups the peer has joined come before the others, then by group id.
+    pub async fn direct_chats(&self) -> Result<BTreeMap<u64, Vec<GroupId>>, GroupError> {
+        let mut chats: BTreeMap<u64, Vec<(bool, GroupId)>> = BTreeMap::new();
+        for group_id in self.groups.list_groups().await {
+            let config = self.get_group_config(&group_id).await?;
+            let Some(peer_id) = config.direct_chat_peer(self.user_id()) else {
+                continue;
+            };
+            let joined = self.get_group_members(&group_id).await?.contains(&peer_id);
+            chats.entry(peer_id).or_default().push((!joined, group_id));
+        }
+
+        Ok(chats
+            .into_iter()
+            .map(|(peer_id, mut groups)| {
+                groups.sort_by(|a, b| (a.0, a.1.as_bytes()).cmp(&(b.0, b.1.as_bytes())));
+                (peer_id, groups.into_iter().map(|(_, id)| id).collect())
+            })
+            .collect())
+    }
+
+    /// Group used to send to `peer_id`, if there is a direct chat with them
+    pub async fn find_direct_chat(&self, peer_id: u64) -> Result<Option<GroupId>, GroupError> {
+        Ok(self
+            .direct_chats()
+            .await?
+            .remove(&peer_id)
+            .and_then(|groups| groups.into_iter().next()))
+    }
+
+    /// Open the direct chat with `peer_id`, creating it if needed
+    ///
+    /// An existing chat the peer has not joined yet, e.g. because they had no
+    /// key packages, is offered to them again.
+    pub async fn create_direct_chat(&mut self, peer_id: u64) -> Result<GroupId, GroupError> {
+        if peer_id == self.user_id() {
+            return Err(GroupError::ConfigError(
+                "Cannot start a direct chat with yourself".to_string(),
+            ));
+        }
+
+        let group_id = match self.find_direct_chat(peer_id).await? {
+            Some(group_id) => {
+                if self.get_group_members(&group_id).await?.contains(&peer_id) {
+                    return Ok(group_id);
+                }
+                group_id
+            }
+            None => {
+                let chat_id = rand::rng().random_range(100_000_000_000_u64..1_000_000_000_000_u64);
+                let config = GroupConfig::direct_chat(chat_id, self.user_id(), peer_id);
+                self.create_group(config).await?
+            }
+        };
+
+        self.add_user_devices(&group_id, peer_id).await?;
+        log::info!("Started direct chat {:?} with {}", group_id, peer_id);
+        Ok(group_id)
+    }
+}
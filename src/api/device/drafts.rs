@@ -0,0 +1,147 @@
+//! Per-group drafts synced between the devices of a user
+//!
+//! Drafts are stored locally and sent to the user's other devices through the
+//! self-channel, a hidden MLS group whose only member is the user. The newest
+//! draft of a group wins on every device. Attachments are local paths and stay
+//! on the device they were picked on.
+
+use rand::RngExt;
+
+use crate::api::device::{
+    db::Draft,
+    device::Device,
+    types::{
+        errors::GroupError,
+        extensions::group_config::{
+            group_config::{JoinMode, SELF_CHANNEL_NAME, Visibility},
+            group_config_builder::GroupConfigBuilder,
+        },
+        group::GroupId,
+        message::{GroupDraftMessage, UserGroupMessage},
+    },
+};
+
+/// Drafts unchanged for this long are sent to the other devices, in milliseconds
+const DRAFT_SYNC_DELAY_MS: i64 = 5_000;
+
+impl Device {
+    /// Get the draft of a group
+    pub async fn get_draft(&self, group_id: &GroupId) -> Result<Option<Draft>, GroupError> {
+        self.groups
+            .messages
+            .get_draft(group_id.as_bytes(), self.user_id() as i64)
+            .await
+    }
+
+    /// Store the draft of a group
+    ///
+    /// An empty draft clears the stored one. The draft is sent to the user's
+    /// other devices once it stops changing, see [`Device::sync_drafts`].
+    pub async fn save_draft(
+        &mut self,
+        group_id: &GroupId,
+        text: String,
+        reply_message_id: Option<i64>,
+        attachment_path: Option<String>,
+    ) -> Result<Draft, GroupError> {
+        // The group must exist, drafts of other groups would never be shown
+        self.groups.get(group_id).await?;
+        // Created here: drafts are synced without exclusive access to the device
+        if let Err(e) = self.self_channel().await {
+            log::warn!("Failed to open the self-channel: {}", e);
+        }
+
+        let draft = Draft {
+            message: GroupDraftMessage {
+                group_id: group_id.to_vec(),
+                user_id: self.user_id() as i64,
+                updated_at: now_millis()?,
+                text,
+                reply_message_id,
+            },
+            attachment_path,
+        };
+        self.groups
+            .messages
+            .save_draft(&draft.message, draft.attachment_path.as_deref())
+            .await?;
+        Ok(draft)
+    }
+
+    /// Clear the draft of a group on all devices of the user
+    pub async fn clear_draft(&mut self, group_id: &GroupId) -> Result<(), GroupError> {
+        self.save_draft(group_id, String::new(), None, None).await?;
+        Ok(())
+    }
+
+    /// Send the drafts that stopped changing to the user's other devices
+    ///
+    /// Run by the scheduler task. A draft that failed to send stays pending
+    /// and is sent on the next run.
+    pub async fn sync_drafts(&self) -> Result<(), GroupError> {
+        let drafts = self
+            .groups
+            .messages
+            .get_unsynced_drafts(self.user_id() as i64, now_millis()? - DRAFT_SYNC_DELAY_MS)
+            .await?;
+        if drafts.is_empty() {
+            return Ok(());
+        }
+        let Some(channel) = self.find_self_channel().await? else {
+            return Ok(());
+        };
+
+        for draft in drafts {
+            let group_id = draft.group_id.clone();
+            let updated_at = draft.updated_at;
+            self.send_message(
+                &channel,
+                Device::generate_message_id(),
+                UserGroupMessage::Draft(draft),
+            )
+            .await?;
+            self.groups
+                .messages
+                .mark_draft_synced(&group_id, updated_at)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Find the self-channel of the user
+    ///
+    /// If several devices created one concurrently, drafts are accepted from
+    /// all of them and sent to the first found.
+    async fn find_self_channel(&self) -> Result<Option<GroupId>, GroupError> {
+        for group_id in self.groups.list_groups().await {
+            let config = self.get_group_config(&group_id).await?;
+            if config.is_self_channel() && config.creator_id == self.user_id() {
+                return Ok(Some(group_id));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Find the self-channel of the user, creating it on first use
+    async fn self_channel(&mut self) -> Result<GroupId, GroupError> {
+        if let Some(group_id) = self.find_self_channel().await? {
+            return Ok(group_id);
+        }
+
+        let id = rand::rng().random_range(100_000_000_000_u64..1_000_000_000_000_u64);
+        let config = GroupConfigBuilder::new(id, SELF_CHANNEL_NAME.to_string(), self.user_id())
+            .with_visibility(Visibility::Hidden)
+            .with_join_mode(JoinMode::InviteOnly)
+            .with_max_members(1)
+            .build()
+            .map_err(|e| GroupError::ConfigError(e.to_string()))?;
+        self.create_group(config).await
+    }
+}
+
+fn now_millis() -> Result<i64, GroupError> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|_| GroupError::SystemTimeError("Failed to get current time".to_string()))?
+        .as_millis() as i64)
+}
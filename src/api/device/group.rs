@@ -4,15 +4,26 @@ use mls_rs_codec::{MlsDecode, MlsEncode};
 use crate::api::device::{
     device::Device,
     types::{
+        audit::{GroupAuditEntry, leaf_user_id},
         config::CREDENTIAL_V1,
+        content_policy::{check_content_policy, check_mentions},
         custom_mls::credentials::DeviceCredential,
         errors::GroupError,
         extensions::{
-            group_config::{group_config::GroupConfig, group_extension::UpdateGroupConfigProposal},
+            group_config::{
+                group_config::{GroupConfig, Permission},
+                group_extension::{
+                    MigrateGroupConfigProposal, UpdateGroupConfigProposal, needs_config_migration,
+                },
+            },
             roster::proposals::RemoveUserProposal,
         },
         group::{GroupId, MlsGroup},
-        message::UserGroupMessage,
+        message::{
+            GroupDeleteMessage, GroupPollClose, GroupPollMessage, GroupPollVote, UserGroupMessage,
+        },
+        poll::check_poll_message,
+        text_entity::check_entities,
     },
 };
 
@@ -42,6 +53,10 @@ impl Device {
 
         let group_id = GroupId::new(group.group_id().to_vec());
         self.groups.insert(group_id.clone(), group).await;
+        self.groups
+            .messages
+            .seed_read_position(group_id.as_bytes())
+            .await?;
 
         log::debug!("Created group with ID: {:?}", group_id);
 
@@ -61,13 +76,28 @@ impl Device {
     ///
     /// - Removes all of the current user's other devices from the roster
     /// - Updates the group config to remove the current user
+    /// - If the user owns the group, hands it to `successor` or to the
+    ///   member picked by `GroupConfig::pick_successor`
     /// - Sends commit and acks its delivery
-    pub async fn leave_group(&self, group_id: &GroupId) -> Result<(), GroupError> {
+    pub async fn leave_group(
+        &self,
+        group_id: &GroupId,
+        successor: Option<u64>,
+    ) -> Result<(), GroupError> {
         {
             let group_arc = self.groups.get(group_id).await?;
             let mut group = group_arc.write().await;
 
             let mut config = self.extract_group_config(&group)?;
+            if config.creator_id == self.user_id()
+                && let Some(successor_id) = successor.or_else(|| config.pick_successor())
+                && !config.transfer_ownership(successor_id)
+            {
+                return Err(GroupError::ConfigError(format!(
+                    "User {} cannot take over the group",
+                    successor_id
+                )));
+            }
             config.remove_member(self.user_id());
             let update_config = UpdateGroupConfigProposal { new_config: config };
 
@@ -207,7 +237,21 @@ impl Device {
             ));
         }
 
-        let user_credential = self.get_contact(user_id).await?;
+        self.add_user_devices(group_id, user_id).await
+    }
+
+    /// Add every device of `user_id` to the group
+    ///
+    /// Builds the invite commit from the user's key packages, sends it to the
+    /// group and the welcome to the user. The config only gains the user if
+    /// it does not list them yet.
+    pub(super) async fn add_user_devices(
+        &mut self,
+        group_id: &GroupId,
+        user_id: u64,
+    ) -> Result<(), GroupError> {
+        // Свежий credential: смена ключа обнаруживается до приглашения
+        let user_credential = self.refresh_contact(user_id).await?;
 
         let devices = self
             .backend
@@ -297,6 +341,221 @@ impl Device {
         Ok(())
     }
 
+    /// Move the group config from the V1 extension to the V2 one
+    ///
+    /// Sends an otherwise empty migration commit; `CustomMlsRules` rewrites
+    /// the extension when it is applied. Returns `false` if the group is
+    /// already on V2 or another member is to migrate it, see
+    /// [`config_migrator`].
+    pub async fn migrate_group_config(&self, group_id: &GroupId) -> Result<bool, GroupError> {
+        let group_arc = self.groups.get(group_id).await?;
+        let mut group = group_arc.write().await;
+
+        if !needs_config_migration(&group.context().extensions) {
+            return Ok(false);
+        }
+        let config = self.extract_group_config(&group)?;
+        if config_migrator(&group, &config) != Some(self.user_id()) {
+            return Ok(false);
+        }
+
+        let migrate_proposal = MigrateGroupConfigProposal { from_version: 1 };
+
+        let commit = group
+            .commit_builder()
+            .custom_proposal(migrate_proposal.to_custom_proposal()?)
+            .build()
+            //.await
+            .map_err(|e| {
+                GroupError::MlsError(format!("Failed to build config migration commit: {}", e))
+            })?;
+
+        self.send_commit_to_group(&group, group_id, &commit).await?;
+
+        self.apply_and_store_commit(&mut group).await?;
+
+        log::info!("Migrated group config for group {:?}", group_id);
+        Ok(true)
+    }
+
+    /// Migrate the config of every stored group still on the V1 extension
+    pub async fn migrate_group_configs(&self) {
+        for group_id in self.groups.list_groups().await {
+            if let Err(e) = self.migrate_group_config(&group_id).await {
+                log::warn!("Failed to migrate config of group {:?}: {}", group_id, e);
+            }
+        }
+    }
+
+    /// Make `new_owner_id` the owner of the group
+    ///
+    /// Only the current owner may transfer the group; the new owner must be
+    /// a member and is given the admin role.
+    pub async fn transfer_ownership(
+        &self,
+        group_id: &GroupId,
+        new_owner_id: u64,
+    ) -> Result<GroupConfig, GroupError> {
+        let mut config = self.get_group_config(group_id).await?;
+        if config.creator_id != self.user_id() {
+            return Err(GroupError::ConfigError(
+                "Only the owner can transfer ownership".to_string(),
+            ));
+        }
+        if !config.transfer_ownership(new_owner_id) {
+            return Err(GroupError::ConfigError(format!(
+                "User {} cannot take over the group",
+                new_owner_id
+            )));
+        }
+        self.update_group_config(group_id, &config).await?;
+        Ok(config)
+    }
+
+    /// Disband the group for all members
+    ///
+    /// Only the owner may disband a group. A single commit removes every
+    /// other leaf and roster entry and marks the config as disbanded; the
+    /// group is then unsubscribed and kept as a read-only archive.
+    pub async fn disband_group(&self, group_id: &GroupId) -> Result<GroupConfig, GroupError> {
+        let config = {
+            let group_arc = self.groups.get(group_id).await?;
+            let mut group = group_arc.write().await;
+
+            let mut config = self.extract_group_config(&group)?;
+            if config.creator_id != self.user_id() {
+                return Err(GroupError::ConfigError(
+                    "Only the owner can disband the group".to_string(),
+                ));
+            }
+            config.disband();
+
+            let own_index = group.current_member_index();
+            let leaf_indexes: Vec<u32> = group
+                .roster()
+                .members()
+                .into_iter()
+                .map(|member| member.index)
+                .filter(|index| *index != own_index)
+                .collect();
+            let removed_users: Vec<u64> = self
+                .extract_group_members(&group)?
+                .into_iter()
+                .filter(|user_id| *user_id != self.user_id())
+                .collect();
+
+            let mut commit = group.commit_builder();
+            for leaf_index in leaf_indexes {
+                commit = commit.remove_member(leaf_index)?;
+            }
+            for user_id in removed_users {
+                commit =
+                    commit.custom_proposal(RemoveUserProposal { user_id }.to_custom_proposal()?);
+            }
+            let update_config = UpdateGroupConfigProposal {
+                new_config: config.clone(),
+            };
+            let commit = commit
+                .custom_proposal(update_config.to_custom_proposal()?)
+                .build()
+                //.await
+                .map_err(|e| {
+                    GroupError::MlsError(format!("Failed to build disband commit: {}", e))
+                })?;
+
+            self.send_commit_to_group(&group, group_id, &commit).await?;
+
+            self.apply_and_store_commit(&mut group).await?;
+            config
+        };
+
+        self.backend
+            .as_ref()
+            .ok_or(GroupError::BackendError("Client is offline".to_string()))?
+            .update_group_subscriptions(vec![], vec![group_id.to_vec()])
+            .await
+            .map_err(|e| {
+                GroupError::BackendError(format!("Failed to update group subscriptions: {}", e))
+            })?;
+        self.groups.archive(group_id, &config).await?;
+
+        log::info!("Disbanded group {:?}", group_id);
+        Ok(config)
+    }
+
+    /// Get the audit log of configuration, roster and leaf changes
+    ///
+    /// Only group admins may read the audit log.
+    pub async fn get_group_audit_log(
+        &self,
+        group_id: &GroupId,
+    ) -> Result<Vec<GroupAuditEntry>, GroupError> {
+        let config = self.get_group_config(group_id).await?;
+        if !config.is_admin(self.user_id()) {
+            return Err(GroupError::ConfigError(
+                "Only admins can view the audit log".to_string(),
+            ));
+        }
+        self.groups
+            .messages
+            .get_audit_log(group_id.as_bytes())
+            .await
+    }
+
+    /// Pin a message of the group
+    ///
+    /// Requires `pin_messages`; the message must be present in local history.
+    /// Returns the updated config, unchanged if the message was already pinned.
+    pub async fn pin_message(
+        &self,
+        group_id: &GroupId,
+        message_id: u64,
+    ) -> Result<GroupConfig, GroupError> {
+        let mut config = self.get_group_config(group_id).await?;
+        if !config.has_permission(self.user_id(), Permission::PinMessages) {
+            return Err(GroupError::ConfigError(
+                "User is not allowed to pin messages".to_string(),
+            ));
+        }
+        if self
+            .groups
+            .messages
+            .get_message(group_id.as_bytes(), message_id as i64)
+            .await?
+            .is_none()
+        {
+            return Err(GroupError::InvalidMessage(format!(
+                "Message {} not found",
+                message_id
+            )));
+        }
+        if config.pin_message(message_id, self.user_id()) {
+            self.update_group_config(group_id, &config).await?;
+        }
+        Ok(config)
+    }
+
+    /// Unpin a message of the group
+    ///
+    /// Requires `pin_messages`. Returns the updated config, unchanged if the
+    /// message was not pinned.
+    pub async fn unpin_message(
+        &self,
+        group_id: &GroupId,
+        message_id: u64,
+    ) -> Result<GroupConfig, GroupError> {
+        let mut config = self.get_group_config(group_id).await?;
+        if !config.has_permission(self.user_id(), Permission::PinMessages) {
+            return Err(GroupError::ConfigError(
+                "User is not allowed to unpin messages".to_string(),
+            ));
+        }
+        if config.unpin_message(message_id) {
+            self.update_group_config(group_id, &config).await?;
+        }
+        Ok(config)
+    }
+
     /// Derive a symmetric key for display/preview purposes
     ///
     /// Uses MLS `export_secret` to derive a 32-byte key scoped to `group_id`.
@@ -317,6 +576,9 @@ impl Device {
     ///
     /// Checks `send_messages` permission, encrypts an application message,
     /// delivers it to all current members, and stores a local copy.
+    /// Tombstones are checked against `delete_messages` and purge the local copy,
+    /// poll messages go through the same checks receivers apply, drafts only
+    /// go to the user's self-channel.
     pub async fn send_message(
         &self,
         group_id: &GroupId,
@@ -326,17 +588,65 @@ impl Device {
         let group_arc = self.groups.get(group_id).await?;
         let mut group = group_arc.write().await;
         let group_config = self.extract_group_config(&group)?;
-        if !group_config.has_permission(self.user_id(), "send_messages") {
-            return Err(GroupError::ConfigError(
-                "User is not allowed to send messages".to_string(),
-            ));
+        match &message {
+            UserGroupMessage::TextMessage(text_message) => {
+                if !group_config.has_permission(self.user_id(), Permission::SendMessages) {
+                    return Err(GroupError::ConfigError(
+                        "User is not allowed to send messages".to_string(),
+                    ));
+                }
+                check_content_policy(&group_config, text_message)?;
+                check_mentions(&group_config, text_message)?;
+                check_entities(&text_message.entities, &text_message.text)
+                    .map_err(GroupError::InvalidMessage)?;
+                if text_message.edit_date.is_none() {
+                    self.check_slow_mode(group_id, &group_config, text_message.date)
+                        .await?;
+                } else {
+                    let original = self
+                        .groups
+                        .messages
+                        .get_message(group_id.as_bytes(), text_message.message_id)
+                        .await?;
+                    if original.is_none_or(|original| original.sender_id != self.user_id() as i64) {
+                        return Err(GroupError::ConfigError(
+                            "Only the sender can edit a message".to_string(),
+                        ));
+                    }
+                }
+            }
+            UserGroupMessage::DeleteMessage(tombstone) => {
+                self.check_delete_allowed(group_id, &group_config, tombstone.message_id)
+                    .await?;
+            }
+            UserGroupMessage::Poll(_)
+            | UserGroupMessage::PollVote(_)
+            | UserGroupMessage::PollClose(_) => {
+                check_poll_message(
+                    &self.groups.messages,
+                    group_id.as_bytes(),
+                    &group_config,
+                    self.user_id(),
+                    &message,
+                )
+                .await?;
+            }
+            UserGroupMessage::Draft(_) => {
+                if !group_config.is_self_channel() || group_config.creator_id != self.user_id() {
+                    return Err(GroupError::ConfigError(
+                        "Drafts are only sent to the self-channel".to_string(),
+                    ));
+                }
+            }
         }
+        let members = self.extract_group_members(&group)?;
+        self.check_key_changes(&members).await?;
+
         let encrypted_message = group
             .encrypt_application_message(&message.to_bytes(), Default::default())
             //.await
             .map_err(|e| GroupError::MlsError(format!("Message encryption failed: {}", e)))?;
 
-        let members = self.extract_group_members(&group)?;
         let message_bytes = encrypted_message.mls_encode_to_vec().map_err(|e| {
             GroupError::EncodingError(format!("Failed to encode encrypted message: {}", e))
         })?;
@@ -357,12 +667,188 @@ impl Device {
             .map_err(|e| {
                 GroupError::BackendError(format!("Failed to send group message: {}", e))
             })?;
-        self.groups
-            .messages
-            .save_message(&message, group_id.as_bytes())
-            .await
-            .map_err(|e| GroupError::StorageError(e.to_string()))?;
+        match &message {
+            UserGroupMessage::DeleteMessage(tombstone) => self
+                .groups
+                .messages
+                .purge_message(tombstone.message_id, group_id.as_bytes())
+                .await
+                .map_err(|e| GroupError::StorageError(e.to_string()))?,
+            // Черновик уже сохранён локально, sync_drafts отмечает его отправку
+            UserGroupMessage::Draft(_) => {}
+            _ => self
+                .groups
+                .messages
+                .save_message(&message, group_id.as_bytes())
+                .await
+                .map_err(|e| GroupError::StorageError(e.to_string()))?,
+        }
         log::debug!("Sent message to group {:?}", group_id);
         Ok(())
     }
+
+    /// Delete a message on every member's device
+    ///
+    /// Sends a tombstone signed with the account key. Senders may delete
+    /// their own messages, members with `delete_messages` anyone's.
+    pub async fn delete_message_for_everyone(
+        &self,
+        group_id: &GroupId,
+        message_id: i64,
+    ) -> Result<GroupDeleteMessage, GroupError> {
+        let mut tombstone = GroupDeleteMessage {
+            message_id,
+            deleter_id: self.user_id() as i64,
+            date: unix_now()?,
+            signature: Vec::new(),
+        };
+        tombstone.signature = self.sign_tbs(&tombstone.tbs(group_id.as_bytes())).await?;
+        self.send_message(
+            group_id,
+            Device::generate_message_id(),
+            UserGroupMessage::DeleteMessage(tombstone.clone()),
+        )
+        .await?;
+        Ok(tombstone)
+    }
+
+    /// Post a poll to the group
+    pub async fn create_poll(
+        &self,
+        group_id: &GroupId,
+        question: String,
+        options: Vec<String>,
+        multiple_choice: bool,
+        anonymous: bool,
+        closes_at: Option<i64>,
+    ) -> Result<GroupPollMessage, GroupError> {
+        let poll = GroupPollMessage {
+            poll_id: Device::generate_message_id() as i64,
+            sender_id: self.user_id() as i64,
+            date: unix_now()?,
+            question,
+            options,
+            multiple_choice,
+            anonymous,
+            closes_at,
+        };
+        self.send_message(
+            group_id,
+            poll.poll_id as u64,
+            UserGroupMessage::Poll(poll.clone()),
+        )
+        .await?;
+        Ok(poll)
+    }
+
+    /// Vote in a poll, replacing the previous vote; no options retracts it
+    pub async fn vote_poll(
+        &self,
+        group_id: &GroupId,
+        poll_id: i64,
+        options: Vec<u32>,
+    ) -> Result<GroupPollVote, GroupError> {
+        let vote = GroupPollVote {
+            poll_id,
+            voter_id: self.user_id() as i64,
+            date: unix_now()?,
+            options,
+        };
+        self.send_message(
+            group_id,
+            Device::generate_message_id(),
+            UserGroupMessage::PollVote(vote.clone()),
+        )
+        .await?;
+        Ok(vote)
+    }
+
+    /// Close a poll created by this user
+    pub async fn close_poll(
+        &self,
+        group_id: &GroupId,
+        poll_id: i64,
+    ) -> Result<GroupPollClose, GroupError> {
+        let close = GroupPollClose {
+            poll_id,
+            closer_id: self.user_id() as i64,
+            date: unix_now()?,
+        };
+        self.send_message(
+            group_id,
+            Device::generate_message_id(),
+            UserGroupMessage::PollClose(close),
+        )
+        .await?;
+        Ok(close)
+    }
+
+    // Удалить можно своё сообщение, чужое — только с правом delete_messages
+    async fn check_delete_allowed(
+        &self,
+        group_id: &GroupId,
+        group_config: &GroupConfig,
+        message_id: i64,
+    ) -> Result<(), GroupError> {
+        let original = self
+            .groups
+            .messages
+            .get_message(group_id.as_bytes(), message_id)
+            .await?
+            .ok_or(GroupError::InvalidMessage("Message not found".to_string()))?;
+        if original.sender_id != self.user_id() as i64
+            && !group_config.has_permission(self.user_id(), Permission::DeleteMessages)
+        {
+            return Err(GroupError::ConfigError(
+                "User is not allowed to delete this message".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check that slow mode allows this user to post to the group at `date`
+    ///
+    /// - Errors: `GroupError::SlowMode` with the remaining wait in seconds
+    pub async fn check_slow_mode(
+        &self,
+        group_id: &GroupId,
+        group_config: &GroupConfig,
+        date: i64,
+    ) -> Result<(), GroupError> {
+        let last_message_date = self
+            .groups
+            .messages
+            .get_last_message_date(group_id.as_bytes(), self.user_id() as i64)
+            .await?;
+        match group_config.slow_mode_remaining(self.user_id(), last_message_date, date) {
+            Some(remaining) => Err(GroupError::SlowMode(remaining)),
+            None => Ok(()),
+        }
+    }
+}
+
+pub(super) fn unix_now() -> Result<i64, GroupError> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|_| GroupError::SystemTimeError("Failed to get current time".to_string()))?
+        .as_secs() as i64)
+}
+
+/// Member that sends the config migration of a group
+///
+/// Every member migrates at startup, so only one of them may send the commit:
+/// the owner, or the admin with the lowest leaf index once the owner left.
+fn config_migrator(group: &MlsGroup, config: &GroupConfig) -> Option<u64> {
+    let mut members = group.roster().members();
+    members.sort_by_key(|member| member.index);
+    let users: Vec<u64> = members
+        .iter()
+        .filter_map(|member| leaf_user_id(group, member.index).ok())
+        .collect();
+    if users.contains(&config.creator_id) {
+        return Some(config.creator_id);
+    }
+    users
+        .into_iter()
+        .find(|user_id| config.admins.contains(user_id))
 }
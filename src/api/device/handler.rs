@@ -1,15 +1,22 @@
 use std::sync::Arc;
 
-use super::types::custom_mls::credentials::DeviceCredential;
-use super::types::extensions::group_config::group_config::GroupConfig;
+use super::group::unix_now;
+use super::helper::{capture_snapshot, record_audit};
+use super::key_change::check_roster_keys;
+use super::types::audit::{GroupSnapshot, leaf_user_id};
+use super::types::config::cipher_suite;
+use super::types::content_policy::{check_content_policy, check_mentions};
+use super::types::custom_mls::credentials::{AccountCredential, DeviceCredential};
+use super::types::extensions::group_config::group_config::{GroupConfig, Permission};
 use super::types::extensions::group_config::group_extension::{
-    GroupConfigExtension, UPDATE_GROUP_CONFIG_PROPOSAL_V1, UpdateGroupConfigProposal,
+    UPDATE_GROUP_CONFIG_PROPOSAL_V2, UpdateGroupConfigProposal, read_group_config,
 };
 use super::types::group::MlsGroup;
+use super::types::poll::check_poll_message;
 use super::types::signature_bytes::UploadKeyPackagesTBS;
 use mls_rs::group::CommitEffect;
 use mls_rs::group::proposal::{MlsCustomProposal, Proposal};
-use mls_rs::{MlsMessage, group::ReceivedMessage};
+use mls_rs::{CipherSuiteProvider, MlsMessage, WireFormat, group::ReceivedMessage};
 use mls_rs_codec::MlsDecode;
 use mls_rs_codec::MlsEncode;
 use moka::future::{Cache, CacheBuilder};
@@ -18,17 +25,41 @@ use tauri::AppHandle;
 
 use super::connection::Backend;
 use super::connection::group_microservice;
-use super::types::message::UserGroupMessage;
+use super::types::message::{GroupDeleteMessage, GroupTextMessage, UserGroupMessage};
 
 use super::mls_client::MlsClient;
 use super::types::errors::GroupError;
 use super::types::group::{GroupId, GroupStorage};
 use crate::api::account::Account;
 use crate::commands::events::{
-    emit_join_group_event, emit_message_delivery_event, emit_new_group_config,
+    emit_draft_updated_event, emit_group_disbanded_event, emit_join_group_event,
+    emit_mentioned_event, emit_message_deleted_event, emit_message_delivery_event,
+    emit_new_chat_event, emit_new_group_config, emit_pinned_messages_events,
     emit_text_message_event, emit_welcome_message_event,
 };
 
+/// Tolerated difference between a sender's clock and ours, in seconds
+const MAX_CLOCK_SKEW_SECS: i64 = 5 * 60;
+
+/// Reject poll messages dated outside `now ± MAX_CLOCK_SKEW_SECS`
+///
+/// Votes are held against the poll cutoff by their own date, so a back-dated
+/// vote would otherwise still count after the poll closed.
+fn check_poll_date(message: &UserGroupMessage) -> Result<(), GroupError> {
+    let date = match message {
+        UserGroupMessage::Poll(poll) => poll.date,
+        UserGroupMessage::PollVote(vote) => vote.date,
+        UserGroupMessage::PollClose(close) => close.date,
+        _ => return Ok(()),
+    };
+    if date.abs_diff(unix_now()?) > MAX_CLOCK_SKEW_SECS as u64 {
+        return Err(GroupError::InvalidMessage(
+            "Poll message date is too far from the current time".to_string(),
+        ));
+    }
+    Ok(())
+}
+
 pub struct GroupHandler {
     pub user_id: u64,
     pub client: MlsClient,
@@ -38,6 +69,7 @@ pub struct GroupHandler {
     pub device_id: String,
     pub account: Arc<Account>,
     sender_credential_cache: Cache<u32, DeviceCredential>,
+    contacts_parsed_cache: Cache<u64, AccountCredential>,
 }
 
 impl GroupHandler {
@@ -49,6 +81,7 @@ impl GroupHandler {
         app_handle: Option<AppHandle>,
         device_id: String,
         account: Arc<Account>,
+        contacts_parsed_cache: Cache<u64, AccountCredential>,
     ) -> Self {
         let sender_credential_cache = CacheBuilder::new(10_000)
             .time_to_live(Duration::from_secs(60 * 10))
@@ -62,18 +95,162 @@ impl GroupHandler {
             device_id,
             account,
             sender_credential_cache,
+            contacts_parsed_cache,
         }
     }
 
     fn extract_group_config(&self, group: &MlsGroup) -> Result<GroupConfig, GroupError> {
-        let current_context = group.context();
-        let current_config: Option<GroupConfigExtension> =
-            current_context.extensions.get_as().map_err(|e| {
-                GroupError::ExtensionError(format!("Failed to get group config extension: {}", e))
+        read_group_config(&group.context().extensions)?.ok_or(GroupError::ConfigurationNotFound)
+    }
+
+    /// Reject messages that arrive faster than the group's slow mode delay
+    ///
+    /// The delay is measured from the last accepted message of the same sender
+    /// to the message date, capped at the local time so that post-dated
+    /// messages cannot skip the delay. Dates too far in the future are rejected.
+    async fn check_slow_mode(
+        &self,
+        group: &MlsGroup,
+        group_config: &GroupConfig,
+        sender_id: u64,
+        text_message: &GroupTextMessage,
+    ) -> Result<(), GroupError> {
+        if text_message.edit_date.is_some()
+            || group_config.slow_mode_delay.unwrap_or(0) == 0
+            || group_config.is_slow_mode_exempt(sender_id)
+        {
+            return Ok(());
+        }
+
+        let now = unix_now()?;
+        if text_message.date > now + MAX_CLOCK_SKEW_SECS {
+            return Err(GroupError::ConfigError(
+                "Message date is too far in the future".to_string(),
+            ));
+        }
+
+        let last_message_date = self
+            .groups
+            .messages
+            .get_last_message_date(group.group_id(), sender_id as i64)
+            .await?;
+        let date = text_message.date.min(now);
+        match group_config.slow_mode_remaining(sender_id, last_message_date, date) {
+            Some(remaining) => Err(GroupError::SlowMode(remaining)),
+            None => Ok(()),
+        }
+    }
+
+    /// Reject edits of messages the sender did not write
+    ///
+    /// Both the sender id claimed in the edit and the author of the stored
+    /// message must match the authenticated sender.
+    async fn check_edit_sender(
+        &self,
+        group: &MlsGroup,
+        sender_id: u64,
+        text_message: &GroupTextMessage,
+    ) -> Result<(), GroupError> {
+        if text_message.edit_date.is_none() {
+            return Ok(());
+        }
+
+        let original = self
+            .groups
+            .messages
+            .get_message(group.group_id(), text_message.message_id)
+            .await?
+            .ok_or_else(|| {
+                GroupError::InvalidMessage(format!(
+                    "Edited message {} not found",
+                    text_message.message_id
+                ))
+            })?;
+        if text_message.sender_id != sender_id as i64 || original.sender_id != sender_id as i64 {
+            return Err(GroupError::ConfigError(
+                "Only the sender can edit a message".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Remove a message on request of a tombstone
+    ///
+    /// Senders may delete their own messages; members with `delete_messages`
+    /// may delete anyone's. The tombstone must be signed by the account of the
+    /// device that sent it.
+    async fn apply_tombstone(
+        &self,
+        group: &MlsGroup,
+        group_config: &GroupConfig,
+        sender_cred: &DeviceCredential,
+        tombstone: &GroupDeleteMessage,
+    ) -> Result<(), GroupError> {
+        let deleter_id = sender_cred.device_id.user_id;
+        if tombstone.deleter_id != deleter_id as i64 {
+            return Err(GroupError::InvalidMessage(
+                "Tombstone deleter does not match its sender".to_string(),
+            ));
+        }
+        let tbs = tombstone.tbs(group.group_id()).mls_encode_to_vec()?;
+        cipher_suite()
+            .verify(&sender_cred.user_public_key, &tombstone.signature, &tbs)
+            .map_err(|_| {
+                GroupError::CryptoError("Tombstone signature verification failed".to_string())
             })?;
 
-        let config_extension = current_config.ok_or(GroupError::ConfigurationNotFound)?;
-        Ok(config_extension.config)
+        let group_id = GroupId::new(group.group_id().to_vec());
+        let Some(original) = self
+            .groups
+            .messages
+            .get_message(group.group_id(), tombstone.message_id)
+            .await?
+        else {
+            log::debug!("Tombstone for unknown message {}", tombstone.message_id);
+            return Ok(());
+        };
+        if original.sender_id != deleter_id as i64
+            && !group_config.has_permission(deleter_id, Permission::DeleteMessages)
+        {
+            return Err(GroupError::ConfigError(
+                "User is not allowed to delete messages".to_string(),
+            ));
+        }
+
+        self.groups
+            .messages
+            .purge_message(tombstone.message_id, group.group_id())
+            .await?;
+        if let Some(app_handle) = &self.app_handle {
+            emit_message_deleted_event(app_handle, self.user_id, &group_id, tombstone).await?;
+        }
+        Ok(())
+    }
+
+    /// Compare the roster of the group with the pinned contact keys
+    async fn check_roster_keys(&self, group: &MlsGroup) {
+        if let Err(e) =
+            check_roster_keys(&self.groups, self.app_handle.as_ref(), group, self.user_id).await
+        {
+            log::warn!("Failed to check roster keys: {}", e);
+        }
+    }
+
+    /// Record the epoch of a group once the server delivered a message for it
+    async fn record_delivery(&self, group_id: &GroupId) {
+        let epoch = match self.groups.get(group_id).await {
+            Ok(group) => group.read().await.current_epoch(),
+            // Группа покинута или в архиве
+            Err(_) => return,
+        };
+        if let Err(e) = self
+            .groups
+            .messages
+            .record_delivery(group_id.as_bytes(), epoch)
+            .await
+        {
+            log::warn!("Failed to record delivery: {}", e);
+        }
     }
 
     async fn sign_tbs<T: MlsEncode>(&self, tbs: &T) -> Result<Vec<u8>, GroupError> {
@@ -120,11 +297,35 @@ impl GroupHandler {
             //.await
             ?;
         let group_config = self.extract_group_config(&group)?;
+        self.check_roster_keys(&group).await;
 
         let group_id = GroupId::new(group.group_id().to_vec());
         self.groups.insert(group_id.clone(), group).await;
+        self.groups
+            .messages
+            .seed_read_position(group_id.as_bytes())
+            .await?;
+        // Direct chats and the self-channel are not shown as groups
         if let Some(app_handle) = &self.app_handle {
-            emit_join_group_event(app_handle, &group_config, &group_id).await?;
+            let verified_members = self
+                .groups
+                .messages
+                .get_verified_users(&group_config.members)
+                .await?;
+            if let Some(peer_id) = group_config.direct_chat_peer(self.user_id) {
+                let peer_verified = verified_members.contains(&peer_id);
+                emit_new_chat_event(app_handle, self.user_id, &group_id, peer_id, peer_verified)
+                    .await?;
+            } else if !group_config.is_self_channel() {
+                emit_join_group_event(
+                    app_handle,
+                    self.user_id,
+                    &group_config,
+                    &group_id,
+                    &verified_members,
+                )
+                .await?;
+            }
         }
         // Upload a fresh key package after consuming one for the welcome join
         self.upload_key_packages().await?;
@@ -148,6 +349,12 @@ impl GroupHandler {
 
         let group_arc = self.groups.get(&group_id).await?;
         let mut group = group_arc.write().await;
+        // Коммиты приходят открытыми (см. CustomMlsRules::encryption_options),
+        // снимок состояния нужен только для них
+        let previous = match message.wire_format() {
+            WireFormat::PublicMessage => capture_snapshot(&group),
+            _ => None,
+        };
         let received_message = group
             .process_incoming_message(message)
             //.await
@@ -156,7 +363,7 @@ impl GroupHandler {
                 GroupError::MessageProcessingError(e.to_string())
             })?;
 
-        self.process_received_message(received_message, &group)
+        self.process_received_message(received_message, &group, previous.as_ref())
             .await
             .map_err(|e| {
                 log::error!("Failed to process received message: {:#}", e);
@@ -175,6 +382,7 @@ impl GroupHandler {
         &self,
         received_message: ReceivedMessage,
         group: &MlsGroup,
+        previous: Option<&GroupSnapshot>,
     ) -> Result<(), GroupError> {
         match received_message {
             ReceivedMessage::ApplicationMessage(app_msg) => {
@@ -199,7 +407,77 @@ impl GroupHandler {
                     parsed
                 };
 
-                if !group_config.has_permission(sender_cred.device_id.user_id, "send_messages") {
+                let text_message = match &message {
+                    UserGroupMessage::TextMessage(text_message) => text_message,
+                    UserGroupMessage::DeleteMessage(tombstone) => {
+                        return self
+                            .apply_tombstone(group, &group_config, &sender_cred, tombstone)
+                            .await;
+                    }
+                    UserGroupMessage::Poll(_)
+                    | UserGroupMessage::PollVote(_)
+                    | UserGroupMessage::PollClose(_) => {
+                        check_poll_date(&message)?;
+                        check_poll_message(
+                            &self.groups.messages,
+                            group.group_id(),
+                            &group_config,
+                            sender_cred.device_id.user_id,
+                            &message,
+                        )
+                        .await?;
+                        self.groups
+                            .messages
+                            .save_message(&message, group.group_id())
+                            .await?;
+                        let group_id = GroupId::new(group.group_id().to_vec());
+                        if let Some(app_handle) = &self.app_handle {
+                            emit_text_message_event(
+                                app_handle,
+                                self.user_id,
+                                &message,
+                                &group_id,
+                                &group_config,
+                            )
+                            .await?;
+                        }
+                        return Ok(());
+                    }
+                    UserGroupMessage::Draft(draft) => {
+                        // Черновики принимаются только от своих устройств через self-channel
+                        if !group_config.is_self_channel()
+                            || group_config.creator_id != self.user_id
+                            || sender_cred.device_id.user_id != self.user_id
+                            || draft.user_id != self.user_id as i64
+                        {
+                            return Err(GroupError::InvalidMessage(
+                                "Draft received outside of the self-channel".to_string(),
+                            ));
+                        }
+                        if self.groups.messages.merge_draft(draft).await?
+                            && let Some(app_handle) = &self.app_handle
+                        {
+                            let attachment_path = self
+                                .groups
+                                .messages
+                                .get_draft(&draft.group_id, draft.user_id)
+                                .await?
+                                .and_then(|draft| draft.attachment_path);
+                            emit_draft_updated_event(
+                                app_handle,
+                                self.user_id,
+                                draft,
+                                &attachment_path,
+                            )
+                            .await?;
+                        }
+                        return Ok(());
+                    }
+                };
+
+                if !group_config
+                    .has_permission(sender_cred.device_id.user_id, Permission::SendMessages)
+                {
                     return Err(GroupError::ConfigError(
                         "User is not allowed to send messages".to_string(),
                     ));
@@ -207,6 +485,17 @@ impl GroupHandler {
                 if group_config.is_muted(sender_cred.device_id.user_id) {
                     return Err(GroupError::ConfigError("User is muted".to_string()));
                 }
+                check_content_policy(&group_config, text_message)?;
+                check_mentions(&group_config, text_message)?;
+                self.check_slow_mode(
+                    group,
+                    &group_config,
+                    sender_cred.device_id.user_id,
+                    text_message,
+                )
+                .await?;
+                self.check_edit_sender(group, sender_cred.device_id.user_id, text_message)
+                    .await?;
 
                 log::info!("Processed application message: {:?}", message);
                 self.groups
@@ -216,7 +505,33 @@ impl GroupHandler {
                     .map_err(|e| GroupError::StorageError(e.to_string()))?;
                 let group_id = GroupId::new(group.group_id().to_vec());
                 if let Some(app_handle) = &self.app_handle {
-                    emit_text_message_event(app_handle, &message, &group_id, &group_config).await?;
+                    emit_text_message_event(
+                        app_handle,
+                        self.user_id,
+                        &message,
+                        &group_id,
+                        &group_config,
+                    )
+                    .await?;
+                    if text_message.edit_date.is_none()
+                        && text_message.sender_id != self.user_id as i64
+                        && text_message.mentions_user(self.user_id as i64)
+                    {
+                        let unread_mentions = self
+                            .groups
+                            .messages
+                            .get_unread_mention_count(group.group_id(), self.user_id as i64)
+                            .await?;
+                        emit_mentioned_event(
+                            app_handle,
+                            self.user_id,
+                            &group_id,
+                            &group_config,
+                            text_message,
+                            unread_mentions,
+                        )
+                        .await?;
+                    }
                 }
 
                 Ok(())
@@ -224,23 +539,43 @@ impl GroupHandler {
             ReceivedMessage::Commit(commit_msg) => match commit_msg.effect {
                 CommitEffect::NewEpoch(new_epoch) => {
                     log::debug!("Processed new epoch message: {:?}", new_epoch);
+                    match leaf_user_id(group, commit_msg.committer) {
+                        Ok(committer_id) => {
+                            record_audit(&self.groups, previous, group, committer_id).await
+                        }
+                        Err(e) => log::warn!("Failed to record audit log entries: {}", e),
+                    }
+                    self.check_roster_keys(group).await;
                     for proposal in &new_epoch.applied_proposals {
                         log::debug!("Applied proposal: {:?}", proposal);
                         match &proposal.proposal {
                             Proposal::Custom(custom) => {
                                 log::debug!("Custom proposal: {:?}", custom);
 
-                                if custom.proposal_type() == UPDATE_GROUP_CONFIG_PROPOSAL_V1 {
+                                if custom.proposal_type() == UPDATE_GROUP_CONFIG_PROPOSAL_V2 {
                                     let update_group_config =
                                         UpdateGroupConfigProposal::from_custom_proposal(custom)?;
                                     let group_id = GroupId::new(group.group_id().to_vec());
                                     if let Some(app_handle) = &self.app_handle {
                                         emit_new_group_config(
                                             app_handle,
+                                            self.user_id,
                                             &group_id,
                                             &update_group_config.new_config,
                                         )
                                         .await?;
+                                        if let Some(old_config) = previous.and_then(|p| p.config())
+                                        {
+                                            emit_pinned_messages_events(
+                                                app_handle,
+                                                self.user_id,
+                                                &group_id,
+                                                old_config,
+                                                &update_group_config.new_config,
+                                                leaf_user_id(group, commit_msg.committer)?,
+                                            )
+                                            .await?;
+                                        }
                                     }
                                 }
                             }
@@ -267,7 +602,36 @@ impl GroupHandler {
                                 e
                             ))
                         })?;
-                    self.groups.remove(&group_id).await?;
+
+                    // Роспуск группы: история остается доступной только для чтения
+                    let disbanded_config = new_epoch
+                        .applied_proposals
+                        .iter()
+                        .find_map(|proposal| match &proposal.proposal {
+                            Proposal::Custom(custom)
+                                if custom.proposal_type() == UPDATE_GROUP_CONFIG_PROPOSAL_V2 =>
+                            {
+                                UpdateGroupConfigProposal::from_custom_proposal(custom)
+                                    .ok()
+                                    .map(|update| update.new_config)
+                            }
+                            _ => None,
+                        })
+                        .filter(GroupConfig::is_disbanded);
+                    if let Some(config) = disbanded_config {
+                        self.groups.archive(&group_id, &config).await?;
+                        if let Some(app_handle) = &self.app_handle {
+                            emit_group_disbanded_event(
+                                app_handle,
+                                self.user_id,
+                                &group_id,
+                                &config,
+                            )
+                            .await?;
+                        }
+                    } else {
+                        self.groups.remove(&group_id).await?;
+                    }
                     Ok(())
                 }
                 CommitEffect::ReInit(proposal) => {
@@ -307,6 +671,7 @@ impl GroupHandler {
                                 log::info!("  Group: {:?}", msg.group_id);
                                 log::info!("  Message length: {:?}", msg.message.len());
 
+                                let _processing = self.groups.lock_processing().await;
                                 if let Err(e) = async {
                                     let message =
                                         MlsMessage::from_bytes(&msg.message).map_err(|e| {
@@ -321,6 +686,7 @@ impl GroupHandler {
                                         e
                                     );
                                 }
+                                self.record_delivery(&GroupId::new(msg.group_id.clone())).await;
                                 self.backend
                                     .ack_delivery(msg.message_id, self.user_id, self.device_id.clone(), msg.group_id)
                                     .await
@@ -345,7 +711,7 @@ impl GroupHandler {
                                     msg.success
                                 );
                                 if let Some(app_handle) = &self.app_handle {
-                                    emit_message_delivery_event(app_handle, msg.message_id, msg.success)
+                                    emit_message_delivery_event(app_handle, self.user_id, msg.message_id, msg.success)
                                         .await?;
                                 }
                             }
@@ -358,18 +724,20 @@ impl GroupHandler {
                                     msg.success
                                 );
                                 if let Some(app_handle) = &self.app_handle {
-                                    emit_welcome_message_event(app_handle, msg.message_id, msg.success)
+                                    emit_welcome_message_event(app_handle, self.user_id, msg.message_id, msg.success)
                                         .await?;
                                 }
                             }
                             group_microservice::stream_response::Response::WelcomeMessage(msg) => {
                                 log::info!("Received welcome message {} for user {}", msg.message_id, self.user_id);
+                                let _processing = self.groups.lock_processing().await;
                                 if let Err(e) = async {
                                     let message = MlsMessage::from_bytes(&msg.welcome_message)
                                         .map_err(|e| {
                                             GroupError::MessageDecodingError(e.to_string())
                                         })?;
-                                    self.join(&message).await?;
+                                    let group_id = self.join(&message).await?;
+                                    self.record_delivery(&group_id).await;
                                     self.backend
                                         .ack_delivery(msg.message_id, self.user_id, self.device_id.clone(), Vec::new())
                                         .await
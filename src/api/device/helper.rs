@@ -9,20 +9,23 @@ use mls_rs_codec::{MlsDecode, MlsEncode};
 use crate::api::device::{
     device::Device,
     types::{
+        audit::GroupSnapshot,
         config::CREDENTIAL_V1,
         custom_mls::credentials::{AccountCredential, DeviceCredential},
         errors::GroupError,
         extensions::{
             group_config::{
                 group_config::GroupConfig,
-                group_extension::{GroupConfigExtension, UpdateGroupConfigProposal},
+                group_extension::{
+                    GroupConfigExtension, UpdateGroupConfigProposal, read_group_config,
+                },
             },
             roster::{
                 proposals::{AddUserProposal, RemoveUserProposal},
                 roster_extension::RosterExtension,
             },
         },
-        group::{GroupId, MlsGroup},
+        group::{GroupId, GroupStorage, MlsGroup},
     },
 };
 
@@ -63,14 +66,7 @@ impl Device {
     /// - Returns: The current `GroupConfig` stored in the group's extensions
     /// - Errors: If the group config extension is missing or cannot be decoded
     pub(super) fn extract_group_config(&self, group: &MlsGroup) -> Result<GroupConfig, GroupError> {
-        let current_context = group.context();
-        let current_config: Option<GroupConfigExtension> =
-            current_context.extensions.get_as().map_err(|e| {
-                GroupError::ExtensionError(format!("Failed to get group config extension: {}", e))
-            })?;
-
-        let config_extension = current_config.ok_or(GroupError::ConfigurationNotFound)?;
-        Ok(config_extension.config)
+        read_group_config(&group.context().extensions)?.ok_or(GroupError::ConfigurationNotFound)
     }
 
     /// Get current group configuration
@@ -118,11 +114,14 @@ impl Device {
     /// Apply commit and store group state
     ///
     /// Applies any pending commit on the group, then persists the state
-    /// to the storage engine associated with this device.
+    /// to the storage engine associated with this device and records the
+    /// resulting changes in the group audit log.
     pub(super) async fn apply_and_store_commit(
         &self,
         group: &mut MlsGroup,
     ) -> Result<(), GroupError> {
+        let previous = capture_snapshot(group);
+
         group
             .apply_pending_commit()
             //.await
@@ -135,6 +134,8 @@ impl Device {
                 GroupError::StorageError(format!("Failed to write group to storage: {}", e))
             })?;
 
+        record_audit(&self.groups, previous.as_ref(), group, self.user_id()).await;
+
         Ok(())
     }
 
@@ -161,14 +162,18 @@ impl Device {
             new_user: user_credential.clone(),
         };
 
-        let mut config = self.extract_group_config(group)?;
-        config.add_member(user_credential.account_id.user_id);
-        let update_config_proposal = UpdateGroupConfigProposal { new_config: config };
-
         let mut commit_builder = group
             .commit_builder()
-            .custom_proposal(add_user_proposal.to_custom_proposal()?)
-            .custom_proposal(update_config_proposal.to_custom_proposal()?);
+            .custom_proposal(add_user_proposal.to_custom_proposal()?);
+
+        // Participants of a direct chat are in the config from the start
+        let mut config = self.extract_group_config(group)?;
+        if !config.is_member(user_credential.account_id.user_id) {
+            config.add_member(user_credential.account_id.user_id);
+            let update_config_proposal = UpdateGroupConfigProposal { new_config: config };
+            commit_builder =
+                commit_builder.custom_proposal(update_config_proposal.to_custom_proposal()?);
+        }
 
         for (_, key_package_bytes) in devices {
             let key_package = MlsMessage::from_bytes(&key_package_bytes).map_err(|e| {
@@ -287,3 +292,43 @@ impl Device {
         Ok(())
     }
 }
+
+/// State of the group before a commit, for the audit log
+///
+/// Failures are logged, the commit is processed without an audit entry.
+pub(super) fn capture_snapshot(group: &MlsGroup) -> Option<GroupSnapshot> {
+    GroupSnapshot::capture(group)
+        .inspect_err(|e| log::warn!("Failed to capture group state for audit log: {}", e))
+        .ok()
+}
+
+/// Persist the changes between `previous` and the current state of `group`
+///
+/// Used for own and received commits. Failures are logged and do not affect
+/// the already applied commit.
+pub(super) async fn record_audit(
+    groups: &GroupStorage,
+    previous: Option<&GroupSnapshot>,
+    group: &MlsGroup,
+    committer_id: u64,
+) {
+    let Some(previous) = previous else {
+        return;
+    };
+    let Some(current) = capture_snapshot(group) else {
+        return;
+    };
+    let changes = previous.changes_to(&current, committer_id);
+    if let Err(e) = groups
+        .messages
+        .save_audit_entries(
+            group.group_id(),
+            group.current_epoch(),
+            committer_id,
+            &changes,
+        )
+        .await
+    {
+        log::warn!("Failed to save audit log entries: {}", e);
+    }
+}
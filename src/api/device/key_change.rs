@@ -0,0 +1,196 @@
+//! Detection of contact key changes
+//!
+//! The first credential seen for a user is pinned. A certified credential
+//! fetched from the backend with another key replaces the pinned one, removes
+//! the verification of the contact and is reported to the user until
+//! acknowledged. Roster entries of groups never move the pin: a different key
+//! there is only reported, once per key, so groups listing an older and a
+//! newer key of the same user cannot swap the pin back and forth.
+
+use mls_rs_codec::MlsDecode;
+use moka::future::Cache;
+use tauri::AppHandle;
+
+use crate::api::device::{
+    db::KeyChange,
+    device::Device,
+    types::{
+        custom_mls::credentials::AccountCredential,
+        errors::GroupError,
+        extensions::roster::roster_extension::RosterExtension,
+        group::{GroupStorage, MlsGroup},
+    },
+};
+use crate::commands::events::emit_contact_key_changed_event;
+
+/// Setting enabling `block_on_key_change`
+const BLOCK_ON_KEY_CHANGE: &str = "block_on_key_change";
+
+/// Pin `credential` fetched from the backend, reporting a change of the user's key
+pub(super) async fn check_contact_key(
+    groups: &GroupStorage,
+    contacts_parsed_cache: &Cache<u64, AccountCredential>,
+    app_handle: Option<&AppHandle>,
+    account_id: u64,
+    credential: &AccountCredential,
+) -> Result<(), GroupError> {
+    let user_id = credential.account_id.user_id;
+    let Some(change) = groups.messages.pin_contact(credential).await? else {
+        return Ok(());
+    };
+    contacts_parsed_cache
+        .insert(user_id, credential.clone())
+        .await;
+
+    log::warn!(
+        "Key of user {} changed{}",
+        user_id,
+        if change.was_verified {
+            ", verification removed"
+        } else {
+            ""
+        }
+    );
+    emit_key_change(app_handle, account_id, &change).await
+}
+
+async fn emit_key_change(
+    app_handle: Option<&AppHandle>,
+    account_id: u64,
+    change: &KeyChange,
+) -> Result<(), GroupError> {
+    if let Some(app) = app_handle {
+        emit_contact_key_changed_event(app, account_id, change).await?;
+    }
+    Ok(())
+}
+
+/// Compare every roster entry of the group but the user's own with the pinned keys
+///
+/// Unknown users are pinned, other keys are reported without replacing the pin.
+pub(super) async fn check_roster_keys(
+    groups: &GroupStorage,
+    app_handle: Option<&AppHandle>,
+    group: &MlsGroup,
+    user_id: u64,
+) -> Result<(), GroupError> {
+    let roster = group
+        .context()
+        .extensions
+        .get_as::<RosterExtension>()
+        .map_err(|e| GroupError::ExtensionError(format!("Failed to get roster extension: {}", e)))?
+        .ok_or(GroupError::RosterNotFound)?;
+
+    for credential in roster
+        .roster
+        .iter()
+        .filter(|credential| credential.account_id.user_id != user_id)
+    {
+        if let Some(change) = groups.messages.check_roster_key(credential).await? {
+            log::warn!(
+                "Group roster lists another key of user {} than the pinned one",
+                change.user_id
+            );
+            emit_key_change(app_handle, user_id, &change).await?;
+        }
+    }
+    Ok(())
+}
+
+impl Device {
+    /// Fetch the credential of `user_id` from the backend
+    ///
+    /// The credential must be certified by a trusted server key and is
+    /// compared with the pinned one, see [`check_contact_key`].
+    pub(super) async fn refresh_contact(
+        &mut self,
+        user_id: u64,
+    ) -> Result<AccountCredential, GroupError> {
+        let user_credential = self
+            .backend
+            .as_mut()
+            .ok_or(GroupError::BackendError("Client is offline".to_string()))?
+            .get_user_credential(user_id)
+            .await
+            .map_err(|e| {
+                GroupError::BackendError(format!("Failed to fetch user credential: {}", e))
+            })?;
+        let credential = AccountCredential::mls_decode(&mut &*user_credential)?;
+        if credential.account_id.user_id != user_id {
+            return Err(GroupError::CredentialError(format!(
+                "Backend returned the credential of user {} for user {}",
+                credential.account_id.user_id, user_id
+            )));
+        }
+        self.server_keys.verify(&credential)?;
+
+        check_contact_key(
+            &self.groups,
+            &self.contacts_parsed_cache,
+            self.app_handle.as_ref(),
+            self.user_id(),
+            &credential,
+        )
+        .await?;
+        self.contacts_parsed_cache
+            .insert(user_id, credential.clone())
+            .await;
+        Ok(credential)
+    }
+
+    /// Key changes the user has not acknowledged yet, newest first
+    pub async fn get_key_changes(&self) -> Result<Vec<KeyChange>, GroupError> {
+        self.groups.messages.get_key_changes().await
+    }
+
+    /// Acknowledge the key change of `user_id`
+    pub async fn acknowledge_key_change(&self, user_id: u64) -> Result<(), GroupError> {
+        if !self
+            .groups
+            .messages
+            .acknowledge_key_change(user_id as i64)
+            .await?
+        {
+            return Err(GroupError::InvalidMessage(format!(
+                "No key change of user {} to acknowledge",
+                user_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Whether sending to a group is blocked while a member's key change is not acknowledged
+    pub async fn block_on_key_change(&self) -> Result<bool, GroupError> {
+        Ok(self
+            .groups
+            .messages
+            .get_setting(BLOCK_ON_KEY_CHANGE)
+            .await?
+            .is_some_and(|value| value == "1"))
+    }
+
+    pub async fn set_block_on_key_change(&self, enabled: bool) -> Result<(), GroupError> {
+        self.groups
+            .messages
+            .set_setting(BLOCK_ON_KEY_CHANGE, if enabled { "1" } else { "0" })
+            .await
+    }
+
+    /// Refuse to send to `members` if one of them has an unacknowledged key change
+    ///
+    /// Only applies with `block_on_key_change` enabled.
+    pub(super) async fn check_key_changes(&self, members: &[u64]) -> Result<(), GroupError> {
+        if !self.block_on_key_change().await? {
+            return Ok(());
+        }
+        let changed = self
+            .groups
+            .messages
+            .get_unacknowledged_key_changes(members)
+            .await?;
+        if !changed.is_empty() {
+            return Err(GroupError::UnacknowledgedKeyChange(changed));
+        }
+        Ok(())
+    }
+}
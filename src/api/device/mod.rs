@@ -1,10 +1,18 @@
+mod backup;
 mod connection;
 mod db;
 mod device;
+mod direct;
+mod drafts;
 mod group;
 mod handler;
 mod helper;
+mod key_change;
 pub mod mls_client;
+mod scheduler;
 pub mod types;
+mod verification;
 
+pub use backup::{UnpackedBackup, restore_backup, unpack_backup};
+pub use db::{Draft, KeyChange, ScheduledMessage, ScheduledStatus, ThreadStats};
 pub use device::*;
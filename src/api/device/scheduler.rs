@@ -0,0 +1,286 @@
+//! Local queue of messages sent at a chosen date
+//!
+//! Messages are stored unencrypted in the local database and encrypted only
+//! when they are due, so they always go out in the group's current epoch.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tauri::Manager;
+
+use crate::api::device::{
+    db::{ScheduledMessage, ScheduledStatus},
+    device::Device,
+    group::unix_now,
+    types::{
+        errors::GroupError, extensions::group_config::group_config::Permission, group::GroupId,
+        message::UserGroupMessage,
+    },
+};
+use crate::commands::events::{
+    emit_scheduled_message_failed_event, emit_scheduled_message_sent_event,
+};
+use crate::commands::session::SessionRegistry;
+
+/// How often the queue is checked for due messages
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(15);
+
+impl Device {
+    /// Queue a message to be sent to the group at `send_at`
+    ///
+    /// - Only new text messages can be scheduled
+    /// - Errors: `GroupError::InvalidMessage` for other kinds or a date in the past,
+    ///   `GroupError::ConfigError` without `send_messages`
+    pub async fn schedule_message(
+        &self,
+        group_id: &GroupId,
+        message: UserGroupMessage,
+        send_at: i64,
+    ) -> Result<ScheduledMessage, GroupError> {
+        let message_id = self.check_schedulable(group_id, &message, send_at).await?;
+        self.groups
+            .messages
+            .schedule_message(group_id.as_bytes(), message_id, send_at, &message)
+            .await?;
+
+        Ok(ScheduledMessage {
+            group_id: group_id.clone(),
+            message_id,
+            send_at,
+            message,
+            status: ScheduledStatus::Pending,
+            last_error: None,
+        })
+    }
+
+    /// Change the content or date of a queued message
+    ///
+    /// A failed message goes back to the queue. Messages already being sent
+    /// cannot be changed.
+    pub async fn reschedule_message(
+        &self,
+        group_id: &GroupId,
+        message_id: i64,
+        message: Option<UserGroupMessage>,
+        send_at: Option<i64>,
+    ) -> Result<ScheduledMessage, GroupError> {
+        let mut scheduled = self
+            .groups
+            .messages
+            .get_scheduled_message(group_id.as_bytes(), message_id)
+            .await?
+            .ok_or(GroupError::InvalidMessage(format!(
+                "Scheduled message {} not found",
+                message_id
+            )))?;
+        if let Some(message) = message {
+            scheduled.message = message;
+        }
+        if let Some(send_at) = send_at {
+            scheduled.send_at = send_at;
+        }
+        if self
+            .check_schedulable(group_id, &scheduled.message, scheduled.send_at)
+            .await?
+            != message_id
+        {
+            return Err(GroupError::InvalidMessage(
+                "Scheduled message id cannot change".to_string(),
+            ));
+        }
+
+        let updated = self
+            .groups
+            .messages
+            .update_scheduled_message(
+                group_id.as_bytes(),
+                message_id,
+                scheduled.send_at,
+                &scheduled.message,
+            )
+            .await?;
+        if !updated {
+            return Err(GroupError::InvalidMessage(format!(
+                "Scheduled message {} is already being sent",
+                message_id
+            )));
+        }
+
+        scheduled.status = ScheduledStatus::Pending;
+        scheduled.last_error = None;
+        Ok(scheduled)
+    }
+
+    /// Remove a message from the queue before it is sent
+    pub async fn cancel_scheduled_message(
+        &self,
+        group_id: &GroupId,
+        message_id: i64,
+    ) -> Result<(), GroupError> {
+        let cancelled = self
+            .groups
+            .messages
+            .cancel_scheduled_message(group_id.as_bytes(), message_id)
+            .await?;
+        if !cancelled {
+            return Err(GroupError::InvalidMessage(format!(
+                "Scheduled message {} not found or already being sent",
+                message_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Send every queued message that is due
+    ///
+    /// Messages are dated at the moment they go out. A send that failed for
+    /// lack of connection stays queued, any other failure marks the message
+    /// failed until it is edited or cancelled.
+    pub async fn send_scheduled_messages(&self) -> Result<(), GroupError> {
+        let now = unix_now()?;
+        let due = self
+            .groups
+            .messages
+            .claim_due_scheduled_messages(now)
+            .await?;
+
+        for scheduled in due {
+            let mut message = scheduled.message;
+            if let UserGroupMessage::TextMessage(text_message) = &mut message {
+                text_message.date = now;
+            }
+
+            let group_id = scheduled.group_id;
+            let message_id = scheduled.message_id;
+            match self
+                .send_message(&group_id, message_id as u64, message)
+                .await
+            {
+                Ok(()) => {
+                    self.groups
+                        .messages
+                        .finish_scheduled_message(group_id.as_bytes(), message_id)
+                        .await?;
+                    if let Some(app) = &self.app_handle
+                        && let Err(e) = emit_scheduled_message_sent_event(
+                            app,
+                            self.user_id(),
+                            &group_id,
+                            message_id,
+                            scheduled.send_at,
+                            now,
+                        )
+                        .await
+                    {
+                        log::error!("Failed to emit scheduled message sent event: {}", e);
+                    }
+                }
+                Err(e) => {
+                    let retry = matches!(
+                        e,
+                        GroupError::BackendError(_) | GroupError::ConnectionError(_)
+                    );
+                    let error = e.to_string();
+                    log::warn!("Scheduled message {} not sent: {}", message_id, error);
+                    self.groups
+                        .messages
+                        .fail_scheduled_message(group_id.as_bytes(), message_id, &error, retry)
+                        .await?;
+                    if let Some(app) = &self.app_handle
+                        && let Err(e) = emit_scheduled_message_failed_event(
+                            app,
+                            self.user_id(),
+                            &group_id,
+                            message_id,
+                            scheduled.send_at,
+                            &error,
+                            retry,
+                        )
+                        .await
+                    {
+                        log::error!("Failed to emit scheduled message failed event: {}", e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the task sending queued messages when they are due
+    ///
+    /// The same task sends drafts to the user's other devices.
+    ///
+    /// Must be called once the device is in its session. The first check runs
+    /// right away so messages due while the app was closed go out on startup.
+    /// The task stops when the account logs out or logs in again.
+    pub fn start_scheduler(&self) {
+        let Some(app_handle) = self.app_handle.clone() else {
+            return;
+        };
+        let account = self.account.clone();
+        let user_id = self.user_id();
+        let messages = self.groups.messages.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = messages.fail_interrupted_scheduled_messages().await {
+                log::error!("Failed to recover scheduled messages: {}", e);
+            }
+
+            let mut interval = tokio::time::interval(SCHEDULER_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(sessions) = app_handle.try_state::<SessionRegistry>() else {
+                    break;
+                };
+                let Ok(session) = sessions.get(Some(user_id)).await else {
+                    break;
+                };
+                let device = session.group_user.read().await;
+                match device.as_ref() {
+                    // Тот же вход в аккаунт: Account пересоздаётся при каждом логине
+                    Some(device) if Arc::ptr_eq(&device.account, &account) => {
+                        if let Err(e) = device.send_scheduled_messages().await {
+                            log::error!("Scheduled messages error: {}", e);
+                        }
+                        if let Err(e) = device.sync_drafts().await {
+                            log::warn!("Failed to sync drafts to other devices: {}", e);
+                        }
+                    }
+                    _ => break,
+                }
+            }
+        });
+    }
+
+    /// Check a message can be queued, returning its id
+    async fn check_schedulable(
+        &self,
+        group_id: &GroupId,
+        message: &UserGroupMessage,
+        send_at: i64,
+    ) -> Result<i64, GroupError> {
+        let message_id = match message {
+            UserGroupMessage::TextMessage(text_message) if text_message.edit_date.is_none() => {
+                text_message.message_id
+            }
+            _ => {
+                return Err(GroupError::InvalidMessage(
+                    "Only new messages can be scheduled".to_string(),
+                ));
+            }
+        };
+        if send_at <= unix_now()? {
+            return Err(GroupError::InvalidMessage(
+                "Scheduled date must be in the future".to_string(),
+            ));
+        }
+
+        let group_config = self.get_group_config(group_id).await?;
+        if !group_config.has_permission(self.user_id(), Permission::SendMessages) {
+            return Err(GroupError::ConfigError(
+                "User is not allowed to send messages".to_string(),
+            ));
+        }
+        Ok(message_id)
+    }
+}
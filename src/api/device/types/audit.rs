@@ -0,0 +1,204 @@
+//! Audit trail of configuration, roster and leaf changes applied by commits
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use mls_rs_codec::MlsDecode;
+use serde::{Deserialize, Serialize};
+
+use crate::api::device::types::{
+    config::CREDENTIAL_V1,
+    custom_mls::credentials::DeviceCredential,
+    errors::GroupError,
+    extensions::{
+        group_config::{
+            group_config::{ConfigChange, GroupConfig},
+            group_extension::read_group_config,
+        },
+        roster::roster_extension::RosterExtension,
+    },
+    group::MlsGroup,
+};
+
+/// Part of the group state an audit entry refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditCategory {
+    Config,
+    Roster,
+    Leaf,
+}
+
+impl AuditCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditCategory::Config => "config",
+            AuditCategory::Roster => "roster",
+            AuditCategory::Leaf => "leaf",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "config" => Some(AuditCategory::Config),
+            "roster" => Some(AuditCategory::Roster),
+            "leaf" => Some(AuditCategory::Leaf),
+            _ => None,
+        }
+    }
+}
+
+/// A single field-level change recorded for a group epoch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupAuditEntry {
+    pub epoch: u64,
+    pub committer_id: u64,
+    pub category: AuditCategory,
+    pub change: ConfigChange,
+    pub timestamp: i64,
+}
+
+/// State of a group captured before a commit is applied
+///
+/// Comparing two snapshots yields the changes introduced by the commit
+/// that moved the group from the first to the second.
+#[derive(Debug, Clone, Default)]
+pub struct GroupSnapshot {
+    config: Option<GroupConfig>,
+    roster: Vec<u64>,
+    // leaf index -> (device label, signature key)
+    leaves: BTreeMap<u32, (String, Vec<u8>)>,
+}
+
+impl GroupSnapshot {
+    /// Capture config, roster and leaves of the group at its current epoch
+    pub fn capture(group: &MlsGroup) -> Result<Self, GroupError> {
+        let extensions = &group.context().extensions;
+        let config = read_group_config(extensions)?;
+        let roster = extensions
+            .get_as::<RosterExtension>()
+            .map_err(|e| {
+                GroupError::ExtensionError(format!("Failed to get roster extension: {}", e))
+            })?
+            .map(|roster| {
+                roster
+                    .roster
+                    .iter()
+                    .map(|user| user.account_id.user_id)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut leaves = BTreeMap::new();
+        for member in group.roster().members() {
+            let label = device_label(&member.signing_identity.credential)?;
+            leaves.insert(
+                member.index,
+                (
+                    label,
+                    member.signing_identity.signature_key.as_bytes().to_vec(),
+                ),
+            );
+        }
+
+        Ok(Self {
+            config,
+            roster,
+            leaves,
+        })
+    }
+
+    /// Group config at the time of the capture
+    pub fn config(&self) -> Option<&GroupConfig> {
+        self.config.as_ref()
+    }
+
+    /// List the changes between this snapshot and `new`, attributed to `committer_id`
+    pub fn changes_to(
+        &self,
+        new: &GroupSnapshot,
+        committer_id: u64,
+    ) -> Vec<(AuditCategory, ConfigChange)> {
+        let mut changes = Vec::new();
+
+        if let (Some(old_config), Some(new_config)) = (&self.config, &new.config) {
+            let validation = old_config.validate_changes(new_config, committer_id);
+            changes.extend(
+                validation
+                    .changes
+                    .into_iter()
+                    .map(|change| (AuditCategory::Config, change)),
+            );
+        }
+
+        if self.roster != new.roster {
+            changes.push((
+                AuditCategory::Roster,
+                ConfigChange {
+                    field: "roster".to_string(),
+                    old_value: format!("{:?}", self.roster),
+                    new_value: format!("{:?}", new.roster),
+                },
+            ));
+        }
+
+        let indexes = self
+            .leaves
+            .keys()
+            .chain(new.leaves.keys())
+            .copied()
+            .collect::<BTreeSet<u32>>();
+        for index in indexes {
+            let old_leaf = self.leaves.get(&index);
+            let new_leaf = new.leaves.get(&index);
+            if old_leaf == new_leaf {
+                continue;
+            }
+            let new_value = match (old_leaf, new_leaf) {
+                (Some((old_label, _)), Some((new_label, _))) if old_label == new_label => {
+                    format!("{} (key updated)", new_label)
+                }
+                (_, Some((new_label, _))) => new_label.clone(),
+                (_, None) => String::new(),
+            };
+            changes.push((
+                AuditCategory::Leaf,
+                ConfigChange {
+                    field: format!("leaf[{}]", index),
+                    old_value: old_leaf.map(|(label, _)| label.clone()).unwrap_or_default(),
+                    new_value,
+                },
+            ));
+        }
+
+        changes
+    }
+}
+
+/// Resolve the user id owning the leaf at `index`
+pub fn leaf_user_id(group: &MlsGroup, index: u32) -> Result<u64, GroupError> {
+    let member = group
+        .member_at_index(index)
+        .ok_or(GroupError::UserIsNotInRoster)?;
+    let custom = member
+        .signing_identity
+        .credential
+        .as_custom()
+        .ok_or(GroupError::CredentialMissmatch)?;
+    Ok(DeviceCredential::mls_decode(&mut &*custom.data)?
+        .device_id
+        .user_id)
+}
+
+fn device_label(credential: &mls_rs::identity::Credential) -> Result<String, GroupError> {
+    if credential.credential_type() != CREDENTIAL_V1 {
+        return Ok("unknown".to_string());
+    }
+    let custom = credential
+        .as_custom()
+        .ok_or(GroupError::CredentialMissmatch)?;
+    let device_credential = DeviceCredential::mls_decode(&mut &*custom.data)?;
+    Ok(format!(
+        "{}:{}",
+        device_credential.device_id.user_id, device_credential.device_id.device_id
+    ))
+}
@@ -0,0 +1,427 @@
+//! Encrypted archive of the local state of one device
+//!
+//! Layout: magic, format version (big endian `u16`), PBKDF2 salt and a nonce
+//! prefix, then the archive encrypted in chunks of [`CHUNK_SIZE`] bytes. Each
+//! chunk is sealed with AES-GCM on its own, with the header as associated
+//! data and a nonce made of the prefix, the chunk counter and a flag set on
+//! the last chunk, so chunks cannot be reordered, dropped or cut off.
+//!
+//! The archive holds the [`BackupManifest`] prefixed with its length, then
+//! each file as its name, its size and its bytes, then an empty name. It is
+//! written and read as a stream, files never have to fit in memory.
+
+use aes_gcm::aead::rand_core::{OsRng, RngCore};
+use aes_gcm::{
+    Aes256Gcm, Nonce,
+    aead::{Aead, KeyInit, Payload},
+};
+use aws_lc_rs::pbkdf2;
+use mls_rs_codec::{MlsDecode, MlsEncode, MlsSize};
+use std::io::{self, BufRead, Read, Write};
+use std::num::NonZeroU32;
+
+use crate::api::device::types::errors::GroupError;
+
+pub const BACKUP_VERSION: u16 = 2;
+const MAGIC: &[u8; 8] = b"SHIPBAK\0";
+const PBKDF2_ITERATIONS: u32 = 600_000;
+const SALT_LEN: usize = 16;
+const NONCE_PREFIX_LEN: usize = 7;
+const HEADER_LEN: usize = MAGIC.len() + 2 + SALT_LEN + NONCE_PREFIX_LEN;
+/// Plaintext bytes per chunk, the last chunk may be shorter
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+const MAX_NAME_LEN: usize = 255;
+const MAX_MANIFEST_LEN: usize = 64 * 1024;
+
+/// Names of the files in an archive
+pub const ACCOUNT_FILE: &str = "account";
+pub const GROUP_DB_FILE: &str = "group.db";
+pub const MLS_DB_FILE: &str = "mls.db";
+pub const CONTACTS_DB_FILE: &str = "contacts.db";
+pub const VOICE_FILE: &str = "voice.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, MlsSize, MlsEncode, MlsDecode)]
+pub struct BackupManifest {
+    pub version: u16,
+    pub created_at: u64,
+    pub user_id: u64,
+    pub device_id: String,
+}
+
+/// Writes an archive to `W`, see the module docs for the format
+pub struct BackupWriter<W: Write> {
+    inner: W,
+    chunks: ChunkCipher,
+    // Открытый текст текущего чанка
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> BackupWriter<W> {
+    /// Start an archive of `manifest` encrypted with a key derived from `password`
+    pub fn new(
+        mut inner: W,
+        password: &str,
+        manifest: &BackupManifest,
+    ) -> Result<Self, GroupError> {
+        let mut header = [0u8; HEADER_LEN];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        header[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&BACKUP_VERSION.to_be_bytes());
+        // Соль и префикс nonce
+        OsRng.fill_bytes(&mut header[MAGIC.len() + 2..]);
+        inner.write_all(&header).map_err(io_error)?;
+
+        let mut writer = Self {
+            inner,
+            chunks: ChunkCipher::new(password, header)?,
+            buffer: Vec::with_capacity(2 * CHUNK_SIZE),
+        };
+        let manifest = manifest.mls_encode_to_vec()?;
+        writer.write_plain(&(manifest.len() as u32).to_be_bytes())?;
+        writer.write_plain(&manifest)?;
+        Ok(writer)
+    }
+
+    /// Add a file of `len` bytes read from `data`
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        data: &mut impl Read,
+        len: u64,
+    ) -> Result<(), GroupError> {
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(GroupError::InvalidMessage(format!(
+                "Invalid backup file name {:?}",
+                name
+            )));
+        }
+        self.write_plain(&(name.len() as u16).to_be_bytes())?;
+        self.write_plain(name.as_bytes())?;
+        self.write_plain(&len.to_be_bytes())?;
+
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        let mut remaining = len;
+        while remaining > 0 {
+            let want = remaining.min(CHUNK_SIZE as u64) as usize;
+            let read = data.read(&mut buf[..want]).map_err(io_error)?;
+            if read == 0 {
+                return Err(GroupError::StorageError(format!(
+                    "{} is shorter than {} bytes",
+                    name, len
+                )));
+            }
+            self.write_plain(&buf[..read])?;
+            remaining -= read as u64;
+        }
+        Ok(())
+    }
+
+    /// Close the archive, returning the underlying writer
+    pub fn finish(mut self) -> Result<W, GroupError> {
+        self.write_plain(&0u16.to_be_bytes())?;
+        let sealed = self.chunks.seal(&self.buffer, true)?;
+        self.inner.write_all(&sealed).map_err(io_error)?;
+        self.inner.flush().map_err(io_error)?;
+        Ok(self.inner)
+    }
+
+    // Чанк уходит, только когда за ним есть ещё данные: последний запечатывает finish
+    fn write_plain(&mut self, data: &[u8]) -> Result<(), GroupError> {
+        self.buffer.extend_from_slice(data);
+        while self.buffer.len() > CHUNK_SIZE {
+            let sealed = self.chunks.seal(&self.buffer[..CHUNK_SIZE], false)?;
+            self.inner.write_all(&sealed).map_err(io_error)?;
+            self.buffer.drain(..CHUNK_SIZE);
+        }
+        Ok(())
+    }
+}
+
+/// Reads an archive written by [`BackupWriter`]
+///
+/// Every chunk is authenticated before its bytes are returned; a truncated
+/// archive is only detected at its end, so nothing read should be used
+/// before [`BackupReader::next_file`] returned `None`.
+pub struct BackupReader<R: BufRead> {
+    inner: R,
+    chunks: ChunkCipher,
+    // Открытый текст текущего чанка и позиция чтения в нём
+    plain: Vec<u8>,
+    position: usize,
+    last_chunk: bool,
+}
+
+impl<R: BufRead> BackupReader<R> {
+    /// Open an archive and read its manifest
+    ///
+    /// - Errors: `GroupError::InvalidMessage` if it is not a supported backup,
+    ///   `GroupError::CryptoError` for a wrong password or a corrupted file
+    pub fn open(mut inner: R, password: &str) -> Result<(Self, BackupManifest), GroupError> {
+        let mut header = [0u8; HEADER_LEN];
+        if read_full(&mut inner, &mut header)? < HEADER_LEN || &header[..MAGIC.len()] != MAGIC {
+            return Err(GroupError::InvalidMessage("Not a backup file".to_string()));
+        }
+        let version = u16::from_be_bytes([header[MAGIC.len()], header[MAGIC.len() + 1]]);
+        if version != BACKUP_VERSION {
+            return Err(GroupError::InvalidMessage(format!(
+                "Unsupported backup version {}",
+                version
+            )));
+        }
+
+        let mut reader = Self {
+            inner,
+            chunks: ChunkCipher::new(password, header)?,
+            plain: Vec::new(),
+            position: 0,
+            last_chunk: false,
+        };
+        let len = u32::from_be_bytes(reader.read_array()?) as usize;
+        if len > MAX_MANIFEST_LEN {
+            return Err(GroupError::InvalidMessage(
+                "Backup manifest is too large".to_string(),
+            ));
+        }
+        let manifest = BackupManifest::mls_decode(&mut &*reader.read_vec(len)?)?;
+        if manifest.version != version {
+            return Err(GroupError::InvalidMessage(
+                "Backup manifest does not match its header".to_string(),
+            ));
+        }
+        Ok((reader, manifest))
+    }
+
+    /// Name and size of the next file, `None` once the whole archive was read
+    pub fn next_file(&mut self) -> Result<Option<(String, u64)>, GroupError> {
+        let name_len = u16::from_be_bytes(self.read_array()?) as usize;
+        if name_len == 0 {
+            if !self.last_chunk
+                || self.position != self.plain.len()
+                || !self.inner.fill_buf().map_err(io_error)?.is_empty()
+            {
+                return Err(GroupError::InvalidMessage(
+                    "Unexpected data at the end of the backup".to_string(),
+                ));
+            }
+            return Ok(None);
+        }
+        if name_len > MAX_NAME_LEN {
+            return Err(GroupError::InvalidMessage(
+                "Backup file name is too long".to_string(),
+            ));
+        }
+        let name = String::from_utf8(self.read_vec(name_len)?)
+            .map_err(|e| GroupError::InvalidMessage(format!("Invalid backup file name: {}", e)))?;
+        let len = u64::from_be_bytes(self.read_array()?);
+        Ok(Some((name, len)))
+    }
+
+    /// Copy the current file of `len` bytes to `to`
+    pub fn read_file(&mut self, to: &mut impl Write, len: u64) -> Result<(), GroupError> {
+        let mut remaining = len;
+        while remaining > 0 {
+            let available = self.fill()?;
+            let take = remaining.min(available as u64) as usize;
+            to.write_all(&self.plain[self.position..self.position + take])
+                .map_err(io_error)?;
+            self.position += take;
+            remaining -= take as u64;
+        }
+        Ok(())
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], GroupError> {
+        let mut bytes = [0u8; N];
+        self.read_file(&mut &mut bytes[..], N as u64)?;
+        Ok(bytes)
+    }
+
+    fn read_vec(&mut self, len: usize) -> Result<Vec<u8>, GroupError> {
+        let mut bytes = Vec::with_capacity(len);
+        self.read_file(&mut bytes, len as u64)?;
+        Ok(bytes)
+    }
+
+    /// Decrypt the next chunk once the current one is consumed, returns the bytes left
+    fn fill(&mut self) -> Result<usize, GroupError> {
+        while self.position == self.plain.len() {
+            if self.last_chunk {
+                return Err(GroupError::InvalidMessage("Backup ends early".to_string()));
+            }
+            let mut sealed = vec![0u8; CHUNK_SIZE + TAG_LEN];
+            let read = read_full(&mut self.inner, &mut sealed)?;
+            let last = read < sealed.len() || self.inner.fill_buf().map_err(io_error)?.is_empty();
+            self.plain = self.chunks.open(&sealed[..read], last)?;
+            self.position = 0;
+            self.last_chunk = last;
+        }
+        Ok(self.plain.len() - self.position)
+    }
+}
+
+/// AES-GCM over the chunks of one archive
+struct ChunkCipher {
+    cipher: Aes256Gcm,
+    header: [u8; HEADER_LEN],
+    counter: u32,
+}
+
+impl ChunkCipher {
+    fn new(password: &str, header: [u8; HEADER_LEN]) -> Result<Self, GroupError> {
+        let salt = &header[MAGIC.len() + 2..MAGIC.len() + 2 + SALT_LEN];
+        Ok(Self {
+            cipher: cipher(password, salt)?,
+            header,
+            counter: 0,
+        })
+    }
+
+    fn seal(&mut self, chunk: &[u8], last: bool) -> Result<Vec<u8>, GroupError> {
+        let nonce = self.next_nonce(last)?;
+        self.cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: chunk,
+                    aad: &self.header,
+                },
+            )
+            .map_err(|e| GroupError::CryptoError(format!("Backup encryption failed: {}", e)))
+    }
+
+    fn open(&mut self, chunk: &[u8], last: bool) -> Result<Vec<u8>, GroupError> {
+        let nonce = self.next_nonce(last)?;
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: chunk,
+                    aad: &self.header,
+                },
+            )
+            .map_err(|_| GroupError::CryptoError("Wrong password or corrupted backup".to_string()))
+    }
+
+    // Префикс из заголовка, номер чанка, флаг последнего чанка
+    fn next_nonce(&mut self, last: bool) -> Result<[u8; 12], GroupError> {
+        let mut nonce = [0u8; 12];
+        nonce[..NONCE_PREFIX_LEN].copy_from_slice(&self.header[HEADER_LEN - NONCE_PREFIX_LEN..]);
+        nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&self.counter.to_be_bytes());
+        nonce[11] = last as u8;
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| GroupError::InvalidMessage("Backup is too large".to_string()))?;
+        Ok(nonce)
+    }
+}
+
+fn cipher(password: &str, salt: &[u8]) -> Result<Aes256Gcm, GroupError> {
+    let mut key = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ITERATIONS).expect("iterations are not zero"),
+        salt,
+        password.as_bytes(),
+        &mut key,
+    );
+    Aes256Gcm::new_from_slice(&key)
+        .map_err(|e| GroupError::CryptoError(format!("Failed to create cipher: {}", e)))
+}
+
+/// Read until `buf` is full or the input ends, returns the bytes read
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize, GroupError> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(io_error(e)),
+        }
+    }
+    Ok(read)
+}
+
+fn io_error(e: io::Error) -> GroupError {
+    GroupError::StorageError(format!("Backup I/O failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest() -> BackupManifest {
+        BackupManifest {
+            version: BACKUP_VERSION,
+            created_at: 1_700_000_000,
+            user_id: 42,
+            device_id: "device".to_string(),
+        }
+    }
+
+    fn read_all(data: &[u8], password: &str) -> Result<Vec<(String, Vec<u8>)>, GroupError> {
+        let (mut reader, read_manifest) = BackupReader::open(data, password)?;
+        assert_eq!(read_manifest, manifest());
+        let mut files = Vec::new();
+        while let Some((name, len)) = reader.next_file()? {
+            let mut file = Vec::new();
+            reader.read_file(&mut file, len)?;
+            files.push((name, file));
+        }
+        Ok(files)
+    }
+
+    #[test]
+    fn test_backup_archive_roundtrip() {
+        // Несколько чанков и файл ровно на границе чанка
+        let large: Vec<u8> = (0..5 * CHUNK_SIZE as u32 + 123)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let aligned = vec![7u8; CHUNK_SIZE];
+        let files = vec![
+            (ACCOUNT_FILE.to_string(), vec![1, 2, 3]),
+            (GROUP_DB_FILE.to_string(), large),
+            (MLS_DB_FILE.to_string(), aligned),
+            (VOICE_FILE.to_string(), Vec::new()),
+        ];
+
+        let mut writer = BackupWriter::new(Vec::new(), "correct horse", &manifest()).unwrap();
+        for (name, data) in &files {
+            writer
+                .add_file(name, &mut data.as_slice(), data.len() as u64)
+                .unwrap();
+        }
+        let encrypted = writer.finish().unwrap();
+        assert_eq!(read_all(&encrypted, "correct horse").unwrap(), files);
+
+        assert!(matches!(
+            read_all(&encrypted, "wrong"),
+            Err(GroupError::CryptoError(_))
+        ));
+        // Заголовок аутентифицирован: изменённый nonce ломает расшифровку
+        let mut tampered = encrypted.clone();
+        tampered[HEADER_LEN - 1] ^= 1;
+        assert!(read_all(&tampered, "correct horse").is_err());
+        // Обрезка по границе чанка и лишние данные в конце
+        let cut = HEADER_LEN + 3 * (CHUNK_SIZE + TAG_LEN);
+        assert!(read_all(&encrypted[..cut], "correct horse").is_err());
+        let mut extended = encrypted.clone();
+        extended.push(0);
+        assert!(read_all(&extended, "correct horse").is_err());
+        assert!(matches!(
+            read_all(&encrypted[..HEADER_LEN - 1], "correct horse"),
+            Err(GroupError::InvalidMessage(_))
+        ));
+    }
+
+    #[test]
+    fn test_short_source_is_rejected() {
+        let mut writer = BackupWriter::new(Vec::new(), "pw", &manifest()).unwrap();
+        assert!(
+            writer
+                .add_file(GROUP_DB_FILE, &mut &[1u8, 2][..], 3)
+                .is_err()
+        );
+    }
+}
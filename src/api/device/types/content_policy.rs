@@ -0,0 +1,364 @@
+//! Content classification used to enforce the group media settings
+//! and validation of structured message content
+
+use crate::api::device::types::{
+    errors::GroupError, extensions::group_config::group_config::GroupConfig,
+    message::GroupTextMessage, text_entity::TextEntityKind,
+};
+
+/// Kind of an attachment, detected from its leading bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Image,
+    /// Small or animated WebP images, see [`is_sticker`]
+    Sticker,
+    Gif,
+    Voice,
+    Video,
+    Other,
+}
+
+impl MediaKind {
+    /// Sniff the media kind from the file signature
+    ///
+    /// The file name is never trusted, a renamed file is classified by content.
+    pub fn detect(data: &[u8]) -> Self {
+        if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            return MediaKind::Gif;
+        }
+        if data.len() >= 12 && &data[..4] == b"RIFF" {
+            return match &data[8..12] {
+                b"WEBP" if is_sticker(data) => MediaKind::Sticker,
+                b"WEBP" => MediaKind::Image,
+                b"WAVE" => MediaKind::Voice,
+                b"AVI " => MediaKind::Video,
+                _ => MediaKind::Other,
+            };
+        }
+        if data.starts_with(b"OggS")
+            || data.starts_with(b"ID3")
+            || data.starts_with(b"fLaC")
+            || data.starts_with(b"#!AMR")
+            || (data.len() >= 2 && data[0] == 0xFF && data[1] & 0xE0 == 0xE0)
+        {
+            return MediaKind::Voice;
+        }
+        if data.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+            // Matroska / WebM
+            return MediaKind::Video;
+        }
+        if data.len() >= 12 && &data[4..8] == b"ftyp" {
+            return match &data[8..12] {
+                b"M4A " | b"M4B " | b"M4P " => MediaKind::Voice,
+                b"avif" | b"avis" | b"heic" | b"heix" | b"mif1" | b"msf1" => MediaKind::Image,
+                _ => MediaKind::Video,
+            };
+        }
+        if data.starts_with(&[0x89, b'P', b'N', b'G'])
+            || data.starts_with(&[0xFF, 0xD8, 0xFF])
+            || data.starts_with(b"BM")
+        {
+            return MediaKind::Image;
+        }
+        MediaKind::Other
+    }
+
+    fn setting_name(&self) -> Option<&'static str> {
+        match self {
+            MediaKind::Sticker => Some("stickers"),
+            MediaKind::Gif => Some("GIFs"),
+            MediaKind::Voice => Some("voice messages"),
+            MediaKind::Video => Some("video messages"),
+            MediaKind::Image | MediaKind::Other => None,
+        }
+    }
+}
+
+/// Largest WebP file sent as a sticker
+const MAX_STICKER_SIZE: usize = 512 * 1024;
+/// Largest side of a static sticker, in pixels
+const MAX_STICKER_SIDE: u32 = 512;
+
+/// Whether a WebP file is a sticker: animated, or small enough in both
+/// file size and dimensions. Other WebP files are regular images.
+fn is_sticker(data: &[u8]) -> bool {
+    if data.len() > MAX_STICKER_SIZE || data.len() < 30 {
+        return false;
+    }
+    let le24 = |b: &[u8]| u32::from(b[0]) | u32::from(b[1]) << 8 | u32::from(b[2]) << 16;
+    let (width, height) = match &data[12..16] {
+        // Расширенный формат: флаг анимации и размер холста
+        b"VP8X" => {
+            if data[20] & 0x02 != 0 {
+                return true;
+            }
+            (le24(&data[24..27]) + 1, le24(&data[27..30]) + 1)
+        }
+        b"VP8 " if data[23..26] == [0x9D, 0x01, 0x2A] => (
+            u32::from(u16::from_le_bytes([data[26], data[27]]) & 0x3FFF),
+            u32::from(u16::from_le_bytes([data[28], data[29]]) & 0x3FFF),
+        ),
+        b"VP8L" if data[20] == 0x2F => {
+            let bits = u32::from_le_bytes([data[21], data[22], data[23], data[24]]);
+            ((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1)
+        }
+        _ => return false,
+    };
+    width <= MAX_STICKER_SIDE && height <= MAX_STICKER_SIDE
+}
+
+/// Top-level domains linked without a scheme besides two-letter country codes
+const LINK_TLDS: &[&str] = &[
+    "com", "net", "org", "info", "biz", "edu", "gov", "io", "dev", "app", "xyz", "online", "site",
+    "shop", "tech", "store", "blog", "pro", "рф",
+];
+
+/// Check whether `text` contains something that renders as a link
+///
+/// Besides URLs with a scheme this matches bare domains like `example.com`.
+pub fn contains_link(text: &str) -> bool {
+    text.split_whitespace().any(|word| {
+        let word = word
+            .trim_start_matches(|c: char| !c.is_alphanumeric())
+            .to_lowercase();
+        word.contains("://")
+            || word.starts_with("www.")
+            || word.starts_with("mailto:")
+            || is_bare_domain(&word)
+    })
+}
+
+/// `example.com`, `sub.example.ru/path`; not `e.g.` or `v1.2`
+fn is_bare_domain(word: &str) -> bool {
+    let host = word.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = host.split(':').next().unwrap_or_default();
+    let host = host.trim_end_matches(|c: char| !c.is_alphanumeric());
+    let labels: Vec<&str> = host.split('.').collect();
+    let Some(tld) = labels.last() else {
+        return false;
+    };
+    labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        })
+        && ((tld.len() == 2 && tld.chars().all(|c| c.is_ascii_alphabetic()))
+            || LINK_TLDS.contains(tld))
+}
+
+/// Validate a message against the content settings of the group
+///
+/// - Errors: `GroupError::ContentNotAllowed` naming the forbidden content type
+pub fn check_content_policy(
+    config: &GroupConfig,
+    message: &GroupTextMessage,
+) -> Result<(), GroupError> {
+    if let Some(media) = &message.media {
+        let kind = MediaKind::detect(media);
+        let allowed = match kind {
+            MediaKind::Sticker => config.allow_stickers,
+            MediaKind::Gif => config.allow_gifs,
+            MediaKind::Voice => config.allow_voice_messages,
+            MediaKind::Video => config.allow_video_messages,
+            MediaKind::Image | MediaKind::Other => true,
+        };
+        if !allowed && let Some(name) = kind.setting_name() {
+            return Err(GroupError::ContentNotAllowed(name.to_string()));
+        }
+    }
+
+    let has_link_entity = message
+        .entities
+        .iter()
+        .any(|entity| matches!(entity.kind, TextEntityKind::Link { .. }));
+    if !config.allow_links && (has_link_entity || contains_link(&message.text)) {
+        return Err(GroupError::ContentNotAllowed("links".to_string()));
+    }
+
+    Ok(())
+}
+
+pub const MAX_MENTIONS: usize = 50;
+
+/// Validate the mentions of a message against its text and the group members
+///
+/// Ranges must be non-empty, inside the text and on character boundaries.
+/// - Errors: `GroupError::InvalidMessage` describing the first invalid mention
+pub fn check_mentions(config: &GroupConfig, message: &GroupTextMessage) -> Result<(), GroupError> {
+    if message.mentions.len() > MAX_MENTIONS {
+        return Err(GroupError::InvalidMessage(format!(
+            "Message has more than {} mentions",
+            MAX_MENTIONS
+        )));
+    }
+    for mention in &message.mentions {
+        let start = mention.offset as usize;
+        let end = start + mention.length as usize;
+        if mention.length == 0
+            || end > message.text.len()
+            || !message.text.is_char_boundary(start)
+            || !message.text.is_char_boundary(end)
+        {
+            return Err(GroupError::InvalidMessage(format!(
+                "Mention range {}..{} is outside the text",
+                start, end
+            )));
+        }
+        if mention.user_id < 0 || !config.members.contains(&(mention.user_id as u64)) {
+            return Err(GroupError::InvalidMessage(format!(
+                "Mentioned user {} is not a member",
+                mention.user_id
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::device::types::message::Mention;
+
+    #[test]
+    fn test_media_kind_detection() {
+        assert_eq!(MediaKind::detect(b"GIF89a\x01\x00"), MediaKind::Gif);
+        assert_eq!(MediaKind::detect(b"RIFF\0\0\0\0WEBPVP8 "), MediaKind::Image);
+        assert_eq!(MediaKind::detect(b"OggS\0\x02"), MediaKind::Voice);
+        assert_eq!(
+            MediaKind::detect(b"\0\0\0\x18ftypmp42\0\0\0\0"),
+            MediaKind::Video
+        );
+        assert_eq!(
+            MediaKind::detect(b"\0\0\0\x18ftypM4A \0\0\0\0"),
+            MediaKind::Voice
+        );
+        assert_eq!(
+            MediaKind::detect(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A]),
+            MediaKind::Image
+        );
+        assert_eq!(MediaKind::detect(b"plain text"), MediaKind::Other);
+    }
+
+    fn webp(chunk: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = b"RIFF\0\0\0\0WEBP".to_vec();
+        data.extend_from_slice(chunk);
+        data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn test_webp_sticker_detection() {
+        // VP8X: флаги, 3 резервных байта, ширина-1 и высота-1 по 24 бита
+        let extended = |flags: u8, side: u32| {
+            let mut payload = vec![flags, 0, 0, 0];
+            payload.extend_from_slice(&(side - 1).to_le_bytes()[..3]);
+            payload.extend_from_slice(&(side - 1).to_le_bytes()[..3]);
+            webp(b"VP8X", &payload)
+        };
+        assert_eq!(MediaKind::detect(&extended(0, 512)), MediaKind::Sticker);
+        assert_eq!(MediaKind::detect(&extended(0, 1920)), MediaKind::Image);
+        assert_eq!(MediaKind::detect(&extended(0x02, 1920)), MediaKind::Sticker);
+
+        let lossy = |side: u16| {
+            let mut payload = vec![0, 0, 0, 0x9D, 0x01, 0x2A];
+            payload.extend_from_slice(&side.to_le_bytes());
+            payload.extend_from_slice(&side.to_le_bytes());
+            webp(b"VP8 ", &payload)
+        };
+        assert_eq!(MediaKind::detect(&lossy(256)), MediaKind::Sticker);
+        assert_eq!(MediaKind::detect(&lossy(1024)), MediaKind::Image);
+
+        let lossless = |side: u32| {
+            let bits = (side - 1) | (side - 1) << 14;
+            let mut payload = vec![0x2F];
+            payload.extend_from_slice(&bits.to_le_bytes());
+            payload.extend_from_slice(&[0; 5]);
+            webp(b"VP8L", &payload)
+        };
+        assert_eq!(MediaKind::detect(&lossless(512)), MediaKind::Sticker);
+        assert_eq!(MediaKind::detect(&lossless(513)), MediaKind::Image);
+
+        // Большой файл не стикер даже при малых размерах
+        let mut large = lossy(256);
+        large.resize(MAX_STICKER_SIZE + 1, 0);
+        assert_eq!(MediaKind::detect(&large), MediaKind::Image);
+    }
+
+    #[test]
+    fn test_contains_link() {
+        assert!(contains_link("see https://example.com"));
+        assert!(contains_link("(www.example.com)"));
+        assert!(contains_link("go to example.com."));
+        assert!(contains_link("docs at sub.example.org/path?q=1"));
+        assert!(contains_link("сайт пример.рф"));
+        assert!(contains_link("example.de:8080"));
+        assert!(!contains_link("no links here, just a sentence."));
+        assert!(!contains_link("e.g. this, i.e. that"));
+        assert!(!contains_link("version 1.2.3 is out"));
+        assert!(!contains_link("Mr.Smith arrived"));
+        assert!(!contains_link("@alice"));
+    }
+
+    #[test]
+    fn test_content_policy_blocks_disallowed_media() {
+        let mut config = GroupConfig::new(1, "Test Group".to_string(), 1);
+        config.set_allow_gifs(false);
+        config.set_allow_links(false);
+
+        let mut message = GroupTextMessage {
+            message_id: 1,
+            group_id: String::new(),
+            sender_id: 1,
+            date: 0,
+            text: "hello".to_string(),
+            media: Some(b"GIF89a\x01\x00".to_vec()),
+            media_name: Some("cat.png".to_string()),
+            reply_message_id: None,
+            expires: None,
+            edit_date: None,
+            mentions: Vec::new(),
+            entities: Vec::new(),
+        };
+        assert!(check_content_policy(&config, &message).is_err());
+
+        message.media = None;
+        assert!(check_content_policy(&config, &message).is_ok());
+
+        message.text = "https://example.com".to_string();
+        assert!(check_content_policy(&config, &message).is_err());
+    }
+
+    #[test]
+    fn test_mentions_must_target_members_and_text() {
+        let config = GroupConfig::new(1, "Test Group".to_string(), 1);
+        let mut message = GroupTextMessage {
+            message_id: 1,
+            group_id: String::new(),
+            sender_id: 1,
+            date: 0,
+            text: "привет @alice".to_string(),
+            media: None,
+            media_name: None,
+            reply_message_id: None,
+            expires: None,
+            edit_date: None,
+            mentions: vec![Mention {
+                user_id: 1,
+                offset: 13,
+                length: 6,
+            }],
+            entities: Vec::new(),
+        };
+        assert!(check_mentions(&config, &message).is_ok());
+
+        message.mentions[0].offset = 1;
+        assert!(check_mentions(&config, &message).is_err());
+
+        message.mentions[0].offset = 13;
+        message.mentions[0].user_id = 2;
+        assert!(check_mentions(&config, &message).is_err());
+    }
+}
@@ -1,12 +1,18 @@
 //! Credential types and related functionality
 
+use std::sync::Arc;
+
+use mls_rs::CipherSuiteProvider;
 use mls_rs_codec::{MlsDecode, MlsEncode, MlsSize};
 use mls_rs_core::{
     crypto::SignaturePublicKey,
     identity::{Credential, CredentialType, CustomCredential, MlsCredential},
 };
 
-use crate::api::device::types::{config::CREDENTIAL_V1, errors::GroupError};
+use crate::api::device::types::{
+    config::{CREDENTIAL_V1, cipher_suite},
+    errors::GroupError,
+};
 
 #[derive(Debug, Clone, MlsSize, MlsDecode, MlsEncode)]
 pub struct AccountId {
@@ -22,6 +28,85 @@ pub struct AccountCredential {
     pub cert: Vec<u8>,
 }
 
+/// Structure signed by the server in `AccountCredential.cert`
+///
+/// The server's certificate layout is not documented in this repository,
+/// this is the MLS encoding the client assumes. The account's own
+/// certificate is checked against it at login.
+#[derive(MlsSize, MlsEncode)]
+pub struct AccountCertTBS<'a> {
+    pub account_id: &'a AccountId,
+    pub public_key: &'a SignaturePublicKey,
+}
+
+/// Server keys trusted to certify accounts
+///
+/// Several keys can be trusted at once so the server can rotate its key.
+/// Without any key every certificate is rejected, unless checks were turned
+/// off explicitly with [`TrustedServerKeys::skip_checks`].
+#[derive(Debug, Clone, Default)]
+pub struct TrustedServerKeys {
+    keys: Arc<Vec<SignaturePublicKey>>,
+    skip_checks: bool,
+}
+
+impl TrustedServerKeys {
+    pub fn new(keys: Vec<SignaturePublicKey>) -> Self {
+        Self {
+            keys: Arc::new(keys),
+            skip_checks: false,
+        }
+    }
+
+    /// Accept every certificate, for servers the user opted out of checks for
+    pub fn skip_checks() -> Self {
+        Self {
+            keys: Arc::default(),
+            skip_checks: true,
+        }
+    }
+
+    pub fn keys(&self) -> &[SignaturePublicKey] {
+        &self.keys
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn skips_checks(&self) -> bool {
+        self.skip_checks
+    }
+
+    /// Check that the cert of `credential` is signed by one of the keys
+    ///
+    /// - Errors: `GroupError::CredentialError` if no trusted key signed it
+    pub fn verify(&self, credential: &AccountCredential) -> Result<(), GroupError> {
+        if self.skip_checks {
+            return Ok(());
+        }
+        let tbs = AccountCertTBS {
+            account_id: &credential.account_id,
+            public_key: &credential.public_key,
+        }
+        .mls_encode_to_vec()?;
+
+        let cipher_suite = cipher_suite();
+        if self
+            .keys
+            .iter()
+            .any(|key| cipher_suite.verify(key, &credential.cert, &tbs).is_ok())
+        {
+            Ok(())
+        } else {
+            Err(GroupError::CredentialError(format!(
+                "Certificate of user {} is not signed by a trusted server key",
+                credential.account_id.user_id
+            )))
+        }
+    }
+}
+
 /// Credential for an MLS member (device owned by a user)
 
 #[derive(Debug, Clone, MlsSize, MlsDecode, MlsEncode)]
@@ -59,3 +144,48 @@ impl MlsCredential for DeviceCredential {
         )))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trusted_server_keys() {
+        let cipher_suite = cipher_suite();
+        let (old_signer, old_key) = cipher_suite.signature_key_generate().unwrap();
+        let (new_signer, new_key) = cipher_suite.signature_key_generate().unwrap();
+        let (_, user_key) = cipher_suite.signature_key_generate().unwrap();
+
+        let mut credential = AccountCredential {
+            account_id: AccountId {
+                user_id: 7,
+                public_address: "user7".to_string(),
+            },
+            public_key: user_key,
+            cert: Vec::new(),
+        };
+        let tbs = AccountCertTBS {
+            account_id: &credential.account_id,
+            public_key: &credential.public_key,
+        }
+        .mls_encode_to_vec()
+        .unwrap();
+        credential.cert = cipher_suite.sign(&new_signer, &tbs).unwrap();
+
+        // Сертификат нового ключа принимается, пока доверены оба ключа
+        let keys = TrustedServerKeys::new(vec![old_key.clone(), new_key]);
+        assert!(keys.verify(&credential).is_ok());
+        assert!(
+            TrustedServerKeys::new(vec![old_key.clone()])
+                .verify(&credential)
+                .is_err()
+        );
+        // Без ключей сертификат не принимается, если проверки не отключены явно
+        assert!(TrustedServerKeys::default().verify(&credential).is_err());
+        assert!(TrustedServerKeys::skip_checks().verify(&credential).is_ok());
+
+        // Подпись над другим ключом пользователя не подходит
+        credential.cert = cipher_suite.sign(&old_signer, b"other").unwrap();
+        assert!(keys.verify(&credential).is_err());
+    }
+}
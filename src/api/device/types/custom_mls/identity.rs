@@ -2,11 +2,13 @@
 
 use crate::api::device::types::{
     config::{CREDENTIAL_V1, cipher_suite},
-    custom_mls::credentials::{DeviceCredential, DeviceCredentialTBS},
+    custom_mls::credentials::{DeviceCredential, DeviceCredentialTBS, TrustedServerKeys},
     errors::GroupError,
     extensions::roster::roster_extension::RosterExtension,
 };
-use mls_rs::{CipherSuiteProvider, ExtensionList, IdentityProvider};
+use mls_rs::{
+    CipherSuiteProvider, ExtensionList, IdentityProvider, extension::built_in::ExternalSendersExt,
+};
 use mls_rs_codec::MlsDecode;
 use mls_rs_codec::MlsEncode;
 use mls_rs_core::{
@@ -15,8 +17,18 @@ use mls_rs_core::{
 };
 
 /// Custom identity provider that validates member credentials against the user roster
-#[derive(Debug, Clone, Copy)]
-pub struct CustomIdentityProvider;
+///
+/// The roster entry owning a member must carry a certificate of a trusted server key.
+#[derive(Debug, Clone)]
+pub struct CustomIdentityProvider {
+    server_keys: TrustedServerKeys,
+}
+
+impl CustomIdentityProvider {
+    pub fn new(server_keys: TrustedServerKeys) -> Self {
+        Self { server_keys }
+    }
+}
 
 impl IdentityProvider for CustomIdentityProvider {
     type Error = GroupError;
@@ -39,17 +51,8 @@ impl IdentityProvider for CustomIdentityProvider {
             .flatten()
             .ok_or(GroupError::RosterNotFound)?;
 
-        // Validate that we have a custom credential of the correct type
-        let Credential::Custom(custom) = &signing_identity.credential else {
-            return Err(GroupError::CredentialMissmatch);
-        };
-
-        if custom.credential_type != CREDENTIAL_V1 {
-            return Err(GroupError::CredentialMissmatch);
-        }
-
-        // Decode the member credential
-        let member = DeviceCredential::mls_decode(&mut &*custom.data)?;
+        // Decode the member credential, checking it has the correct type
+        let member = decode_member(signing_identity)?;
 
         // Verify the member credential signature
         // This ensures the member was authorized by the user who owns them
@@ -65,16 +68,17 @@ impl IdentityProvider for CustomIdentityProvider {
             .map_err(|_| GroupError::MlsError("Verify error".to_string()))?;
 
         // Verify that the user who owns this member is in the authorized roster
-        let user_in_roster = roster
+        let user = roster
             .roster
             .iter()
-            .any(|u| u.public_key == member.user_public_key);
+            .find(|u| {
+                u.public_key == member.user_public_key
+                    && u.account_id.user_id == member.device_id.user_id
+            })
+            .ok_or(GroupError::UserIsNotInRoster)?;
 
-        if !user_in_roster {
-            return Err(GroupError::UserIsNotInRoster);
-        }
-
-        Ok(())
+        // and that the server certified the user's key
+        self.server_keys.verify(user)
     }
 
     fn identity(
@@ -82,14 +86,7 @@ impl IdentityProvider for CustomIdentityProvider {
         signing_identity: &SigningIdentity,
         _extensions: &ExtensionList,
     ) -> Result<Vec<u8>, Self::Error> {
-        let Credential::Custom(custom) = &signing_identity.credential else {
-            return Err(GroupError::CredentialMissmatch);
-        };
-
-        if custom.credential_type != CREDENTIAL_V1 {
-            return Err(GroupError::CredentialMissmatch);
-        }
-        let member = DeviceCredential::mls_decode(&mut &*custom.data)?;
+        let member = decode_member(signing_identity)?;
         Ok(member.device_id.mls_encode_to_vec()?)
     }
 
@@ -97,21 +94,196 @@ impl IdentityProvider for CustomIdentityProvider {
         vec![CREDENTIAL_V1]
     }
 
+    /// A leaf can only be updated by a device of the same user
+    ///
+    /// The account key may change only if the roster already lists the new key
+    /// for that user, roster entries being certified by the server.
     fn valid_successor(
         &self,
-        _predecessor: &SigningIdentity,
-        _successor: &SigningIdentity,
-        _extensions: &ExtensionList,
+        predecessor: &SigningIdentity,
+        successor: &SigningIdentity,
+        extensions: &ExtensionList,
     ) -> Result<bool, Self::Error> {
-        Ok(true)
+        let predecessor = decode_member(predecessor)?;
+        let successor = decode_member(successor)?;
+
+        if predecessor.device_id.user_id != successor.device_id.user_id {
+            return Ok(false);
+        }
+        if predecessor.user_public_key == successor.user_public_key {
+            return Ok(true);
+        }
+
+        // Смена ключа аккаунта
+        let roster = extensions
+            .get_as::<RosterExtension>()
+            .ok()
+            .flatten()
+            .ok_or(GroupError::RosterNotFound)?;
+        Ok(roster.roster.iter().any(|user| {
+            user.account_id.user_id == successor.device_id.user_id
+                && user.public_key == successor.user_public_key
+        }))
     }
 
+    /// External senders must be listed in the group's `ExternalSendersExt`
+    ///
+    /// The list is part of the group context, only members can change it.
     fn validate_external_sender(
         &self,
-        _identity: &SigningIdentity,
+        identity: &SigningIdentity,
         _timestamp: Option<MlsTime>,
-        _extensions: Option<&ExtensionList>,
+        extensions: Option<&ExtensionList>,
     ) -> Result<(), Self::Error> {
+        let allowed = extensions
+            .and_then(|extensions| extensions.get_as::<ExternalSendersExt>().ok().flatten())
+            .is_some_and(|external_senders| {
+                external_senders
+                    .allowed_senders
+                    .iter()
+                    .any(|sender| sender.signature_key == identity.signature_key)
+            });
+        if !allowed {
+            return Err(GroupError::CredentialError(
+                "External sender is not in the group allowlist".to_string(),
+            ));
+        }
         Ok(())
     }
 }
+
+/// Decode the member credential of a signing identity
+fn decode_member(signing_identity: &SigningIdentity) -> Result<DeviceCredential, GroupError> {
+    let Credential::Custom(custom) = &signing_identity.credential else {
+        return Err(GroupError::CredentialMissmatch);
+    };
+    if custom.credential_type != CREDENTIAL_V1 {
+        return Err(GroupError::CredentialMissmatch);
+    }
+    Ok(DeviceCredential::mls_decode(&mut &*custom.data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::device::types::custom_mls::credentials::{
+        AccountCredential, AccountId, DeviceId,
+    };
+    use mls_rs_core::{crypto::SignaturePublicKey, identity::MlsCredential};
+
+    fn key() -> SignaturePublicKey {
+        cipher_suite().signature_key_generate().unwrap().1
+    }
+
+    fn member(user_id: u64, user_public_key: &SignaturePublicKey) -> SigningIdentity {
+        let credential = DeviceCredential {
+            device_id: DeviceId {
+                user_id,
+                device_id: "device".to_string(),
+            },
+            user_public_key: user_public_key.clone(),
+            signature: Vec::new(),
+        };
+        SigningIdentity::new(credential.into_credential().unwrap(), key())
+    }
+
+    fn roster(users: &[(u64, &SignaturePublicKey)]) -> ExtensionList {
+        let roster = RosterExtension {
+            roster: users
+                .iter()
+                .map(|(user_id, public_key)| AccountCredential {
+                    account_id: AccountId {
+                        user_id: *user_id,
+                        public_address: format!("user{}", user_id),
+                    },
+                    public_key: (*public_key).clone(),
+                    cert: Vec::new(),
+                })
+                .collect(),
+        };
+        let mut extensions = ExtensionList::new();
+        extensions.set_from(roster).unwrap();
+        extensions
+    }
+
+    #[test]
+    fn test_successor_of_another_user_is_rejected() {
+        let provider = CustomIdentityProvider::new(TrustedServerKeys::default());
+        let (alice, bob) = (key(), key());
+        let extensions = roster(&[(1, &alice), (2, &bob)]);
+
+        assert!(
+            provider
+                .valid_successor(&member(1, &alice), &member(1, &alice), &extensions)
+                .unwrap()
+        );
+        assert!(
+            !provider
+                .valid_successor(&member(1, &alice), &member(2, &bob), &extensions)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_key_rotation_requires_roster_entry() {
+        let provider = CustomIdentityProvider::new(TrustedServerKeys::default());
+        let (old_key, new_key) = (key(), key());
+
+        // Новый ключ ещё не в ростере
+        assert!(
+            !provider
+                .valid_successor(
+                    &member(1, &old_key),
+                    &member(1, &new_key),
+                    &roster(&[(1, &old_key)])
+                )
+                .unwrap()
+        );
+        // Ключ в ростере у другого пользователя не подходит
+        assert!(
+            !provider
+                .valid_successor(
+                    &member(1, &old_key),
+                    &member(1, &new_key),
+                    &roster(&[(1, &old_key), (2, &new_key)])
+                )
+                .unwrap()
+        );
+        assert!(
+            provider
+                .valid_successor(
+                    &member(1, &old_key),
+                    &member(1, &new_key),
+                    &roster(&[(1, &new_key)])
+                )
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_external_sender_outside_allowlist_is_rejected() {
+        let provider = CustomIdentityProvider::new(TrustedServerKeys::default());
+        let server = member(0, &key());
+        let other = member(0, &key());
+        let mut extensions = ExtensionList::new();
+        extensions
+            .set_from(ExternalSendersExt::new(vec![server.clone()]))
+            .unwrap();
+
+        assert!(
+            provider
+                .validate_external_sender(&server, None, Some(&extensions))
+                .is_ok()
+        );
+        assert!(
+            provider
+                .validate_external_sender(&other, None, Some(&extensions))
+                .is_err()
+        );
+        assert!(
+            provider
+                .validate_external_sender(&server, None, None)
+                .is_err()
+        );
+    }
+}
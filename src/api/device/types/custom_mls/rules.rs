@@ -12,11 +12,16 @@ use mls_rs::{
 };
 
 use crate::api::device::types::{
-    config::CREDENTIAL_V1, custom_mls::credentials::DeviceCredential, errors::GroupError,
+    config::CREDENTIAL_V1,
+    custom_mls::credentials::{DeviceCredential, TrustedServerKeys},
+    errors::GroupError,
 };
 
+use crate::api::device::types::extensions::group_config::group_config::Permission;
 use crate::api::device::types::extensions::group_config::group_extension::{
-    GroupConfigExtension, UPDATE_GROUP_CONFIG_PROPOSAL_V1, UpdateGroupConfigProposal,
+    GROUP_CONFIG_EXTENSION_V1, GroupConfigExtension, MIGRATE_GROUP_CONFIG_PROPOSAL_V1,
+    UPDATE_GROUP_CONFIG_PROPOSAL_V1, UPDATE_GROUP_CONFIG_PROPOSAL_V2, UpdateGroupConfigProposal,
+    needs_config_migration, read_group_config,
 };
 use crate::api::device::types::extensions::roster::{
     proposals::{AddUserProposal, RemoveUserProposal},
@@ -24,8 +29,18 @@ use crate::api::device::types::extensions::roster::{
 };
 
 /// Custom MLS rules that handle our AddUser proposals and maintain the user roster
-#[derive(Debug, Clone, Copy)]
-pub struct CustomMlsRules;
+///
+/// Users are only added with a certificate of a trusted server key.
+#[derive(Debug, Clone)]
+pub struct CustomMlsRules {
+    server_keys: TrustedServerKeys,
+}
+
+impl CustomMlsRules {
+    pub fn new(server_keys: TrustedServerKeys) -> Self {
+        Self { server_keys }
+    }
+}
 
 impl MlsRules for CustomMlsRules {
     type Error = GroupError;
@@ -63,8 +78,9 @@ impl MlsRules for CustomMlsRules {
             }
         };
 
-        let config_extension: Option<GroupConfigExtension> =
-            context.extensions.get_as().ok().flatten();
+        // V1 конфиг читается с конвертацией, как V2 он записывается миграцией или обновлением
+        let config = read_group_config(&context.extensions)?;
+        let migrating = needs_config_migration(&context.extensions);
 
         let mut roster: RosterExtension = context
             .extensions
@@ -79,26 +95,36 @@ impl MlsRules for CustomMlsRules {
             .filter(|p| p.proposal.proposal_type() == ADD_USER_PROPOSAL_V1);
 
         for add_user_info in add_user_proposals {
-            if let Some(ref config_extension) = config_extension {
-                if !config_extension
-                    .config
-                    .has_permission(sender_user_id, "manage_members")
+            if let Some(ref config) = config {
+                let add_user = AddUserProposal::from_custom_proposal(&add_user_info.proposal)?;
+                self.server_keys.verify(&add_user.new_user)?;
+                let new_user_id = add_user.new_user.account_id.user_id;
+                // В личном чате оба участника заданы в конфиге при создании,
+                // любой из них может добавить устройства собеседника
+                let direct_chat_peer = config.direct_chat_peer(sender_user_id) == Some(new_user_id)
+                    && !roster
+                        .roster
+                        .iter()
+                        .any(|user| user.account_id.user_id == new_user_id);
+
+                if !direct_chat_peer
+                    && !config.has_permission(sender_user_id, Permission::ManageMembers)
                 {
                     return Err(GroupError::ConfigError(
                         "User is not allowed to manage members".to_string(),
                     ));
                 }
 
-                let add_user = AddUserProposal::from_custom_proposal(&add_user_info.proposal)?;
-                if config_extension
-                    .config
-                    .is_banned(add_user.new_user.account_id.user_id)
-                {
+                if config.is_disbanded() {
+                    return Err(GroupError::ConfigError("Group is disbanded".to_string()));
+                }
+
+                if config.is_banned(new_user_id) {
                     return Err(GroupError::ConfigError(
                         "User is banned from this group".to_string(),
                     ));
                 }
-                if config_extension.config.is_full() {
+                if !direct_chat_peer && config.is_full() {
                     return Err(GroupError::ConfigError("Group is full".to_string()));
                 }
                 roster.roster.push(add_user.new_user);
@@ -111,23 +137,31 @@ impl MlsRules for CustomMlsRules {
             .iter()
             .filter(|p| p.proposal.proposal_type() == REMOVE_USER_PROPOSAL_V1);
 
+        let mut removed_users = Vec::new();
         for remove_user_info in remove_user_proposals {
             let remove_user = RemoveUserProposal::from_custom_proposal(&remove_user_info.proposal)?;
 
             if remove_user.user_id != sender_user_id
-                && let Some(ref config_extension) = config_extension
-                && !config_extension
-                    .config
-                    .has_permission(sender_user_id, "manage_members")
+                && let Some(ref config) = config
+                && !config.has_permission(sender_user_id, Permission::ManageMembers)
             {
                 return Err(GroupError::ConfigError(
                     "User is not allowed to manage members".to_string(),
                 ));
             }
+            if remove_user.user_id != sender_user_id
+                && let Some(ref config) = config
+                && !config.can_manage_member(sender_user_id, remove_user.user_id)
+            {
+                return Err(GroupError::ConfigError(
+                    "User cannot remove a member of equal or higher rank".to_string(),
+                ));
+            }
 
             roster
                 .roster
                 .retain(|user| user.account_id.user_id != remove_user.user_id);
+            removed_users.push(remove_user.user_id);
             extensions_modified = true;
         }
 
@@ -136,21 +170,60 @@ impl MlsRules for CustomMlsRules {
         }
 
         // Обрабатываем GroupConfigExtension
-        let mut group_config_updated = config_extension.clone();
+        let mut group_config_updated = config.clone();
+        // Конфиг переписывается только миграцией или обновлением, иначе остаётся в своей версии
+        let mut config_rewritten = false;
+
+        if proposals
+            .custom_proposals()
+            .iter()
+            .any(|p| p.proposal.proposal_type() == UPDATE_GROUP_CONFIG_PROPOSAL_V1)
+        {
+            return Err(GroupError::ConfigValidationError(
+                "Group config V1 updates are no longer supported".to_string(),
+            ));
+        }
+
+        let migrate_requested = proposals
+            .custom_proposals()
+            .iter()
+            .any(|p| p.proposal.proposal_type() == MIGRATE_GROUP_CONFIG_PROPOSAL_V1);
+        if migrate_requested {
+            if !migrating {
+                return Err(GroupError::ConfigValidationError(
+                    "Group config is already migrated".to_string(),
+                ));
+            }
+            if !config
+                .as_ref()
+                .is_some_and(|config| config.is_member(sender_user_id))
+            {
+                return Err(GroupError::ConfigError(
+                    "Only members can migrate the group config".to_string(),
+                ));
+            }
+            config_rewritten = true;
+        }
 
         let update_config_proposals = proposals
             .custom_proposals()
             .iter()
-            .filter(|p| p.proposal.proposal_type() == UPDATE_GROUP_CONFIG_PROPOSAL_V1);
+            .filter(|p| p.proposal.proposal_type() == UPDATE_GROUP_CONFIG_PROPOSAL_V2);
 
         for update_info in update_config_proposals {
             let update_config =
                 UpdateGroupConfigProposal::from_custom_proposal(&update_info.proposal)?;
 
             if let Some(ref current_config) = group_config_updated {
-                let validation_result = current_config
-                    .config
-                    .validate_changes(&update_config.new_config, sender_user_id);
+                if update_config.new_config.creator_id != current_config.creator_id
+                    && current_config.creator_id != sender_user_id
+                {
+                    return Err(GroupError::ConfigError(
+                        "Only the owner can transfer ownership".to_string(),
+                    ));
+                }
+                let validation_result =
+                    current_config.validate_changes(&update_config.new_config, sender_user_id);
                 if !validation_result.valid {
                     return Err(GroupError::ConfigValidationError(
                         "Invalid config changes".to_string(),
@@ -158,14 +231,25 @@ impl MlsRules for CustomMlsRules {
                 }
             }
 
-            group_config_updated = Some(GroupConfigExtension {
-                config: update_config.new_config,
-            });
-            extensions_modified = true;
+            group_config_updated = Some(update_config.new_config);
+            config_rewritten = true;
+        }
+
+        // Владелец может выйти только вместе с передачей группы
+        if let Some(ref config) = group_config_updated
+            && removed_users.contains(&config.creator_id)
+            && !roster.roster.is_empty()
+        {
+            return Err(GroupError::ConfigError(
+                "Owner must transfer ownership before leaving".to_string(),
+            ));
         }
 
-        if let Some(config) = group_config_updated {
-            new_extensions.set_from(config)?;
+        if config_rewritten && let Some(config) = group_config_updated {
+            new_extensions.set_from(GroupConfigExtension { config })?;
+            if migrating {
+                new_extensions.remove(GROUP_CONFIG_EXTENSION_V1);
+            }
             extensions_modified = true;
         }
 
@@ -80,6 +80,18 @@ pub enum GroupError {
 
     #[error("Database error: {0}")]
     DatabaseError(String),
+
+    #[error("Slow mode is active, wait {0} seconds before sending")]
+    SlowMode(u64),
+
+    #[error("This group does not allow {0}")]
+    ContentNotAllowed(String),
+
+    #[error("Keys of users {0:?} changed, acknowledge the change before sending")]
+    UnacknowledgedKeyChange(Vec<u64>),
+
+    #[error("Backup is older than the state already delivered for {0} groups")]
+    StaleBackup(usize),
 }
 
 impl IntoAnyError for GroupError {
@@ -2,12 +2,24 @@ use mls_rs_codec::{MlsDecode, MlsEncode, MlsSize};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+pub use super::permissions::{Permission, Permissions};
+
 // DateTime структура
-#[derive(Debug, Clone, Serialize, Deserialize, MlsSize, MlsDecode, MlsEncode, Copy)]
+#[derive(
+    Debug, Clone, Serialize, Deserialize, PartialEq, Eq, MlsSize, MlsDecode, MlsEncode, Copy,
+)]
 pub struct DateTime {
     pub timestamp: u64,
 }
 
+// Закреплённое сообщение
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, MlsSize, MlsDecode, MlsEncode)]
+pub struct PinnedMessage {
+    pub message_id: u64,
+    pub pinned_by: u64,
+    pub pinned_at: DateTime,
+}
+
 // Видимость группы
 #[derive(Debug, Clone, Serialize, Deserialize, MlsSize, MlsDecode, MlsEncode)]
 #[repr(u8)]
@@ -26,125 +38,45 @@ pub enum JoinMode {
     RequestToJoin = 3,
 }
 
-// Права участника
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, MlsSize, MlsDecode, MlsEncode)]
-pub struct Permissions {
-    pub manage_members: bool,
-    pub send_messages: bool,
-    pub delete_messages: bool,
-    pub rename_group: bool,
-    pub manage_permissions: bool,
-    pub pin_messages: bool,
-    pub manage_admins: bool,
-}
-
-impl Permissions {
-    pub fn default() -> Self {
-        Self {
-            manage_members: true,
-            send_messages: true,
-            delete_messages: false,
-            rename_group: false,
-            manage_permissions: false,
-            pin_messages: false,
-            manage_admins: false,
-        }
-    }
-
-    pub fn admin() -> Self {
-        Self {
-            manage_members: true,
-            send_messages: true,
-            delete_messages: true,
-            rename_group: true,
-            manage_permissions: true,
-            pin_messages: true,
-            manage_admins: true,
-        }
-    }
+/// Built-in role granting full rights, holders are kept in `GroupConfig::admins`
+pub const ADMIN_ROLE: &str = "admin";
+pub const MODERATOR_ROLE: &str = "moderator";
+/// Role assigned to members when they join
+pub const MEMBER_ROLE: &str = "member";
+pub const READER_ROLE: &str = "reader";
 
-    pub fn moderator() -> Self {
-        Self {
-            manage_members: true,
-            send_messages: true,
-            delete_messages: true,
-            rename_group: false,
-            manage_permissions: false,
-            pin_messages: true,
-            manage_admins: false,
-        }
-    }
+/// Name of the hidden group linking the devices of one user, see `GroupConfig::is_self_channel`
+pub const SELF_CHANNEL_NAME: &str = "self-channel";
+/// Name of the two-party groups backing direct chats, see `GroupConfig::is_direct_chat`
+pub const DIRECT_CHAT_NAME: &str = "direct-chat";
 
-    pub fn member() -> Self {
-        Self {
-            manage_members: false,
-            send_messages: true,
-            delete_messages: false,
-            rename_group: false,
-            manage_permissions: false,
-            pin_messages: false,
-            manage_admins: false,
-        }
-    }
+// Именованная роль участника
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, MlsSize, MlsDecode, MlsEncode)]
+pub struct Role {
+    pub name: String,
+    pub color: u32, // 0xRRGGBB
+    pub rank: u32,  // a member may only manage members of a lower rank
+    pub permissions: Permissions,
+}
 
-    pub fn reader() -> Self {
+impl Role {
+    pub fn new<S: Into<String>>(name: S, color: u32, rank: u32, permissions: Permissions) -> Self {
         Self {
-            manage_members: false,
-            send_messages: false,
-            delete_messages: false,
-            rename_group: false,
-            manage_permissions: false,
-            pin_messages: false,
-            manage_admins: false,
+            name: name.into(),
+            color,
+            rank,
+            permissions,
         }
     }
 
-    pub fn with_manage_members(mut self, value: bool) -> Self {
-        self.manage_members = value;
-        self
-    }
-
-    pub fn with_send_messages(mut self, value: bool) -> Self {
-        self.send_messages = value;
-        self
-    }
-
-    pub fn with_delete_messages(mut self, value: bool) -> Self {
-        self.delete_messages = value;
-        self
-    }
-
-    pub fn with_rename_group(mut self, value: bool) -> Self {
-        self.rename_group = value;
-        self
-    }
-
-    pub fn with_manage_permissions(mut self, value: bool) -> Self {
-        self.manage_permissions = value;
-        self
-    }
-
-    pub fn with_pin_messages(mut self, value: bool) -> Self {
-        self.pin_messages = value;
-        self
-    }
-
-    pub fn with_manage_admins(mut self, value: bool) -> Self {
-        self.manage_admins = value;
-        self
-    }
-
-    pub fn has_permission(&self, permission: &str) -> bool {
-        match permission {
-            "manage_members" => self.manage_members,
-            "send_messages" => self.send_messages,
-            "delete_messages" => self.delete_messages,
-            "rename_group" => self.rename_group,
-            "manage_permissions" => self.manage_permissions,
-            "pin_messages" => self.pin_messages,
-            "manage_admins" => self.manage_admins,
-            _ => false,
-        }
+    /// Roles every new group starts with, replacing the old hard-coded presets
+    pub fn defaults() -> Vec<Role> {
+        vec![
+            Role::new(ADMIN_ROLE, 0xE53935, 300, Permissions::admin()),
+            Role::new(MODERATOR_ROLE, 0x1E88E5, 200, Permissions::moderator()),
+            Role::new(MEMBER_ROLE, 0x9E9E9E, 100, Permissions::member()),
+            Role::new(READER_ROLE, 0x757575, 50, Permissions::reader()),
+        ]
     }
 }
 
@@ -168,10 +100,12 @@ pub struct GroupConfig {
     pub creator_id: u64, // Например, идентификатор владельца группы
     pub members: Vec<u64>,
     pub admins: Vec<u64>,
-    pub permissions: HashMap<u64, Permissions>, // member_id -> Permissions
-    pub default_permissions: Permissions,
-    pub banned: Vec<u64>,              // Список ID забаненных пользователей
-    pub muted: HashMap<u64, DateTime>, // member_id -> mute_until
+    pub permissions: HashMap<u64, Permissions>, // member_id -> override of role permissions
+    pub default_permissions: Permissions,       // members without roles or override
+    pub roles: Vec<Role>,
+    pub member_roles: HashMap<u64, Vec<String>>, // member_id -> role names
+    pub banned: Vec<u64>,                        // Список ID забаненных пользователей
+    pub muted: HashMap<u64, DateTime>,           // member_id -> mute_until
 
     // Content and media
     pub description: Option<String>, // Опциональное описание
@@ -181,8 +115,8 @@ pub struct GroupConfig {
     pub banner: Option<Vec<u8>>, // Опциональный баннер как байты
 
     // Group settings
-    pub pinned_message_id: Option<u64>,
-    pub slow_mode_delay: Option<u32>, // in seconds
+    pub pinned_messages: Vec<PinnedMessage>, // oldest pin first
+    pub slow_mode_delay: Option<u32>,        // in seconds
 
     // Additional settings
     pub allow_stickers: bool,
@@ -190,13 +124,15 @@ pub struct GroupConfig {
     pub allow_voice_messages: bool,
     pub allow_video_messages: bool,
     pub allow_links: bool,
+
+    // Set once the owner disbands the group, the group is read-only afterwards
+    pub disbanded_at: Option<DateTime>,
 }
 
 impl GroupConfig {
     pub fn new(group_id: u64, group_name: String, creator_id: u64) -> Self {
-        let permissions = Permissions::admin();
-        let mut permissions_map = HashMap::new();
-        permissions_map.insert(creator_id, permissions);
+        let mut member_roles = HashMap::new();
+        member_roles.insert(creator_id, vec![ADMIN_ROLE.to_string()]);
         let default_permissions = Permissions::member();
         let now = DateTime {
             timestamp: std::time::SystemTime::now()
@@ -216,27 +152,49 @@ impl GroupConfig {
             creator_id,
             members: vec![creator_id],
             admins: vec![creator_id],
-            permissions: permissions_map,
+            permissions: HashMap::new(),
             default_permissions,
+            roles: Role::defaults(),
+            member_roles,
             banned: Vec::new(),
             muted: HashMap::new(),
             description: None,
             avatar: None,
             banner: None,
-            pinned_message_id: None,
+            pinned_messages: Vec::new(),
             slow_mode_delay: None,
             allow_stickers: true,
             allow_gifs: true,
             allow_voice_messages: true,
             allow_video_messages: true,
             allow_links: true,
+            disbanded_at: None,
         }
     }
 
+    /// Config of a direct chat between `creator_id` and `peer_id`
+    ///
+    /// Both users are members from the start and nobody is an admin, so the
+    /// chat can never grow past the two of them or be renamed.
+    pub fn direct_chat(chat_id: u64, creator_id: u64, peer_id: u64) -> Self {
+        let mut config = Self::new(chat_id, DIRECT_CHAT_NAME.to_string(), creator_id);
+        config.visibility = Visibility::Hidden;
+        config.max_members = Some(2);
+        config.admins.clear();
+        config.member_roles.clear();
+        config.add_member(peer_id);
+        config
+            .member_roles
+            .insert(creator_id, vec![MEMBER_ROLE.to_string()]);
+        config
+    }
+
     pub fn add_member(&mut self, member_id: u64) {
         self.members.push(member_id);
-        self.permissions
-            .insert(member_id, self.default_permissions.clone());
+        if self.get_role(MEMBER_ROLE).is_some() {
+            self.member_roles
+                .insert(member_id, vec![MEMBER_ROLE.to_string()]);
+        }
         self.update_timestamp();
     }
 
@@ -244,19 +202,16 @@ impl GroupConfig {
         self.members
             .remove(self.members.iter().position(|id| *id == member_id).unwrap());
         self.permissions.remove(&member_id);
+        self.member_roles.remove(&member_id);
         self.update_timestamp();
     }
 
     pub fn add_admin(&mut self, admin_id: u64) {
-        self.admins.push(admin_id);
-        self.permissions.insert(admin_id, Permissions::admin());
-        self.update_timestamp();
+        self.add_member_role(admin_id, ADMIN_ROLE);
     }
 
     pub fn remove_admin(&mut self, admin_id: u64) {
-        self.admins
-            .remove(self.admins.iter().position(|id| *id == admin_id).unwrap());
-        self.update_timestamp();
+        self.remove_member_role(admin_id, ADMIN_ROLE);
     }
 
     pub fn add_banned(&mut self, banned_id: u64) {
@@ -300,9 +255,38 @@ impl GroupConfig {
         self.update_timestamp();
     }
 
-    pub fn set_pinned_message_id(&mut self, pinned_message_id: u64) {
-        self.pinned_message_id = Some(pinned_message_id);
+    /// Append `message_id` to the pinned list, returns false if it is already pinned
+    pub fn pin_message(&mut self, message_id: u64, pinned_by: u64) -> bool {
+        if self.is_pinned(message_id) {
+            return false;
+        }
+        self.update_timestamp();
+        self.pinned_messages.push(PinnedMessage {
+            message_id,
+            pinned_by,
+            pinned_at: self.updated_at,
+        });
+        true
+    }
+
+    /// Remove `message_id` from the pinned list, returns false if it was not pinned
+    pub fn unpin_message(&mut self, message_id: u64) -> bool {
+        let Some(position) = self
+            .pinned_messages
+            .iter()
+            .position(|pinned| pinned.message_id == message_id)
+        else {
+            return false;
+        };
+        self.pinned_messages.remove(position);
         self.update_timestamp();
+        true
+    }
+
+    pub fn is_pinned(&self, message_id: u64) -> bool {
+        self.pinned_messages
+            .iter()
+            .any(|pinned| pinned.message_id == message_id)
     }
 
     pub fn set_slow_mode_delay(&mut self, slow_mode_delay: u32) {
@@ -344,25 +328,63 @@ impl GroupConfig {
         self.admins.contains(&user_id)
     }
 
+    /// Members whose permissions come from the member role or the defaults only
     pub fn get_regular_members(&self) -> Vec<u64> {
-        self.permissions
+        self.members
             .iter()
-            .filter(|(_, permissions)| *permissions == &self.default_permissions)
-            .map(|(member_id, _)| *member_id)
+            .copied()
+            .filter(|member_id| !self.permissions.contains_key(member_id))
+            .filter(|member_id| {
+                self.member_roles
+                    .get(member_id)
+                    .is_none_or(|roles| roles.iter().all(|role| role == MEMBER_ROLE))
+            })
             .collect()
     }
 
-    pub fn set_allow_messages(&mut self, allow_messages: bool) {
-        let mut permissions = self.default_permissions.clone();
-        permissions = permissions.with_send_messages(allow_messages);
-        self.default_permissions = permissions;
-        for member_id in self.get_regular_members() {
-            self.permissions
-                .insert(member_id, self.default_permissions.clone());
+    /// Set the permissions of regular members, both the defaults and the member role
+    pub fn set_default_permissions(&mut self, permissions: Permissions) {
+        if let Some(role) = self.roles.iter_mut().find(|role| role.name == MEMBER_ROLE) {
+            role.permissions = permissions.clone();
         }
+        self.default_permissions = permissions;
         self.update_timestamp();
     }
 
+    pub fn set_allow_messages(&mut self, allow_messages: bool) {
+        let permissions = self
+            .default_permissions
+            .clone()
+            .with(Permission::SendMessages, allow_messages);
+        self.set_default_permissions(permissions);
+    }
+
+    /// Whether this is the hidden group used to sync data between the devices of its creator
+    pub fn is_self_channel(&self) -> bool {
+        self.name == SELF_CHANNEL_NAME
+            && matches!(self.visibility, Visibility::Hidden)
+            && self.members == [self.creator_id]
+    }
+
+    /// Whether this is the config of a direct chat, see `GroupConfig::direct_chat`
+    pub fn is_direct_chat(&self) -> bool {
+        self.name == DIRECT_CHAT_NAME
+            && matches!(self.visibility, Visibility::Hidden)
+            && self.members.len() == 2
+            && self.members[0] != self.members[1]
+            && self.admins.is_empty()
+            && self.invite_link.is_none()
+            && self.max_members == Some(2)
+    }
+
+    /// The other member of a direct chat `user_id` takes part in
+    pub fn direct_chat_peer(&self, user_id: u64) -> Option<u64> {
+        if !self.is_direct_chat() || !self.is_member(user_id) {
+            return None;
+        }
+        self.members.iter().copied().find(|id| *id != user_id)
+    }
+
     pub fn is_member(&self, user_id: u64) -> bool {
         self.members.contains(&user_id)
     }
@@ -375,48 +397,215 @@ impl GroupConfig {
         self.muted.contains_key(&user_id)
     }
 
+    /// Per-user override of the role permissions, if any
     pub fn get_member_permissions(&self, user_id: u64) -> Option<&Permissions> {
         self.permissions.get(&user_id)
     }
 
-    pub fn has_permission(&self, user_id: u64, permission: &str) -> bool {
+    /// Permissions a member actually has
+    ///
+    /// A per-user override wins, otherwise the union of the member's roles,
+    /// otherwise `default_permissions`.
+    pub fn effective_permissions(&self, user_id: u64) -> Permissions {
+        if let Some(permissions) = self.get_member_permissions(user_id) {
+            return permissions.clone();
+        }
+        self.member_role_list(user_id)
+            .map(|role| role.permissions.clone())
+            .reduce(|permissions, role_permissions| permissions.union(&role_permissions))
+            .unwrap_or_else(|| self.default_permissions.clone())
+    }
+
+    pub fn has_permission(&self, user_id: u64, permission: Permission) -> bool {
         if self.is_admin(user_id) {
             return true;
         }
+        if !self.is_member(user_id) {
+            return false;
+        }
+        self.effective_permissions(user_id)
+            .has_permission(permission)
+    }
 
-        if let Some(perms) = self.get_member_permissions(user_id) {
-            perms.has_permission(permission)
-        } else {
-            false
+    pub fn get_role(&self, name: &str) -> Option<&Role> {
+        self.roles.iter().find(|role| role.name == name)
+    }
+
+    /// Defined roles assigned to `user_id`, unknown role names are skipped
+    pub fn member_role_list(&self, user_id: u64) -> impl Iterator<Item = &Role> {
+        self.member_roles
+            .get(&user_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|name| self.get_role(name))
+    }
+
+    /// Highest rank among the member's roles, the owner outranks everyone
+    pub fn member_rank(&self, user_id: u64) -> u32 {
+        if user_id == self.creator_id {
+            return u32::MAX;
         }
+        self.member_role_list(user_id)
+            .map(|role| role.rank)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Whether `actor_id` is high enough in the hierarchy to change `target_id`
+    pub fn can_manage_member(&self, actor_id: u64, target_id: u64) -> bool {
+        actor_id == self.creator_id || self.member_rank(actor_id) > self.member_rank(target_id)
     }
 
-    pub fn set_member_role(&mut self, user_id: u64, role: &str) {
-        let permissions = match role {
-            "admin" => Permissions::admin(),
-            "moderator" => Permissions::moderator(),
-            "member" => Permissions::member(),
-            "reader" => Permissions::reader(),
-            _ => Permissions::member(),
+    /// Whether `actor_id` may hand out or edit a role of rank `rank`
+    pub fn can_grant_rank(&self, actor_id: u64, rank: u32) -> bool {
+        actor_id == self.creator_id || rank <= self.member_rank(actor_id)
+    }
+
+    /// Whether `actor_id` may define, edit or delete a role of rank `rank`
+    pub fn can_edit_role(&self, actor_id: u64, rank: u32) -> bool {
+        actor_id == self.creator_id || rank < self.member_rank(actor_id)
+    }
+
+    /// Create a role or replace the role with the same name
+    pub fn set_role(&mut self, role: Role) {
+        match self.roles.iter_mut().find(|r| r.name == role.name) {
+            Some(existing) => *existing = role,
+            None => self.roles.push(role),
+        }
+        self.update_timestamp();
+    }
+
+    /// Delete a role and its assignments, the admin role cannot be deleted
+    pub fn remove_role(&mut self, name: &str) -> bool {
+        if name == ADMIN_ROLE || self.get_role(name).is_none() {
+            return false;
+        }
+        self.roles.retain(|role| role.name != name);
+        for roles in self.member_roles.values_mut() {
+            roles.retain(|role| role != name);
+        }
+        self.update_timestamp();
+        true
+    }
+
+    /// Give `user_id` an additional role, returns false if the role is not defined
+    pub fn add_member_role(&mut self, user_id: u64, role: &str) -> bool {
+        if self.get_role(role).is_none() {
+            return false;
+        }
+        let roles = self.member_roles.entry(user_id).or_default();
+        if !roles.iter().any(|r| r == role) {
+            roles.push(role.to_string());
+        }
+        if role == ADMIN_ROLE && !self.is_admin(user_id) {
+            self.admins.push(user_id);
+        }
+        self.update_timestamp();
+        true
+    }
+
+    /// Take a role away from `user_id`, returns false if it was not assigned
+    pub fn remove_member_role(&mut self, user_id: u64, role: &str) -> bool {
+        let Some(roles) = self.member_roles.get_mut(&user_id) else {
+            return false;
+        };
+        let Some(position) = roles.iter().position(|r| r == role) else {
+            return false;
         };
+        roles.remove(position);
+        if role == ADMIN_ROLE {
+            self.admins.retain(|id| *id != user_id);
+        }
+        self.update_timestamp();
+        true
+    }
 
-        self.update_permissions(user_id, |p| {
-            *p = permissions;
-        });
+    pub fn is_slow_mode_exempt(&self, user_id: u64) -> bool {
+        self.has_permission(user_id, Permission::BypassSlowMode)
+    }
 
-        match role {
-            "admin" => {
-                if !self.is_admin(user_id) {
-                    self.add_admin(user_id);
-                }
-            }
-            _ => {
-                if self.is_admin(user_id) {
-                    self.remove_admin(user_id);
-                }
+    /// Seconds `user_id` still has to wait before sending under slow mode
+    ///
+    /// `last_message_date` is the date of the sender's last accepted message
+    /// and `date` the date of the message being sent. Returns `None` when
+    /// slow mode is off, the user is exempt or the delay has passed.
+    pub fn slow_mode_remaining(
+        &self,
+        user_id: u64,
+        last_message_date: Option<i64>,
+        date: i64,
+    ) -> Option<u64> {
+        let delay = self.slow_mode_delay.filter(|delay| *delay > 0)?;
+        if self.is_slow_mode_exempt(user_id) {
+            return None;
+        }
+        let next_allowed = last_message_date? + delay as i64;
+        if date < next_allowed {
+            Some((next_allowed - date) as u64)
+        } else {
+            None
+        }
+    }
+
+    /// Hand the group over to `new_owner_id`, who also gets the admin role
+    ///
+    /// Returns false if the new owner is not a member or already owns the group.
+    pub fn transfer_ownership(&mut self, new_owner_id: u64) -> bool {
+        if new_owner_id == self.creator_id || !self.is_member(new_owner_id) {
+            return false;
+        }
+        self.add_member_role(new_owner_id, ADMIN_ROLE);
+        self.creator_id = new_owner_id;
+        self.update_timestamp();
+        true
+    }
+
+    /// Member who should own the group after the owner leaves
+    ///
+    /// Picks the highest ranked member, the earliest to join on a tie.
+    pub fn pick_successor(&self) -> Option<u64> {
+        self.members
+            .iter()
+            .rev()
+            .copied()
+            .filter(|id| *id != self.creator_id)
+            .max_by_key(|id| self.member_rank(*id))
+    }
+
+    /// Remove everyone but the owner and mark the group as disbanded
+    pub fn disband(&mut self) {
+        let owner_id = self.creator_id;
+        self.members.retain(|id| *id == owner_id);
+        self.admins.retain(|id| *id == owner_id);
+        self.permissions.retain(|id, _| *id == owner_id);
+        self.member_roles.retain(|id, _| *id == owner_id);
+        self.update_timestamp();
+        self.disbanded_at = Some(self.updated_at);
+    }
+
+    pub fn is_disbanded(&self) -> bool {
+        self.disbanded_at.is_some()
+    }
+
+    /// Make `role` the only role of `user_id` and drop any permission override
+    ///
+    /// Returns false if the role is not defined.
+    pub fn set_member_role(&mut self, user_id: u64, role: &str) -> bool {
+        if self.get_role(role).is_none() {
+            return false;
+        }
+        self.member_roles.insert(user_id, vec![role.to_string()]);
+        self.permissions.remove(&user_id);
+
+        if role == ADMIN_ROLE {
+            if !self.is_admin(user_id) {
+                self.admins.push(user_id);
             }
+        } else if user_id != self.creator_id {
+            self.admins.retain(|id| *id != user_id);
         }
         self.update_timestamp();
+        true
     }
 
     pub fn update_permissions(&mut self, user_id: u64, f: impl FnOnce(&mut Permissions)) {
@@ -472,7 +661,7 @@ impl GroupConfig {
                 new_value: new_config.name.clone(),
             });
 
-            if !self.has_permission(user_id, "rename_group") {
+            if !self.has_permission(user_id, Permission::RenameGroup) {
                 valid = false;
             }
         }
@@ -487,7 +676,7 @@ impl GroupConfig {
                 new_value: format!("{:?}", new_config.visibility),
             });
 
-            if !self.has_permission(user_id, "manage_permissions") {
+            if !self.has_permission(user_id, Permission::ManagePermissions) {
                 valid = false;
             }
         }
@@ -501,7 +690,7 @@ impl GroupConfig {
                 new_value: format!("{:?}", new_config.join_mode),
             });
 
-            if !self.has_permission(user_id, "manage_permissions") {
+            if !self.has_permission(user_id, Permission::ManagePermissions) {
                 valid = false;
             }
         }
@@ -514,7 +703,7 @@ impl GroupConfig {
                 new_value: new_config.invite_link.clone().unwrap_or_default(),
             });
 
-            if !self.has_permission(user_id, "manage_members") {
+            if !self.has_permission(user_id, Permission::ManageMembers) {
                 valid = false;
             }
         }
@@ -531,7 +720,24 @@ impl GroupConfig {
                     .map_or("None".to_string(), |v| v.to_string()),
             });
 
-            if !self.has_permission(user_id, "manage_permissions") {
+            if !self.has_permission(user_id, Permission::ManagePermissions) {
+                valid = false;
+            }
+        }
+
+        // Check ownership change
+        if self.creator_id != new_config.creator_id {
+            changes.push(ConfigChange {
+                field: "creator_id".to_string(),
+                old_value: self.creator_id.to_string(),
+                new_value: new_config.creator_id.to_string(),
+            });
+
+            // Передать группу может только владелец, и только админу из участников
+            if user_id != self.creator_id
+                || !new_config.is_member(new_config.creator_id)
+                || !new_config.is_admin(new_config.creator_id)
+            {
                 valid = false;
             }
         }
@@ -547,7 +753,22 @@ impl GroupConfig {
             // Если пользователь удаляет себя — разрешаем
             let removing_self = self.is_member(user_id) && !new_config.is_member(user_id);
 
-            if !removing_self && !self.has_permission(user_id, "manage_members") {
+            if !removing_self && !self.has_permission(user_id, Permission::ManageMembers) {
+                valid = false;
+            }
+
+            // Нельзя исключить участника с рангом не ниже своего
+            let removes_superior = self.members.iter().any(|member_id| {
+                *member_id != user_id
+                    && !new_config.is_member(*member_id)
+                    && !self.can_manage_member(user_id, *member_id)
+            });
+            if removes_superior {
+                valid = false;
+            }
+
+            // Владелец не может уйти, оставив группу без владельца
+            if !new_config.members.is_empty() && !new_config.is_member(new_config.creator_id) {
                 valid = false;
             }
         }
@@ -560,27 +781,135 @@ impl GroupConfig {
                 new_value: format!("{:?}", new_config.admins),
             });
 
-            if !self.has_permission(user_id, "manage_admins") {
+            if !self.has_permission(user_id, Permission::ManageAdmins) {
                 valid = false;
             }
+
+            let changed_admins = self
+                .admins
+                .iter()
+                .filter(|id| !new_config.admins.contains(id))
+                .chain(
+                    new_config
+                        .admins
+                        .iter()
+                        .filter(|id| !self.admins.contains(id)),
+                );
+            for target_id in changed_admins {
+                if !self.is_leaving(new_config, user_id, *target_id)
+                    && !self.can_manage_member(user_id, *target_id)
+                {
+                    valid = false;
+                }
+            }
         }
 
         // Check permissions changes
-        if self.permissions.len() != new_config.permissions.len()
-            || self
-                .permissions
-                .iter()
-                .any(|(k, v)| !(new_config.permissions.get(k) == Some(v)))
-        {
+        let changed_overrides = changed_keys(&self.permissions, &new_config.permissions);
+        if !changed_overrides.is_empty() {
             changes.push(ConfigChange {
                 field: "permissions".to_string(),
                 old_value: "...".to_string(), // Simplified for readability
                 new_value: "...".to_string(),
             });
 
-            if !self.has_permission(user_id, "manage_permissions") {
+            for target_id in changed_overrides {
+                // Удаление override вместе с участником проверяется выше
+                if !new_config.is_member(target_id) {
+                    continue;
+                }
+                if !self.has_permission(user_id, Permission::ManagePermissions)
+                    || !self.can_manage_member(user_id, target_id)
+                {
+                    valid = false;
+                }
+            }
+        }
+
+        // Check role definitions changes
+        if self.roles != new_config.roles {
+            let role_names = |roles: &[Role]| {
+                format!(
+                    "{:?}",
+                    roles
+                        .iter()
+                        .map(|role| role.name.as_str())
+                        .collect::<Vec<_>>()
+                )
+            };
+            changes.push(ConfigChange {
+                field: "roles".to_string(),
+                old_value: role_names(&self.roles),
+                new_value: role_names(&new_config.roles),
+            });
+
+            if !self.has_permission(user_id, Permission::ManagePermissions) {
                 valid = false;
             }
+
+            // Роли своего ранга и выше менять нельзя
+            let changed_roles = self
+                .roles
+                .iter()
+                .filter(|role| new_config.get_role(&role.name) != Some(*role))
+                .chain(
+                    new_config
+                        .roles
+                        .iter()
+                        .filter(|role| self.get_role(&role.name) != Some(*role)),
+                );
+            for role in changed_roles {
+                if !self.can_edit_role(user_id, role.rank) {
+                    valid = false;
+                }
+            }
+        }
+
+        // Check role assignments changes
+        let changed_assignments = changed_keys(&self.member_roles, &new_config.member_roles);
+        if !changed_assignments.is_empty() {
+            let assignments = |member_roles: &HashMap<u64, Vec<String>>| {
+                format!(
+                    "{:?}",
+                    member_roles
+                        .iter()
+                        .collect::<std::collections::BTreeMap<_, _>>()
+                )
+            };
+            changes.push(ConfigChange {
+                field: "member_roles".to_string(),
+                old_value: assignments(&self.member_roles),
+                new_value: assignments(&new_config.member_roles),
+            });
+
+            for target_id in changed_assignments {
+                let joining = !self.is_member(target_id) && new_config.is_member(target_id);
+                let leaving = self.is_member(target_id) && !new_config.is_member(target_id);
+                // Роли уходящего участника проверяются вместе с составом группы
+                if leaving {
+                    continue;
+                }
+                if !joining
+                    && (!self.has_permission(user_id, Permission::ManagePermissions)
+                        || !self.can_manage_member(user_id, target_id))
+                {
+                    valid = false;
+                }
+
+                let old_roles = self.member_roles.get(&target_id);
+                let granted = new_config
+                    .member_roles
+                    .get(&target_id)
+                    .into_iter()
+                    .flatten()
+                    .filter(|role| old_roles.is_none_or(|old| !old.contains(role)));
+                for role in granted {
+                    let rank = new_config.get_role(role).map_or(0, |role| role.rank);
+                    if !self.can_grant_rank(user_id, rank) {
+                        valid = false;
+                    }
+                }
+            }
         }
 
         // Check description change
@@ -591,7 +920,7 @@ impl GroupConfig {
                 new_value: new_config.description.clone().unwrap_or_default(),
             });
 
-            if !self.has_permission(user_id, "rename_group") {
+            if !self.has_permission(user_id, Permission::RenameGroup) {
                 valid = false;
             }
         }
@@ -604,7 +933,7 @@ impl GroupConfig {
                 new_value: "...".to_string(),
             });
 
-            if !self.has_permission(user_id, "rename_group") {
+            if !self.has_permission(user_id, Permission::RenameGroup) {
                 valid = false;
             }
         }
@@ -617,24 +946,26 @@ impl GroupConfig {
                 new_value: "...".to_string(),
             });
 
-            if !self.has_permission(user_id, "rename_group") {
+            if !self.has_permission(user_id, Permission::RenameGroup) {
                 valid = false;
             }
         }
 
-        // Check pinned message change
-        if self.pinned_message_id != new_config.pinned_message_id {
+        // Check pinned messages change
+        if self.pinned_messages != new_config.pinned_messages {
+            let pinned_ids = |pinned: &[PinnedMessage]| {
+                format!(
+                    "{:?}",
+                    pinned.iter().map(|p| p.message_id).collect::<Vec<_>>()
+                )
+            };
             changes.push(ConfigChange {
-                field: "pinned_message_id".to_string(),
-                old_value: self
-                    .pinned_message_id
-                    .map_or("None".to_string(), |v| v.to_string()),
-                new_value: new_config
-                    .pinned_message_id
-                    .map_or("None".to_string(), |v| v.to_string()),
+                field: "pinned_messages".to_string(),
+                old_value: pinned_ids(&self.pinned_messages),
+                new_value: pinned_ids(&new_config.pinned_messages),
             });
 
-            if !self.has_permission(user_id, "pin_messages") {
+            if !self.has_permission(user_id, Permission::PinMessages) {
                 valid = false;
             }
         }
@@ -651,7 +982,7 @@ impl GroupConfig {
                     .map_or("None".to_string(), |v| v.to_string()),
             });
 
-            if !self.has_permission(user_id, "manage_permissions") {
+            if !self.has_permission(user_id, Permission::ManagePermissions) {
                 valid = false;
             }
         }
@@ -664,7 +995,7 @@ impl GroupConfig {
                 new_value: new_config.allow_stickers.to_string(),
             });
 
-            if !self.has_permission(user_id, "manage_permissions") {
+            if !self.has_permission(user_id, Permission::ManagePermissions) {
                 valid = false;
             }
         }
@@ -676,7 +1007,7 @@ impl GroupConfig {
                 new_value: new_config.allow_gifs.to_string(),
             });
 
-            if !self.has_permission(user_id, "manage_permissions") {
+            if !self.has_permission(user_id, Permission::ManagePermissions) {
                 valid = false;
             }
         }
@@ -688,7 +1019,7 @@ impl GroupConfig {
                 new_value: new_config.allow_voice_messages.to_string(),
             });
 
-            if !self.has_permission(user_id, "manage_permissions") {
+            if !self.has_permission(user_id, Permission::ManagePermissions) {
                 valid = false;
             }
         }
@@ -700,7 +1031,7 @@ impl GroupConfig {
                 new_value: new_config.allow_video_messages.to_string(),
             });
 
-            if !self.has_permission(user_id, "manage_permissions") {
+            if !self.has_permission(user_id, Permission::ManagePermissions) {
                 valid = false;
             }
         }
@@ -712,13 +1043,39 @@ impl GroupConfig {
                 new_value: new_config.allow_links.to_string(),
             });
 
-            if !self.has_permission(user_id, "manage_permissions") {
+            if !self.has_permission(user_id, Permission::ManagePermissions) {
                 valid = false;
             }
         }
 
+        // Check disband marker
+        if self.disbanded_at != new_config.disbanded_at {
+            changes.push(ConfigChange {
+                field: "disbanded_at".to_string(),
+                old_value: self
+                    .disbanded_at
+                    .map_or("None".to_string(), |v| v.timestamp.to_string()),
+                new_value: new_config
+                    .disbanded_at
+                    .map_or("None".to_string(), |v| v.timestamp.to_string()),
+            });
+
+            if user_id != self.creator_id {
+                valid = false;
+            }
+        }
+
+        // Распущенная группа доступна только для чтения
+        if self.is_disbanded() && !changes.is_empty() {
+            valid = false;
+        }
+
         ConfigValidationResult { changes, valid }
     }
+    fn is_leaving(&self, new_config: &GroupConfig, user_id: u64, target_id: u64) -> bool {
+        user_id == target_id && self.is_member(user_id) && !new_config.is_member(user_id)
+    }
+
     fn update_timestamp(&mut self) {
         self.updated_at = DateTime {
             timestamp: std::time::SystemTime::now()
@@ -729,6 +1086,17 @@ impl GroupConfig {
     }
 }
 
+/// Keys whose value differs between two maps, including added and removed keys
+fn changed_keys<V: PartialEq>(old: &HashMap<u64, V>, new: &HashMap<u64, V>) -> Vec<u64> {
+    old.keys()
+        .chain(new.keys())
+        .copied()
+        .filter(|key| old.get(key) != new.get(key))
+        .collect::<std::collections::BTreeSet<u64>>()
+        .into_iter()
+        .collect()
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ConfigChange {
     pub field: String,
@@ -741,3 +1109,104 @@ pub struct ConfigValidationResult {
     pub changes: Vec<ConfigChange>,
     pub valid: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_direct_chat_config() {
+        let config = GroupConfig::direct_chat(1, 10, 20);
+        assert!(config.is_direct_chat());
+        assert_eq!(config.direct_chat_peer(10), Some(20));
+        assert_eq!(config.direct_chat_peer(20), Some(10));
+        assert_eq!(config.direct_chat_peer(30), None);
+        assert!(config.has_permission(20, Permission::SendMessages));
+        assert!(!config.has_permission(10, Permission::ManageMembers));
+
+        let mut group = config.clone();
+        group.admins.push(10);
+        assert!(!group.is_direct_chat());
+    }
+
+    #[test]
+    fn test_role_hierarchy() {
+        let owner = 1;
+        let admin = 2;
+        let moderator = 3;
+        let member = 4;
+        let mut config = GroupConfig::new(1, "Test Group".to_string(), owner);
+        for user_id in [admin, moderator, member] {
+            config.add_member(user_id);
+        }
+        config.set_member_role(admin, ADMIN_ROLE);
+        config.set_member_role(moderator, MODERATOR_ROLE);
+
+        // A moderator can change a member but not an admin
+        let mut demoted_member = config.clone();
+        demoted_member.set_member_role(member, READER_ROLE);
+        assert!(config.validate_changes(&demoted_member, admin).valid);
+        assert!(!config.validate_changes(&demoted_member, member).valid);
+
+        let mut demoted_admin = config.clone();
+        demoted_admin.set_member_role(admin, MEMBER_ROLE);
+        assert!(!config.validate_changes(&demoted_admin, moderator).valid);
+        assert!(config.validate_changes(&demoted_admin, owner).valid);
+
+        let mut kicked_admin = config.clone();
+        kicked_admin.remove_member(admin);
+        assert!(!config.validate_changes(&kicked_admin, moderator).valid);
+
+        // Effective permissions come from roles unless overridden
+        assert!(config.has_permission(moderator, Permission::PinMessages));
+        config.set_permissions(moderator, Permissions::member());
+        assert!(!config.has_permission(moderator, Permission::PinMessages));
+    }
+
+    #[test]
+    fn test_ownership_transfer() {
+        let owner = 1;
+        let admin = 2;
+        let member = 3;
+        let mut config = GroupConfig::new(1, "Test Group".to_string(), owner);
+        config.add_member(member);
+        config.add_member(admin);
+        config.set_member_role(admin, ADMIN_ROLE);
+
+        let mut transferred = config.clone();
+        assert!(transferred.transfer_ownership(member));
+        assert!(transferred.is_admin(member));
+        assert!(config.validate_changes(&transferred, owner).valid);
+        assert!(!config.validate_changes(&transferred, admin).valid);
+
+        // The owner cannot leave without handing the group over
+        let mut abandoned = config.clone();
+        abandoned.remove_member(owner);
+        assert!(!config.validate_changes(&abandoned, owner).valid);
+
+        assert_eq!(config.pick_successor(), Some(admin));
+        let mut left = config.clone();
+        left.transfer_ownership(admin);
+        left.remove_member(owner);
+        assert!(config.validate_changes(&left, owner).valid);
+    }
+
+    #[test]
+    fn test_disband() {
+        let owner = 1;
+        let admin = 2;
+        let mut config = GroupConfig::new(1, "Test Group".to_string(), owner);
+        config.add_member(admin);
+        config.set_member_role(admin, ADMIN_ROLE);
+
+        let mut disbanded = config.clone();
+        disbanded.disband();
+        assert_eq!(disbanded.members, vec![owner]);
+        assert!(config.validate_changes(&disbanded, owner).valid);
+        assert!(!config.validate_changes(&disbanded, admin).valid);
+
+        let mut renamed = disbanded.clone();
+        renamed.set_name("Revived".to_string());
+        assert!(!disbanded.validate_changes(&renamed, owner).valid);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::api::device::types::extensions::group_config::group_config::{
-    DateTime, GroupConfig, JoinMode, Permissions, Visibility,
+    DateTime, GroupConfig, JoinMode, Permissions, Role, Visibility,
 };
 use std::collections::BTreeMap;
 
@@ -21,7 +21,7 @@ pub struct GroupConfigBuilder {
     max_members: Option<u32>,
     slow_mode_delay: Option<u32>,
     invite_link: Option<String>,
-    pinned_message_id: Option<u64>,
+    pinned_messages: Vec<u64>,
 
     // Content settings
     allow_stickers: Option<bool>,
@@ -35,6 +35,7 @@ pub struct GroupConfigBuilder {
     additional_admins: Vec<u64>,
     custom_permissions: BTreeMap<u64, Permissions>,
     default_member_permissions: Option<Permissions>,
+    roles: Vec<Role>,
     banned_users: Vec<u64>,
     muted_users: BTreeMap<u64, DateTime>,
 }
@@ -59,7 +60,7 @@ impl GroupConfigBuilder {
             max_members: None,
             slow_mode_delay: None,
             invite_link: None,
-            pinned_message_id: None,
+            pinned_messages: Vec::new(),
             allow_stickers: None,
             allow_gifs: None,
             allow_voice_messages: None,
@@ -69,6 +70,7 @@ impl GroupConfigBuilder {
             additional_admins: Vec::new(),
             custom_permissions: BTreeMap::new(),
             default_member_permissions: None,
+            roles: Vec::new(),
             banned_users: Vec::new(),
             muted_users: BTreeMap::new(),
         }
@@ -122,9 +124,9 @@ impl GroupConfigBuilder {
         self
     }
 
-    /// Set the pinned message ID
+    /// Pin a message, pins are attributed to the creator
     pub fn with_pinned_message(mut self, message_id: u64) -> Self {
-        self.pinned_message_id = Some(message_id);
+        self.pinned_messages.push(message_id);
         self
     }
 
@@ -194,6 +196,12 @@ impl GroupConfigBuilder {
         self
     }
 
+    /// Define a role, replacing the default role with the same name
+    pub fn with_role(mut self, role: Role) -> Self {
+        self.roles.push(role);
+        self
+    }
+
     /// Add banned users to the group
     pub fn with_banned_users(mut self, banned_ids: Vec<u64>) -> Self {
         self.banned_users = banned_ids;
@@ -275,8 +283,8 @@ impl GroupConfigBuilder {
             config.set_invite_link(Some(link));
         }
 
-        if let Some(message_id) = self.pinned_message_id {
-            config.set_pinned_message_id(message_id);
+        for message_id in self.pinned_messages {
+            config.pin_message(message_id, self.creator_id);
         }
 
         // Apply content settings
@@ -302,7 +310,12 @@ impl GroupConfigBuilder {
 
         // Set default permissions if specified
         if let Some(default_perms) = self.default_member_permissions {
-            config.default_permissions = default_perms;
+            config.set_default_permissions(default_perms);
+        }
+
+        // Define custom roles
+        for role in self.roles {
+            config.set_role(role);
         }
 
         // Add additional members
@@ -1,12 +1,18 @@
 use mls_rs::group::proposal::{MlsCustomProposal, ProposalType};
 use mls_rs_codec::{MlsDecode, MlsEncode, MlsSize};
-use mls_rs_core::extension::{ExtensionType, MlsCodecExtension};
+use mls_rs_core::extension::{ExtensionList, ExtensionType, MlsCodecExtension};
 
+use crate::api::device::types::errors::GroupError;
 use crate::api::device::types::extensions::group_config::group_config::GroupConfig;
+use crate::api::device::types::extensions::group_config::legacy::GroupConfigV1;
 // Новый ExtensionType
 pub const GROUP_CONFIG_EXTENSION_V1: ExtensionType = ExtensionType::new(65003);
+// Права как набор кодов, роли и список закреплённых сообщений
+pub const GROUP_CONFIG_EXTENSION_V2: ExtensionType = ExtensionType::new(65005);
 
 pub const UPDATE_GROUP_CONFIG_PROPOSAL_V1: ProposalType = ProposalType::new(65004);
+pub const UPDATE_GROUP_CONFIG_PROPOSAL_V2: ProposalType = ProposalType::new(65006);
+pub const MIGRATE_GROUP_CONFIG_PROPOSAL_V1: ProposalType = ProposalType::new(65007);
 
 // Расширение GroupConfig
 #[derive(Debug, Clone, MlsSize, MlsDecode, MlsEncode)]
@@ -15,6 +21,18 @@ pub struct GroupConfigExtension {
 }
 
 impl MlsCodecExtension for GroupConfigExtension {
+    fn extension_type() -> ExtensionType {
+        GROUP_CONFIG_EXTENSION_V2
+    }
+}
+
+// Устаревшее расширение, только для чтения и миграции
+#[derive(Debug, Clone, MlsSize, MlsDecode, MlsEncode)]
+pub struct GroupConfigExtensionV1 {
+    pub config: GroupConfigV1,
+}
+
+impl MlsCodecExtension for GroupConfigExtensionV1 {
     fn extension_type() -> ExtensionType {
         GROUP_CONFIG_EXTENSION_V1
     }
@@ -27,14 +45,53 @@ pub struct UpdateGroupConfigProposal {
 
 impl MlsCustomProposal for UpdateGroupConfigProposal {
     fn proposal_type() -> ProposalType {
-        UPDATE_GROUP_CONFIG_PROPOSAL_V1
+        UPDATE_GROUP_CONFIG_PROPOSAL_V2
+    }
+}
+
+/// Replace the V1 config extension of a group with the V2 extension
+///
+/// Carries no config: every member converts the current V1 config itself,
+/// so the migration cannot be used to smuggle in other changes.
+#[derive(MlsSize, MlsDecode, MlsEncode)]
+pub struct MigrateGroupConfigProposal {
+    pub from_version: u8,
+}
+
+impl MlsCustomProposal for MigrateGroupConfigProposal {
+    fn proposal_type() -> ProposalType {
+        MIGRATE_GROUP_CONFIG_PROPOSAL_V1
+    }
+}
+
+/// Read the group config from context extensions, converting a V1 config
+pub fn read_group_config(extensions: &ExtensionList) -> Result<Option<GroupConfig>, GroupError> {
+    if let Some(extension) = extensions.get_as::<GroupConfigExtension>().map_err(|e| {
+        GroupError::ExtensionError(format!("Failed to get group config extension: {}", e))
+    })? {
+        return Ok(Some(extension.config));
     }
+    let legacy = extensions.get_as::<GroupConfigExtensionV1>().map_err(|e| {
+        GroupError::ExtensionError(format!(
+            "Failed to get legacy group config extension: {}",
+            e
+        ))
+    })?;
+    Ok(legacy.map(|extension| extension.config.into()))
+}
+
+/// Whether the group still stores its config under `GROUP_CONFIG_EXTENSION_V1`
+pub fn needs_config_migration(extensions: &ExtensionList) -> bool {
+    extensions.has_extension(GROUP_CONFIG_EXTENSION_V1)
+        && !extensions.has_extension(GROUP_CONFIG_EXTENSION_V2)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::api::device::types::extensions::group_config::group_config::GroupConfig;
+    use crate::api::device::types::extensions::group_config::group_config::{
+        GroupConfig, MEMBER_ROLE,
+    };
     use mls_rs_core::extension::ExtensionList;
 
     #[test]
@@ -54,8 +111,156 @@ mod tests {
         let retrieved_config = retrieved.unwrap().config;
         assert_eq!(retrieved_config.name, "Test Group");
         assert_eq!(retrieved_config.creator_id, 123);
-        let member_config = retrieved_config.permissions.get(&member_id);
+        let member_roles = retrieved_config.member_roles.get(&member_id);
         println!("{:?}", retrieved_config);
-        assert!(member_config.is_some());
+        assert!(member_roles.is_some());
+        assert_eq!(
+            retrieved_config.effective_permissions(member_id),
+            retrieved_config.get_role(MEMBER_ROLE).unwrap().permissions
+        );
+    }
+
+    #[test]
+    fn test_read_legacy_group_config() {
+        use crate::api::device::types::extensions::group_config::{
+            group_config::{DateTime, JoinMode, MODERATOR_ROLE, Permission, Visibility},
+            legacy::PermissionsV1,
+        };
+        use std::collections::HashMap;
+
+        let member = PermissionsV1 {
+            manage_members: false,
+            send_messages: true,
+            delete_messages: false,
+            rename_group: false,
+            manage_permissions: false,
+            pin_messages: false,
+            manage_admins: false,
+        };
+        let moderator = PermissionsV1 {
+            manage_members: true,
+            delete_messages: true,
+            pin_messages: true,
+            ..member.clone()
+        };
+        let custom = PermissionsV1 {
+            rename_group: true,
+            ..member.clone()
+        };
+        let legacy = GroupConfigV1 {
+            id: 1,
+            name: "Legacy Group".to_string(),
+            created_at: DateTime { timestamp: 10 },
+            updated_at: DateTime { timestamp: 20 },
+            visibility: Visibility::Private,
+            join_mode: JoinMode::InviteOnly,
+            invite_link: None,
+            max_members: None,
+            creator_id: 1,
+            members: vec![1, 2, 3, 4],
+            admins: vec![1],
+            permissions: HashMap::from([(2, member.clone()), (3, moderator), (4, custom)]),
+            default_permissions: member,
+            banned: Vec::new(),
+            muted: HashMap::new(),
+            description: None,
+            avatar: None,
+            banner: None,
+            pinned_message_id: Some(7),
+            slow_mode_delay: None,
+            allow_stickers: true,
+            allow_gifs: true,
+            allow_voice_messages: true,
+            allow_video_messages: true,
+            allow_links: true,
+        };
+        let mut extensions = ExtensionList::new();
+        extensions
+            .set_from(GroupConfigExtensionV1 { config: legacy })
+            .unwrap();
+        assert!(needs_config_migration(&extensions));
+
+        let config = read_group_config(&extensions).unwrap().unwrap();
+        assert_eq!(config.member_roles[&2], vec![MEMBER_ROLE.to_string()]);
+        assert_eq!(config.member_roles[&3], vec![MODERATOR_ROLE.to_string()]);
+        assert!(config.has_permission(4, Permission::RenameGroup));
+        assert!(!config.has_permission(2, Permission::RenameGroup));
+        assert!(config.is_pinned(7));
+
+        extensions
+            .set_from(GroupConfigExtension { config })
+            .unwrap();
+        assert!(!needs_config_migration(&extensions));
+    }
+
+    /// V1 must keep the layout of the groups created before V2, new fields
+    /// only go to `GroupConfigExtension`
+    #[test]
+    fn test_legacy_group_config_layout() {
+        use crate::api::device::types::extensions::group_config::{
+            group_config::{DateTime, JoinMode, Visibility},
+            legacy::PermissionsV1,
+        };
+        use std::collections::HashMap;
+
+        let legacy = GroupConfigV1 {
+            id: 1,
+            name: "G".to_string(),
+            created_at: DateTime { timestamp: 10 },
+            updated_at: DateTime { timestamp: 20 },
+            visibility: Visibility::Private,
+            join_mode: JoinMode::InviteOnly,
+            invite_link: None,
+            max_members: None,
+            creator_id: 1,
+            members: vec![1],
+            admins: Vec::new(),
+            permissions: HashMap::new(),
+            default_permissions: PermissionsV1 {
+                manage_members: false,
+                send_messages: true,
+                delete_messages: false,
+                rename_group: false,
+                manage_permissions: false,
+                pin_messages: false,
+                manage_admins: false,
+            },
+            banned: Vec::new(),
+            muted: HashMap::new(),
+            description: None,
+            avatar: None,
+            banner: None,
+            pinned_message_id: Some(7),
+            slow_mode_delay: None,
+            allow_stickers: true,
+            allow_gifs: true,
+            allow_voice_messages: true,
+            allow_video_messages: true,
+            allow_links: true,
+        };
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&1u64.to_be_bytes());
+        expected.extend_from_slice(&[1, b'G']);
+        expected.extend_from_slice(&10u64.to_be_bytes());
+        expected.extend_from_slice(&20u64.to_be_bytes());
+        // visibility, join_mode, invite_link, max_members
+        expected.extend_from_slice(&[2, 2, 0, 0]);
+        expected.extend_from_slice(&1u64.to_be_bytes());
+        expected.push(8);
+        expected.extend_from_slice(&1u64.to_be_bytes());
+        // admins, permissions
+        expected.extend_from_slice(&[0, 0]);
+        expected.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0]);
+        // banned, muted, description, avatar, banner
+        expected.extend_from_slice(&[0, 0, 0, 0, 0]);
+        expected.push(1);
+        expected.extend_from_slice(&7u64.to_be_bytes());
+        // slow_mode_delay, allow_*
+        expected.extend_from_slice(&[0, 1, 1, 1, 1, 1]);
+
+        assert_eq!(legacy.mls_encode_to_vec().unwrap(), expected);
+        let decoded = GroupConfigV1::mls_decode(&mut &*expected).unwrap();
+        assert_eq!(decoded.pinned_message_id, Some(7));
     }
 }
@@ -0,0 +1,167 @@
+//! Group config layout stored under `GROUP_CONFIG_EXTENSION_V1`
+//!
+//! Kept only to read groups created before the V2 extension and to migrate
+//! them. The conversion must stay deterministic: every member computes it
+//! independently when a migration commit is applied.
+
+use std::collections::HashMap;
+
+use mls_rs_codec::{MlsDecode, MlsEncode, MlsSize};
+
+use crate::api::device::types::extensions::group_config::group_config::{
+    ADMIN_ROLE, DateTime, GroupConfig, JoinMode, MEMBER_ROLE, MODERATOR_ROLE, Permission,
+    Permissions, PinnedMessage, READER_ROLE, Role, Visibility,
+};
+
+#[derive(Debug, Clone, PartialEq, MlsSize, MlsDecode, MlsEncode)]
+pub struct PermissionsV1 {
+    pub manage_members: bool,
+    pub send_messages: bool,
+    pub delete_messages: bool,
+    pub rename_group: bool,
+    pub manage_permissions: bool,
+    pub pin_messages: bool,
+    pub manage_admins: bool,
+}
+
+impl PermissionsV1 {
+    fn moderator() -> Self {
+        Self {
+            manage_members: true,
+            send_messages: true,
+            delete_messages: true,
+            rename_group: false,
+            manage_permissions: false,
+            pin_messages: true,
+            manage_admins: false,
+        }
+    }
+
+    fn reader() -> Self {
+        Self {
+            manage_members: false,
+            send_messages: false,
+            delete_messages: false,
+            rename_group: false,
+            manage_permissions: false,
+            pin_messages: false,
+            manage_admins: false,
+        }
+    }
+}
+
+impl From<&PermissionsV1> for Permissions {
+    fn from(v1: &PermissionsV1) -> Self {
+        Permissions::none()
+            .with(Permission::ManageMembers, v1.manage_members)
+            .with(Permission::SendMessages, v1.send_messages)
+            .with(Permission::DeleteMessages, v1.delete_messages)
+            .with(Permission::RenameGroup, v1.rename_group)
+            .with(Permission::ManagePermissions, v1.manage_permissions)
+            .with(Permission::PinMessages, v1.pin_messages)
+            .with(Permission::ManageAdmins, v1.manage_admins)
+    }
+}
+
+#[derive(Debug, Clone, MlsSize, MlsDecode, MlsEncode)]
+pub struct GroupConfigV1 {
+    pub id: u64,
+    pub name: String,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+    pub visibility: Visibility,
+    pub join_mode: JoinMode,
+    pub invite_link: Option<String>,
+    pub max_members: Option<u32>,
+    pub creator_id: u64,
+    pub members: Vec<u64>,
+    pub admins: Vec<u64>,
+    pub permissions: HashMap<u64, PermissionsV1>,
+    pub default_permissions: PermissionsV1,
+    pub banned: Vec<u64>,
+    pub muted: HashMap<u64, DateTime>,
+    pub description: Option<String>,
+    pub avatar: Option<Vec<u8>>,
+    pub banner: Option<Vec<u8>>,
+    pub pinned_message_id: Option<u64>,
+    pub slow_mode_delay: Option<u32>,
+    pub allow_stickers: bool,
+    pub allow_gifs: bool,
+    pub allow_voice_messages: bool,
+    pub allow_video_messages: bool,
+    pub allow_links: bool,
+}
+
+impl From<GroupConfigV1> for GroupConfig {
+    /// Map the V1 per-member permission copies onto roles
+    ///
+    /// Members whose permissions match a V1 preset get the matching role,
+    /// anyone else keeps the member role plus a per-user override.
+    fn from(v1: GroupConfigV1) -> Self {
+        let mut roles = Role::defaults();
+        for role in roles.iter_mut().filter(|role| role.name == MEMBER_ROLE) {
+            role.permissions = Permissions::from(&v1.default_permissions);
+        }
+
+        let mut member_roles = HashMap::new();
+        let mut permissions = HashMap::new();
+        for member_id in &v1.members {
+            let role = if *member_id == v1.creator_id || v1.admins.contains(member_id) {
+                ADMIN_ROLE
+            } else {
+                match v1.permissions.get(member_id) {
+                    None => MEMBER_ROLE,
+                    Some(p) if *p == v1.default_permissions => MEMBER_ROLE,
+                    Some(p) if *p == PermissionsV1::moderator() => MODERATOR_ROLE,
+                    Some(p) if *p == PermissionsV1::reader() => READER_ROLE,
+                    Some(p) => {
+                        permissions.insert(*member_id, Permissions::from(p));
+                        MEMBER_ROLE
+                    }
+                }
+            };
+            member_roles.insert(*member_id, vec![role.to_string()]);
+        }
+
+        let pinned_messages = v1
+            .pinned_message_id
+            .map(|message_id| PinnedMessage {
+                message_id,
+                pinned_by: v1.creator_id,
+                pinned_at: v1.updated_at,
+            })
+            .into_iter()
+            .collect();
+
+        GroupConfig {
+            id: v1.id,
+            name: v1.name,
+            created_at: v1.created_at,
+            updated_at: v1.updated_at,
+            visibility: v1.visibility,
+            join_mode: v1.join_mode,
+            invite_link: v1.invite_link,
+            max_members: v1.max_members,
+            creator_id: v1.creator_id,
+            members: v1.members,
+            admins: v1.admins,
+            permissions,
+            default_permissions: Permissions::from(&v1.default_permissions),
+            roles,
+            member_roles,
+            banned: v1.banned,
+            muted: v1.muted,
+            description: v1.description,
+            avatar: v1.avatar,
+            banner: v1.banner,
+            pinned_messages,
+            slow_mode_delay: v1.slow_mode_delay,
+            allow_stickers: v1.allow_stickers,
+            allow_gifs: v1.allow_gifs,
+            allow_voice_messages: v1.allow_voice_messages,
+            allow_video_messages: v1.allow_video_messages,
+            allow_links: v1.allow_links,
+            disbanded_at: None,
+        }
+    }
+}
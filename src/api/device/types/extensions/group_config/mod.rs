@@ -1,3 +1,5 @@
 pub mod group_config;
 pub mod group_config_builder;
 pub mod group_extension;
+pub mod legacy;
+pub mod permissions;
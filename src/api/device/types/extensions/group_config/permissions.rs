@@ -0,0 +1,244 @@
+//! Typed permission set shared by roles, overrides and defaults
+//!
+//! Permissions are encoded as a sorted list of numeric codes, so adding a new
+//! `Permission` does not change the encoding of `GroupConfig`. Codes unknown
+//! to this client are kept as-is and survive config updates; the UI sees them
+//! as numeric keys and passes them back unchanged.
+
+use std::collections::BTreeMap;
+
+use mls_rs_codec::{MlsDecode, MlsEncode, MlsSize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+
+// Право участника
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u16)]
+pub enum Permission {
+    ManageMembers = 0,
+    SendMessages = 1,
+    DeleteMessages = 2,
+    RenameGroup = 3,
+    ManagePermissions = 4,
+    PinMessages = 5,
+    ManageAdmins = 6,
+    BypassSlowMode = 7,
+}
+
+impl Permission {
+    /// Every permission known to this client, new ones get the next free code
+    pub const ALL: [Permission; 8] = [
+        Permission::ManageMembers,
+        Permission::SendMessages,
+        Permission::DeleteMessages,
+        Permission::RenameGroup,
+        Permission::ManagePermissions,
+        Permission::PinMessages,
+        Permission::ManageAdmins,
+        Permission::BypassSlowMode,
+    ];
+
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    pub fn from_code(code: u16) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.code() == code)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::ManageMembers => "manage_members",
+            Permission::SendMessages => "send_messages",
+            Permission::DeleteMessages => "delete_messages",
+            Permission::RenameGroup => "rename_group",
+            Permission::ManagePermissions => "manage_permissions",
+            Permission::PinMessages => "pin_messages",
+            Permission::ManageAdmins => "manage_admins",
+            Permission::BypassSlowMode => "bypass_slow_mode",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.as_str() == value)
+    }
+}
+
+// Набор прав участника
+#[derive(Debug, Clone, PartialEq, Eq, MlsSize, MlsEncode)]
+pub struct Permissions {
+    // sorted, deduplicated permission codes
+    granted: Vec<u16>,
+}
+
+impl Permissions {
+    /// An empty set
+    pub fn none() -> Self {
+        Self {
+            granted: Vec::new(),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn default() -> Self {
+        Self::from_iter([Permission::ManageMembers, Permission::SendMessages])
+    }
+
+    pub fn admin() -> Self {
+        Self::from_iter(Permission::ALL)
+    }
+
+    pub fn moderator() -> Self {
+        Self::from_iter([
+            Permission::ManageMembers,
+            Permission::SendMessages,
+            Permission::DeleteMessages,
+            Permission::PinMessages,
+            Permission::BypassSlowMode,
+        ])
+    }
+
+    pub fn member() -> Self {
+        Self::from_iter([Permission::SendMessages])
+    }
+
+    pub fn reader() -> Self {
+        Self::none()
+    }
+
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.granted.binary_search(&permission.code()).is_ok()
+    }
+
+    pub fn set(&mut self, permission: Permission, value: bool) {
+        match (self.granted.binary_search(&permission.code()), value) {
+            (Err(index), true) => self.granted.insert(index, permission.code()),
+            (Ok(index), false) => {
+                self.granted.remove(index);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn with(mut self, permission: Permission, value: bool) -> Self {
+        self.set(permission, value);
+        self
+    }
+
+    /// Known permissions in this set
+    pub fn iter(&self) -> impl Iterator<Item = Permission> + '_ {
+        self.granted
+            .iter()
+            .filter_map(|code| Permission::from_code(*code))
+    }
+
+    /// Permissions granted by either set, unknown codes included
+    pub fn union(&self, other: &Permissions) -> Self {
+        let mut granted = self.granted.clone();
+        granted.extend_from_slice(&other.granted);
+        granted.sort_unstable();
+        granted.dedup();
+        Self { granted }
+    }
+}
+
+// Список приходит от других участников: без сортировки binary_search ошибается
+impl MlsDecode for Permissions {
+    fn mls_decode(reader: &mut &[u8]) -> Result<Self, mls_rs_codec::Error> {
+        let mut granted = Vec::<u16>::mls_decode(reader)?;
+        granted.sort_unstable();
+        granted.dedup();
+        Ok(Self { granted })
+    }
+}
+
+impl FromIterator<Permission> for Permissions {
+    fn from_iter<T: IntoIterator<Item = Permission>>(iter: T) -> Self {
+        let mut granted: Vec<u16> = iter.into_iter().map(Permission::code).collect();
+        granted.sort_unstable();
+        granted.dedup();
+        Self { granted }
+    }
+}
+
+// Для UI права передаются как объект { "send_messages": true, ... },
+// неизвестные коды как { "42": true }
+impl Serialize for Permissions {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut values: BTreeMap<String, bool> = Permission::ALL
+            .into_iter()
+            .map(|p| (p.as_str().to_string(), self.has_permission(p)))
+            .collect();
+        for code in &self.granted {
+            if Permission::from_code(*code).is_none() {
+                values.insert(code.to_string(), true);
+            }
+        }
+        values.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Permissions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let values = BTreeMap::<String, bool>::deserialize(deserializer)?;
+        let mut granted = Vec::new();
+        for (name, value) in values {
+            if !value {
+                continue;
+            }
+            let code = match Permission::parse(&name) {
+                Some(permission) => permission.code(),
+                None => name
+                    .parse::<u16>()
+                    .map_err(|_| D::Error::custom(format!("Unknown permission {:?}", name)))?,
+            };
+            granted.push(code);
+        }
+        granted.sort_unstable();
+        granted.dedup();
+        Ok(Self { granted })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_permission_codes_survive_union() {
+        let future = Permissions {
+            granted: vec![Permission::SendMessages.code(), 42],
+        };
+        let merged = Permissions::member().union(&future);
+        assert!(merged.has_permission(Permission::SendMessages));
+        assert_eq!(merged.granted, vec![Permission::SendMessages.code(), 42]);
+        assert_eq!(merged.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_decode_normalizes_codes() {
+        let unsorted = Permissions {
+            granted: vec![Permission::PinMessages.code(), 42, 1, 1],
+        };
+        let bytes = unsorted.mls_encode_to_vec().unwrap();
+        let decoded = Permissions::mls_decode(&mut &*bytes).unwrap();
+        assert_eq!(decoded.granted, vec![1, Permission::PinMessages.code(), 42]);
+        assert!(decoded.has_permission(Permission::SendMessages));
+        assert!(decoded.has_permission(Permission::PinMessages));
+    }
+
+    #[test]
+    fn test_unknown_permission_codes_survive_json() {
+        let future = Permissions {
+            granted: vec![Permission::SendMessages.code(), 42],
+        };
+        let json = serde_json::to_value(&future).unwrap();
+        assert_eq!(json["send_messages"], true);
+        assert_eq!(json["pin_messages"], false);
+        assert_eq!(json["42"], true);
+
+        let parsed: Permissions = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, future);
+        assert!(serde_json::from_str::<Permissions>(r#"{"fly": true}"#).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use super::{
     custom_mls::{identity::CustomIdentityProvider, rules::CustomMlsRules},
     errors::GroupError,
+    extensions::group_config::group_config::GroupConfig,
 };
 
 use crate::api::device::{db::GroupManager, mls_client::MlsClient};
@@ -13,7 +14,7 @@ use mls_rs_crypto_awslc::AwsLcCryptoProvider;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::{collections::HashMap, fmt::Display};
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, OwnedMutexGuard, RwLock};
 pub type MlsGroup = Group<
     WithCryptoProvider<
         AwsLcCryptoProvider,
@@ -63,6 +64,8 @@ impl From<Vec<u8>> for GroupId {
 pub struct GroupStorage {
     groups: Arc<RwLock<HashMap<GroupId, Arc<RwLock<MlsGroup>>>>>,
     pub messages: GroupManager,
+    /// Held while an inbound message is processed, see [`GroupStorage::lock_processing`]
+    processing: Arc<Mutex<()>>,
 }
 
 impl GroupStorage {
@@ -73,6 +76,7 @@ impl GroupStorage {
         Ok(Self {
             groups: Arc::new(RwLock::new(HashMap::new())),
             messages: group_manager,
+            processing: Arc::new(Mutex::new(())),
         })
     }
 
@@ -97,6 +101,28 @@ impl GroupStorage {
             .ok_or_else(|| GroupError::GroupNotFound(group_id.clone()))
     }
 
+    /// Move a disbanded group to the read-only archive
+    ///
+    /// The group stops being active; its local history stays readable.
+    pub async fn archive(
+        &self,
+        group_id: &GroupId,
+        config: &GroupConfig,
+    ) -> Result<(), GroupError> {
+        self.messages
+            .archive_group(group_id.as_bytes(), config)
+            .await?;
+        self.remove(group_id).await
+    }
+
+    /// Wait for the inbound message being processed and hold back the next ones
+    ///
+    /// Keeps the database and the MLS state consistent with each other while
+    /// the guard lives.
+    pub async fn lock_processing(&self) -> OwnedMutexGuard<()> {
+        self.processing.clone().lock_owned().await
+    }
+
     pub async fn list_groups(&self) -> Vec<GroupId> {
         let groups = self.groups.read().await;
         groups.keys().cloned().collect()
@@ -107,10 +133,16 @@ impl GroupStorage {
             .group_state_storage()
             .group_ids()
             .map_err(|e| GroupError::StorageError(format!("Failed to get group ids: {}", e)))?;
+        let archived = self.messages.get_archived_group_ids().await?;
         for group_id in group_ids {
+            if archived.contains(&group_id) {
+                continue;
+            }
             let group = client.load_group(&group_id)
                 //.await
                 ?;
+            // Groups joined before read positions were stored start as read
+            self.messages.seed_read_position(&group_id).await?;
             let group_id = GroupId(group_id);
             self.insert(group_id, group).await
         }
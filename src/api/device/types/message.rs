@@ -1,5 +1,18 @@
 use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
 
+use super::signature_bytes::DeleteMessageTBS;
+use super::text_entity::{TextEntity, decode_entities, encode_entities};
+
+/// End of a field of `len` bytes starting at `offset`, if it fits in `data`
+///
+/// Lengths come from the sender, so the arithmetic must not overflow.
+fn field_end(data: &[u8], offset: usize, len: u64) -> Option<usize> {
+    usize::try_from(len)
+        .ok()?
+        .checked_add(offset)
+        .filter(|end| *end <= data.len())
+}
+
 #[derive(IntoBytes, FromBytes, Clone, Copy, Immutable, KnownLayout)]
 #[repr(C)]
 pub struct GroupTextMessageHeader {
@@ -15,6 +28,24 @@ pub struct GroupTextMessageHeader {
     pub edit_date: i64,
 }
 
+// Records of the extension block that follows the media name of a text
+// message: `tag: u16`, `len: u32` (little endian), then `len` bytes. The block
+// itself is prefixed with its `u64` length. Older clients stop reading after
+// the media name, newer ones skip tags they do not know.
+const TEXT_EXTENSION_MENTIONS: u16 = 1;
+const TEXT_EXTENSION_ENTITIES: u16 = 2;
+
+/// Mention of a group member
+///
+/// `offset` and `length` are a UTF-8 byte range of the message text.
+#[derive(IntoBytes, FromBytes, Debug, Clone, Copy, PartialEq, Eq, Immutable, KnownLayout)]
+#[repr(C)]
+pub struct Mention {
+    pub user_id: i64,
+    pub offset: u32,
+    pub length: u32,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct GroupTextMessage {
     pub message_id: i64,
@@ -27,9 +58,18 @@ pub struct GroupTextMessage {
     pub reply_message_id: Option<i64>,
     pub expires: Option<i64>,
     pub edit_date: Option<i64>,
+    pub mentions: Vec<Mention>,
+    pub entities: Vec<TextEntity>,
 }
 
 impl GroupTextMessage {
+    /// Whether the message mentions `user_id`
+    pub fn mentions_user(&self, user_id: i64) -> bool {
+        self.mentions
+            .iter()
+            .any(|mention| mention.user_id == user_id)
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let group_id_bytes = self.group_id.as_bytes();
         let text_bytes = self.text.as_bytes();
@@ -59,6 +99,27 @@ impl GroupTextMessage {
         bytes.extend_from_slice(text_bytes);
         bytes.extend_from_slice(media_bytes);
         bytes.extend_from_slice(media_name_bytes);
+
+        let mut extensions = Vec::new();
+        if !self.mentions.is_empty() {
+            write_text_extension(
+                &mut extensions,
+                TEXT_EXTENSION_MENTIONS,
+                self.mentions.as_bytes(),
+            );
+        }
+        if !self.entities.is_empty() {
+            write_text_extension(
+                &mut extensions,
+                TEXT_EXTENSION_ENTITIES,
+                &encode_entities(&self.entities),
+            );
+        }
+        // Без упоминаний и разметки байты совпадают со старым форматом
+        if !extensions.is_empty() {
+            bytes.extend_from_slice(&(extensions.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(&extensions);
+        }
         bytes
     }
 
@@ -73,27 +134,21 @@ impl GroupTextMessage {
 
         let mut offset = header_size;
 
-        let group_id_end = offset + header.group_id_len as usize;
-        if group_id_end > data.len() {
-            return Err("Data too short for group_id".to_string());
-        }
+        let group_id_end = field_end(data, offset, header.group_id_len)
+            .ok_or("Data too short for group_id".to_string())?;
         let group_id = String::from_utf8(data[offset..group_id_end].to_vec())
             .map_err(|e| format!("Invalid UTF-8 in group_id: {}", e))?;
         offset = group_id_end;
 
-        let text_end = offset + header.text_len as usize;
-        if text_end > data.len() {
-            return Err("Data too short for text".to_string());
-        }
+        let text_end = field_end(data, offset, header.text_len)
+            .ok_or("Data too short for text".to_string())?;
         let text = String::from_utf8(data[offset..text_end].to_vec())
             .map_err(|e| format!("Invalid UTF-8 in text: {}", e))?;
         offset = text_end;
 
         let media = if header.media_len > 0 {
-            let media_end = offset + header.media_len as usize;
-            if media_end > data.len() {
-                return Err("Data too short for media".to_string());
-            }
+            let media_end = field_end(data, offset, header.media_len)
+                .ok_or("Data too short for media".to_string())?;
             let media_bytes = data[offset..media_end].to_vec();
             offset = media_end;
             Some(media_bytes)
@@ -102,17 +157,50 @@ impl GroupTextMessage {
         };
 
         let media_name = if header.media_name_len > 0 {
-            let media_name_end = offset + header.media_name_len as usize;
-            if media_name_end > data.len() {
-                return Err("Data too short for media_name".to_string());
-            }
+            let media_name_end = field_end(data, offset, header.media_name_len)
+                .ok_or("Data too short for media_name".to_string())?;
             let name = String::from_utf8(data[offset..media_name_end].to_vec())
                 .map_err(|e| format!("Invalid UTF-8 in media name: {}", e))?;
+            offset = media_name_end;
             Some(name)
         } else {
             None
         };
 
+        let mut mentions = Vec::new();
+        let mut entities = Vec::new();
+        if offset < data.len() {
+            let len_end =
+                field_end(data, offset, 8).ok_or("Data too short for extensions".to_string())?;
+            let mut len_bytes = [0u8; 8];
+            len_bytes.copy_from_slice(&data[offset..len_end]);
+            let extensions_end = field_end(data, len_end, u64::from_le_bytes(len_bytes))
+                .ok_or("Data too short for extensions".to_string())?;
+            let mut extensions = &data[len_end..extensions_end];
+            while !extensions.is_empty() {
+                let (tag, payload, rest) = read_text_extension(extensions)?;
+                extensions = rest;
+                match tag {
+                    TEXT_EXTENSION_MENTIONS => {
+                        let mention_size = std::mem::size_of::<Mention>();
+                        if payload.len() % mention_size != 0 {
+                            return Err("Invalid mentions length".to_string());
+                        }
+                        mentions = payload
+                            .chunks_exact(mention_size)
+                            .map(|chunk| {
+                                Mention::read_from_bytes(chunk)
+                                    .map_err(|e| format!("Failed to read mention: {}", e))
+                            })
+                            .collect::<Result<Vec<_>, _>>()?;
+                    }
+                    // Диапазоны проверяются по тексту уже при разборе
+                    TEXT_EXTENSION_ENTITIES => entities = decode_entities(payload, &text)?,
+                    _ => {}
+                }
+            }
+        }
+
         Ok(GroupTextMessage {
             message_id: header.message_id,
             group_id,
@@ -136,6 +224,361 @@ impl GroupTextMessage {
             } else {
                 None
             },
+            mentions,
+            entities,
+        })
+    }
+}
+
+fn write_text_extension(bytes: &mut Vec<u8>, tag: u16, payload: &[u8]) {
+    bytes.extend_from_slice(&tag.to_le_bytes());
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(payload);
+}
+
+/// Tag, payload and the remaining records
+fn read_text_extension(data: &[u8]) -> Result<(u16, &[u8], &[u8]), String> {
+    if data.len() < 6 {
+        return Err("Data too short for extension header".to_string());
+    }
+    let tag = u16::from_le_bytes([data[0], data[1]]);
+    let len = u32::from_le_bytes([data[2], data[3], data[4], data[5]]);
+    let end =
+        field_end(data, 6, u64::from(len)).ok_or("Data too short for extension".to_string())?;
+    Ok((tag, &data[6..end], &data[end..]))
+}
+
+#[derive(IntoBytes, FromBytes, Clone, Copy, Immutable, KnownLayout)]
+#[repr(C)]
+pub struct GroupDeleteMessageHeader {
+    pub message_id: i64,
+    pub deleter_id: i64,
+    pub date: i64,
+    pub signature_len: u64,
+}
+
+/// Tombstone removing a message on every member's device
+///
+/// `signature` is made with the deleter's account key over
+/// `DeleteMessageTBS`, which binds the tombstone to the group it is sent to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupDeleteMessage {
+    pub message_id: i64,
+    pub deleter_id: i64,
+    pub date: i64,
+    pub signature: Vec<u8>,
+}
+
+impl GroupDeleteMessage {
+    /// Data covered by `signature`
+    pub fn tbs(&self, group_id: &[u8]) -> DeleteMessageTBS {
+        DeleteMessageTBS {
+            group_id: group_id.to_vec(),
+            message_id: self.message_id as u64,
+            deleter_id: self.deleter_id as u64,
+            date: self.date as u64,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = GroupDeleteMessageHeader {
+            message_id: self.message_id,
+            deleter_id: self.deleter_id,
+            date: self.date,
+            signature_len: self.signature.len() as u64,
+        };
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(&self.signature);
+        bytes
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let header_size = std::mem::size_of::<GroupDeleteMessageHeader>();
+        if data.len() < header_size {
+            return Err("Data too short for header".to_string());
+        }
+
+        let header = GroupDeleteMessageHeader::read_from_bytes(&data[..header_size])
+            .map_err(|e| format!("Failed to read header: {}", e))?;
+
+        let signature_end = field_end(data, header_size, header.signature_len)
+            .ok_or("Data too short for signature".to_string())?;
+
+        Ok(GroupDeleteMessage {
+            message_id: header.message_id,
+            deleter_id: header.deleter_id,
+            date: header.date,
+            signature: data[header_size..signature_end].to_vec(),
+        })
+    }
+}
+
+// Poll flags stored in `GroupPollMessageHeader::flags`
+const POLL_MULTIPLE_CHOICE: u64 = 1;
+const POLL_ANONYMOUS: u64 = 1 << 1;
+
+#[derive(IntoBytes, FromBytes, Clone, Copy, Immutable, KnownLayout)]
+#[repr(C)]
+pub struct GroupPollMessageHeader {
+    pub poll_id: i64,
+    pub sender_id: i64,
+    pub date: i64,
+    pub closes_at: i64,
+    pub flags: u64,
+    pub question_len: u64,
+    pub options_count: u64,
+}
+
+/// Poll posted to a group
+///
+/// Options are addressed by their index. Votes of an anonymous poll are still
+/// sent by authenticated members, only the results hide who voted for what.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupPollMessage {
+    pub poll_id: i64,
+    pub sender_id: i64,
+    pub date: i64,
+    pub question: String,
+    pub options: Vec<String>,
+    pub multiple_choice: bool,
+    pub anonymous: bool,
+    pub closes_at: Option<i64>,
+}
+
+impl GroupPollMessage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.multiple_choice {
+            flags |= POLL_MULTIPLE_CHOICE;
+        }
+        if self.anonymous {
+            flags |= POLL_ANONYMOUS;
+        }
+        let header = GroupPollMessageHeader {
+            poll_id: self.poll_id,
+            sender_id: self.sender_id,
+            date: self.date,
+            closes_at: self.closes_at.unwrap_or(-1),
+            flags,
+            question_len: self.question.len() as u64,
+            options_count: self.options.len() as u64,
+        };
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(self.question.as_bytes());
+        for option in &self.options {
+            bytes.extend_from_slice(&(option.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(option.as_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let header_size = std::mem::size_of::<GroupPollMessageHeader>();
+        if data.len() < header_size {
+            return Err("Data too short for header".to_string());
+        }
+
+        let header = GroupPollMessageHeader::read_from_bytes(&data[..header_size])
+            .map_err(|e| format!("Failed to read header: {}", e))?;
+
+        let mut offset = header_size;
+        let question_end = field_end(data, offset, header.question_len)
+            .ok_or("Data too short for question".to_string())?;
+        let question = String::from_utf8(data[offset..question_end].to_vec())
+            .map_err(|e| format!("Invalid UTF-8 in question: {}", e))?;
+        offset = question_end;
+
+        let mut options = Vec::new();
+        for _ in 0..header.options_count {
+            let len_end =
+                field_end(data, offset, 8).ok_or("Data too short for option length".to_string())?;
+            let mut len_bytes = [0u8; 8];
+            len_bytes.copy_from_slice(&data[offset..len_end]);
+            let option_end = field_end(data, len_end, u64::from_le_bytes(len_bytes))
+                .ok_or("Data too short for option".to_string())?;
+            let option = String::from_utf8(data[len_end..option_end].to_vec())
+                .map_err(|e| format!("Invalid UTF-8 in option: {}", e))?;
+            options.push(option);
+            offset = option_end;
+        }
+
+        Ok(GroupPollMessage {
+            poll_id: header.poll_id,
+            sender_id: header.sender_id,
+            date: header.date,
+            question,
+            options,
+            multiple_choice: header.flags & POLL_MULTIPLE_CHOICE != 0,
+            anonymous: header.flags & POLL_ANONYMOUS != 0,
+            closes_at: if header.closes_at >= 0 {
+                Some(header.closes_at)
+            } else {
+                None
+            },
+        })
+    }
+}
+
+#[derive(IntoBytes, FromBytes, Clone, Copy, Immutable, KnownLayout)]
+#[repr(C)]
+pub struct GroupPollVoteHeader {
+    pub poll_id: i64,
+    pub voter_id: i64,
+    pub date: i64,
+    pub options_count: u64,
+}
+
+/// Vote in a poll, replacing any earlier vote of the same member
+///
+/// An empty `options` list retracts the vote.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupPollVote {
+    pub poll_id: i64,
+    pub voter_id: i64,
+    pub date: i64,
+    pub options: Vec<u32>,
+}
+
+impl GroupPollVote {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = GroupPollVoteHeader {
+            poll_id: self.poll_id,
+            voter_id: self.voter_id,
+            date: self.date,
+            options_count: self.options.len() as u64,
+        };
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(header.as_bytes());
+        for option in &self.options {
+            bytes.extend_from_slice(&option.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let header_size = std::mem::size_of::<GroupPollVoteHeader>();
+        if data.len() < header_size {
+            return Err("Data too short for header".to_string());
+        }
+
+        let header = GroupPollVoteHeader::read_from_bytes(&data[..header_size])
+            .map_err(|e| format!("Failed to read header: {}", e))?;
+
+        let options_end = header
+            .options_count
+            .checked_mul(4)
+            .and_then(|len| field_end(data, header_size, len))
+            .ok_or("Data too short for options".to_string())?;
+        let options = data[header_size..options_end]
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+
+        Ok(GroupPollVote {
+            poll_id: header.poll_id,
+            voter_id: header.voter_id,
+            date: header.date,
+            options,
+        })
+    }
+}
+
+/// Closes a poll before its close time, only its creator may send it
+#[derive(IntoBytes, FromBytes, Debug, Clone, Copy, PartialEq, Immutable, KnownLayout)]
+#[repr(C)]
+pub struct GroupPollClose {
+    pub poll_id: i64,
+    pub closer_id: i64,
+    pub date: i64,
+}
+
+impl GroupPollClose {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let size = std::mem::size_of::<GroupPollClose>();
+        if data.len() < size {
+            return Err("Data too short for poll close".to_string());
+        }
+        GroupPollClose::read_from_bytes(&data[..size])
+            .map_err(|e| format!("Failed to read poll close: {}", e))
+    }
+}
+
+#[derive(IntoBytes, FromBytes, Clone, Copy, Immutable, KnownLayout)]
+#[repr(C)]
+pub struct GroupDraftMessageHeader {
+    pub user_id: i64,
+    pub updated_at: i64,
+    pub reply_message_id: i64,
+    pub group_id_len: u64,
+    pub text_len: u64,
+}
+
+/// Unsent message of a group, synced between the devices of its author
+///
+/// Only travels in the author's self-channel. `group_id` is the group the
+/// draft is written in and `updated_at` is in milliseconds; a cleared draft
+/// is sent as an empty one so it replaces older copies on other devices.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupDraftMessage {
+    pub group_id: Vec<u8>,
+    pub user_id: i64,
+    pub updated_at: i64,
+    pub text: String,
+    pub reply_message_id: Option<i64>,
+}
+
+impl GroupDraftMessage {
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty() && self.reply_message_id.is_none()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = GroupDraftMessageHeader {
+            user_id: self.user_id,
+            updated_at: self.updated_at,
+            reply_message_id: self.reply_message_id.unwrap_or(-1),
+            group_id_len: self.group_id.len() as u64,
+            text_len: self.text.len() as u64,
+        };
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(&self.group_id);
+        bytes.extend_from_slice(self.text.as_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let header_size = std::mem::size_of::<GroupDraftMessageHeader>();
+        if data.len() < header_size {
+            return Err("Data too short for header".to_string());
+        }
+
+        let header = GroupDraftMessageHeader::read_from_bytes(&data[..header_size])
+            .map_err(|e| format!("Failed to read header: {}", e))?;
+
+        let group_id_end = field_end(data, header_size, header.group_id_len)
+            .ok_or("Data too short for draft group id".to_string())?;
+        let text_end = field_end(data, group_id_end, header.text_len)
+            .ok_or("Data too short for draft text".to_string())?;
+        let text = String::from_utf8(data[group_id_end..text_end].to_vec())
+            .map_err(|e| format!("Invalid UTF-8 in draft text: {}", e))?;
+
+        Ok(GroupDraftMessage {
+            group_id: data[header_size..group_id_end].to_vec(),
+            user_id: header.user_id,
+            updated_at: header.updated_at,
+            text,
+            reply_message_id: (header.reply_message_id != -1).then_some(header.reply_message_id),
         })
     }
 }
@@ -143,6 +586,11 @@ impl GroupTextMessage {
 #[derive(Debug, Clone, PartialEq)]
 pub enum UserGroupMessage {
     TextMessage(GroupTextMessage),
+    DeleteMessage(GroupDeleteMessage),
+    Poll(GroupPollMessage),
+    PollVote(GroupPollVote),
+    PollClose(GroupPollClose),
+    Draft(GroupDraftMessage),
 }
 
 impl UserGroupMessage {
@@ -152,12 +600,22 @@ impl UserGroupMessage {
         // Write message type (1 byte)
         let message_type = match self {
             UserGroupMessage::TextMessage(_) => 0u8,
+            UserGroupMessage::DeleteMessage(_) => 1u8,
+            UserGroupMessage::Poll(_) => 2u8,
+            UserGroupMessage::PollVote(_) => 3u8,
+            UserGroupMessage::PollClose(_) => 4u8,
+            UserGroupMessage::Draft(_) => 5u8,
         };
         bytes.push(message_type);
 
         // Write message data
         match self {
             UserGroupMessage::TextMessage(msg) => bytes.extend_from_slice(&msg.to_bytes()),
+            UserGroupMessage::DeleteMessage(msg) => bytes.extend_from_slice(&msg.to_bytes()),
+            UserGroupMessage::Poll(msg) => bytes.extend_from_slice(&msg.to_bytes()),
+            UserGroupMessage::PollVote(msg) => bytes.extend_from_slice(&msg.to_bytes()),
+            UserGroupMessage::PollClose(msg) => bytes.extend_from_slice(&msg.to_bytes()),
+            UserGroupMessage::Draft(msg) => bytes.extend_from_slice(&msg.to_bytes()),
         }
 
         bytes
@@ -175,7 +633,137 @@ impl UserGroupMessage {
             0 => Ok(UserGroupMessage::TextMessage(GroupTextMessage::from_bytes(
                 message_data,
             )?)),
+            1 => Ok(UserGroupMessage::DeleteMessage(
+                GroupDeleteMessage::from_bytes(message_data)?,
+            )),
+            2 => Ok(UserGroupMessage::Poll(GroupPollMessage::from_bytes(
+                message_data,
+            )?)),
+            3 => Ok(UserGroupMessage::PollVote(GroupPollVote::from_bytes(
+                message_data,
+            )?)),
+            4 => Ok(UserGroupMessage::PollClose(GroupPollClose::from_bytes(
+                message_data,
+            )?)),
+            5 => Ok(UserGroupMessage::Draft(GroupDraftMessage::from_bytes(
+                message_data,
+            )?)),
             _ => Err(format!("Unknown message type: {}", message_type)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oversized_counts_are_rejected() {
+        let vote = GroupPollVoteHeader {
+            poll_id: 1,
+            voter_id: 2,
+            date: 3,
+            options_count: u64::MAX / 4 + 1,
+        };
+        assert!(GroupPollVote::from_bytes(vote.as_bytes()).is_err());
+
+        let mut poll = GroupPollMessageHeader {
+            poll_id: 1,
+            sender_id: 2,
+            date: 3,
+            closes_at: -1,
+            flags: 0,
+            question_len: u64::MAX,
+            options_count: 1,
+        };
+        assert!(GroupPollMessage::from_bytes(poll.as_bytes()).is_err());
+
+        poll.question_len = 0;
+        let mut bytes = poll.as_bytes().to_vec();
+        bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(GroupPollMessage::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_draft_rejects_oversized_lengths() {
+        let draft = GroupDraftMessage {
+            group_id: vec![1, 2, 3],
+            user_id: 4,
+            updated_at: 5,
+            text: "draft".to_string(),
+            reply_message_id: Some(6),
+        };
+        let bytes = draft.to_bytes();
+        assert_eq!(GroupDraftMessage::from_bytes(&bytes).unwrap(), draft);
+
+        let mut header = GroupDraftMessageHeader::read_from_bytes(
+            &bytes[..std::mem::size_of::<GroupDraftMessageHeader>()],
+        )
+        .unwrap();
+        header.group_id_len = u64::MAX;
+        assert!(GroupDraftMessage::from_bytes(header.as_bytes()).is_err());
+        header.group_id_len = 0;
+        header.text_len = u64::MAX;
+        assert!(GroupDraftMessage::from_bytes(header.as_bytes()).is_err());
+    }
+
+    fn text_message() -> GroupTextMessage {
+        GroupTextMessage {
+            message_id: 1,
+            group_id: "group".to_string(),
+            sender_id: 2,
+            date: 3,
+            text: "hi @bob".to_string(),
+            media: None,
+            media_name: Some("a.txt".to_string()),
+            reply_message_id: None,
+            expires: None,
+            edit_date: None,
+            mentions: Vec::new(),
+            entities: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_text_message_extensions_are_optional() {
+        let mut message = text_message();
+        // Сообщение без упоминаний читается и старыми клиентами
+        let plain = message.to_bytes();
+        assert_eq!(
+            plain.len(),
+            std::mem::size_of::<GroupTextMessageHeader>() + "group".len() + "hi @bob".len() + 5
+        );
+        assert_eq!(GroupTextMessage::from_bytes(&plain).unwrap(), message);
+
+        message.mentions.push(Mention {
+            user_id: 7,
+            offset: 3,
+            length: 4,
+        });
+        let mut bytes = message.to_bytes();
+        assert_eq!(&bytes[..plain.len()], &plain[..]);
+        assert_eq!(GroupTextMessage::from_bytes(&bytes).unwrap(), message);
+
+        // Неизвестная запись пропускается
+        let extensions_len = bytes.len() - plain.len() - 8;
+        let mut unknown = Vec::new();
+        write_text_extension(&mut unknown, 99, &[1, 2, 3]);
+        bytes.extend_from_slice(&unknown);
+        bytes[plain.len()..plain.len() + 8]
+            .copy_from_slice(&((extensions_len + unknown.len()) as u64).to_le_bytes());
+        assert_eq!(GroupTextMessage::from_bytes(&bytes).unwrap(), message);
+    }
+
+    #[test]
+    fn test_text_message_rejects_oversized_lengths() {
+        let mut bytes = text_message().to_bytes();
+        bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(GroupTextMessage::from_bytes(&bytes).is_err());
+
+        let mut bytes = text_message().to_bytes();
+        bytes.extend_from_slice(&6u64.to_le_bytes());
+        bytes.extend_from_slice(&TEXT_EXTENSION_MENTIONS.to_le_bytes());
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(GroupTextMessage::from_bytes(&bytes).is_err());
+    }
+}
@@ -1,4 +1,5 @@
-use crate::api::device::types::message::GroupTextMessage;
+use crate::api::device::types::message::{GroupTextMessage, Mention};
+use crate::api::device::types::text_entity::TextEntity;
 use std::str::FromStr;
 use tauri::AppHandle;
 use tauri_plugin_fs::{FilePath, FsExt};
@@ -10,6 +11,8 @@ pub struct MessageBuilder {
     reply_to: Option<String>,
     edit_message_id: Option<String>,
     expires_at: Option<i64>,
+    mentions: Vec<Mention>,
+    entities: Vec<TextEntity>,
 }
 
 impl MessageBuilder {
@@ -21,6 +24,8 @@ impl MessageBuilder {
             reply_to: None,
             edit_message_id: None,
             expires_at: None,
+            mentions: Vec::new(),
+            entities: Vec::new(),
         }
     }
 
@@ -44,6 +49,16 @@ impl MessageBuilder {
         self
     }
 
+    pub fn with_mentions(mut self, mentions: Vec<Mention>) -> Self {
+        self.mentions = mentions;
+        self
+    }
+
+    pub fn with_entities(mut self, entities: Vec<TextEntity>) -> Self {
+        self.entities = entities;
+        self
+    }
+
     // Теперь build принимает уже сгенерированный message_id
     pub fn build(
         self,
@@ -107,6 +122,8 @@ impl MessageBuilder {
                 .map_err(|e| format!("Invalid reply_message_id: {}", e))?,
             edit_date,
             expires: self.expires_at,
+            mentions: self.mentions,
+            entities: self.entities,
         })
     }
 }
@@ -1,4 +1,7 @@
+pub mod audit;
+pub mod backup;
 pub mod config;
+pub mod content_policy;
 pub mod custom_mls;
 pub mod errors;
 pub mod extensions;
@@ -6,4 +9,7 @@ pub mod group;
 pub mod identity_keypair;
 pub mod message;
 pub mod message_builder;
+pub mod poll;
+pub mod safety_number;
 pub mod signature_bytes;
+pub mod text_entity;
@@ -0,0 +1,250 @@
+//! Poll validation and vote tallying shared by senders and receivers
+
+use crate::api::device::{
+    db::GroupManager,
+    types::{
+        errors::GroupError,
+        extensions::group_config::group_config::{GroupConfig, Permission},
+        message::{GroupPollMessage, GroupPollVote, UserGroupMessage},
+    },
+};
+
+pub const MAX_POLL_OPTIONS: usize = 10;
+pub const MAX_POLL_TEXT_LEN: usize = 300;
+
+/// Current state and tally of a poll as seen by one member
+#[derive(Debug, Clone)]
+pub struct PollResults {
+    pub poll: GroupPollMessage,
+    // Date the creator closed the poll, if closed before `closes_at`
+    pub closed_at: Option<i64>,
+    pub counts: Vec<u32>,
+    // Voters per option, ordered by user id; empty for anonymous polls
+    pub voters: Vec<Vec<i64>>,
+    pub total_voters: u32,
+    pub own_vote: Vec<u32>,
+}
+
+impl PollResults {
+    pub fn is_closed(&self, now: i64) -> bool {
+        poll_cutoff(&self.poll, self.closed_at).is_some_and(|cutoff| cutoff <= now)
+    }
+}
+
+/// Date after which votes no longer count
+pub fn poll_cutoff(poll: &GroupPollMessage, closed_at: Option<i64>) -> Option<i64> {
+    match (poll.closes_at, closed_at) {
+        (Some(closes_at), Some(closed_at)) => Some(closes_at.min(closed_at)),
+        (closes_at, closed_at) => closes_at.or(closed_at),
+    }
+}
+
+/// Validate a new poll
+///
+/// - Errors: `GroupError::InvalidMessage` describing the problem
+pub fn check_poll(poll: &GroupPollMessage) -> Result<(), GroupError> {
+    if poll.question.trim().is_empty() || poll.question.len() > MAX_POLL_TEXT_LEN {
+        return Err(GroupError::InvalidMessage(
+            "Poll question must be 1 to 300 bytes".to_string(),
+        ));
+    }
+    if poll.options.len() < 2 || poll.options.len() > MAX_POLL_OPTIONS {
+        return Err(GroupError::InvalidMessage(format!(
+            "Poll must have 2 to {} options",
+            MAX_POLL_OPTIONS
+        )));
+    }
+    if poll
+        .options
+        .iter()
+        .any(|option| option.trim().is_empty() || option.len() > MAX_POLL_TEXT_LEN)
+    {
+        return Err(GroupError::InvalidMessage(
+            "Poll option must be 1 to 300 bytes".to_string(),
+        ));
+    }
+    if poll
+        .closes_at
+        .is_some_and(|closes_at| closes_at <= poll.date)
+    {
+        return Err(GroupError::InvalidMessage(
+            "Poll must close after it is posted".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Validate a vote against its poll
+///
+/// - Errors: `GroupError::InvalidMessage` for unknown or repeated options,
+///   several options in a single choice poll, or a vote after the cutoff
+pub fn check_vote(
+    poll: &GroupPollMessage,
+    closed_at: Option<i64>,
+    vote: &GroupPollVote,
+) -> Result<(), GroupError> {
+    if poll_cutoff(poll, closed_at).is_some_and(|cutoff| vote.date > cutoff) {
+        return Err(GroupError::InvalidMessage("Poll is closed".to_string()));
+    }
+    if !poll.multiple_choice && vote.options.len() > 1 {
+        return Err(GroupError::InvalidMessage(
+            "Poll allows a single option".to_string(),
+        ));
+    }
+    for (i, option) in vote.options.iter().enumerate() {
+        if *option as usize >= poll.options.len() {
+            return Err(GroupError::InvalidMessage(format!(
+                "Unknown poll option {}",
+                option
+            )));
+        }
+        if vote.options[..i].contains(option) {
+            return Err(GroupError::InvalidMessage(format!(
+                "Poll option {} repeated",
+                option
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Check a poll, vote or close sent by `sender_id` against the stored polls
+///
+/// Posting a poll needs `send_messages`, any member may vote, and only the
+/// creator may close a poll. Other message kinds pass unchecked.
+pub async fn check_poll_message(
+    messages: &GroupManager,
+    group_id: &[u8],
+    config: &GroupConfig,
+    sender_id: u64,
+    message: &UserGroupMessage,
+) -> Result<(), GroupError> {
+    let (author_id, poll_id) = match message {
+        UserGroupMessage::Poll(poll) => (poll.sender_id, None),
+        UserGroupMessage::PollVote(vote) => (vote.voter_id, Some(vote.poll_id)),
+        UserGroupMessage::PollClose(close) => (close.closer_id, Some(close.poll_id)),
+        UserGroupMessage::TextMessage(_)
+        | UserGroupMessage::DeleteMessage(_)
+        | UserGroupMessage::Draft(_) => return Ok(()),
+    };
+    if author_id != sender_id as i64 {
+        return Err(GroupError::InvalidMessage(
+            "Poll message author does not match its sender".to_string(),
+        ));
+    }
+    if config.is_muted(sender_id) {
+        return Err(GroupError::ConfigError("User is muted".to_string()));
+    }
+
+    let stored = match poll_id {
+        Some(poll_id) => Some(messages.get_poll(group_id, poll_id).await?.ok_or(
+            GroupError::InvalidMessage(format!("Poll {} not found", poll_id)),
+        )?),
+        None => None,
+    };
+    match (message, stored) {
+        (UserGroupMessage::Poll(poll), _) => {
+            if !config.has_permission(sender_id, Permission::SendMessages) {
+                return Err(GroupError::ConfigError(
+                    "User is not allowed to send messages".to_string(),
+                ));
+            }
+            check_poll(poll)
+        }
+        (UserGroupMessage::PollVote(vote), Some((poll, closed_at))) => {
+            check_vote(&poll, closed_at, vote)
+        }
+        (UserGroupMessage::PollClose(_), Some((poll, _))) => {
+            if poll.sender_id != sender_id as i64 {
+                return Err(GroupError::ConfigError(
+                    "Only the creator can close a poll".to_string(),
+                ));
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Count the live votes of a poll
+///
+/// `votes` holds the latest vote of every member before the cutoff; the
+/// result depends only on that set, not on the order votes arrived in.
+pub fn tally(poll: &GroupPollMessage, votes: &[(i64, Vec<u32>)]) -> (Vec<u32>, Vec<Vec<i64>>, u32) {
+    let mut counts = vec![0; poll.options.len()];
+    let mut voters = vec![Vec::new(); poll.options.len()];
+    let mut total_voters = 0;
+    for (voter_id, options) in votes {
+        if options.is_empty() {
+            continue;
+        }
+        total_voters += 1;
+        for option in options {
+            if let Some(count) = counts.get_mut(*option as usize) {
+                *count += 1;
+                if !poll.anonymous {
+                    voters[*option as usize].push(*voter_id);
+                }
+            }
+        }
+    }
+    for option_voters in &mut voters {
+        option_voters.sort_unstable();
+    }
+    if poll.anonymous {
+        voters.clear();
+    }
+    (counts, voters, total_voters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll(multiple_choice: bool) -> GroupPollMessage {
+        GroupPollMessage {
+            poll_id: 1,
+            sender_id: 1,
+            date: 100,
+            question: "Lunch?".to_string(),
+            options: vec!["Pizza".to_string(), "Sushi".to_string()],
+            multiple_choice,
+            anonymous: false,
+            closes_at: Some(200),
+        }
+    }
+
+    #[test]
+    fn test_vote_validation() {
+        let poll = poll(false);
+        let mut vote = GroupPollVote {
+            poll_id: 1,
+            voter_id: 2,
+            date: 150,
+            options: vec![1],
+        };
+        assert!(check_vote(&poll, None, &vote).is_ok());
+        assert!(check_vote(&poll, Some(120), &vote).is_err());
+
+        vote.options = vec![0, 1];
+        assert!(check_vote(&poll, None, &vote).is_err());
+        assert!(check_vote(&self::poll(true), None, &vote).is_ok());
+
+        vote.options = vec![2];
+        assert!(check_vote(&poll, None, &vote).is_err());
+    }
+
+    #[test]
+    fn test_tally_is_order_independent() {
+        let poll = poll(true);
+        let votes = vec![(3, vec![0, 1]), (2, vec![1]), (4, vec![])];
+        let mut reversed = votes.clone();
+        reversed.reverse();
+
+        let (counts, voters, total) = tally(&poll, &votes);
+        assert_eq!(counts, vec![1, 2]);
+        assert_eq!(voters, vec![vec![3], vec![2, 3]]);
+        assert_eq!(total, 2);
+        assert_eq!(tally(&poll, &reversed), (counts, voters, total));
+    }
+}
@@ -0,0 +1,138 @@
+//! Safety numbers used to verify contacts out of band
+//!
+//! Each account has a fingerprint derived from its user id and public key.
+//! The safety number of two accounts is both fingerprints shown as digits,
+//! ordered by user id so both sides see the same number.
+
+use sha2::{Digest, Sha512};
+
+use crate::api::device::types::{custom_mls::credentials::AccountCredential, errors::GroupError};
+
+const FINGERPRINT_VERSION: u16 = 0;
+/// Hash iterations, makes finding a key with a colliding number expensive
+const FINGERPRINT_ITERATIONS: usize = 5200;
+const FINGERPRINT_LEN: usize = 32;
+/// Bytes of a fingerprint shown as digits, 5 bytes per group of 5 digits
+const DISPLAYED_LEN: usize = 30;
+const QR_PAYLOAD_VERSION: u8 = 1;
+
+/// Safety number of two accounts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SafetyNumber {
+    /// 60 digits in groups of 5 separated by spaces
+    pub digits: String,
+    /// Payload of the QR code scanned by the other side
+    pub qr_payload: Vec<u8>,
+}
+
+/// Fingerprint of an account: iterated SHA-512 of its id and public key
+pub fn fingerprint(credential: &AccountCredential) -> [u8; FINGERPRINT_LEN] {
+    let public_key = credential.public_key.as_bytes();
+    let mut hasher = Sha512::new();
+    hasher.update(FINGERPRINT_VERSION.to_be_bytes());
+    hasher.update(public_key);
+    hasher.update(credential.account_id.user_id.to_be_bytes());
+    let mut digest = hasher.finalize();
+
+    for _ in 0..FINGERPRINT_ITERATIONS {
+        let mut hasher = Sha512::new();
+        hasher.update(digest);
+        hasher.update(public_key);
+        digest = hasher.finalize();
+    }
+
+    let mut fingerprint = [0u8; FINGERPRINT_LEN];
+    fingerprint.copy_from_slice(&digest[..FINGERPRINT_LEN]);
+    fingerprint
+}
+
+/// Safety number shared by `local` and `remote`, the same on both sides
+///
+/// QR payload: version byte, then user id (big endian) and fingerprint of
+/// each account, lower user id first.
+pub fn safety_number(local: &AccountCredential, remote: &AccountCredential) -> SafetyNumber {
+    let mut accounts = [
+        (local.account_id.user_id, fingerprint(local)),
+        (remote.account_id.user_id, fingerprint(remote)),
+    ];
+    accounts.sort_by_key(|(user_id, _)| *user_id);
+
+    let mut groups = Vec::new();
+    let mut qr_payload = vec![QR_PAYLOAD_VERSION];
+    for (user_id, fingerprint) in &accounts {
+        for chunk in fingerprint[..DISPLAYED_LEN].chunks(5) {
+            let value = chunk.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+            groups.push(format!("{:05}", value % 100_000));
+        }
+        qr_payload.extend_from_slice(&user_id.to_be_bytes());
+        qr_payload.extend_from_slice(fingerprint);
+    }
+
+    SafetyNumber {
+        digits: groups.join(" "),
+        qr_payload,
+    }
+}
+
+/// Check a scanned QR payload against the expected safety number
+///
+/// - Errors: `GroupError::InvalidMessage` for a malformed payload,
+///   `GroupError::CryptoError` if it belongs to other keys
+pub fn verify_qr_payload(
+    local: &AccountCredential,
+    remote: &AccountCredential,
+    payload: &[u8],
+) -> Result<(), GroupError> {
+    if payload.len() != 1 + 2 * (8 + FINGERPRINT_LEN) || payload[0] != QR_PAYLOAD_VERSION {
+        return Err(GroupError::InvalidMessage(
+            "Unsupported safety number code".to_string(),
+        ));
+    }
+    if safety_number(local, remote).qr_payload != payload {
+        return Err(GroupError::CryptoError(
+            "Safety number does not match".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::device::types::custom_mls::credentials::AccountId;
+    use mls_rs_core::crypto::SignaturePublicKey;
+
+    fn credential(user_id: u64, key: u8) -> AccountCredential {
+        AccountCredential {
+            account_id: AccountId {
+                user_id,
+                public_address: format!("user{}", user_id),
+            },
+            public_key: SignaturePublicKey::new(vec![key; 32]),
+            cert: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_safety_number_is_symmetric() {
+        let alice = credential(1, 0xA1);
+        let bob = credential(2, 0xB2);
+
+        let number = safety_number(&alice, &bob);
+        assert_eq!(number, safety_number(&bob, &alice));
+        assert_eq!(number.digits.len(), 12 * 5 + 11);
+        assert!(verify_qr_payload(&bob, &alice, &number.qr_payload).is_ok());
+
+        // Другой ключ у Боба даёт другой номер
+        let mallory = credential(2, 0xC3);
+        assert_ne!(safety_number(&alice, &mallory).digits, number.digits);
+        assert!(matches!(
+            verify_qr_payload(&alice, &mallory, &number.qr_payload),
+            Err(GroupError::CryptoError(_))
+        ));
+        assert!(matches!(
+            verify_qr_payload(&alice, &bob, &number.qr_payload[1..]),
+            Err(GroupError::InvalidMessage(_))
+        ));
+    }
+}
@@ -19,3 +19,11 @@ pub struct InitGroupStreamTBS {
     pub device_id: String,
     pub date: u64,
 }
+
+#[derive(MlsDecode, MlsEncode, MlsSize)]
+pub struct DeleteMessageTBS {
+    pub group_id: Vec<u8>,
+    pub message_id: u64,
+    pub deleter_id: u64,
+    pub date: u64,
+}
@@ -0,0 +1,240 @@
+//! Formatting entities carried inside the encrypted text message payload
+//!
+//! An entity marks a UTF-8 byte range of the message text. Entities may nest
+//! or overlap; every client renders the same ranges since they travel with
+//! the message instead of being parsed out of the text.
+
+use serde::{Deserialize, Serialize};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
+pub const MAX_TEXT_ENTITIES: usize = 100;
+pub const MAX_ENTITY_URL_LEN: usize = 2048;
+pub const MAX_PRE_LANGUAGE_LEN: usize = 32;
+
+const URL_SCHEMES: [&str; 3] = ["https://", "http://", "mailto:"];
+
+// Тип сущности форматирования
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TextEntityKind {
+    Bold,
+    Italic,
+    Code,
+    Pre { language: Option<String> },
+    Link { url: String },
+    Spoiler,
+}
+
+impl TextEntityKind {
+    fn code(&self) -> u16 {
+        match self {
+            TextEntityKind::Bold => 0,
+            TextEntityKind::Italic => 1,
+            TextEntityKind::Code => 2,
+            TextEntityKind::Pre { .. } => 3,
+            TextEntityKind::Link { .. } => 4,
+            TextEntityKind::Spoiler => 5,
+        }
+    }
+
+    fn value(&self) -> &str {
+        match self {
+            TextEntityKind::Pre { language } => language.as_deref().unwrap_or(""),
+            TextEntityKind::Link { url } => url,
+            _ => "",
+        }
+    }
+
+    fn from_code(code: u16, value: String) -> Result<Self, String> {
+        Ok(match code {
+            0 => TextEntityKind::Bold,
+            1 => TextEntityKind::Italic,
+            2 => TextEntityKind::Code,
+            3 => TextEntityKind::Pre {
+                language: (!value.is_empty()).then_some(value),
+            },
+            4 => TextEntityKind::Link { url: value },
+            5 => TextEntityKind::Spoiler,
+            _ => return Err(format!("Unknown text entity type: {}", code)),
+        })
+    }
+}
+
+#[derive(IntoBytes, FromBytes, Clone, Copy, Immutable, KnownLayout)]
+#[repr(C)]
+pub struct TextEntityHeader {
+    pub offset: u32,
+    pub length: u32,
+    pub kind: u16,
+    pub reserved: u16,
+    pub value_len: u32,
+}
+
+/// Formatting applied to `length` bytes of the text starting at `offset`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextEntity {
+    pub offset: u32,
+    pub length: u32,
+    #[serde(flatten)]
+    pub kind: TextEntityKind,
+}
+
+impl TextEntity {
+    pub fn write(&self, bytes: &mut Vec<u8>) {
+        let value = self.kind.value().as_bytes();
+        let header = TextEntityHeader {
+            offset: self.offset,
+            length: self.length,
+            kind: self.kind.code(),
+            reserved: 0,
+            value_len: value.len() as u32,
+        };
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(value);
+    }
+
+    /// Read one entity from the start of `data`, returning it with the bytes consumed
+    pub fn read(data: &[u8]) -> Result<(Self, usize), String> {
+        let header_size = std::mem::size_of::<TextEntityHeader>();
+        if data.len() < header_size {
+            return Err("Data too short for text entity".to_string());
+        }
+        let header = TextEntityHeader::read_from_bytes(&data[..header_size])
+            .map_err(|e| format!("Failed to read text entity: {}", e))?;
+        let value_end = header_size + header.value_len as usize;
+        if value_end > data.len() {
+            return Err("Data too short for text entity value".to_string());
+        }
+        let value = String::from_utf8(data[header_size..value_end].to_vec())
+            .map_err(|e| format!("Invalid UTF-8 in text entity value: {}", e))?;
+
+        Ok((
+            TextEntity {
+                offset: header.offset,
+                length: header.length,
+                kind: TextEntityKind::from_code(header.kind, value)?,
+            },
+            value_end,
+        ))
+    }
+
+    /// Check the entity against the text it formats
+    pub fn validate(&self, text: &str) -> Result<(), String> {
+        let start = self.offset as usize;
+        let end = start + self.length as usize;
+        if self.length == 0
+            || end > text.len()
+            || !text.is_char_boundary(start)
+            || !text.is_char_boundary(end)
+        {
+            return Err(format!(
+                "Text entity range {}..{} is outside the text",
+                start, end
+            ));
+        }
+        match &self.kind {
+            TextEntityKind::Link { url } => {
+                if url.len() > MAX_ENTITY_URL_LEN
+                    || !URL_SCHEMES
+                        .iter()
+                        .any(|scheme| url.len() > scheme.len() && url.starts_with(scheme))
+                {
+                    return Err(format!("Invalid link URL: {}", url));
+                }
+            }
+            TextEntityKind::Pre {
+                language: Some(language),
+            } => {
+                if language.len() > MAX_PRE_LANGUAGE_LEN
+                    || !language
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "+-#._".contains(c))
+                {
+                    return Err(format!("Invalid code block language: {}", language));
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Encode a list of entities without a count prefix
+pub fn encode_entities(entities: &[TextEntity]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for entity in entities {
+        entity.write(&mut bytes);
+    }
+    bytes
+}
+
+/// Decode entities written by `encode_entities` and validate them against `text`
+pub fn decode_entities(mut data: &[u8], text: &str) -> Result<Vec<TextEntity>, String> {
+    let mut entities = Vec::new();
+    while !data.is_empty() {
+        let (entity, consumed) = TextEntity::read(data)?;
+        entities.push(entity);
+        data = &data[consumed..];
+    }
+    check_entities(&entities, text)?;
+    Ok(entities)
+}
+
+/// Validate a list of entities against `text`
+pub fn check_entities(entities: &[TextEntity], text: &str) -> Result<(), String> {
+    if entities.len() > MAX_TEXT_ENTITIES {
+        return Err(format!(
+            "Message has more than {} text entities",
+            MAX_TEXT_ENTITIES
+        ));
+    }
+    entities.iter().try_for_each(|entity| entity.validate(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entities_roundtrip_and_validation() {
+        let text = "жирный link";
+        let entities = vec![
+            TextEntity {
+                offset: 0,
+                length: 12,
+                kind: TextEntityKind::Bold,
+            },
+            TextEntity {
+                offset: 13,
+                length: 4,
+                kind: TextEntityKind::Link {
+                    url: "https://example.com".to_string(),
+                },
+            },
+            TextEntity {
+                offset: 0,
+                length: 17,
+                kind: TextEntityKind::Pre { language: None },
+            },
+        ];
+        let bytes = encode_entities(&entities);
+        assert_eq!(decode_entities(&bytes, text).unwrap(), entities);
+
+        // Range splitting a UTF-8 character
+        let broken = [TextEntity {
+            offset: 1,
+            length: 2,
+            kind: TextEntityKind::Italic,
+        }];
+        assert!(decode_entities(&encode_entities(&broken), text).is_err());
+
+        let script = [TextEntity {
+            offset: 13,
+            length: 4,
+            kind: TextEntityKind::Link {
+                url: "javascript:alert(1)".to_string(),
+            },
+        }];
+        assert!(check_entities(&script, text).is_err());
+    }
+}
@@ -0,0 +1,68 @@
+//! Contact verification with safety numbers
+//!
+//! A contact is verified for the public key the user compared. The
+//! verification no longer applies once the contact's credential has another
+//! key.
+
+use crate::api::device::{
+    device::Device,
+    types::{
+        errors::GroupError,
+        safety_number::{SafetyNumber, safety_number, verify_qr_payload},
+    },
+};
+
+impl Device {
+    /// Safety number shared with `user_id`
+    pub async fn safety_number(&mut self, user_id: u64) -> Result<SafetyNumber, GroupError> {
+        let contact = self.get_contact(user_id).await?;
+        Ok(safety_number(&self.account.credential, &contact))
+    }
+
+    /// Mark `user_id` as verified with their current key
+    ///
+    /// With a scanned `qr_payload` the safety number is checked, otherwise the
+    /// user confirmed comparing the digits.
+    pub async fn verify_contact(
+        &mut self,
+        user_id: u64,
+        qr_payload: Option<&[u8]>,
+    ) -> Result<(), GroupError> {
+        if user_id == self.user_id() {
+            return Err(GroupError::ConfigError(
+                "Cannot verify yourself".to_string(),
+            ));
+        }
+
+        let contact = self.get_contact(user_id).await?;
+        if let Some(payload) = qr_payload {
+            verify_qr_payload(&self.account.credential, &contact, payload)?;
+        }
+        self.groups
+            .messages
+            .set_contact_verified(user_id as i64, contact.public_key.as_bytes())
+            .await?;
+        log::info!("Contact {} verified", user_id);
+        Ok(())
+    }
+
+    /// Remove the verification of `user_id`
+    pub async fn unverify_contact(&self, user_id: u64) -> Result<(), GroupError> {
+        self.groups
+            .messages
+            .remove_contact_verification(user_id as i64)
+            .await
+    }
+
+    /// Users among `user_ids` verified with the key they have now
+    ///
+    /// Only stored credentials are used, users never fetched are unverified.
+    pub async fn verified_users(&self, user_ids: &[u64]) -> Result<Vec<u64>, GroupError> {
+        self.groups.messages.get_verified_users(user_ids).await
+    }
+
+    /// Whether `user_id` is verified with the key they have now
+    pub async fn is_contact_verified(&self, user_id: u64) -> Result<bool, GroupError> {
+        Ok(!self.verified_users(&[user_id]).await?.is_empty())
+    }
+}
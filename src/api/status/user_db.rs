@@ -1,4 +1,5 @@
 use crate::api::status::types::DisplayUserInfo;
+use crate::api::storage;
 use sqlx::{Row, SqlitePool, sqlite::SqliteConnectOptions};
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -111,20 +112,5 @@ impl UserManager {
 }
 
 pub fn get_default_db_path(account_id: u64) -> std::path::PathBuf {
-    #[cfg(not(target_os = "ios"))]
-    {
-        let mut path = dirs::home_dir().expect("Could not find home directory");
-        path.push(".ship");
-        std::fs::create_dir_all(&path).expect("Could not create .ship directory");
-        path.push(format!("contacts_{}.db", account_id));
-        path
-    }
-    #[cfg(target_os = "ios")]
-    {
-        let mut path = dirs::document_dir().expect("Could not find home directory");
-        path.push(".ship");
-        std::fs::create_dir_all(&path).expect("Could not create .ship directory");
-        path.push(format!("contacts_{}.db", account_id));
-        path
-    }
+    storage::layout().contacts_db(account_id)
 }
@@ -195,6 +195,7 @@ impl UserStatusClient {
 
         let status_cache = self.status_cache.clone();
         let app_handler = self.app_handler.clone();
+        let account_id = self.account.user_id;
         let stream = self.backend.stream.clone();
         log::info!("Stream initialized");
         // Читаем стрим в отдельном таске — не трогаем client вообще
@@ -231,13 +232,15 @@ impl UserStatusClient {
                                 if let Some(app) = &app_handler {
                                     let _ = emit_user_status_event(
                                         app,
+                                        account_id,
                                         DisplayUserStatus {
                                             status: online_status.as_str_name().to_string(),
                                             user_id: status.user_id,
                                             last_seen: seconds,
                                             is_online: online_status != OnlineStatus::Offline,
                                         },
-                                    );
+                                    )
+                                    .await;
                                 }
                             }
                         }
@@ -247,12 +250,14 @@ impl UserStatusClient {
                             if let Some(app) = &app_handler {
                                 let _ = emit_user_typing_status_event(
                                     app,
+                                    account_id,
                                     DisplayUserTypingStatus {
                                         user_id: status.user_id,
                                         chat_id: status.chat_id,
                                         status: typing_status.as_str_name().to_string(),
                                     },
-                                );
+                                )
+                                .await;
                             }
                         }
                         Some(user_status_response::Message::InitStreamResponse(s)) => {
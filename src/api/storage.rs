@@ -0,0 +1,460 @@
+//! Location of the files kept on disk
+//!
+//! Everything lives in a root directory, split into named profiles:
+//!
+//! ```text
+//! <root>/
+//!   storage.json                  config, see StorageConfig
+//!   profiles/<profile>/
+//!     accounts.db
+//!     accounts/<user_id>/
+//!       group.db
+//!       contacts.db
+//!       voice.json
+//!       mls/<device_id>.db
+//! ```
+//!
+//! The root is, by priority: `SHIP_DATA_DIR`, `data` next to the binary in
+//! portable mode (a `portable` file next to the binary), `data_dir` of the
+//! config, then `~/.ship` (`Documents/.ship` on iOS). The profile is
+//! `SHIP_PROFILE`, `profile` of the config or `default`.
+//!
+//! Files of the flat layout used before profiles are moved into the default
+//! profile when it is opened, until all of them were moved.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+pub const DATA_DIR_ENV: &str = "SHIP_DATA_DIR";
+pub const PROFILE_ENV: &str = "SHIP_PROFILE";
+pub const DEFAULT_PROFILE: &str = "default";
+const CONFIG_FILE: &str = "storage.json";
+/// File next to the binary enabling portable mode
+const PORTABLE_MARKER: &str = "portable";
+const PORTABLE_DATA_DIR: &str = "data";
+/// SQLite files kept next to a database
+const SQLITE_SIDE_FILES: [&str; 3] = ["-wal", "-shm", "-journal"];
+/// Legacy roots fully moved into a profile, one path per line
+const MIGRATED_MARKER: &str = "legacy-migrated";
+
+static LAYOUT: OnceLock<StorageLayout> = OnceLock::new();
+
+/// Storage layout of the running app, resolved on first use
+pub fn layout() -> &'static StorageLayout {
+    LAYOUT.get_or_init(|| {
+        let layout = StorageLayout::resolve().expect("Could not resolve the storage directory");
+        if layout.profile == DEFAULT_PROFILE {
+            for legacy_root in legacy_roots() {
+                match layout.migrate_legacy(&legacy_root) {
+                    Ok(0) => {}
+                    Ok(moved) => log::info!(
+                        "Moved {} files from {} to {}",
+                        moved,
+                        legacy_root.display(),
+                        layout.profile_dir().display()
+                    ),
+                    Err(e) => log::error!(
+                        "Failed to migrate storage from {}: {}",
+                        legacy_root.display(),
+                        e
+                    ),
+                }
+            }
+        }
+        layout
+    })
+}
+
+/// `storage.json` in the base directory: the portable directory or `~/.ship`
+///
+/// Changes apply from the next start.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageConfig {
+    pub data_dir: Option<PathBuf>,
+    pub profile: Option<String>,
+}
+
+impl StorageConfig {
+    /// Config of this install, the default one if there is no config file
+    pub fn load() -> io::Result<Self> {
+        let path = config_path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = fs::read(path)?;
+        serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(profile) = &self.profile {
+            check_profile_name(profile)?;
+        }
+        let path = config_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let data = serde_json::to_vec_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, data)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageLayout {
+    root: PathBuf,
+    profile: String,
+    portable: bool,
+}
+
+impl StorageLayout {
+    pub fn new(root: PathBuf, profile: &str, portable: bool) -> io::Result<Self> {
+        check_profile_name(profile)?;
+        Ok(Self {
+            root,
+            profile: profile.to_string(),
+            portable,
+        })
+    }
+
+    /// Layout selected by the environment and the config
+    pub fn resolve() -> io::Result<Self> {
+        let config = StorageConfig::load()?;
+        let portable_dir = portable_dir();
+        let root = match std::env::var_os(DATA_DIR_ENV) {
+            Some(root) if !root.is_empty() => PathBuf::from(root),
+            _ => portable_dir
+                .clone()
+                .or(config.data_dir)
+                .unwrap_or_else(default_root),
+        };
+        let profile = std::env::var(PROFILE_ENV)
+            .ok()
+            .filter(|profile| !profile.is_empty())
+            .or(config.profile)
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+        Self::new(root, &profile, portable_dir.is_some())
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    pub fn is_portable(&self) -> bool {
+        self.portable
+    }
+
+    pub fn profile_dir(&self) -> PathBuf {
+        self.root.join("profiles").join(&self.profile)
+    }
+
+    /// Profiles of the root, by name
+    pub fn profiles(&self) -> io::Result<Vec<String>> {
+        let dir = self.root.join("profiles");
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut profiles = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir()
+                && let Some(name) = entry.file_name().to_str()
+            {
+                profiles.push(name.to_string());
+            }
+        }
+        profiles.sort();
+        Ok(profiles)
+    }
+
+    /// Database of the accounts of the profile
+    pub fn accounts_db(&self) -> PathBuf {
+        create_dir(self.profile_dir()).join("accounts.db")
+    }
+
+    pub fn account_dir(&self, user_id: u64) -> PathBuf {
+        create_dir(
+            self.profile_dir()
+                .join("accounts")
+                .join(user_id.to_string()),
+        )
+    }
+
+    /// Groups and messages of an account
+    pub fn group_db(&self, user_id: u64) -> PathBuf {
+        self.account_dir(user_id).join("group.db")
+    }
+
+    pub fn contacts_db(&self, user_id: u64) -> PathBuf {
+        self.account_dir(user_id).join("contacts.db")
+    }
+
+    pub fn voice_file(&self, user_id: u64) -> PathBuf {
+        self.account_dir(user_id).join("voice.json")
+    }
+
+    /// MLS state of one device of an account
+    pub fn mls_db(&self, user_id: u64, device_id: &str) -> PathBuf {
+        create_dir(self.account_dir(user_id).join("mls")).join(format!("{}.db", device_id))
+    }
+
+    /// Move the files of the flat layout in `legacy_root` into this profile
+    ///
+    /// Existing files of the profile are never overwritten. The root is
+    /// marked migrated once every file was moved; until then each call moves
+    /// what is left, so an interrupted migration resumes on the next start.
+    /// Returns the number of files moved.
+    pub fn migrate_legacy(&self, legacy_root: &Path) -> io::Result<usize> {
+        if !legacy_root.is_dir() || self.is_migrated(legacy_root)? {
+            return Ok(0);
+        }
+
+        let mut moved = 0;
+        for entry in fs::read_dir(legacy_root)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            let target = if name == "accounts.db" {
+                Some(self.accounts_db())
+            } else if let Some(user_id) = parse_user_file(&name, "group_", ".db") {
+                Some(self.group_db(user_id))
+            } else if let Some(user_id) = parse_user_file(&name, "contacts_", ".db") {
+                Some(self.contacts_db(user_id))
+            } else if let Some(user_id) = parse_user_file(&name, "voice_", ".json") {
+                Some(self.voice_file(user_id))
+            } else {
+                None
+            };
+            if let Some(target) = target {
+                moved += move_database(&entry.path(), &target)?;
+            }
+        }
+
+        // MLS state: group/group_<user_id>_<device_id>.db
+        let legacy_mls = legacy_root.join("group");
+        if legacy_mls.is_dir() {
+            for entry in fs::read_dir(&legacy_mls)? {
+                let entry = entry?;
+                let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                    continue;
+                };
+                let Some((user_id, device_id)) = name
+                    .strip_prefix("group_")
+                    .and_then(|rest| rest.strip_suffix(".db"))
+                    .and_then(|rest| rest.split_once('_'))
+                else {
+                    continue;
+                };
+                if let Ok(user_id) = user_id.parse::<u64>() {
+                    moved += move_database(&entry.path(), &self.mls_db(user_id, device_id))?;
+                }
+            }
+            // Остаются только чужие файлы — тогда папку не трогаем
+            let _ = fs::remove_dir(&legacy_mls);
+        }
+
+        let mut marker = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(create_dir(self.profile_dir()).join(MIGRATED_MARKER))?;
+        writeln!(marker, "{}", legacy_root.display())?;
+        marker.sync_all()?;
+        Ok(moved)
+    }
+
+    fn is_migrated(&self, legacy_root: &Path) -> io::Result<bool> {
+        match fs::read_to_string(self.profile_dir().join(MIGRATED_MARKER)) {
+            Ok(migrated) => Ok(migrated.lines().any(|root| Path::new(root) == legacy_root)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn check_profile_name(profile: &str) -> io::Result<()> {
+    let valid = !profile.is_empty()
+        && profile.len() <= 64
+        && profile
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid profile name: {:?}", profile),
+        ));
+    }
+    Ok(())
+}
+
+fn create_dir(path: PathBuf) -> PathBuf {
+    fs::create_dir_all(&path).expect("Could not create storage directory");
+    path
+}
+
+/// `~/.ship`, `Documents/.ship` on iOS
+fn default_root() -> PathBuf {
+    #[cfg(not(target_os = "ios"))]
+    {
+        dirs::home_dir()
+            .expect("Could not find home directory")
+            .join(".ship")
+    }
+    #[cfg(target_os = "ios")]
+    {
+        dirs::document_dir()
+            .expect("Could not find documents directory")
+            .join(".ship")
+    }
+}
+
+/// Data directory next to the binary if portable mode is enabled
+fn portable_dir() -> Option<PathBuf> {
+    let exe_dir = std::env::current_exe().ok()?.parent()?.to_path_buf();
+    exe_dir
+        .join(PORTABLE_MARKER)
+        .exists()
+        .then(|| exe_dir.join(PORTABLE_DATA_DIR))
+}
+
+fn config_path() -> PathBuf {
+    portable_dir()
+        .unwrap_or_else(default_root)
+        .join(CONFIG_FILE)
+}
+
+/// Directories of the flat layout, iOS kept some databases in `ship`
+fn legacy_roots() -> Vec<PathBuf> {
+    #[cfg(not(target_os = "ios"))]
+    {
+        vec![default_root()]
+    }
+    #[cfg(target_os = "ios")]
+    {
+        let documents = dirs::document_dir().expect("Could not find documents directory");
+        vec![documents.join(".ship"), documents.join("ship")]
+    }
+}
+
+/// User id of `<prefix><user_id><suffix>`
+fn parse_user_file(name: &str, prefix: &str, suffix: &str) -> Option<u64> {
+    name.strip_prefix(prefix)?
+        .strip_suffix(suffix)?
+        .parse()
+        .ok()
+}
+
+/// Move a file with its SQLite side files, returning the number moved
+fn move_database(from: &Path, to: &Path) -> io::Result<usize> {
+    let mut moved = 0;
+    for side in std::iter::once("").chain(SQLITE_SIDE_FILES) {
+        let from = PathBuf::from(format!("{}{}", from.display(), side));
+        if !from.exists() {
+            continue;
+        }
+        let to = PathBuf::from(format!("{}{}", to.display(), side));
+        if to.exists() {
+            log::warn!("Not moving {}, {} exists", from.display(), to.display());
+            continue;
+        }
+        // rename не работает между дисками, например для portable на флешке
+        if fs::rename(&from, &to).is_err() {
+            fs::copy(&from, &to)?;
+            fs::remove_file(&from)?;
+        }
+        moved += 1;
+    }
+    Ok(moved)
+}
+
+/// Replace the database `to` and drop its SQLite side files, which belong to
+/// the old contents
+pub fn replace_database(from: &Path, to: &Path) -> io::Result<()> {
+    for side in SQLITE_SIDE_FILES {
+        let side = PathBuf::from(format!("{}{}", to.display(), side));
+        if side.exists() {
+            fs::remove_file(&side)?;
+        }
+    }
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_legacy_layout() {
+        let base = std::env::temp_dir().join(format!("ship-storage-{}", std::process::id()));
+        let legacy = base.join("legacy");
+        fs::create_dir_all(legacy.join("group")).unwrap();
+        for name in [
+            "accounts.db",
+            "accounts.db-wal",
+            "group_7.db",
+            "contacts_7.db",
+            "voice_7.json",
+            "notes.txt",
+        ] {
+            fs::write(legacy.join(name), name).unwrap();
+        }
+        fs::write(legacy.join("group/group_7_a1b2-c3.db"), "mls").unwrap();
+
+        let layout = StorageLayout::new(base.join("root"), DEFAULT_PROFILE, false).unwrap();
+        assert_eq!(layout.migrate_legacy(&legacy).unwrap(), 6);
+
+        assert_eq!(fs::read(layout.accounts_db()).unwrap(), b"accounts.db");
+        assert!(layout.profile_dir().join("accounts.db-wal").exists());
+        assert_eq!(fs::read(layout.group_db(7)).unwrap(), b"group_7.db");
+        assert_eq!(fs::read(layout.contacts_db(7)).unwrap(), b"contacts_7.db");
+        assert_eq!(fs::read(layout.voice_file(7)).unwrap(), b"voice_7.json");
+        assert_eq!(fs::read(layout.mls_db(7, "a1b2-c3")).unwrap(), b"mls");
+        assert!(legacy.join("notes.txt").exists());
+        assert!(!legacy.join("group").exists());
+
+        // Второй запуск ничего не трогает
+        fs::write(legacy.join("group_8.db"), "late").unwrap();
+        assert_eq!(layout.migrate_legacy(&legacy).unwrap(), 0);
+        assert_eq!(
+            layout.profiles().unwrap(),
+            vec![DEFAULT_PROFILE.to_string()]
+        );
+        assert!(StorageLayout::new(base.join("root"), "../other", false).is_err());
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_legacy_resumes() {
+        let base = std::env::temp_dir().join(format!("ship-resume-{}", std::process::id()));
+        let legacy = base.join("legacy");
+        fs::create_dir_all(&legacy).unwrap();
+        for name in ["accounts.db", "group_7.db", "contacts_7.db"] {
+            fs::write(legacy.join(name), name).unwrap();
+        }
+
+        // Прерванный запуск успел перенести только accounts.db
+        let layout = StorageLayout::new(base.join("root"), DEFAULT_PROFILE, false).unwrap();
+        fs::rename(legacy.join("accounts.db"), layout.accounts_db()).unwrap();
+
+        assert_eq!(layout.migrate_legacy(&legacy).unwrap(), 2);
+        assert_eq!(fs::read(layout.group_db(7)).unwrap(), b"group_7.db");
+        assert_eq!(fs::read(layout.contacts_db(7)).unwrap(), b"contacts_7.db");
+        assert_eq!(layout.migrate_legacy(&legacy).unwrap(), 0);
+
+        fs::remove_dir_all(base).unwrap();
+    }
+}
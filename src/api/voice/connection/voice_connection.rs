@@ -27,6 +27,8 @@ pub struct Backend {
     mls_event_handler: Arc<Mutex<Option<MlsEventHandler>>>,
     commit_response_handler: Arc<Mutex<Option<CommitResponseHandler>>>,
     app_handle: Option<AppHandle>,
+    /// User id of the account events are emitted for
+    account_id: u64,
 }
 
 impl std::fmt::Debug for Backend {
@@ -55,10 +57,11 @@ impl Backend {
             mls_event_handler: Arc::new(Mutex::new(None)),
             commit_response_handler: Arc::new(Mutex::new(None)),
             app_handle: None,
+            account_id: 0,
         }
     }
 
-    pub fn with_app_handle(app_handle: AppHandle) -> Self {
+    pub fn with_app_handle(app_handle: AppHandle, account_id: u64) -> Self {
         let addr = get_avaliable_voice_servers();
         Backend {
             client: Arc::new(Mutex::new(None)),
@@ -68,6 +71,7 @@ impl Backend {
             mls_event_handler: Arc::new(Mutex::new(None)),
             commit_response_handler: Arc::new(Mutex::new(None)),
             app_handle: Some(app_handle),
+            account_id,
         }
     }
 
@@ -396,6 +400,7 @@ impl Backend {
 
         // Запускаем задачу для обработки входящих сообщений
         let app_handle = self.app_handle.clone();
+        let account_id = self.account_id;
         let voice_data_handler = self.voice_data_handler.clone();
         let mls_event_handler = self.mls_event_handler.clone();
         let commit_response_handler = self.commit_response_handler.clone();
@@ -475,7 +480,7 @@ impl Backend {
                                     }
                                     if let Some(app_handle) = &app_handle
                                         && let Err(e) = emit_voice_server_commit(
-                                            app_handle,
+                                            app_handle, account_id,
                                             server_commit.voice_id.clone(),
                                             server_commit.commit_id.clone(),
                                         )
@@ -509,7 +514,7 @@ impl Backend {
                                     if commit_response.accepted
                                         && let Some(app_handle) = &app_handle
                                             && let Err(e) = emit_voice_server_commit(
-                                                app_handle,
+                                                app_handle, account_id,
                                                 commit_response.voice_id.clone(),
                                                 commit_response.commit_id.clone(),
                                             )
@@ -528,7 +533,8 @@ impl Backend {
                         // Emit Tauri event for WebRTC signaling messages
                         if let Some(app_handle) = &app_handle
                             && let Err(e) =
-                                emit_voice_signaling_message(app_handle, server_message).await
+                                emit_voice_signaling_message(app_handle, account_id, server_message)
+                                    .await
                         {
                             log::error!("Failed to emit server-event: {}", e);
                         }
@@ -20,6 +20,7 @@ use tokio::{
 
 use anyhow::Error;
 
+use crate::api::storage;
 use crate::api::voice::types::ratchet_key::GroupRatchetManager;
 use crate::api::voice::types::ratchet_key::RatchetConfig;
 use crate::api::voice::voice_handler::VoiceHandler;
@@ -74,7 +75,7 @@ impl VoiceUser {
             .build();
 
         let backend = if let Some(app_handle) = &app_handle {
-            Backend::with_app_handle(app_handle.clone())
+            Backend::with_app_handle(app_handle.clone(), user_id)
         } else {
             Backend::new()
         };
@@ -153,22 +154,7 @@ impl VoiceUser {
     }
 
     fn get_file_path(user_id: u64) -> PathBuf {
-        #[cfg(not(target_os = "ios"))]
-        {
-            let mut path = dirs::home_dir().expect("Could not find home directory");
-            path.push(".ship");
-            std::fs::create_dir_all(&path).expect("Could not create .ship directory");
-            path.push(format!("voice_{}.json", user_id));
-            path
-        }
-        #[cfg(target_os = "ios")]
-        {
-            let mut path = dirs::document_dir().expect("Could not find home directory");
-            path.push(".ship");
-            std::fs::create_dir_all(&path).expect("Could not create .ship directory");
-            path.push(format!("voice_{}.json", user_id));
-            path
-        }
+        storage::layout().voice_file(user_id)
     }
 
     pub async fn create_voice_channel(&self, voice_id: String) -> Result<Vec<u8>, anyhow::Error> {
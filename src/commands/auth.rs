@@ -1,3 +1,4 @@
+use base64::{Engine as _, engine::general_purpose};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tauri::AppHandle;
@@ -22,6 +23,14 @@ pub struct AccountInfo {
     pub avatar_url: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub user_id: u64,
+    pub username: String,
+    pub server_address: String,
+    pub active: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeviceResponse {
     pub device_id: String,
@@ -41,22 +50,18 @@ use crate::api::account::get_default_db_path;
 
 use crate::api::device::Device;
 use crate::api::voice::VoiceUser;
-use tauri::Manager;
-
-type SafeAccount = Arc<Account>;
-type SafeGroupUser = Arc<RwLock<Option<Device>>>;
-
-use std::panic::{AssertUnwindSafe, catch_unwind};
+use crate::commands::session::{Session, SessionRegistry};
 
 #[tauri::command]
-pub async fn login(app_handle: AppHandle, username: String) -> Result<LoginResponse, String> {
+pub async fn login(
+    app_handle: AppHandle,
+    sessions: tauri::State<'_, SessionRegistry>,
+    username: String,
+) -> Result<LoginResponse, String> {
     let account = Account::load_from_db(username)
         .await
         .map_err(|e| e.to_string())?;
     let account = Arc::new(account);
-    if !app_handle.manage(account.clone()) {
-        log::error!("Failed to manage account")
-    }
 
     let (group_account_result, voice_client_result, user_status_result) = tokio::join!(
         Device::load_from_db(account.clone(), Some(app_handle.clone())),
@@ -74,34 +79,21 @@ pub async fn login(app_handle: AppHandle, username: String) -> Result<LoginRespo
     );
 
     let group_account = group_account_result.map_err(|e| e.to_string())?;
-
-    let state_result = catch_unwind(AssertUnwindSafe(|| {
-        app_handle.state::<Arc<RwLock<Option<Device>>>>()
-    }));
-
-    match state_result {
-        Ok(device_account_state) => {
-            let mut guard = device_account_state.inner().write().await;
-            *guard = Some(group_account);
-        }
-        Err(_) => {
-            log::warn!("State not managed yet, managing now...");
-            let safe_group_user = Arc::new(RwLock::new(Some(group_account)));
-            app_handle.manage(safe_group_user.clone());
-        }
-    }
-
-    let user_status = user_status_result.ok();
-    let safe_user_status = Arc::new(RwLock::new(user_status));
-    if !app_handle.manage(safe_user_status) {
-        log::error!("Failed to manage user status")
-    };
-
     let voice_client = voice_client_result.map_err(|e| e.to_string())?;
-    let safe_voice_client = Arc::new(RwLock::new(voice_client));
-    if !app_handle.manage(safe_voice_client) {
-        log::error!("Failed to manage voice client")
-    };
+    let session = Arc::new(Session {
+        account: account.clone(),
+        group_user: Arc::new(RwLock::new(Some(group_account))),
+        user_status: Arc::new(RwLock::new(user_status_result.ok())),
+        voice_user: Arc::new(RwLock::new(voice_client)),
+    });
+
+    // Повторный вход в тот же аккаунт заменяет его сессию
+    if let Some(previous) = sessions.insert(session.clone()).await {
+        previous.group_user.write().await.take();
+    }
+    if let Some(group_user) = session.group_user.read().await.as_ref() {
+        group_user.start_scheduler();
+    }
 
     Ok(LoginResponse {
         user_id: account.credential.account_id.user_id,
@@ -127,7 +119,6 @@ pub async fn register(
     let device_id = uuid::Uuid::new_v4().to_string();
 
     let account = Arc::new(account);
-    app_handle.manage(account.clone());
 
     let _ = Device::register_new_device(account.clone(), &device_id, Some(app_handle.clone()))
         .await
@@ -137,8 +128,12 @@ pub async fn register(
 }
 
 #[tauri::command]
-pub async fn reconnect(user_account: tauri::State<'_, SafeGroupUser>) -> Result<(), String> {
-    let mut user_account = user_account.write().await;
+pub async fn reconnect(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<(), String> {
+    let session = sessions.get(account_id).await?;
+    let mut user_account = session.group_user.write().await;
     if let Some(user_account) = user_account.as_mut() {
         user_account
             .init_backend()
@@ -150,9 +145,11 @@ pub async fn reconnect(user_account: tauri::State<'_, SafeGroupUser>) -> Result<
 
 #[tauri::command]
 pub async fn export_account(
-    account_state: tauri::State<'_, SafeAccount>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
 ) -> Result<EncryptedExportedAccount, String> {
-    let account_bytes = account_state.to_mls_bytes().map_err(|e| e.to_string())?;
+    let session = sessions.get(account_id).await?;
+    let account_bytes = session.account.to_mls_bytes().map_err(|e| e.to_string())?;
     let exported = ExportedAccount::new(account_bytes);
 
     let (encrypted_data, key) = exported.encrypt().map_err(|e| e.to_string())?;
@@ -185,19 +182,44 @@ pub async fn import_account(
 }
 
 #[tauri::command]
-pub async fn log_out(app_handle: AppHandle) -> Result<String, String> {
-    app_handle
-        .state::<Arc<RwLock<Option<Device>>>>()
-        .inner()
-        .write()
-        .await
-        .take();
-    app_handle.unmanage::<Arc<Account>>(); // Changing account to Account Option 
-    // will cause too many problems
-    // maybe after i will change it
+pub async fn log_out(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<String, String> {
+    let session = sessions.get(account_id).await?;
+    sessions.remove(session.user_id()).await;
+    session.group_user.write().await.take();
     Ok("Logged out successfully".to_string())
 }
 
+/// Accounts logged in, see `set_active_account`
+#[tauri::command]
+pub async fn get_sessions(
+    sessions: tauri::State<'_, SessionRegistry>,
+) -> Result<Vec<SessionInfo>, String> {
+    let active = sessions.active().await;
+    Ok(sessions
+        .accounts()
+        .await
+        .into_iter()
+        .map(|account| SessionInfo {
+            user_id: account.user_id,
+            username: account.username.clone(),
+            server_address: account.server_address.clone(),
+            active: active == Some(account.user_id),
+        })
+        .collect())
+}
+
+/// Account used by commands called without `account_id`
+#[tauri::command]
+pub async fn set_active_account(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: u64,
+) -> Result<(), String> {
+    sessions.set_active(account_id).await
+}
+
 #[tauri::command]
 pub async fn get_account_list() -> Result<Vec<AccountInfo>, String> {
     let account_list = Account::list_accounts().await.map_err(|e| e.to_string())?;
@@ -225,9 +247,11 @@ pub async fn delete_account(username: String) -> Result<String, String> {
 
 #[tauri::command]
 pub async fn get_user_devices(
-    group_user_state: tauri::State<'_, SafeGroupUser>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
 ) -> Result<Vec<DeviceResponse>, String> {
-    let mut group_user = group_user_state.write().await;
+    let session = sessions.get(account_id).await?;
+    let mut group_user = session.group_user.write().await;
     let group_user = group_user.as_mut().unwrap();
     let devices = group_user
         .get_account_devices()
@@ -241,3 +265,83 @@ pub async fn get_user_devices(
         })
         .collect())
 }
+
+/// Trust another server key for account certificates, e.g. during key rotation
+///
+/// Applies from the next login.
+#[tauri::command]
+pub async fn add_trusted_server_key(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+    public_key: String,
+) -> Result<(), String> {
+    let session = sessions.get(account_id).await?;
+    let public_key = general_purpose::STANDARD
+        .decode(public_key)
+        .map_err(|e| e.to_string())?;
+    let db = AccountManager::new(get_default_db_path())
+        .await
+        .map_err(|e| e.to_string())?;
+    db.add_server_key(&session.account.server_address, &public_key)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Stop trusting a server key added with `add_trusted_server_key`
+///
+/// Applies from the next login.
+#[tauri::command]
+pub async fn remove_trusted_server_key(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+    public_key: String,
+) -> Result<(), String> {
+    let session = sessions.get(account_id).await?;
+    let public_key = general_purpose::STANDARD
+        .decode(public_key)
+        .map_err(|e| e.to_string())?;
+    let db = AccountManager::new(get_default_db_path())
+        .await
+        .map_err(|e| e.to_string())?;
+    db.remove_server_key(&session.account.server_address, &public_key)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Turn account certificate checks off or back on for the account's server
+///
+/// Without checks any account can be added to groups under another user's
+/// name. Applies from the next login.
+#[tauri::command]
+pub async fn set_skip_certificate_checks(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+    skip: bool,
+) -> Result<(), String> {
+    let session = sessions.get(account_id).await?;
+    let db = AccountManager::new(get_default_db_path())
+        .await
+        .map_err(|e| e.to_string())?;
+    db.set_skip_certificate_checks(&session.account.server_address, skip)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Base64 server keys trusted to certify accounts
+#[tauri::command]
+pub async fn get_trusted_server_keys(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<Vec<String>, String> {
+    let session = sessions.get(account_id).await?;
+    let keys = session
+        .account
+        .trusted_server_keys()
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(keys
+        .keys()
+        .iter()
+        .map(|key| general_purpose::STANDARD.encode(key.as_bytes()))
+        .collect())
+}
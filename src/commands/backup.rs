@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::api::device::{restore_backup as restore_device_backup, unpack_backup};
+use crate::commands::session::SessionRegistry;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestoredBackup {
+    pub user_id: u64,
+    pub username: String,
+    pub device_id: String,
+    pub created_at: u64,
+}
+
+/// Write an encrypted backup of the account's local state to `path`
+///
+/// Other commands of the account wait until the backup is written.
+#[tauri::command]
+pub async fn create_backup(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+    password: String,
+    path: String,
+) -> Result<(), String> {
+    if password.is_empty() {
+        return Err("Backup password is empty".to_string());
+    }
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.write().await;
+    let device = group_user
+        .as_ref()
+        .ok_or("Group user not initialized. Call init_group_user first.".to_string())?;
+    device
+        .create_backup(&password, Path::new(&path))
+        .await
+        .map_err(|e| e.to_string())?;
+    log::info!("Backup of account {} written to {}", device.user_id(), path);
+    Ok(())
+}
+
+/// Restore a backup written by `create_backup`, then call `login`
+///
+/// The account must be logged out. Fails without changing anything if the
+/// backup belongs to another device than this install has, or if its MLS
+/// state is older than what this install already received. The delivery
+/// server must be reachable to confirm the device is still registered.
+#[tauri::command]
+pub async fn restore_backup(
+    sessions: tauri::State<'_, SessionRegistry>,
+    path: String,
+    password: String,
+) -> Result<RestoredBackup, String> {
+    let backup = unpack_backup(Path::new(&path), &password)
+        .await
+        .map_err(|e| e.to_string())?;
+    let user_id = backup.manifest.user_id;
+    if sessions.get(Some(user_id)).await.is_ok() {
+        return Err(format!(
+            "Account {} is logged in, log out before restoring",
+            user_id
+        ));
+    }
+    let manifest = backup.manifest.clone();
+    let account = restore_device_backup(backup)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(RestoredBackup {
+        user_id,
+        username: account.username,
+        device_id: manifest.device_id,
+        created_at: manifest.created_at,
+    })
+}
@@ -1,28 +1,213 @@
 use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ChatInfo {}
+use crate::api::device::types::{
+    message::UserGroupMessage, message_builder::MessageBuilder, text_entity::TextEntity,
+};
+use crate::commands::group::GroupMessageResponse;
+use crate::commands::session::SessionRegistry;
 
+/// Direct chat with one peer; `chat_id` is the group messages are sent to
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ChatMessage {}
+pub struct ChatInfo {
+    pub chat_id: String,
+    pub peer_id: String,
+    pub last_message: Option<ChatMessage>,
+    pub unread_count: u32,
+    /// Peer verified with a safety number
+    pub peer_verified: bool,
+}
+
+/// Message of a direct chat, `chat_id` is set to the chat it is shown in
+pub type ChatMessage = GroupMessageResponse;
 
 #[allow(clippy::too_many_arguments)]
 #[tauri::command]
-pub async fn send_message() -> Result<String, String> {
-    Ok("".to_string())
+pub async fn send_message(
+    app_handle: AppHandle,
+    user_id: String,
+    message_id: u64,
+    text: String,
+    file: Option<String>,
+    reply_message_id: Option<String>,
+    edit_message_id: Option<String>,
+    entities: Option<Vec<TextEntity>>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<String, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let peer_id = user_id.parse::<u64>().map_err(|e| e.to_string())?;
+
+    if let Some(user) = group_user.as_ref() {
+        let chat_id = user
+            .find_direct_chat(peer_id)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or(format!("No chat with user {}", peer_id))?;
+
+        let mut builder = MessageBuilder::new(chat_id.to_string(), text);
+        if let Some(file) = file {
+            builder = builder.with_file(file);
+        }
+        if let Some(reply) = reply_message_id {
+            builder = builder.reply_to(reply);
+        }
+        if let Some(edit_id) = edit_message_id {
+            builder = builder.edit_message(edit_id);
+        }
+        let message = builder.with_entities(entities.unwrap_or_default()).build(
+            message_id as i64,
+            &app_handle,
+            user.user_id() as i64,
+        )?;
+        let message_id = message.message_id;
+
+        user.send_message(
+            &chat_id,
+            message_id as u64,
+            UserGroupMessage::TextMessage(message),
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+        Ok(message_id.to_string())
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
 }
 
 #[tauri::command]
-pub async fn create_chat() -> Result<String, String> {
-    Ok("Template chat id".to_string())
+pub async fn create_chat(
+    user_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<String, String> {
+    let session = sessions.get(account_id).await?;
+    let mut group_user = session.group_user.write().await;
+    let peer_id = user_id.parse::<u64>().map_err(|e| e.to_string())?;
+
+    if let Some(user) = group_user.as_mut() {
+        let chat_id = user
+            .create_direct_chat(peer_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(chat_id.to_string())
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
 }
 
 #[tauri::command]
-pub async fn get_chats() -> Result<Vec<ChatInfo>, String> {
-    Ok(Vec::new())
+pub async fn get_chats(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<Vec<ChatInfo>, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+
+    if let Some(user) = group_user.as_ref() {
+        let unread_counts = user
+            .groups
+            .messages
+            .get_unread_counts(user.user_id() as i64)
+            .await
+            .map_err(|e| e.to_string())?;
+        let direct_chats = user.direct_chats().await.map_err(|e| e.to_string())?;
+        let peers: Vec<u64> = direct_chats.keys().copied().collect();
+        let verified_peers = user
+            .verified_users(&peers)
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut chats = Vec::new();
+
+        for (peer_id, groups) in direct_chats {
+            let chat_id = groups[0].to_string();
+            let mut last_message = None;
+            let mut unread_count = 0;
+            for group_id in &groups {
+                unread_count += unread_counts.get(group_id.as_bytes()).copied().unwrap_or(0);
+                let message = user
+                    .groups
+                    .messages
+                    .get_last_message(group_id)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                if let Some(message) = message
+                    && last_message
+                        .as_ref()
+                        .is_none_or(|last: &ChatMessage| message.date > last.timestamp)
+                {
+                    last_message = Some(GroupMessageResponse::from_text_message(
+                        message,
+                        Some(chat_id.clone()),
+                    ));
+                }
+            }
+
+            chats.push(ChatInfo {
+                chat_id,
+                peer_id: peer_id.to_string(),
+                last_message,
+                unread_count,
+                peer_verified: verified_peers.contains(&peer_id),
+            });
+        }
+
+        // Самые свежие чаты сверху
+        chats.sort_by_key(|chat| {
+            std::cmp::Reverse(chat.last_message.as_ref().map(|message| message.timestamp))
+        });
+        Ok(chats)
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
 }
 
+/// Messages exchanged with `user_id` across every group backing the chat
 #[tauri::command]
-pub async fn get_messages() -> Result<Vec<ChatMessage>, String> {
-    Ok(Vec::new())
+pub async fn get_messages(
+    user_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<Vec<ChatMessage>, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let peer_id = user_id.parse::<u64>().map_err(|e| e.to_string())?;
+
+    if let Some(user) = group_user.as_ref() {
+        let Some(groups) = user
+            .direct_chats()
+            .await
+            .map_err(|e| e.to_string())?
+            .remove(&peer_id)
+        else {
+            return Ok(Vec::new());
+        };
+        let chat_id = groups[0].to_string();
+
+        let mut messages = Vec::new();
+        for group_id in &groups {
+            let group_messages = user
+                .groups
+                .messages
+                .get_group_messages(group_id.as_bytes())
+                .await
+                .map_err(|e| e.to_string())?;
+            messages.extend(
+                group_messages
+                    .into_iter()
+                    .filter_map(|message| match message {
+                        UserGroupMessage::TextMessage(message) => Some(
+                            GroupMessageResponse::from_text_message(message, Some(chat_id.clone())),
+                        ),
+                        _ => None,
+                    }),
+            );
+        }
+
+        messages.sort_by_key(|message| message.timestamp);
+        Ok(messages)
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
 }
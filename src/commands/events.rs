@@ -2,9 +2,16 @@ use anyhow::anyhow;
 use base64::{Engine, engine::general_purpose};
 use tauri::{AppHandle, Emitter};
 
+use crate::api::device::KeyChange;
 use crate::api::device::types::{
-    errors::GroupError, extensions::group_config::group_config::GroupConfig, group::GroupId,
-    message::UserGroupMessage,
+    errors::GroupError,
+    extensions::group_config::group_config::{GroupConfig, PinnedMessage},
+    group::GroupId,
+    message::{
+        GroupDeleteMessage, GroupDraftMessage, GroupPollMessage, GroupTextMessage, Mention,
+        UserGroupMessage,
+    },
+    text_entity::TextEntity,
 };
 use crate::api::status::{DisplayUserStatus, DisplayUserTypingStatus};
 use crate::api::voice::echolocator::ServerMessage;
@@ -21,6 +28,15 @@ pub struct JoinGroupData<'a> {
     pub group_id: String,
     pub group_config: &'a GroupConfig,
     pub avatar: &'a Option<String>,
+    /// Members verified with a safety number
+    pub verified_members: &'a [u64],
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct NewChatData {
+    pub chat_id: String,
+    pub peer_id: String,
+    pub peer_verified: bool,
 }
 
 #[derive(serde::Serialize, Clone)]
@@ -37,6 +53,36 @@ pub struct NewGroupMessageData<'a> {
     pub edit_date: Option<String>,
     pub is_edit: bool,
     pub expires: Option<String>,
+    pub mentions: Vec<MentionData>,
+    pub entities: &'a [TextEntity],
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct MentionData {
+    pub user_id: String,
+    pub offset: u32,
+    pub length: u32,
+}
+
+impl From<&Mention> for MentionData {
+    fn from(mention: &Mention) -> Self {
+        Self {
+            user_id: mention.user_id.to_string(),
+            offset: mention.offset,
+            length: mention.length,
+        }
+    }
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct MentionedData<'a> {
+    pub group_id: String,
+    pub group_name: &'a str,
+    pub message_id: String,
+    pub sender_id: String,
+    pub text: &'a str,
+    pub timestamp: i64,
+    pub unread_mentions: u32,
 }
 
 #[derive(serde::Serialize, Clone)]
@@ -58,6 +104,86 @@ pub struct GroupConfigUpdatedData<'a> {
     pub avatar: &'a Option<String>,
 }
 
+#[derive(serde::Serialize, Clone)]
+pub struct MessagePinData {
+    pub group_id: String,
+    pub message_id: String,
+    pub user_id: String,
+    pub timestamp: u64,
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct MessageDeletedData {
+    pub group_id: String,
+    pub message_id: String,
+    pub user_id: String,
+    pub timestamp: i64,
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct NewPollData<'a> {
+    pub group_id: String,
+    pub poll_id: String,
+    pub sender_id: String,
+    pub question: &'a str,
+    pub options: &'a [String],
+    pub multiple_choice: bool,
+    pub anonymous: bool,
+    pub closes_at: Option<String>,
+    pub timestamp: i64,
+}
+
+// Voters are left out so anonymous polls do not leak them, the UI refetches results
+#[derive(serde::Serialize, Clone)]
+pub struct PollUpdatedData {
+    pub group_id: String,
+    pub poll_id: String,
+    pub closed: bool,
+    pub timestamp: i64,
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct DraftUpdatedData<'a> {
+    pub group_id: String,
+    pub text: &'a str,
+    pub reply_message_id: Option<String>,
+    pub attachment_path: &'a Option<String>,
+    pub updated_at: i64,
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct ScheduledMessageSentData {
+    pub group_id: String,
+    pub message_id: String,
+    pub send_at: i64,
+    pub timestamp: i64,
+}
+
+// `will_retry` is set when the send failed for lack of connection and stays queued
+#[derive(serde::Serialize, Clone)]
+pub struct ScheduledMessageFailedData<'a> {
+    pub group_id: String,
+    pub message_id: String,
+    pub send_at: i64,
+    pub error: &'a str,
+    pub will_retry: bool,
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct GroupDisbandedData {
+    pub group_id: String,
+    pub user_id: String,
+    pub timestamp: u64,
+}
+
+// `was_verified`: the verification of the contact was removed by the change
+#[derive(serde::Serialize, Clone)]
+pub struct ContactKeyChangedData {
+    pub user_id: String,
+    pub was_verified: bool,
+    pub changed_at: i64,
+}
+
 // --- Voice Data Structures ---
 
 #[derive(serde::Serialize, Clone)]
@@ -76,14 +202,38 @@ pub enum SystemEvent<'a> {
     // --- Group Events ---
     #[serde(rename = "join_group")]
     JoinGroup(JoinGroupData<'a>),
+    #[serde(rename = "new_chat")]
+    NewChat(NewChatData),
     #[serde(rename = "new_group_message")]
     NewGroupMessage(NewGroupMessageData<'a>),
+    #[serde(rename = "mentioned")]
+    Mentioned(MentionedData<'a>),
     #[serde(rename = "message_delivery")]
     MessageDelivery(MessageDeliveryData),
     #[serde(rename = "welcome_message")]
     WelcomeMessage(WelcomeMessageData),
     #[serde(rename = "group_config_updated")]
     GroupConfigUpdated(GroupConfigUpdatedData<'a>),
+    #[serde(rename = "message_pinned")]
+    MessagePinned(MessagePinData),
+    #[serde(rename = "message_unpinned")]
+    MessageUnpinned(MessagePinData),
+    #[serde(rename = "message_deleted")]
+    MessageDeleted(MessageDeletedData),
+    #[serde(rename = "group_disbanded")]
+    GroupDisbanded(GroupDisbandedData),
+    #[serde(rename = "new_poll")]
+    NewPoll(NewPollData<'a>),
+    #[serde(rename = "poll_updated")]
+    PollUpdated(PollUpdatedData),
+    #[serde(rename = "draft_updated")]
+    DraftUpdated(DraftUpdatedData<'a>),
+    #[serde(rename = "scheduled_message_sent")]
+    ScheduledMessageSent(ScheduledMessageSentData),
+    #[serde(rename = "scheduled_message_failed")]
+    ScheduledMessageFailed(ScheduledMessageFailedData<'a>),
+    #[serde(rename = "contact_key_changed")]
+    ContactKeyChanged(ContactKeyChangedData),
 
     // --- Status Events ---
     #[serde(rename = "user_status_changed")]
@@ -101,14 +251,51 @@ pub enum VoiceEvent {
     SignalingMessage(VoiceResponse),
 }
 
+/// Event of one of the logged-in accounts, `account_id` is its user id
+#[derive(serde::Serialize, Clone)]
+pub struct AccountEvent<T> {
+    pub account_id: String,
+    #[serde(flatten)]
+    pub event: T,
+}
+
 // =============================================================================
 // Event Emission Helpers
 // =============================================================================
 
+pub fn emit_server_event<T: serde::Serialize + Clone>(
+    app: &AppHandle,
+    account_id: u64,
+    event: T,
+) -> Result<(), tauri::Error> {
+    app.emit(
+        "server-event",
+        AccountEvent {
+            account_id: account_id.to_string(),
+            event,
+        },
+    )
+}
+
+fn emit_voice_event(
+    app: &AppHandle,
+    account_id: u64,
+    event: VoiceEvent,
+) -> Result<(), tauri::Error> {
+    app.emit(
+        "voice-event",
+        AccountEvent {
+            account_id: account_id.to_string(),
+            event,
+        },
+    )
+}
+
 // --- Group Event Helpers ---
 
 pub async fn emit_text_message_event(
     app: &AppHandle,
+    account_id: u64,
     text_msg: &UserGroupMessage,
     group_id: &GroupId,
     group_config: &GroupConfig,
@@ -134,19 +321,225 @@ pub async fn emit_text_message_event(
                 edit_date: text_msg.edit_date.map(|date| date.to_string()),
                 is_edit: text_msg.edit_date.is_some(),
                 expires: text_msg.expires.map(|date| date.to_string()),
+                mentions: text_msg.mentions.iter().map(MentionData::from).collect(),
+                entities: &text_msg.entities,
             });
 
-            app.emit("server-event", event_payload)
+            emit_server_event(app, account_id, event_payload)
                 .map_err(|e| GroupError::EventError(e.to_string()))?;
         }
+        UserGroupMessage::DeleteMessage(tombstone) => {
+            emit_message_deleted_event(app, account_id, group_id, tombstone).await?;
+        }
+        UserGroupMessage::Poll(poll) => {
+            emit_new_poll_event(app, account_id, group_id, poll).await?;
+        }
+        UserGroupMessage::PollVote(vote) => {
+            emit_poll_updated_event(app, account_id, group_id, vote.poll_id, false, vote.date)
+                .await?;
+        }
+        UserGroupMessage::PollClose(close) => {
+            emit_poll_updated_event(app, account_id, group_id, close.poll_id, true, close.date)
+                .await?;
+        }
+        // Событие черновика шлёт обработчик, вместе с локальным вложением
+        UserGroupMessage::Draft(_) => {}
     }
     Ok(())
 }
 
+pub async fn emit_new_poll_event(
+    app: &AppHandle,
+    account_id: u64,
+    group_id: &GroupId,
+    poll: &GroupPollMessage,
+) -> Result<(), GroupError> {
+    let event_payload = SystemEvent::NewPoll(NewPollData {
+        group_id: group_id.to_string(),
+        poll_id: poll.poll_id.to_string(),
+        sender_id: poll.sender_id.to_string(),
+        question: &poll.question,
+        options: &poll.options,
+        multiple_choice: poll.multiple_choice,
+        anonymous: poll.anonymous,
+        closes_at: poll.closes_at.map(|date| date.to_string()),
+        timestamp: poll.date,
+    });
+
+    emit_server_event(app, account_id, event_payload)
+        .map_err(|e| GroupError::EventError(e.to_string()))?;
+    Ok(())
+}
+
+pub async fn emit_poll_updated_event(
+    app: &AppHandle,
+    account_id: u64,
+    group_id: &GroupId,
+    poll_id: i64,
+    closed: bool,
+    timestamp: i64,
+) -> Result<(), GroupError> {
+    let event_payload = SystemEvent::PollUpdated(PollUpdatedData {
+        group_id: group_id.to_string(),
+        poll_id: poll_id.to_string(),
+        closed,
+        timestamp,
+    });
+
+    emit_server_event(app, account_id, event_payload)
+        .map_err(|e| GroupError::EventError(e.to_string()))?;
+    Ok(())
+}
+
+/// Emit `new_chat` when a peer starts a direct chat with the user
+pub async fn emit_new_chat_event(
+    app: &AppHandle,
+    account_id: u64,
+    group_id: &GroupId,
+    peer_id: u64,
+    peer_verified: bool,
+) -> Result<(), GroupError> {
+    let event_payload = SystemEvent::NewChat(NewChatData {
+        chat_id: group_id.to_string(),
+        peer_id: peer_id.to_string(),
+        peer_verified,
+    });
+
+    emit_server_event(app, account_id, event_payload)
+        .map_err(|e| GroupError::EventError(e.to_string()))?;
+    Ok(())
+}
+
+/// Emit `contact_key_changed` when a contact's key differs from the pinned one
+pub async fn emit_contact_key_changed_event(
+    app: &AppHandle,
+    account_id: u64,
+    change: &KeyChange,
+) -> Result<(), GroupError> {
+    let event_payload = SystemEvent::ContactKeyChanged(ContactKeyChangedData {
+        user_id: change.user_id.to_string(),
+        was_verified: change.was_verified,
+        changed_at: change.changed_at,
+    });
+
+    emit_server_event(app, account_id, event_payload)
+        .map_err(|e| GroupError::EventError(e.to_string()))?;
+    Ok(())
+}
+
+/// Emit `draft_updated` for a draft received from another device of the user
+pub async fn emit_draft_updated_event(
+    app: &AppHandle,
+    account_id: u64,
+    draft: &GroupDraftMessage,
+    attachment_path: &Option<String>,
+) -> Result<(), GroupError> {
+    let event_payload = SystemEvent::DraftUpdated(DraftUpdatedData {
+        group_id: GroupId::new(draft.group_id.clone()).to_string(),
+        text: &draft.text,
+        reply_message_id: draft.reply_message_id.map(|id| id.to_string()),
+        attachment_path,
+        updated_at: draft.updated_at,
+    });
+
+    emit_server_event(app, account_id, event_payload)
+        .map_err(|e| GroupError::EventError(e.to_string()))?;
+    Ok(())
+}
+
+pub async fn emit_scheduled_message_sent_event(
+    app: &AppHandle,
+    account_id: u64,
+    group_id: &GroupId,
+    message_id: i64,
+    send_at: i64,
+    timestamp: i64,
+) -> Result<(), GroupError> {
+    let event_payload = SystemEvent::ScheduledMessageSent(ScheduledMessageSentData {
+        group_id: group_id.to_string(),
+        message_id: message_id.to_string(),
+        send_at,
+        timestamp,
+    });
+
+    emit_server_event(app, account_id, event_payload)
+        .map_err(|e| GroupError::EventError(e.to_string()))?;
+    Ok(())
+}
+
+pub async fn emit_scheduled_message_failed_event(
+    app: &AppHandle,
+    account_id: u64,
+    group_id: &GroupId,
+    message_id: i64,
+    send_at: i64,
+    error: &str,
+    will_retry: bool,
+) -> Result<(), GroupError> {
+    let event_payload = SystemEvent::ScheduledMessageFailed(ScheduledMessageFailedData {
+        group_id: group_id.to_string(),
+        message_id: message_id.to_string(),
+        send_at,
+        error,
+        will_retry,
+    });
+
+    emit_server_event(app, account_id, event_payload)
+        .map_err(|e| GroupError::EventError(e.to_string()))?;
+    Ok(())
+}
+
+/// Emit `mentioned` for a message mentioning the local user
+///
+/// Sent in addition to `new_group_message` so the UI can surface it
+/// regardless of how the group's notifications are set up.
+pub async fn emit_mentioned_event(
+    app: &AppHandle,
+    account_id: u64,
+    group_id: &GroupId,
+    group_config: &GroupConfig,
+    message: &GroupTextMessage,
+    unread_mentions: u32,
+) -> Result<(), GroupError> {
+    let event_payload = SystemEvent::Mentioned(MentionedData {
+        group_id: group_id.to_string(),
+        group_name: &group_config.name,
+        message_id: message.message_id.to_string(),
+        sender_id: message.sender_id.to_string(),
+        text: &message.text,
+        timestamp: message.date,
+        unread_mentions,
+    });
+
+    emit_server_event(app, account_id, event_payload)
+        .map_err(|e| GroupError::EventError(e.to_string()))?;
+    Ok(())
+}
+
+pub async fn emit_message_deleted_event(
+    app: &AppHandle,
+    account_id: u64,
+    group_id: &GroupId,
+    tombstone: &GroupDeleteMessage,
+) -> Result<(), GroupError> {
+    let event_payload = SystemEvent::MessageDeleted(MessageDeletedData {
+        group_id: group_id.to_string(),
+        message_id: tombstone.message_id.to_string(),
+        user_id: tombstone.deleter_id.to_string(),
+        timestamp: tombstone.date,
+    });
+
+    emit_server_event(app, account_id, event_payload)
+        .map_err(|e| GroupError::EventError(e.to_string()))?;
+    Ok(())
+}
+
 pub async fn emit_join_group_event(
     app: &AppHandle,
+    account_id: u64,
     group_config: &GroupConfig,
     group_id: &GroupId,
+    verified_members: &[u64],
 ) -> Result<(), GroupError> {
     let avatar = group_config
         .avatar
@@ -156,15 +549,17 @@ pub async fn emit_join_group_event(
         group_id: group_id.to_string(),
         group_config,
         avatar: &avatar,
+        verified_members,
     });
 
-    app.emit("server-event", event_payload)
+    emit_server_event(app, account_id, event_payload)
         .map_err(|e| GroupError::EventError(e.to_string()))?;
     Ok(())
 }
 
 pub async fn emit_message_delivery_event(
     app: &AppHandle,
+    account_id: u64,
     message_id: u64,
     success: bool,
 ) -> Result<(), GroupError> {
@@ -173,13 +568,14 @@ pub async fn emit_message_delivery_event(
         success,
     });
 
-    app.emit("server-event", event_payload)
+    emit_server_event(app, account_id, event_payload)
         .map_err(|e| GroupError::EventError(e.to_string()))?;
     Ok(())
 }
 
 pub async fn emit_welcome_message_event(
     app: &AppHandle,
+    account_id: u64,
     message_id: u64,
     success: bool,
 ) -> Result<(), GroupError> {
@@ -188,13 +584,14 @@ pub async fn emit_welcome_message_event(
         success,
     });
 
-    app.emit("server-event", event_payload)
+    emit_server_event(app, account_id, event_payload)
         .map_err(|e| GroupError::EventError(e.to_string()))?;
     Ok(())
 }
 
 pub async fn emit_new_group_config(
     app: &AppHandle,
+    account_id: u64,
     group_id: &GroupId,
     group_config: &GroupConfig,
 ) -> Result<(), GroupError> {
@@ -208,7 +605,72 @@ pub async fn emit_new_group_config(
         avatar: &avatar,
     });
 
-    app.emit("server-event", event_payload)
+    emit_server_event(app, account_id, event_payload)
+        .map_err(|e| GroupError::EventError(e.to_string()))?;
+    Ok(())
+}
+
+/// Emit `message_pinned` / `message_unpinned` for every pin that differs between configs
+///
+/// Unpins are attributed to `user_id`, the member whose commit changed the config.
+pub async fn emit_pinned_messages_events(
+    app: &AppHandle,
+    account_id: u64,
+    group_id: &GroupId,
+    old_config: &GroupConfig,
+    new_config: &GroupConfig,
+    user_id: u64,
+) -> Result<(), GroupError> {
+    let is_in = |list: &[PinnedMessage], message_id: u64| {
+        list.iter().any(|pinned| pinned.message_id == message_id)
+    };
+
+    for pinned in &new_config.pinned_messages {
+        if is_in(&old_config.pinned_messages, pinned.message_id) {
+            continue;
+        }
+        let event_payload = SystemEvent::MessagePinned(MessagePinData {
+            group_id: group_id.to_string(),
+            message_id: pinned.message_id.to_string(),
+            user_id: pinned.pinned_by.to_string(),
+            timestamp: pinned.pinned_at.timestamp,
+        });
+        emit_server_event(app, account_id, event_payload)
+            .map_err(|e| GroupError::EventError(e.to_string()))?;
+    }
+
+    for pinned in &old_config.pinned_messages {
+        if is_in(&new_config.pinned_messages, pinned.message_id) {
+            continue;
+        }
+        let event_payload = SystemEvent::MessageUnpinned(MessagePinData {
+            group_id: group_id.to_string(),
+            message_id: pinned.message_id.to_string(),
+            user_id: user_id.to_string(),
+            timestamp: new_config.updated_at.timestamp,
+        });
+        emit_server_event(app, account_id, event_payload)
+            .map_err(|e| GroupError::EventError(e.to_string()))?;
+    }
+    Ok(())
+}
+
+pub async fn emit_group_disbanded_event(
+    app: &AppHandle,
+    account_id: u64,
+    group_id: &GroupId,
+    group_config: &GroupConfig,
+) -> Result<(), GroupError> {
+    let event_payload = SystemEvent::GroupDisbanded(GroupDisbandedData {
+        group_id: group_id.to_string(),
+        user_id: group_config.creator_id.to_string(),
+        timestamp: group_config
+            .disbanded_at
+            .unwrap_or(group_config.updated_at)
+            .timestamp,
+    });
+
+    emit_server_event(app, account_id, event_payload)
         .map_err(|e| GroupError::EventError(e.to_string()))?;
     Ok(())
 }
@@ -217,22 +679,25 @@ pub async fn emit_new_group_config(
 
 pub async fn emit_user_status_event(
     app: &AppHandle,
+    account_id: u64,
     data: DisplayUserStatus,
 ) -> Result<(), tauri::Error> {
-    app.emit("server-event", SystemEvent::UserStatusChanged(data))
+    emit_server_event(app, account_id, SystemEvent::UserStatusChanged(data))
 }
 
 pub async fn emit_user_typing_status_event(
     app: &AppHandle,
+    account_id: u64,
     data: DisplayUserTypingStatus,
 ) -> Result<(), tauri::Error> {
-    app.emit("server-event", SystemEvent::UserTypingStatusChanged(data))
+    emit_server_event(app, account_id, SystemEvent::UserTypingStatusChanged(data))
 }
 
 // --- Voice Event Helpers ---
 
 pub async fn emit_voice_server_commit(
     app: &AppHandle,
+    account_id: u64,
     voice_id: String,
     commit_id: String,
 ) -> Result<(), tauri::Error> {
@@ -240,16 +705,17 @@ pub async fn emit_voice_server_commit(
         voice_id,
         commit_id,
     });
-    app.emit("voice-event", server_commit_data)
+    emit_voice_event(app, account_id, server_commit_data)
 }
 
 pub async fn emit_voice_signaling_message(
     app: &AppHandle,
+    account_id: u64,
     server_message: ServerMessage,
 ) -> Result<(), tauri::Error> {
     let message: VoiceResponse = server_message
         .voice_response
         .ok_or_else(|| anyhow!("No message"))?;
     let event_payload = VoiceEvent::SignalingMessage(message);
-    app.emit("voice-event", event_payload)
+    emit_voice_event(app, account_id, event_payload)
 }
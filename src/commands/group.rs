@@ -1,10 +1,8 @@
 use base64::{Engine as _, engine::general_purpose};
 use rand::RngExt;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::collections::BTreeMap;
 use tauri::AppHandle;
-use tauri::Emitter;
-use tokio::sync::RwLock;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GroupActionResponse {
@@ -28,6 +26,77 @@ pub struct GroupMessageResponse {
     pub edit_date: Option<String>,
     pub is_edit: bool,
     pub expires: Option<String>,
+    pub reply_count: u32,
+    pub last_reply_at: Option<i64>,
+    pub unread_replies: u32,
+    pub mentions: Vec<MentionResponse>,
+    pub entities: Vec<TextEntity>,
+}
+
+/// Mention of a member, `offset` and `length` are a UTF-8 byte range of the text
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MentionResponse {
+    pub user_id: String,
+    pub offset: u32,
+    pub length: u32,
+}
+
+impl MentionResponse {
+    fn to_mention(&self) -> Result<Mention, String> {
+        Ok(Mention {
+            user_id: self.user_id.parse::<i64>().map_err(|e| e.to_string())?,
+            offset: self.offset,
+            length: self.length,
+        })
+    }
+}
+
+impl From<&Mention> for MentionResponse {
+    fn from(mention: &Mention) -> Self {
+        Self {
+            user_id: mention.user_id.to_string(),
+            offset: mention.offset,
+            length: mention.length,
+        }
+    }
+}
+
+impl GroupMessageResponse {
+    pub fn from_text_message(message: GroupTextMessage, chat_id: Option<String>) -> Self {
+        let media_data = message
+            .media
+            .as_ref()
+            .map(|data| general_purpose::STANDARD.encode(data));
+        let mentions = message.mentions.iter().map(MentionResponse::from).collect();
+        GroupMessageResponse {
+            id: message.message_id.to_string(),
+            chat_id,
+            sender_id: message.sender_id,
+            content: message.text,
+            timestamp: message.date,
+            media: message.media.is_some(),
+            media_name: message.media_name,
+            media_data,
+            reply_to: message.reply_message_id.map(|id| id.to_string()),
+            edit_date: message.edit_date.map(|date| date.to_string()),
+            is_edit: message.edit_date.is_some(),
+            expires: message.expires.map(|date| date.to_string()),
+            reply_count: 0,
+            last_reply_at: None,
+            unread_replies: 0,
+            mentions,
+            entities: message.entities,
+        }
+    }
+
+    pub fn with_thread_stats(mut self, stats: Option<&ThreadStats>) -> Self {
+        if let Some(stats) = stats {
+            self.reply_count = stats.reply_count;
+            self.last_reply_at = stats.last_reply_at;
+            self.unread_replies = stats.unread_count;
+        }
+        self
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,6 +112,11 @@ pub struct GroupResponse {
     pub group_config: GroupConfig,
     pub avatar: Option<String>,
     pub last_message: Option<GroupMessageResponse>,
+    /// Messages from other members after the read position
+    pub unread_count: u32,
+    pub unread_mentions: u32,
+    /// Members verified with a safety number
+    pub verified_members: Vec<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -68,26 +142,193 @@ pub struct CacheSizeResponse {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct UpdatePermissions {
-    pub manage_members: Option<bool>,
-    pub send_messages: Option<bool>,
-    pub delete_messages: Option<bool>,
-    pub rename_group: Option<bool>,
-    pub manage_permissions: Option<bool>,
-    pub pin_messages: Option<bool>,
-    pub manage_admins: Option<bool>,
-}
-
-use crate::api::device::Device;
-use crate::api::device::types::extensions::group_config::group_config::GroupConfig;
+pub struct AuditLogEntryResponse {
+    pub epoch: u64,
+    pub committer_id: u64,
+    pub category: AuditCategory,
+    pub field: String,
+    pub old_value: String,
+    pub new_value: String,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditLogResponse {
+    pub entries: Vec<AuditLogEntryResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinnedMessageResponse {
+    pub message_id: String,
+    pub pinned_by: u64,
+    pub pinned_at: u64,
+    /// `None` when the message is not in local history
+    pub message: Option<GroupMessageResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinnedMessagesResponse {
+    pub pinned: Vec<PinnedMessageResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadResponse {
+    pub root: GroupMessageResponse,
+    /// Replies in chronological order, replies to replies included
+    pub replies: Vec<GroupMessageResponse>,
+    pub has_more: bool,
+}
+
+const THREAD_PAGE_SIZE: u32 = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageRevisionResponse {
+    pub content: String,
+    pub entities: Vec<TextEntity>,
+    pub edit_date: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageHistoryResponse {
+    pub message_id: String,
+    /// Oldest revision first, the last one is the current text
+    pub revisions: Vec<MessageRevisionResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DraftResponse {
+    pub group_id: String,
+    pub text: String,
+    pub reply_message_id: Option<String>,
+    pub attachment_path: Option<String>,
+    /// Milliseconds since the epoch
+    pub updated_at: i64,
+}
+
+impl From<Draft> for DraftResponse {
+    fn from(draft: Draft) -> Self {
+        let message = draft.message;
+        Self {
+            group_id: GroupId::new(message.group_id).to_string(),
+            text: message.text,
+            reply_message_id: message.reply_message_id.map(|id| id.to_string()),
+            attachment_path: draft.attachment_path,
+            updated_at: message.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledMessageResponse {
+    pub group_id: String,
+    pub send_at: i64,
+    /// `pending`, `sending` or `failed`
+    pub status: String,
+    pub last_error: Option<String>,
+    pub message: GroupMessageResponse,
+}
+
+impl ScheduledMessageResponse {
+    fn from_scheduled(scheduled: ScheduledMessage) -> Option<Self> {
+        let UserGroupMessage::TextMessage(message) = scheduled.message else {
+            return None;
+        };
+        let status = match scheduled.status {
+            ScheduledStatus::Pending => "pending",
+            ScheduledStatus::Sending => "sending",
+            ScheduledStatus::Failed => "failed",
+        };
+        Some(Self {
+            group_id: scheduled.group_id.to_string(),
+            send_at: scheduled.send_at,
+            status: status.to_string(),
+            last_error: scheduled.last_error,
+            message: GroupMessageResponse::from_text_message(message, None),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PollResponse {
+    pub poll_id: String,
+    pub group_id: String,
+    pub sender_id: String,
+    pub question: String,
+    pub options: Vec<String>,
+    pub multiple_choice: bool,
+    pub anonymous: bool,
+    pub created_at: i64,
+    pub closes_at: Option<i64>,
+    pub closed: bool,
+    /// Votes per option, in option order
+    pub counts: Vec<u32>,
+    /// Voter ids per option, empty for anonymous polls
+    pub voters: Vec<Vec<String>>,
+    pub total_voters: u32,
+    pub own_vote: Vec<u32>,
+}
+
+impl PollResponse {
+    fn from_results(results: PollResults, group_id: &GroupId, now: i64) -> Self {
+        let closed = results.is_closed(now);
+        let poll = results.poll;
+        Self {
+            poll_id: poll.poll_id.to_string(),
+            group_id: group_id.to_string(),
+            sender_id: poll.sender_id.to_string(),
+            question: poll.question,
+            options: poll.options,
+            multiple_choice: poll.multiple_choice,
+            anonymous: poll.anonymous,
+            created_at: poll.date,
+            closes_at: poll.closes_at,
+            closed,
+            counts: results.counts,
+            voters: results
+                .voters
+                .into_iter()
+                .map(|ids| ids.into_iter().map(|id| id.to_string()).collect())
+                .collect(),
+            total_voters: results.total_voters,
+            own_vote: results.own_vote,
+        }
+    }
+}
+
+/// Permissions to change, e.g. `{ "send_messages": false }`; absent ones are kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct UpdatePermissions(pub BTreeMap<Permission, bool>);
+
+impl UpdatePermissions {
+    /// Overwrite the permissions that are set, keeping the rest of `permissions`
+    pub fn apply_to(&self, permissions: Permissions) -> Permissions {
+        self.0
+            .iter()
+            .fold(permissions, |permissions, (permission, value)| {
+                permissions.with(*permission, *value)
+            })
+    }
+}
+
+use crate::api::device::types::extensions::group_config::group_config::{
+    GroupConfig, Permission, Permissions,
+};
 use crate::api::device::types::{
+    audit::AuditCategory,
     extensions::group_config::{group_config, group_config_builder},
     group::GroupId,
-    message::UserGroupMessage,
+    message::{GroupTextMessage, Mention, UserGroupMessage},
     message_builder::MessageBuilder,
+    poll::PollResults,
+    text_entity::TextEntity,
 };
-
-type SafeGroupUser = Arc<RwLock<Option<Device>>>;
+use crate::api::device::{Device, Draft, ScheduledMessage, ScheduledStatus, ThreadStats};
+use crate::commands::events::{
+    emit_group_disbanded_event, emit_message_deleted_event, emit_pinned_messages_events,
+    emit_server_event,
+};
+use crate::commands::session::SessionRegistry;
 
 pub fn format_group_config(
     group_config: &GroupConfig,
@@ -101,9 +342,7 @@ pub fn format_group_config(
 
     let users_permisions = &group_config.permissions;
     let default_permissions = &group_config.default_permissions;
-    let user_permissions = users_permisions
-        .get(&user_id)
-        .unwrap_or(default_permissions);
+    let user_permissions = group_config.effective_permissions(user_id);
 
     serde_json::json!({
         "type": "group_config_updated",
@@ -128,7 +367,8 @@ pub fn format_group_config(
 pub async fn create_group(
     _app_handle: AppHandle,
     group_name: String,
-    group_user_state: tauri::State<'_, SafeGroupUser>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
     visibility: Option<String>,
     join_mode: Option<String>,
     description: Option<String>,
@@ -140,7 +380,8 @@ pub async fn create_group(
     allow_video_messages: Option<bool>,
     allow_links: Option<bool>,
 ) -> Result<CreateGroupResponse, String> {
-    let mut group_user = group_user_state.write().await;
+    let session = sessions.get(account_id).await?;
+    let mut group_user = session.group_user.write().await;
     log::info!("Creating group: {}", group_name);
     if let Some(user) = group_user.as_mut() {
         let visibility_enum = match visibility.as_deref() {
@@ -227,12 +468,19 @@ pub async fn create_group(
 pub async fn leave_group(
     app_handle: AppHandle,
     group_name: String,
-    group_user_state: tauri::State<'_, SafeGroupUser>,
+    successor_id: Option<String>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
 ) -> Result<GroupActionResponse, String> {
-    let mut group_user = group_user_state.write().await;
+    let session = sessions.get(account_id).await?;
+    let mut group_user = session.group_user.write().await;
     let group_id = GroupId::from_string(&group_name).map_err(|e| e.to_string())?;
+    let successor_id = successor_id
+        .map(|id| id.parse::<u64>())
+        .transpose()
+        .map_err(|e| e.to_string())?;
     if let Some(user) = group_user.as_mut() {
-        user.leave_group(&group_id)
+        user.leave_group(&group_id, successor_id)
             .await
             .map_err(|e| e.to_string())?;
 
@@ -242,7 +490,7 @@ pub async fn leave_group(
                 "group_id": group_id.to_string()
             }
         });
-        app_handle.emit("server-event", event_payload).unwrap();
+        emit_server_event(&app_handle, session.user_id(), event_payload).unwrap();
     }
     Ok(GroupActionResponse {
         success: true,
@@ -250,13 +498,60 @@ pub async fn leave_group(
     })
 }
 
+#[tauri::command]
+pub async fn transfer_ownership(
+    app_handle: AppHandle,
+    group_id: String,
+    new_owner_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<GroupActionResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    let new_owner_id = new_owner_id.parse::<u64>().map_err(|e| e.to_string())?;
+    if let Some(user) = group_user.as_ref() {
+        let new_config = user
+            .transfer_ownership(&group_id, new_owner_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        emit_server_event(
+            &app_handle,
+            session.user_id(),
+            format_group_config(&new_config, group_id, user.user_id()),
+        )
+        .unwrap();
+
+        Ok(GroupActionResponse {
+            success: true,
+            message: format!("Ownership transferred to user {}", new_owner_id),
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
 #[tauri::command]
 pub async fn get_groups(
-    group_user_state: tauri::State<'_, SafeGroupUser>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
 ) -> Result<Vec<GroupResponse>, String> {
-    let group_user = group_user_state.read().await;
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
     if let Some(user) = group_user.as_ref() {
         let groups = user.groups.list_groups().await;
+        let unread_counts = user
+            .groups
+            .messages
+            .get_unread_counts(user.user_id() as i64)
+            .await
+            .map_err(|e| e.to_string())?;
+        let unread_mentions = user
+            .groups
+            .messages
+            .get_unread_mention_counts(user.user_id() as i64)
+            .await
+            .map_err(|e| e.to_string())?;
         let mut groups_list = Vec::new();
 
         for group_id in groups.iter() {
@@ -264,6 +559,10 @@ pub async fn get_groups(
                 .get_group_config(group_id)
                 .await
                 .map_err(|e| e.to_string())?;
+            // Direct chats are listed by `get_chats`
+            if group_config.is_self_channel() || group_config.is_direct_chat() {
+                continue;
+            }
             let last_message = user
                 .groups
                 .messages
@@ -271,39 +570,80 @@ pub async fn get_groups(
                 .await
                 .map_err(|e| e.to_string())?;
 
-            let last_message: Option<GroupMessageResponse> = if let Some(message) = last_message {
-                let media_data = message
-                    .media
-                    .as_ref()
-                    .map(|data| general_purpose::STANDARD.encode(data));
-                Some(GroupMessageResponse {
-                    id: message.message_id.to_string(),
-                    chat_id: None,
-                    content: message.text,
-                    timestamp: message.date,
-                    sender_id: message.sender_id,
-                    media: message.media.is_some(),
-                    media_name: message.media_name,
-                    media_data,
-                    reply_to: message.reply_message_id.map(|id| id.to_string()),
-                    edit_date: message.edit_date.map(|date| date.to_string()),
-                    is_edit: message.edit_date.is_some(),
-                    expires: message.expires.map(|date| date.to_string()),
-                })
-            } else {
-                None
-            };
+            let last_message =
+                last_message.map(|message| GroupMessageResponse::from_text_message(message, None));
+
+            let avatar = group_config
+                .avatar
+                .clone()
+                .map(|avatar| general_purpose::STANDARD.encode(avatar));
+            let verified_members = user
+                .verified_users(&group_config.members)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            groups_list.push(GroupResponse {
+                group_id: group_id.to_string(),
+                group_config,
+                avatar,
+                last_message,
+                unread_count: unread_counts.get(group_id.as_bytes()).copied().unwrap_or(0),
+                unread_mentions: unread_mentions
+                    .get(group_id.as_bytes())
+                    .copied()
+                    .unwrap_or(0),
+                verified_members,
+            });
+        }
+
+        Ok(groups_list)
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn get_archived_groups(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<Vec<GroupResponse>, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    if let Some(user) = group_user.as_ref() {
+        let archived = user
+            .groups
+            .messages
+            .get_archived_groups()
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut groups_list = Vec::new();
+
+        for (group_id, group_config) in archived {
+            let last_message = user
+                .groups
+                .messages
+                .get_last_message(&group_id)
+                .await
+                .map_err(|e| e.to_string())?
+                .map(|message| GroupMessageResponse::from_text_message(message, None));
 
             let avatar = group_config
                 .avatar
                 .clone()
                 .map(|avatar| general_purpose::STANDARD.encode(avatar));
+            let verified_members = user
+                .verified_users(&group_config.members)
+                .await
+                .map_err(|e| e.to_string())?;
 
             groups_list.push(GroupResponse {
                 group_id: group_id.to_string(),
                 group_config,
                 avatar,
                 last_message,
+                unread_count: 0,
+                unread_mentions: 0,
+                verified_members,
             });
         }
 
@@ -313,26 +653,58 @@ pub async fn get_groups(
     }
 }
 
+#[tauri::command]
+pub async fn disband_group(
+    app_handle: AppHandle,
+    group_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<GroupActionResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    if let Some(user) = group_user.as_ref() {
+        let config = user
+            .disband_group(&group_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Err(e) =
+            emit_group_disbanded_event(&app_handle, session.user_id(), &group_id, &config).await
+        {
+            log::error!("Failed to emit group disbanded event: {}", e);
+        }
+
+        Ok(GroupActionResponse {
+            success: true,
+            message: "Group disbanded successfully".to_string(),
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
 #[tauri::command]
 pub async fn invite_to_group(
     app_handle: AppHandle,
     user_id: u64,
     group_id: String,
-    group_user_state: tauri::State<'_, SafeGroupUser>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
 ) -> Result<GroupActionResponse, String> {
-    let mut group_user = group_user_state.write().await;
+    let session = sessions.get(account_id).await?;
+    let mut group_user = session.group_user.write().await;
     let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
     if let Some(user) = group_user.as_mut() {
         match user.invite(&group_id, user_id).await {
             Ok(_) => {
                 // Emit updated config
                 if let Ok(group_config) = user.get_group_config(&group_id).await {
-                    app_handle
-                        .emit(
-                            "server-event",
-                            format_group_config(&group_config, group_id, user.user_id()),
-                        )
-                        .unwrap();
+                    emit_server_event(
+                        &app_handle,
+                        session.user_id(),
+                        format_group_config(&group_config, group_id, user.user_id()),
+                    )
+                    .unwrap();
                 }
 
                 Ok(GroupActionResponse {
@@ -355,16 +727,18 @@ pub async fn remove_from_group(
     app_handle: AppHandle,
     user_id: u64,
     group_id: String,
-    group_user_state: tauri::State<'_, SafeGroupUser>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
 ) -> Result<GroupActionResponse, String> {
+    let session = sessions.get(account_id).await?;
     log::info!("Removing user from group: {}", user_id);
-    let mut group_user = group_user_state.write().await;
+    let mut group_user = session.group_user.write().await;
     let group_id_str = group_id.clone();
     let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
 
     if let Some(user) = group_user.as_mut() {
         if user.user_id() == user_id {
-            user.leave_group(&group_id)
+            user.leave_group(&group_id, None)
                 .await
                 .map_err(|e| e.to_string())?;
 
@@ -374,7 +748,7 @@ pub async fn remove_from_group(
                     "group_id": group_id_str.clone()
                 }
             });
-            app_handle.emit("server-event", event_payload).unwrap();
+            emit_server_event(&app_handle, session.user_id(), event_payload).unwrap();
 
             Ok(GroupActionResponse {
                 success: true,
@@ -385,12 +759,12 @@ pub async fn remove_from_group(
                 Ok(_) => {
                     // Emit updated config
                     if let Ok(group_config) = user.get_group_config(&group_id).await {
-                        app_handle
-                            .emit(
-                                "server-event",
-                                format_group_config(&group_config, group_id, user_id),
-                            )
-                            .unwrap();
+                        emit_server_event(
+                            &app_handle,
+                            session.user_id(),
+                            format_group_config(&group_config, group_id, user_id),
+                        )
+                        .unwrap();
                     }
 
                     Ok(GroupActionResponse {
@@ -423,169 +797,814 @@ pub async fn send_group_message(
     reply_message_id: Option<String>,
     edit_message_id: Option<String>,
     expires: Option<i64>,
-    group_user_state: tauri::State<'_, SafeGroupUser>,
+    mentions: Option<Vec<MentionResponse>>,
+    entities: Option<Vec<TextEntity>>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
 ) -> Result<String, String> {
-    let group_user = group_user_state.inner().clone();
-    tauri::async_runtime::spawn(async move {
-        if let Some(user) = group_user.read().await.as_ref() {
-            let mut builder = MessageBuilder::new(group_id.clone(), text);
-
-            if let Some(file) = file {
-                builder = builder.with_file(file);
-            }
-
-            if let Some(reply) = reply_message_id.clone() {
-                builder = builder.reply_to(reply);
-            }
-
-            if let Some(edit_id) = edit_message_id {
-                builder = builder.edit_message(edit_id);
-            }
+    let session = sessions.get(account_id).await?;
+    let mentions = mentions
+        .unwrap_or_default()
+        .iter()
+        .map(MentionResponse::to_mention)
+        .collect::<Result<Vec<_>, _>>()?;
+    let group_user = session.group_user.read().await;
+    let user = group_user
+        .as_ref()
+        .ok_or("Group user not initialized. Call init_group_user first.".to_string())?;
+    let mut builder = MessageBuilder::new(group_id.clone(), text);
+
+    if let Some(file) = file {
+        builder = builder.with_file(file);
+    }
 
-            if let Some(expires) = expires {
-                builder = builder.expires_at(expires);
-            }
-            let group_id = GroupId::from_string(&group_id)
-                .map_err(|e| e.to_string())
-                .unwrap();
-            let message = builder
-                .build(message_id as i64, &app_handle, user.user_id() as i64)
-                .unwrap();
-
-            user.send_message(
-                &group_id,
-                message_id,
-                UserGroupMessage::TextMessage(message.clone()),
-            )
-            .await
-            .map_err(|e| {
-                log::error!("Failed to send message: {}", e);
-                e.to_string()
-            })
-            .ok();
+    if let Some(reply) = reply_message_id.clone() {
+        builder = builder.reply_to(reply);
+    }
 
-            let success_payload = {
-                serde_json::json!({
-                "type": "group_message_sent",
-                "data": {
-                    "message_id": message.message_id.to_string(),
-                    "text": message.text,
-                    "group_name": group_id.to_string(),
-                    "media_data": message.media.clone(),
-                    "media_name": message.media_name,
-                    "reply_message_id": reply_message_id.clone().map(|id| id.to_string()),
-                    "edit_date": message.edit_date.map(|date| date.to_string()),
-                    "expires": expires.map(|date| date.to_string()),
-                }
-                })
-            };
+    if let Some(edit_id) = edit_message_id {
+        builder = builder.edit_message(edit_id);
+    }
 
-            if let Err(e) = app_handle.emit("server-event", success_payload) {
-                log::error!("Failed to emit group message success event: {}", e);
-            }
+    if let Some(expires) = expires {
+        builder = builder.expires_at(expires);
+    }
+    builder = builder.with_mentions(mentions);
+    builder = builder.with_entities(entities.unwrap_or_default());
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    let message = builder.build(message_id as i64, &app_handle, user.user_id() as i64)?;
+
+    // Ошибка отправки возвращается вызывающему, для slow mode в ней оставшееся ожидание
+    user.send_message(
+        &group_id,
+        message_id,
+        UserGroupMessage::TextMessage(message.clone()),
+    )
+    .await
+    .map_err(|e| {
+        log::error!("Failed to send message: {}", e);
+        e.to_string()
+    })?;
+
+    let success_payload = {
+        serde_json::json!({
+        "type": "group_message_sent",
+        "data": {
+            "message_id": message.message_id.to_string(),
+            "text": message.text,
+            "group_name": group_id.to_string(),
+            "media_data": message.media.clone(),
+            "media_name": message.media_name,
+            "reply_message_id": reply_message_id.clone().map(|id| id.to_string()),
+            "edit_date": message.edit_date.map(|date| date.to_string()),
+            "expires": expires.map(|date| date.to_string()),
+            "mentions": message.mentions.iter().map(MentionResponse::from).collect::<Vec<_>>(),
+            "entities": message.entities,
         }
-    });
+        })
+    };
+
+    if let Err(e) = emit_server_event(&app_handle, user.user_id(), success_payload) {
+        log::error!("Failed to emit group message success event: {}", e);
+    }
 
     Ok((message_id as i64).to_string())
 }
 
+#[allow(clippy::too_many_arguments)]
 #[tauri::command]
-pub async fn get_group_messages(
+pub async fn schedule_group_message(
+    app_handle: AppHandle,
     group_id: String,
-    group_user_state: tauri::State<'_, SafeGroupUser>,
-) -> Result<MessagesListResponse, String> {
-    log::debug!("Get group messages called");
-    let group_user = group_user_state.read().await;
-    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
-    log::info!("Getting group messages for: {:?}", group_id);
-    if let Some(user) = group_user.as_ref() {
-        match user
-            .groups
-            .messages
-            .get_group_messages(group_id.as_bytes())
-            .await
-        {
-            Ok(messages) => {
-                let msg_json: Vec<GroupMessageResponse> = messages
-                    .into_iter()
-                    .map(|message| match message {
-                        UserGroupMessage::TextMessage(text_message) => {
-                            let media_data = text_message
-                                .media
-                                .as_ref()
-                                .map(|data| general_purpose::STANDARD.encode(data));
-                            GroupMessageResponse {
-                                id: text_message.message_id.to_string(),
-                                chat_id: Some(group_id.to_string()),
-                                sender_id: text_message.sender_id,
-                                content: text_message.text,
-                                timestamp: text_message.date,
-                                media: text_message.media.is_some(),
-                                media_name: text_message.media_name,
-                                media_data,
-                                reply_to: text_message.reply_message_id.map(|id| id.to_string()),
-                                edit_date: text_message.edit_date.map(|date| date.to_string()),
-                                is_edit: text_message.edit_date.is_some(),
-                                expires: text_message.expires.map(|date| date.to_string()),
-                            }
-                        }
-                    })
-                    .collect();
+    message_id: u64,
+    text: String,
+    file: Option<String>,
+    reply_message_id: Option<String>,
+    expires: Option<i64>,
+    mentions: Option<Vec<MentionResponse>>,
+    entities: Option<Vec<TextEntity>>,
+    send_at: i64,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<ScheduledMessageResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let mentions = mentions
+        .unwrap_or_default()
+        .iter()
+        .map(MentionResponse::to_mention)
+        .collect::<Result<Vec<_>, _>>()?;
 
-                Ok(MessagesListResponse { messages: msg_json })
-            }
-            Err(e) => Err(format!("Failed to read messages from database: {}", e)),
+    if let Some(user) = group_user.as_ref() {
+        let mut builder = MessageBuilder::new(group_id.clone(), text);
+        if let Some(file) = file {
+            builder = builder.with_file(file);
+        }
+        if let Some(reply) = reply_message_id {
+            builder = builder.reply_to(reply);
         }
+        if let Some(expires) = expires {
+            builder = builder.expires_at(expires);
+        }
+        let message = builder
+            .with_mentions(mentions)
+            .with_entities(entities.unwrap_or_default())
+            .build(message_id as i64, &app_handle, user.user_id() as i64)?;
+
+        let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+        let scheduled = user
+            .schedule_message(&group_id, UserGroupMessage::TextMessage(message), send_at)
+            .await
+            .map_err(|e| e.to_string())?;
+        ScheduledMessageResponse::from_scheduled(scheduled)
+            .ok_or("Scheduled message is not a text message".to_string())
     } else {
         Err("Group user not initialized. Call init_group_user first.".to_string())
     }
 }
 
 #[tauri::command]
-pub async fn delete_group_message(
-    group_name: String,
-    message_id: i64,
-    group_user_state: tauri::State<'_, SafeGroupUser>,
-) -> Result<GroupActionResponse, String> {
-    let group_user = group_user_state.read().await;
+pub async fn get_scheduled_messages(
+    group_id: Option<String>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<Vec<ScheduledMessageResponse>, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = group_id
+        .map(|group_id| GroupId::from_string(&group_id))
+        .transpose()
+        .map_err(|e| e.to_string())?;
 
     if let Some(user) = group_user.as_ref() {
-        match user
+        let scheduled = user
             .groups
             .messages
-            .delete_message(message_id, group_name.as_bytes())
+            .get_scheduled_messages(group_id.as_ref().map(|group_id| group_id.as_bytes()))
             .await
-        {
-            Ok(_) => Ok(GroupActionResponse {
-                success: true,
-                message: "Message deleted successfully".to_string(),
-            }),
-            Err(e) => Ok(GroupActionResponse {
-                success: false,
-                message: format!("Failed to delete message: {}", e),
-            }),
-        }
+            .map_err(|e| e.to_string())?;
+        Ok(scheduled
+            .into_iter()
+            .filter_map(ScheduledMessageResponse::from_scheduled)
+            .collect())
     } else {
         Err("Group user not initialized. Call init_group_user first.".to_string())
     }
 }
 
+/// Change a queued message; a new `text` replaces its mentions and entities too
 #[tauri::command]
-pub async fn get_group_media(
-    media_id: String,
-    group_user_state: tauri::State<'_, SafeGroupUser>,
-) -> Result<Vec<u8>, String> {
-    let group_user = group_user_state.read().await;
-    log::info!("Getting media with ID: {}", media_id);
+pub async fn edit_scheduled_message(
+    group_id: String,
+    message_id: String,
+    text: Option<String>,
+    mentions: Option<Vec<MentionResponse>>,
+    entities: Option<Vec<TextEntity>>,
+    send_at: Option<i64>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<ScheduledMessageResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    let message_id = message_id.parse::<i64>().map_err(|e| e.to_string())?;
+
     if let Some(user) = group_user.as_ref() {
-        match user.groups.messages.get_media_data(&media_id).await {
-            Ok(Some((media_data, _, _))) => {
-                log::info!("Found media in cache, size: {}", media_data.len());
-                Ok(media_data)
+        let message = match text {
+            Some(text) => {
+                let scheduled = user
+                    .groups
+                    .messages
+                    .get_scheduled_message(group_id.as_bytes(), message_id)
+                    .await
+                    .map_err(|e| e.to_string())?
+                    .ok_or(format!("Scheduled message {} not found", message_id))?;
+                let UserGroupMessage::TextMessage(mut message) = scheduled.message else {
+                    return Err("Scheduled message is not a text message".to_string());
+                };
+                message.text = text;
+                message.mentions = mentions
+                    .unwrap_or_default()
+                    .iter()
+                    .map(MentionResponse::to_mention)
+                    .collect::<Result<Vec<_>, _>>()?;
+                message.entities = entities.unwrap_or_default();
+                Some(UserGroupMessage::TextMessage(message))
             }
-            Ok(None) => match user.groups.messages.get_media_data(&media_id).await {
-                Ok(Some((data, name, size))) => {
-                    log::info!("Found media in DB: {} ({}), size: {}", media_id, name, size);
+            None => None,
+        };
+
+        let scheduled = user
+            .reschedule_message(&group_id, message_id, message, send_at)
+            .await
+            .map_err(|e| e.to_string())?;
+        ScheduledMessageResponse::from_scheduled(scheduled)
+            .ok_or("Scheduled message is not a text message".to_string())
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn cancel_scheduled_message(
+    group_id: String,
+    message_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<GroupActionResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    let message_id = message_id.parse::<i64>().map_err(|e| e.to_string())?;
+
+    if let Some(user) = group_user.as_ref() {
+        user.cancel_scheduled_message(&group_id, message_id)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(GroupActionResponse {
+            success: true,
+            message: "Scheduled message cancelled".to_string(),
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn save_draft(
+    group_id: String,
+    text: String,
+    reply_message_id: Option<String>,
+    attachment_path: Option<String>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<DraftResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let mut group_user = session.group_user.write().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    let reply_message_id = reply_message_id
+        .map(|id| id.parse::<i64>())
+        .transpose()
+        .map_err(|e| e.to_string())?;
+
+    if let Some(user) = group_user.as_mut() {
+        let draft = user
+            .save_draft(&group_id, text, reply_message_id, attachment_path)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(draft.into())
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn get_draft(
+    group_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<Option<DraftResponse>, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+
+    if let Some(user) = group_user.as_ref() {
+        let draft = user.get_draft(&group_id).await.map_err(|e| e.to_string())?;
+        Ok(draft.map(DraftResponse::from))
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn clear_draft(
+    group_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<GroupActionResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let mut group_user = session.group_user.write().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+
+    if let Some(user) = group_user.as_mut() {
+        user.clear_draft(&group_id)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(GroupActionResponse {
+            success: true,
+            message: "Draft cleared".to_string(),
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn get_group_messages(
+    group_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<MessagesListResponse, String> {
+    log::debug!("Get group messages called");
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    log::info!("Getting group messages for: {:?}", group_id);
+    if let Some(user) = group_user.as_ref() {
+        match user
+            .groups
+            .messages
+            .get_group_messages(group_id.as_bytes())
+            .await
+        {
+            Ok(messages) => {
+                let root_ids: Vec<i64> = messages
+                    .iter()
+                    .filter_map(|message| match message {
+                        UserGroupMessage::TextMessage(text_message) => {
+                            Some(text_message.message_id)
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let thread_stats = user
+                    .groups
+                    .messages
+                    .get_thread_stats(group_id.as_bytes(), &root_ids, user.user_id() as i64)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let msg_json: Vec<GroupMessageResponse> = messages
+                    .into_iter()
+                    .filter_map(|message| match message {
+                        UserGroupMessage::TextMessage(text_message) => {
+                            let stats = thread_stats.get(&text_message.message_id);
+                            Some(
+                                GroupMessageResponse::from_text_message(
+                                    text_message,
+                                    Some(group_id.to_string()),
+                                )
+                                .with_thread_stats(stats),
+                            )
+                        }
+                        _ => None,
+                    })
+                    .collect();
+
+                Ok(MessagesListResponse { messages: msg_json })
+            }
+            Err(e) => Err(format!("Failed to read messages from database: {}", e)),
+        }
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn get_thread(
+    group_id: String,
+    message_id: String,
+    offset: Option<u32>,
+    limit: Option<u32>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<ThreadResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    let message_id = message_id.parse::<i64>().map_err(|e| e.to_string())?;
+    let offset = offset.unwrap_or(0);
+    let limit = limit.unwrap_or(THREAD_PAGE_SIZE);
+    if let Some(user) = group_user.as_ref() {
+        let messages = &user.groups.messages;
+        let root = messages
+            .get_message(group_id.as_bytes(), message_id)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Message {} not found", message_id))?;
+        let replies = messages
+            .get_thread_replies(group_id.as_bytes(), message_id, offset, limit)
+            .await
+            .map_err(|e| e.to_string())?;
+        let root_ids: Vec<i64> = std::iter::once(message_id)
+            .chain(replies.iter().map(|reply| reply.message_id))
+            .collect();
+        let thread_stats = messages
+            .get_thread_stats(group_id.as_bytes(), &root_ids, user.user_id() as i64)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let total = thread_stats.get(&message_id).map_or(0, |s| s.reply_count);
+        let has_more = offset.saturating_add(replies.len() as u32) < total;
+        let replies = replies
+            .into_iter()
+            .map(|reply| {
+                let stats = thread_stats.get(&reply.message_id);
+                GroupMessageResponse::from_text_message(reply, Some(group_id.to_string()))
+                    .with_thread_stats(stats)
+            })
+            .collect();
+
+        Ok(ThreadResponse {
+            root: GroupMessageResponse::from_text_message(root, Some(group_id.to_string()))
+                .with_thread_stats(thread_stats.get(&message_id)),
+            replies,
+            has_more,
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn mark_thread_read(
+    group_id: String,
+    message_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<GroupActionResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    let message_id = message_id.parse::<i64>().map_err(|e| e.to_string())?;
+    if let Some(user) = group_user.as_ref() {
+        let messages = &user.groups.messages;
+        let last_reply_at = messages
+            .get_thread_stats(group_id.as_bytes(), &[message_id], user.user_id() as i64)
+            .await
+            .map_err(|e| e.to_string())?
+            .get(&message_id)
+            .and_then(|stats| stats.last_reply_at);
+        if let Some(read_at) = last_reply_at {
+            messages
+                .mark_thread_read(group_id.as_bytes(), message_id, read_at)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
+        Ok(GroupActionResponse {
+            success: true,
+            message: "Thread marked as read".to_string(),
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn get_message_history(
+    group_id: String,
+    message_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<MessageHistoryResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    let message_id = message_id.parse::<i64>().map_err(|e| e.to_string())?;
+    if let Some(user) = group_user.as_ref() {
+        let messages = &user.groups.messages;
+        let current = messages
+            .get_message(group_id.as_bytes(), message_id)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Message {} not found", message_id))?;
+        let mut revisions: Vec<MessageRevisionResponse> = messages
+            .get_message_revisions(group_id.as_bytes(), message_id)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|revision| MessageRevisionResponse {
+                content: revision.text,
+                entities: revision.entities,
+                edit_date: revision.edit_date,
+            })
+            .collect();
+        revisions.push(MessageRevisionResponse {
+            content: current.text,
+            entities: current.entities,
+            edit_date: current.edit_date.unwrap_or(current.date),
+        });
+
+        Ok(MessageHistoryResponse {
+            message_id: message_id.to_string(),
+            revisions,
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn delete_group_message(
+    group_name: String,
+    message_id: i64,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<GroupActionResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+
+    if let Some(user) = group_user.as_ref() {
+        match user
+            .groups
+            .messages
+            .delete_message(message_id, group_name.as_bytes())
+            .await
+        {
+            Ok(_) => Ok(GroupActionResponse {
+                success: true,
+                message: "Message deleted successfully".to_string(),
+            }),
+            Err(e) => Ok(GroupActionResponse {
+                success: false,
+                message: format!("Failed to delete message: {}", e),
+            }),
+        }
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn delete_message_for_everyone(
+    app: AppHandle,
+    group_id: String,
+    message_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<GroupActionResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    let message_id = message_id.parse::<i64>().map_err(|e| e.to_string())?;
+
+    if let Some(user) = group_user.as_ref() {
+        let tombstone = user
+            .delete_message_for_everyone(&group_id, message_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Err(e) =
+            emit_message_deleted_event(&app, session.user_id(), &group_id, &tombstone).await
+        {
+            log::error!("Failed to emit message deleted event: {}", e);
+        }
+        Ok(GroupActionResponse {
+            success: true,
+            message: "Message deleted for everyone".to_string(),
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+async fn poll_response(
+    user: &Device,
+    group_id: &GroupId,
+    poll_id: i64,
+) -> Result<PollResponse, String> {
+    let results = user
+        .groups
+        .messages
+        .get_poll_results(group_id.as_bytes(), poll_id, user.user_id() as i64)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Poll {} not found", poll_id))?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_secs() as i64;
+    Ok(PollResponse::from_results(results, group_id, now))
+}
+
+#[tauri::command]
+pub async fn create_poll(
+    group_id: String,
+    question: String,
+    options: Vec<String>,
+    multiple_choice: bool,
+    anonymous: bool,
+    closes_at: Option<i64>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<PollResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+
+    if let Some(user) = group_user.as_ref() {
+        let poll = user
+            .create_poll(
+                &group_id,
+                question,
+                options,
+                multiple_choice,
+                anonymous,
+                closes_at,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        poll_response(user, &group_id, poll.poll_id).await
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn vote_poll(
+    group_id: String,
+    poll_id: String,
+    options: Vec<u32>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<PollResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    let poll_id = poll_id.parse::<i64>().map_err(|e| e.to_string())?;
+
+    if let Some(user) = group_user.as_ref() {
+        user.vote_poll(&group_id, poll_id, options)
+            .await
+            .map_err(|e| e.to_string())?;
+        poll_response(user, &group_id, poll_id).await
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn close_poll(
+    group_id: String,
+    poll_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<PollResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    let poll_id = poll_id.parse::<i64>().map_err(|e| e.to_string())?;
+
+    if let Some(user) = group_user.as_ref() {
+        user.close_poll(&group_id, poll_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        poll_response(user, &group_id, poll_id).await
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn get_poll_results(
+    group_id: String,
+    poll_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<PollResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    let poll_id = poll_id.parse::<i64>().map_err(|e| e.to_string())?;
+
+    if let Some(user) = group_user.as_ref() {
+        poll_response(user, &group_id, poll_id).await
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn get_group_polls(
+    group_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<Vec<PollResponse>, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+
+    if let Some(user) = group_user.as_ref() {
+        let poll_ids = user
+            .groups
+            .messages
+            .get_group_poll_ids(group_id.as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut polls = Vec::with_capacity(poll_ids.len());
+        for poll_id in poll_ids {
+            polls.push(poll_response(user, &group_id, poll_id).await?);
+        }
+        Ok(polls)
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn mark_group_read(
+    group_id: String,
+    message_id: Option<String>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<GroupActionResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    let message_id = message_id
+        .map(|id| id.parse::<i64>())
+        .transpose()
+        .map_err(|e| e.to_string())?;
+
+    if let Some(user) = group_user.as_ref() {
+        user.groups
+            .messages
+            .mark_group_read(group_id.as_bytes(), user.user_id() as i64, message_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(GroupActionResponse {
+            success: true,
+            message: "Group marked as read".to_string(),
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn mark_unread(
+    group_id: String,
+    message_id: Option<String>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<GroupActionResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    let message_id = message_id
+        .map(|id| id.parse::<i64>())
+        .transpose()
+        .map_err(|e| e.to_string())?;
+
+    if let Some(user) = group_user.as_ref() {
+        user.groups
+            .messages
+            .mark_unread(group_id.as_bytes(), message_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(GroupActionResponse {
+            success: true,
+            message: "Group marked as unread".to_string(),
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn mark_mentions_read(
+    group_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<GroupActionResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+
+    if let Some(user) = group_user.as_ref() {
+        user.groups
+            .messages
+            .mark_mentions_read(group_id.as_bytes(), user.user_id() as i64)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(GroupActionResponse {
+            success: true,
+            message: "Mentions marked as read".to_string(),
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn get_group_media(
+    media_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<Vec<u8>, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    log::info!("Getting media with ID: {}", media_id);
+    if let Some(user) = group_user.as_ref() {
+        match user.groups.messages.get_media_data(&media_id).await {
+            Ok(Some((media_data, _, _))) => {
+                log::info!("Found media in cache, size: {}", media_data.len());
+                Ok(media_data)
+            }
+            Ok(None) => match user.groups.messages.get_media_data(&media_id).await {
+                Ok(Some((data, name, size))) => {
+                    log::info!("Found media in DB: {} ({}), size: {}", media_id, name, size);
                     Ok(data)
                 }
                 Ok(None) => {
@@ -610,9 +1629,11 @@ pub async fn get_group_media(
 #[tauri::command]
 pub async fn get_all_group_media(
     group_name: String,
-    group_user_state: tauri::State<'_, SafeGroupUser>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
 ) -> Result<MediaListResponse, String> {
-    let group_user = group_user_state.read().await;
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
 
     if let Some(user) = group_user.as_ref() {
         match user
@@ -642,9 +1663,11 @@ pub async fn get_all_group_media(
 
 #[tauri::command]
 pub async fn clear_group_media_cache(
-    group_user_state: tauri::State<'_, SafeGroupUser>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
 ) -> Result<GroupActionResponse, String> {
-    let group_user = group_user_state.read().await;
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
 
     if let Some(user) = group_user.as_ref() {
         match user.groups.messages.clear_media_cache().await {
@@ -664,9 +1687,11 @@ pub async fn clear_group_media_cache(
 
 #[tauri::command]
 pub async fn get_group_media_cache_size(
-    group_user_state: tauri::State<'_, SafeGroupUser>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
 ) -> Result<CacheSizeResponse, String> {
-    let group_user = group_user_state.read().await;
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
 
     if let Some(user) = group_user.as_ref() {
         match user.groups.messages.get_media_cache_size().await {
@@ -686,15 +1711,17 @@ pub async fn update_member_permissions(
     member_id: u64,
     permissions: UpdatePermissions,
     role: Option<String>,
-    group_user_state: tauri::State<'_, SafeGroupUser>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
 ) -> Result<GroupActionResponse, String> {
-    let group_user = group_user_state.read().await;
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
     let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
     if let Some(user) = group_user.as_ref() {
         let group_config = user.get_group_config(&group_id).await.unwrap();
         // Проверка прав текущего пользователя на изменение прав
         let user_id = user.user_id();
-        let can_edit = group_config.has_permission(user_id, "manage_permissions");
+        let can_edit = group_config.has_permission(user_id, Permission::ManagePermissions);
 
         if !can_edit {
             return Err("You don't have permission to edit user permissions".to_string());
@@ -709,49 +1736,26 @@ pub async fn update_member_permissions(
             ));
         }
 
+        if !group_config.can_manage_member(user_id, member_id) {
+            return Err("You can't edit a member of equal or higher rank".to_string());
+        }
+
         // Если указана роль, устанавливаем соответствующие права
         if let Some(role_name) = role {
-            new_config.set_member_role(member_id, &role_name);
-        } else {
-            // Иначе устанавливаем индивидуальные права
-            let mut member_permissions = new_config
-                .get_member_permissions(member_id)
-                .cloned()
-                .unwrap_or_else(group_config::Permissions::default);
-
-            // Обновляем каждое право, если оно указано в переданном JSON
-            if let Some(val) = permissions.manage_members {
-                member_permissions = member_permissions.with_manage_members(val);
-            }
-
-            if let Some(val) = permissions.send_messages {
-                member_permissions = member_permissions.with_send_messages(val);
-            }
-
-            if let Some(val) = permissions.delete_messages {
-                member_permissions = member_permissions.with_delete_messages(val);
-            }
-
-            if let Some(val) = permissions.rename_group {
-                member_permissions = member_permissions.with_rename_group(val);
-            }
-
-            if let Some(val) = permissions.manage_permissions {
-                member_permissions = member_permissions.with_manage_permissions(val);
-            }
-
-            if let Some(val) = permissions.pin_messages {
-                member_permissions = member_permissions.with_pin_messages(val);
+            let rank = group_config
+                .get_role(&role_name)
+                .map_or(0, |role| role.rank);
+            if !group_config.can_grant_rank(user_id, rank) {
+                return Err(format!("You can't assign the role {}", role_name));
             }
-
-            if let Some(val) = permissions.manage_admins {
-                member_permissions = member_permissions.with_manage_admins(val);
+            if !new_config.set_member_role(member_id, &role_name) {
+                return Err(format!("Role {} is not defined in this group", role_name));
             }
-
-            // Устанавливаем обновленные права используя метод GroupConfig
-            new_config.update_permissions(member_id, |p| {
-                *p = member_permissions;
-            });
+        } else {
+            // Иначе устанавливаем индивидуальные права поверх прав ролей
+            let member_permissions =
+                permissions.apply_to(new_config.effective_permissions(member_id));
+            new_config.set_permissions(member_id, member_permissions);
         }
 
         user.update_group_config(&group_id, &new_config)
@@ -759,7 +1763,7 @@ pub async fn update_member_permissions(
             .map_err(|e| e.to_string())?;
 
         let event_payload = format_group_config(&group_config, group_id, user_id);
-        app_handle.emit("server-event", event_payload).unwrap();
+        emit_server_event(&app_handle, session.user_id(), event_payload).unwrap();
 
         Ok(GroupActionResponse {
             success: true,
@@ -775,7 +1779,8 @@ pub async fn update_member_permissions(
 pub async fn update_group_config(
     group_id: String,
     app_handle: AppHandle,
-    group_user_state: tauri::State<'_, SafeGroupUser>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
     group_name: Option<String>,
     visibility: Option<String>,
     join_mode: Option<String>,
@@ -790,15 +1795,16 @@ pub async fn update_group_config(
     allow_links: Option<bool>,
     allow_messages: Option<bool>,
 ) -> Result<GroupActionResponse, String> {
-    let group_user = group_user_state.read().await;
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
     let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
     if let Some(user) = group_user.as_ref() {
         let group_config = user.get_group_config(&group_id).await.unwrap();
 
         // Проверка прав пользователя на редактирование группы
         let user_id = user.user_id();
-        let can_edit = group_config.has_permission(user_id, "rename_group")
-            || group_config.has_permission(user_id, "manage_permissions");
+        let can_edit = group_config.has_permission(user_id, Permission::RenameGroup)
+            || group_config.has_permission(user_id, Permission::ManagePermissions);
 
         if !can_edit {
             return Err("You don't have permission to edit group configuration".to_string());
@@ -901,9 +1907,7 @@ pub async fn update_group_config(
 
         let users_permisions = &new_config.permissions;
         let default_permissions = &new_config.default_permissions;
-        let user_permissions = users_permisions
-            .get(&user_id)
-            .unwrap_or(default_permissions);
+        let user_permissions = new_config.effective_permissions(user_id);
         let event_payload = serde_json::json!({
             "type": "group_config_updated",
             "data": {
@@ -920,7 +1924,7 @@ pub async fn update_group_config(
                 "default_permissions": default_permissions,
             }
         });
-        app_handle.emit("server-event", event_payload).unwrap();
+        emit_server_event(&app_handle, session.user_id(), event_payload).unwrap();
 
         Ok(GroupActionResponse {
             success: true,
@@ -934,9 +1938,11 @@ pub async fn update_group_config(
 #[tauri::command]
 pub async fn get_group_display_key(
     group_id: String,
-    group_user_state: tauri::State<'_, SafeGroupUser>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
 ) -> Result<Vec<u8>, String> {
-    let group_user = group_user_state.read().await;
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
     let group_user = group_user.as_ref().unwrap();
     let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
     let display_key = group_user
@@ -945,3 +1951,330 @@ pub async fn get_group_display_key(
         .map_err(|e| e.to_string())?;
     Ok(display_key)
 }
+
+#[tauri::command]
+pub async fn get_group_audit_log(
+    group_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<AuditLogResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    if let Some(user) = group_user.as_ref() {
+        let entries = user
+            .get_group_audit_log(&group_id)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|entry| AuditLogEntryResponse {
+                epoch: entry.epoch,
+                committer_id: entry.committer_id,
+                category: entry.category,
+                field: entry.change.field,
+                old_value: entry.change.old_value,
+                new_value: entry.change.new_value,
+                timestamp: entry.timestamp,
+            })
+            .collect();
+
+        Ok(AuditLogResponse { entries })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn pin_message(
+    group_id: String,
+    message_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+    app_handle: AppHandle,
+) -> Result<GroupActionResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    let message_id = message_id.parse::<u64>().map_err(|e| e.to_string())?;
+    if let Some(user) = group_user.as_ref() {
+        let old_config = user
+            .get_group_config(&group_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        let new_config = user
+            .pin_message(&group_id, message_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Err(e) = emit_pinned_messages_events(
+            &app_handle,
+            session.user_id(),
+            &group_id,
+            &old_config,
+            &new_config,
+            user.user_id(),
+        )
+        .await
+        {
+            log::error!("Failed to emit message pinned event: {}", e);
+        }
+
+        Ok(GroupActionResponse {
+            success: true,
+            message: "Message pinned successfully".to_string(),
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn unpin_message(
+    group_id: String,
+    message_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+    app_handle: AppHandle,
+) -> Result<GroupActionResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    let message_id = message_id.parse::<u64>().map_err(|e| e.to_string())?;
+    if let Some(user) = group_user.as_ref() {
+        let old_config = user
+            .get_group_config(&group_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        let new_config = user
+            .unpin_message(&group_id, message_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Err(e) = emit_pinned_messages_events(
+            &app_handle,
+            session.user_id(),
+            &group_id,
+            &old_config,
+            &new_config,
+            user.user_id(),
+        )
+        .await
+        {
+            log::error!("Failed to emit message unpinned event: {}", e);
+        }
+
+        Ok(GroupActionResponse {
+            success: true,
+            message: "Message unpinned successfully".to_string(),
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn get_pinned_messages(
+    group_id: String,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<PinnedMessagesResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    if let Some(user) = group_user.as_ref() {
+        let group_config = user
+            .get_group_config(&group_id)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut pinned = Vec::with_capacity(group_config.pinned_messages.len());
+        for pinned_message in group_config.pinned_messages {
+            let message = user
+                .groups
+                .messages
+                .get_message(group_id.as_bytes(), pinned_message.message_id as i64)
+                .await
+                .map_err(|e| e.to_string())?
+                .map(|message| {
+                    GroupMessageResponse::from_text_message(message, Some(group_id.to_string()))
+                });
+            pinned.push(PinnedMessageResponse {
+                message_id: pinned_message.message_id.to_string(),
+                pinned_by: pinned_message.pinned_by,
+                pinned_at: pinned_message.pinned_at.timestamp,
+                message,
+            });
+        }
+
+        Ok(PinnedMessagesResponse { pinned })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn save_group_role(
+    group_id: String,
+    name: String,
+    color: u32,
+    rank: u32,
+    permissions: UpdatePermissions,
+    app_handle: AppHandle,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<GroupActionResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    if let Some(user) = group_user.as_ref() {
+        let group_config = user
+            .get_group_config(&group_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        let user_id = user.user_id();
+        if !group_config.has_permission(user_id, Permission::ManagePermissions) {
+            return Err("You don't have permission to manage roles".to_string());
+        }
+
+        let name = name.trim().to_string();
+        if name.is_empty() {
+            return Err("Role name cannot be empty".to_string());
+        }
+        let existing = group_config.get_role(&name);
+        if !group_config.can_edit_role(user_id, rank)
+            || existing.is_some_and(|role| !group_config.can_edit_role(user_id, role.rank))
+        {
+            return Err("You can't edit a role of equal or higher rank".to_string());
+        }
+
+        let base_permissions = existing
+            .map(|role| role.permissions.clone())
+            .unwrap_or_else(Permissions::none);
+        let role =
+            group_config::Role::new(name, color, rank, permissions.apply_to(base_permissions));
+
+        let mut new_config = group_config.clone();
+        new_config.set_role(role);
+        user.update_group_config(&group_id, &new_config)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let event_payload = format_group_config(&new_config, group_id, user_id);
+        emit_server_event(&app_handle, session.user_id(), event_payload).unwrap();
+
+        Ok(GroupActionResponse {
+            success: true,
+            message: "Role saved successfully".to_string(),
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn delete_group_role(
+    group_id: String,
+    name: String,
+    app_handle: AppHandle,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<GroupActionResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    if let Some(user) = group_user.as_ref() {
+        let group_config = user
+            .get_group_config(&group_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        let user_id = user.user_id();
+        if !group_config.has_permission(user_id, Permission::ManagePermissions) {
+            return Err("You don't have permission to manage roles".to_string());
+        }
+        let role = group_config
+            .get_role(&name)
+            .ok_or_else(|| format!("Role {} is not defined in this group", name))?;
+        if !group_config.can_edit_role(user_id, role.rank) {
+            return Err("You can't delete a role of equal or higher rank".to_string());
+        }
+
+        let mut new_config = group_config.clone();
+        if !new_config.remove_role(&name) {
+            return Err(format!("Role {} cannot be deleted", name));
+        }
+        user.update_group_config(&group_id, &new_config)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let event_payload = format_group_config(&new_config, group_id, user_id);
+        emit_server_event(&app_handle, session.user_id(), event_payload).unwrap();
+
+        Ok(GroupActionResponse {
+            success: true,
+            message: "Role deleted successfully".to_string(),
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn assign_group_role(
+    group_id: String,
+    member_id: u64,
+    role: String,
+    assign: bool,
+    app_handle: AppHandle,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<GroupActionResponse, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+    let group_id = GroupId::from_string(&group_id).map_err(|e| e.to_string())?;
+    if let Some(user) = group_user.as_ref() {
+        let group_config = user
+            .get_group_config(&group_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        let user_id = user.user_id();
+        if !group_config.has_permission(user_id, Permission::ManagePermissions) {
+            return Err("You don't have permission to manage roles".to_string());
+        }
+        if !group_config.is_member(member_id) {
+            return Err(format!(
+                "User with ID {} is not a member of the group",
+                member_id
+            ));
+        }
+        if !group_config.can_manage_member(user_id, member_id) {
+            return Err("You can't edit a member of equal or higher rank".to_string());
+        }
+        let rank = group_config
+            .get_role(&role)
+            .map(|role| role.rank)
+            .ok_or_else(|| format!("Role {} is not defined in this group", role))?;
+        if assign && !group_config.can_grant_rank(user_id, rank) {
+            return Err(format!("You can't assign the role {}", role));
+        }
+
+        let mut new_config = group_config.clone();
+        let changed = if assign {
+            new_config.add_member_role(member_id, &role)
+        } else {
+            new_config.remove_member_role(member_id, &role)
+        };
+        if changed {
+            user.update_group_config(&group_id, &new_config)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            let event_payload = format_group_config(&new_config, group_id, user_id);
+            emit_server_event(&app_handle, session.user_id(), event_payload).unwrap();
+        }
+
+        Ok(GroupActionResponse {
+            success: true,
+            message: "Member roles updated successfully".to_string(),
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
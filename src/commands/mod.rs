@@ -1,7 +1,10 @@
 pub mod auth;
+pub mod backup;
 pub mod chat;
 pub mod events;
 pub mod group;
+pub mod session;
+pub mod storage;
 pub mod user;
 pub mod utils;
 pub mod voice;
@@ -0,0 +1,104 @@
+//! Accounts logged in at the same time
+//!
+//! Each logged-in account has its own session: device, status and voice
+//! clients, each running its own streams and handlers. Commands select the
+//! session with `account_id`, without it the active account is used: the
+//! last one logged in or the one set with `set_active_account`.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::api::account::Account;
+use crate::api::device::Device;
+use crate::api::status::user_status::UserStatusClient;
+use crate::api::voice::VoiceUser;
+
+pub type SafeGroupUser = Arc<RwLock<Option<Device>>>;
+pub type SafeUserStatus = Arc<RwLock<Option<UserStatusClient>>>;
+pub type SafeVoiceUser = Arc<RwLock<VoiceUser>>;
+
+/// Clients of one logged-in account
+pub struct Session {
+    pub account: Arc<Account>,
+    pub group_user: SafeGroupUser,
+    pub user_status: SafeUserStatus,
+    pub voice_user: SafeVoiceUser,
+}
+
+impl Session {
+    pub fn user_id(&self) -> u64 {
+        self.account.user_id
+    }
+}
+
+#[derive(Default)]
+struct Sessions {
+    by_user_id: BTreeMap<u64, Arc<Session>>,
+    active: Option<u64>,
+}
+
+/// Sessions keyed by user id, managed once for the whole app
+#[derive(Default)]
+pub struct SessionRegistry {
+    sessions: RwLock<Sessions>,
+}
+
+impl SessionRegistry {
+    /// Add `session` and make it active, returning the one it replaces
+    pub async fn insert(&self, session: Arc<Session>) -> Option<Arc<Session>> {
+        let mut sessions = self.sessions.write().await;
+        let user_id = session.user_id();
+        sessions.active = Some(user_id);
+        sessions.by_user_id.insert(user_id, session)
+    }
+
+    /// Remove the session of `user_id`
+    ///
+    /// If it was active another logged-in account, if any, becomes active.
+    pub async fn remove(&self, user_id: u64) -> Option<Arc<Session>> {
+        let mut sessions = self.sessions.write().await;
+        let removed = sessions.by_user_id.remove(&user_id);
+        if sessions.active == Some(user_id) {
+            sessions.active = sessions.by_user_id.keys().next().copied();
+        }
+        removed
+    }
+
+    /// Session selected by `account_id`, the active one for `None`
+    pub async fn get(&self, account_id: Option<u64>) -> Result<Arc<Session>, String> {
+        let sessions = self.sessions.read().await;
+        let user_id = account_id
+            .or(sessions.active)
+            .ok_or("No account logged in. Call login first.".to_string())?;
+        sessions
+            .by_user_id
+            .get(&user_id)
+            .cloned()
+            .ok_or(format!("Account {} is not logged in", user_id))
+    }
+
+    pub async fn set_active(&self, user_id: u64) -> Result<(), String> {
+        let mut sessions = self.sessions.write().await;
+        if !sessions.by_user_id.contains_key(&user_id) {
+            return Err(format!("Account {} is not logged in", user_id));
+        }
+        sessions.active = Some(user_id);
+        Ok(())
+    }
+
+    pub async fn active(&self) -> Option<u64> {
+        self.sessions.read().await.active
+    }
+
+    /// Accounts of all sessions, by user id
+    pub async fn accounts(&self) -> Vec<Arc<Account>> {
+        self.sessions
+            .read()
+            .await
+            .by_user_id
+            .values()
+            .map(|session| session.account.clone())
+            .collect()
+    }
+}
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::api::storage::{self, StorageConfig};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageInfo {
+    pub root: String,
+    pub profile: String,
+    pub portable: bool,
+    pub profiles: Vec<String>,
+    /// Config applied from the next start
+    pub data_dir: Option<String>,
+    pub next_profile: Option<String>,
+}
+
+#[tauri::command]
+pub async fn get_storage_info() -> Result<StorageInfo, String> {
+    let layout = storage::layout();
+    let config = StorageConfig::load().map_err(|e| e.to_string())?;
+    Ok(StorageInfo {
+        root: layout.root().display().to_string(),
+        profile: layout.profile().to_string(),
+        portable: layout.is_portable(),
+        profiles: layout.profiles().map_err(|e| e.to_string())?,
+        data_dir: config.data_dir.map(|dir| dir.display().to_string()),
+        next_profile: config.profile,
+    })
+}
+
+/// Change the data directory and profile, applied from the next start
+///
+/// `SHIP_DATA_DIR` and `SHIP_PROFILE` still take precedence. Files are not
+/// moved to a new data directory.
+#[tauri::command]
+pub async fn set_storage_config(
+    data_dir: Option<String>,
+    profile: Option<String>,
+) -> Result<(), String> {
+    StorageConfig {
+        data_dir: data_dir.map(PathBuf::from),
+        profile,
+    }
+    .save()
+    .map_err(|e| e.to_string())
+}
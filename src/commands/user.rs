@@ -1,24 +1,52 @@
 use crate::api::{
-    account::Account,
+    device::KeyChange,
     status::{
         Avatar, DisplayUserInfo, DisplayUserStatus, UpdateUserAvatarResponse, UserManager,
-        get_default_db_path, user_status::UserStatusClient,
+        get_default_db_path,
     },
 };
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use crate::commands::session::SessionRegistry;
+use base64::{Engine as _, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SafetyNumberResponse {
+    pub user_id: i64,
+    /// 60 digits in groups of 5 to compare with the contact
+    pub safety_number: String,
+    /// Base64 payload of the QR code to show the contact
+    pub qr_payload: String,
+    pub verified: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyChangeResponse {
+    pub user_id: i64,
+    /// The contact was verified with the previous key
+    pub was_verified: bool,
+    pub changed_at: i64,
+}
 
-type SafeUserStatus = Arc<RwLock<Option<UserStatusClient>>>;
-type SafeAccount = Arc<Account>;
+impl From<KeyChange> for KeyChangeResponse {
+    fn from(change: KeyChange) -> Self {
+        Self {
+            user_id: change.user_id as i64,
+            was_verified: change.was_verified,
+            changed_at: change.changed_at,
+        }
+    }
+}
 
 #[tauri::command]
 pub async fn get_user_status(
-    user_status: tauri::State<'_, SafeUserStatus>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
     user_id: i64,
 ) -> Result<DisplayUserStatus, String> {
     log::debug!("Get user status called");
+    let session = sessions.get(account_id).await?;
 
-    let mut user_status = user_status.write().await;
+    let mut user_status = session.user_status.write().await;
 
     if let Some(user_status) = user_status.as_mut() {
         let status = user_status
@@ -34,12 +62,14 @@ pub async fn get_user_status(
 
 #[tauri::command]
 pub async fn get_user_info(
-    user_status: tauri::State<'_, SafeUserStatus>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
     user_id: i64,
 ) -> Result<DisplayUserInfo, String> {
     log::debug!("Get user info called");
+    let session = sessions.get(account_id).await?;
 
-    let mut user_status = user_status.write().await;
+    let mut user_status = session.user_status.write().await;
 
     if let Some(user_status) = user_status.as_mut() {
         let user_info = user_status
@@ -55,12 +85,14 @@ pub async fn get_user_info(
 
 #[tauri::command]
 pub async fn update_username(
-    user_status: tauri::State<'_, SafeUserStatus>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
     new_username: String,
 ) -> Result<bool, String> {
     log::debug!("Update username called");
+    let session = sessions.get(account_id).await?;
 
-    let mut user_status = user_status.write().await;
+    let mut user_status = session.user_status.write().await;
 
     if let Some(user_status) = user_status.as_mut() {
         let response = user_status
@@ -76,7 +108,8 @@ pub async fn update_username(
 
 #[tauri::command]
 pub async fn update_avatar(
-    user_status: tauri::State<'_, SafeUserStatus>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
     avatar: Vec<u8>,
     avatar_hash: String,
     file_size: i32,
@@ -85,6 +118,7 @@ pub async fn update_avatar(
     height: i32,
 ) -> Result<UpdateUserAvatarResponse, String> {
     log::debug!("Update avatar called");
+    let session = sessions.get(account_id).await?;
 
     let avatar = Avatar {
         avatar_data: avatar,
@@ -95,7 +129,7 @@ pub async fn update_avatar(
         height,
     };
 
-    let mut user_status = user_status.write().await;
+    let mut user_status = session.user_status.write().await;
 
     if let Some(user_status) = user_status.as_mut() {
         let response = user_status
@@ -111,12 +145,14 @@ pub async fn update_avatar(
 
 #[tauri::command]
 pub async fn set_user_status(
-    user_status: tauri::State<'_, SafeUserStatus>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
     status: String,
 ) -> Result<(), String> {
     log::debug!("Set user status called");
+    let session = sessions.get(account_id).await?;
 
-    let mut user_status = user_status.write().await;
+    let mut user_status = session.user_status.write().await;
 
     if let Some(user_status) = user_status.as_mut() {
         user_status
@@ -132,14 +168,16 @@ pub async fn set_user_status(
 
 #[tauri::command]
 pub async fn send_typing_status(
-    user_status: tauri::State<'_, SafeUserStatus>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
     chat_id: String,
     status: String,
     subscribers: Vec<i64>,
 ) -> Result<(), String> {
     log::debug!("Subscribe to users called");
+    let session = sessions.get(account_id).await?;
 
-    let mut user_status = user_status.write().await;
+    let mut user_status = session.user_status.write().await;
 
     if let Some(user_status) = user_status.as_mut() {
         user_status
@@ -155,16 +193,17 @@ pub async fn send_typing_status(
 
 #[tauri::command]
 pub async fn subscribe_to_users(
-    user_status: tauri::State<'_, SafeUserStatus>,
-    account: tauri::State<'_, SafeAccount>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
     user_ids: Vec<i64>,
 ) -> Result<(), String> {
     log::debug!("Subscribe to users called");
+    let session = sessions.get(account_id).await?;
     let user_ids = user_ids
         .into_iter()
-        .filter(|&id| id != account.user_id as i64)
+        .filter(|&id| id != session.account.user_id as i64)
         .collect();
-    let mut user_status = user_status.write().await;
+    let mut user_status = session.user_status.write().await;
 
     if let Some(user_status) = user_status.as_mut() {
         user_status
@@ -180,10 +219,12 @@ pub async fn subscribe_to_users(
 
 #[tauri::command]
 pub async fn unsubscribe_from_users(
-    user_status: tauri::State<'_, SafeUserStatus>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
     user_ids: Vec<i64>,
 ) -> Result<(), String> {
-    let mut user_status = user_status.write().await;
+    let session = sessions.get(account_id).await?;
+    let mut user_status = session.user_status.write().await;
 
     if let Some(user_status) = user_status.as_mut() {
         user_status
@@ -199,11 +240,12 @@ pub async fn unsubscribe_from_users(
 
 #[tauri::command]
 pub async fn get_contacts(
-    user_status: tauri::State<'_, SafeUserStatus>,
-    account: tauri::State<'_, SafeAccount>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
 ) -> Result<Vec<DisplayUserInfo>, String> {
     log::debug!("Get contacts called");
-    let mut user_status = user_status.write().await;
+    let session = sessions.get(account_id).await?;
+    let mut user_status = session.user_status.write().await;
     if let Some(user_status) = user_status.as_mut() {
         let contacts = user_status
             .get_contacts()
@@ -212,7 +254,7 @@ pub async fn get_contacts(
 
         Ok(contacts)
     } else {
-        let user_manager = UserManager::new(get_default_db_path(account.user_id))
+        let user_manager = UserManager::new(get_default_db_path(session.account.user_id))
             .await
             .map_err(|e| e.to_string())?;
         let contacts = user_manager
@@ -223,3 +265,146 @@ pub async fn get_contacts(
         Ok(contacts)
     }
 }
+
+#[tauri::command]
+pub async fn get_safety_number(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+    user_id: i64,
+) -> Result<SafetyNumberResponse, String> {
+    log::debug!("Get safety number called");
+    let session = sessions.get(account_id).await?;
+
+    let mut group_user = session.group_user.write().await;
+
+    if let Some(user) = group_user.as_mut() {
+        let safety_number = user
+            .safety_number(user_id as u64)
+            .await
+            .map_err(|e| e.to_string())?;
+        let verified = user
+            .is_contact_verified(user_id as u64)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(SafetyNumberResponse {
+            user_id,
+            safety_number: safety_number.digits,
+            qr_payload: general_purpose::STANDARD.encode(safety_number.qr_payload),
+            verified,
+        })
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+/// Mark a contact verified, checking the scanned QR code if one is given
+#[tauri::command]
+pub async fn verify_contact(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+    user_id: i64,
+    qr_payload: Option<String>,
+) -> Result<(), String> {
+    log::debug!("Verify contact called");
+    let session = sessions.get(account_id).await?;
+
+    let qr_payload = qr_payload
+        .map(|payload| general_purpose::STANDARD.decode(payload))
+        .transpose()
+        .map_err(|e| e.to_string())?;
+    let mut group_user = session.group_user.write().await;
+
+    if let Some(user) = group_user.as_mut() {
+        user.verify_contact(user_id as u64, qr_payload.as_deref())
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn unverify_contact(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+    user_id: i64,
+) -> Result<(), String> {
+    log::debug!("Unverify contact called");
+    let session = sessions.get(account_id).await?;
+
+    let group_user = session.group_user.read().await;
+
+    if let Some(user) = group_user.as_ref() {
+        user.unverify_contact(user_id as u64)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+/// Key changes of contacts not acknowledged yet, newest first
+#[tauri::command]
+pub async fn get_key_changes(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<Vec<KeyChangeResponse>, String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+
+    if let Some(user) = group_user.as_ref() {
+        let changes = user.get_key_changes().await.map_err(|e| e.to_string())?;
+
+        Ok(changes.into_iter().map(KeyChangeResponse::from).collect())
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+#[tauri::command]
+pub async fn acknowledge_key_change(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+    user_id: i64,
+) -> Result<(), String> {
+    log::debug!("Acknowledge key change called");
+    let session = sessions.get(account_id).await?;
+
+    let group_user = session.group_user.read().await;
+
+    if let Some(user) = group_user.as_ref() {
+        user.acknowledge_key_change(user_id as u64)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
+
+/// Block sending to groups with a member whose key change is not acknowledged
+#[tauri::command]
+pub async fn set_block_on_key_change(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+    enabled: bool,
+) -> Result<(), String> {
+    let session = sessions.get(account_id).await?;
+    let group_user = session.group_user.read().await;
+
+    if let Some(user) = group_user.as_ref() {
+        user.set_block_on_key_change(enabled)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    } else {
+        Err("Group user not initialized. Call init_group_user first.".to_string())
+    }
+}
@@ -1,24 +1,28 @@
 use crate::api::connection::get_avaliable_voice_servers;
 use crate::api::voice::echolocator::client_message::VoiceRequest;
-use crate::api::voice::{VoiceKeysPayload, VoiceUser, echolocator::ClientMessage};
-use std::sync::Arc;
-use tauri::State;
-use tokio::sync::RwLock;
-
-type SafeVoiceUser = Arc<RwLock<VoiceUser>>;
+use crate::api::voice::{VoiceKeysPayload, echolocator::ClientMessage};
+use crate::commands::session::SessionRegistry;
 
 /// Export key material for TypeScript SubtleCrypto
 #[tauri::command]
-pub async fn get_voice_keys(state: State<'_, SafeVoiceUser>) -> Result<VoiceKeysPayload, String> {
-    let voice_user = state.read().await;
+pub async fn get_voice_keys(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<VoiceKeysPayload, String> {
+    let session = sessions.get(account_id).await?;
+    let voice_user = session.voice_user.read().await;
     voice_user.get_voice_keys().await.map_err(|e| e.to_string())
 }
 
 // Команда для инициализации соединения
 #[tauri::command]
-pub async fn initialize_connection(state: State<'_, SafeVoiceUser>) -> Result<(), String> {
+pub async fn initialize_connection(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<(), String> {
+    let session = sessions.get(account_id).await?;
     log::info!("Initializing connection...");
-    let voice_user = state.read().await;
+    let voice_user = session.voice_user.read().await;
 
     match voice_user.initialize().await {
         Ok(_) => {
@@ -36,11 +40,13 @@ pub async fn initialize_connection(state: State<'_, SafeVoiceUser>) -> Result<()
 #[tauri::command]
 pub async fn join_session(
     session_id: String,
-    state: State<'_, SafeVoiceUser>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
 ) -> Result<(), String> {
+    let session = sessions.get(account_id).await?;
     log::info!("join_session: session_id={}", session_id);
 
-    let voice_user = state.read().await;
+    let voice_user = session.voice_user.read().await;
     voice_user.initialize().await.map_err(|e| e.to_string())?;
     if voice_user.is_joined().await {
         log::error!("Already joined session");
@@ -60,8 +66,12 @@ pub async fn join_session(
 
 // Команда для отсоединения от сессии
 #[tauri::command]
-pub async fn leave_session(state: State<'_, SafeVoiceUser>) -> Result<(), String> {
-    let voice_user = state.read().await;
+pub async fn leave_session(
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
+) -> Result<(), String> {
+    let session = sessions.get(account_id).await?;
+    let voice_user = session.voice_user.read().await;
     match voice_user.leave_voice_channel().await {
         Ok(_) => {
             log::info!("Successfully left voice channel");
@@ -85,9 +95,11 @@ pub async fn get_voice_servers() -> Result<Vec<String>, String> {
 pub async fn init_webrtc_signaling(
     session_id: String,
     rtp_capabilities: Option<String>,
-    state: State<'_, SafeVoiceUser>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
 ) -> Result<(), String> {
-    let voice_user = state.read().await;
+    let session = sessions.get(account_id).await?;
+    let voice_user = session.voice_user.read().await;
     voice_user
         .init_signaling_stream(session_id, rtp_capabilities)
         .await
@@ -100,13 +112,15 @@ pub async fn init_webrtc_signaling(
 #[tauri::command]
 pub async fn send_webrtc_message(
     message: VoiceRequest,
-    state: State<'_, SafeVoiceUser>,
+    sessions: tauri::State<'_, SessionRegistry>,
+    account_id: Option<u64>,
 ) -> Result<(), String> {
+    let session = sessions.get(account_id).await?;
     log::info!("send_webrtc_message: message={:?}", message);
     let voice_message = ClientMessage {
         voice_request: Some(message),
     };
-    let voice_user = state.read().await;
+    let voice_user = session.voice_user.read().await;
     voice_user
         .send_signaling_message(voice_message)
         .await
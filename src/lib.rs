@@ -7,6 +7,7 @@ mod api {
     pub mod connection;
     pub mod device;
     pub mod status;
+    pub mod storage;
     pub mod voice;
 }
 
@@ -16,6 +17,12 @@ mod commands;
 mod tests;
 
 async fn init_client(_app_handle: tauri::AppHandle) -> Result<(), String> {
+    let layout = api::storage::layout();
+    log::info!(
+        "Storage: {} (profile {})",
+        layout.root().display(),
+        layout.profile()
+    );
     log::info!("Client initialized");
     Ok(())
 }
@@ -40,6 +47,7 @@ pub fn run() {
                     eprintln!("Error configuring webview: {:?}", e);
                 }
             });
+            app.manage(commands::session::SessionRegistry::default());
             tauri::async_runtime::spawn(init_client(app.handle().clone()));
             Ok(())
         })
@@ -50,21 +58,49 @@ pub fn run() {
             commands::auth::get_account_list,
             commands::auth::delete_account,
             commands::auth::log_out,
+            commands::auth::get_sessions,
+            commands::auth::set_active_account,
             commands::auth::export_account,
             commands::auth::import_account,
             commands::auth::get_user_devices,
+            commands::auth::add_trusted_server_key,
+            commands::auth::remove_trusted_server_key,
+            commands::auth::get_trusted_server_keys,
+            commands::auth::set_skip_certificate_checks,
             commands::chat::send_message,
             commands::chat::create_chat,
             commands::chat::get_chats,
             commands::chat::get_messages,
             commands::group::create_group,
             commands::group::leave_group,
+            commands::group::transfer_ownership,
+            commands::group::disband_group,
+            commands::group::get_archived_groups,
             commands::group::get_groups,
             commands::group::invite_to_group,
             commands::group::remove_from_group,
             commands::group::send_group_message,
+            commands::group::schedule_group_message,
+            commands::group::get_scheduled_messages,
+            commands::group::edit_scheduled_message,
+            commands::group::cancel_scheduled_message,
+            commands::group::save_draft,
+            commands::group::get_draft,
+            commands::group::clear_draft,
             commands::group::get_group_messages,
+            commands::group::get_thread,
+            commands::group::mark_thread_read,
+            commands::group::get_message_history,
             commands::group::delete_group_message,
+            commands::group::delete_message_for_everyone,
+            commands::group::create_poll,
+            commands::group::vote_poll,
+            commands::group::close_poll,
+            commands::group::get_poll_results,
+            commands::group::get_group_polls,
+            commands::group::mark_mentions_read,
+            commands::group::mark_group_read,
+            commands::group::mark_unread,
             commands::group::get_group_media,
             commands::group::get_all_group_media,
             commands::group::clear_group_media_cache,
@@ -72,6 +108,13 @@ pub fn run() {
             commands::group::update_group_config,
             commands::group::update_member_permissions,
             commands::group::get_group_display_key,
+            commands::group::get_group_audit_log,
+            commands::group::pin_message,
+            commands::group::unpin_message,
+            commands::group::get_pinned_messages,
+            commands::group::save_group_role,
+            commands::group::delete_group_role,
+            commands::group::assign_group_role,
             commands::voice::get_voice_keys,
             commands::voice::initialize_connection,
             commands::voice::join_session,
@@ -88,6 +131,16 @@ pub fn run() {
             commands::user::subscribe_to_users,
             commands::user::unsubscribe_from_users,
             commands::user::get_contacts,
+            commands::user::get_safety_number,
+            commands::user::verify_contact,
+            commands::user::unverify_contact,
+            commands::user::get_key_changes,
+            commands::user::acknowledge_key_change,
+            commands::user::set_block_on_key_change,
+            commands::storage::get_storage_info,
+            commands::storage::set_storage_config,
+            commands::backup::create_backup,
+            commands::backup::restore_backup,
             commands::utils::save_media_file,
             commands::utils::save_file_from_memory,
         ])
@@ -1,4 +1,11 @@
-import type { DateTime, JoinMode, Permissions, Visibility } from "./generated";
+import type {
+	DateTime,
+	JoinMode,
+	Permissions,
+	PinnedMessage,
+	Role,
+	Visibility,
+} from "./generated";
 
 export interface GroupPermissions extends Permissions {
 	allow_links: boolean;
@@ -24,17 +31,20 @@ export interface GroupConfig {
 	admins: string[];
 	permissions: Record<string, Permissions>;
 	default_permissions: Permissions;
+	roles: Role[];
+	member_roles: Record<string, string[]>;
 	banned: string[];
 	muted: Record<string, DateTime>;
 
 	description?: string | null;
-	pinned_message_id?: string | null;
+	pinned_messages: PinnedMessage[];
 	slow_mode_delay?: number | null;
 	allow_stickers: boolean;
 	allow_gifs: boolean;
 	allow_voice_messages: boolean;
 	allow_video_messages: boolean;
 	allow_links: boolean;
+	disbanded_at?: DateTime | null;
 }
 
 // Basic Chat interface that can be a single user or a group
@@ -32,7 +32,7 @@ export function mapGroupConfig(config: BackendGroupConfig): GroupConfig {
 		muted: Object.fromEntries(
 			Object.entries(config.muted).map(([k, v]) => [String(k), v]),
 		),
-		pinned_message_id: String(config.pinned_message_id),
+		pinned_messages: config.pinned_messages,
 	};
 }
 
@@ -97,6 +97,14 @@ export function useGroups(currentUser?: User | null) {
 				return Boolean(user_permissions[permissionKey]);
 			}
 
+			const roleNames = group.group_config?.member_roles?.[userId] ?? [];
+			const roles = (group.group_config?.roles ?? []).filter((role) =>
+				roleNames.includes(role.name),
+			);
+			if (roles.length > 0) {
+				return roles.some((role) => Boolean(role.permissions[permissionKey]));
+			}
+
 			if (group.group_config?.default_permissions) {
 				return Boolean(group.group_config.default_permissions[permissionKey]);
 			}